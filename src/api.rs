@@ -2,19 +2,328 @@
 #![allow(dead_code)]
 
 use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
+use crate::status::{self, debug, note};
+use crate::telemetry;
+
 const BASE_URL: &str = "https://graph.microsoft.com/v1.0";
-const MAX_RETRIES: u32 = 3;
-const INITIAL_BACKOFF_MS: u64 = 1000;
+// Graph rejects $batch payloads with more than 20 requests
+const BATCH_LIMIT: usize = 20;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+/// Inbox rule that collects blocked senders; Graph has no blocked-senders API
+pub const BLOCK_RULE_NAME: &str = "Blocked senders (outlook-cli)";
+/// Page size used when walking every page of a listing
+pub const PAGE_SIZE: u32 = 100;
+// Largest $top Graph accepts for messages; used when only counting
+const COUNT_PAGE_SIZE: u32 = 1000;
+// Asks Graph to return `body` as text rather than HTML
+const PREFER_TEXT_BODY: &str = "outlook.body-content-type=\"text\"";
+// PidTagMessageSize, exposed through singleValueExtendedProperties
+const SIZE_PROPERTY: &str = "Integer 0x0E08";
+const LIST_SELECT: &str = "id,subject,from,receivedDateTime,bodyPreview,isRead,categories,parentFolderId,inferenceClassification,conversationId,hasAttachments";
+
+// Shared or delegated mailbox to use instead of the signed-in user's
+static MAILBOX: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Send every `/me` request to another mailbox (`--mailbox`); set once at startup
+pub fn set_mailbox(mailbox: Option<String>) {
+    if let Some(mailbox) = mailbox.filter(|m| !m.trim().is_empty()) {
+        let _ = MAILBOX.set(mailbox.trim().to_string());
+    }
+}
+
+pub fn mailbox() -> Option<&'static str> {
+    MAILBOX.get().map(String::as_str)
+}
+
+fn log_headers(direction: &str, headers: &reqwest::header::HeaderMap) {
+    for (name, value) in headers {
+        let value = if name == reqwest::header::AUTHORIZATION {
+            "[redacted]"
+        } else {
+            value.to_str().unwrap_or("[binary]")
+        };
+        debug!(2, "{} {}: {}", direction, name, value);
+    }
+}
+
+fn log_body(direction: &str, body: &[u8]) {
+    match std::str::from_utf8(body) {
+        Ok(text) if !text.is_empty() => debug!(2, "{}\n{}", direction, text),
+        Ok(_) => {}
+        Err(_) => debug!(2, "{} [{} bytes of binary data]", direction, body.len()),
+    }
+}
+
+// POST actions that only read, so --dry-run still sends them
+const READ_ONLY_ACTIONS: &[&str] = &["/translateExchangeIds"];
+// Request bodies longer than this are cut short in --dry-run output
+const DRY_RUN_BODY_CHARS: usize = 200;
+
+fn is_write(method: &str, path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or(path);
+    !matches!(method, "GET" | "HEAD") && !READ_ONLY_ACTIONS.iter().any(|a| path.ends_with(a))
+}
+
+fn dry_run_line(method: &str, path: &str, body: Option<&serde_json::Value>) -> String {
+    let mut line = format!("Would {} {}", method, path);
+    if let Some(body) = body {
+        let body = body.to_string();
+        line.push(' ');
+        match body.char_indices().nth(DRY_RUN_BODY_CHARS) {
+            Some((end, _)) => {
+                line.push_str(&body[..end]);
+                line.push_str("...");
+            }
+            None => line.push_str(&body),
+        }
+    }
+    line
+}
+
+/// What `--dry-run` answers instead of sending a request: a "Would ..." line
+/// per write, and a success reply echoing the request body (with a
+/// placeholder id) so callers that parse the result carry on. A $batch gets
+/// one reply per item. None for reads, which are sent as usual.
+fn dry_run_reply(
+    method: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Option<(Vec<String>, serde_json::Value)> {
+    let echo = |body: Option<&serde_json::Value>| {
+        let mut reply = match body {
+            Some(serde_json::Value::Object(fields)) => fields.clone(),
+            _ => serde_json::Map::new(),
+        };
+        reply
+            .entry("id")
+            .or_insert_with(|| serde_json::Value::from("dry-run"));
+        serde_json::Value::Object(reply)
+    };
+    if path != "/$batch" {
+        return is_write(method, path)
+            .then(|| (vec![dry_run_line(method, path, body)], echo(body)));
+    }
+
+    let requests = body?.get("requests")?.as_array()?;
+    let mut lines = Vec::new();
+    let mut responses = Vec::new();
+    for request in requests {
+        let method = request["method"].as_str().unwrap_or_default();
+        let url = request["url"].as_str().unwrap_or_default();
+        if is_write(method, url) {
+            lines.push(dry_run_line(method, url, request.get("body")));
+        }
+        responses.push(serde_json::json!({
+            "id": request["id"],
+            "status": 200,
+            "body": echo(request.get("body")),
+        }));
+    }
+    (!lines.is_empty()).then(|| (lines, serde_json::json!({ "responses": responses })))
+}
+
+/// Request timeout and how failed or throttled requests are retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// First backoff; doubled on each retry unless Graph sends Retry-After
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_secs(30),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(1000),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Exponential backoff: 1s, 2s, 4s... with the default policy, each varied
+    // by up to 50% so parallel requests don't retry in lockstep
+    fn backoff(&self, attempt: u32) -> Duration {
+        use rand::Rng;
+        let base = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt));
+        let factor = rand::thread_rng().gen_range(0.5..1.5);
+        Duration::try_from_secs_f64(base.as_secs_f64() * factor).unwrap_or(base)
+    }
+}
+
+// Stop sending once Graph has throttled this many requests within the window
+const BREAKER_THRESHOLD: usize = 8;
+const BREAKER_WINDOW: Duration = Duration::from_secs(60);
+
+/// Graph kept answering 429/503, so requests stopped rather than pile on
+#[derive(Debug)]
+pub struct Throttled {
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Round up so "retry after 0s" never shows while still throttled
+        let seconds = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        write!(f, "Throttled by Microsoft Graph, retry after {}s", seconds)
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+// Shared by every request on a Client, so bulk operations back off together
+#[derive(Default)]
+struct CircuitBreaker {
+    throttled: std::collections::VecDeque<std::time::Instant>,
+    open_until: Option<std::time::Instant>,
+}
+
+impl CircuitBreaker {
+    fn check(&self, now: std::time::Instant) -> Result<(), Throttled> {
+        match self.open_until {
+            Some(until) if until > now => Err(Throttled {
+                retry_after: until - now,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a 429/503; opens the breaker for `retry_after` once there have
+    /// been too many within the window
+    fn record(&mut self, now: std::time::Instant, retry_after: Duration) -> Result<(), Throttled> {
+        while self
+            .throttled
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= BREAKER_WINDOW)
+        {
+            self.throttled.pop_front();
+        }
+        self.throttled.push_back(now);
+        if self.throttled.len() < BREAKER_THRESHOLD {
+            return Ok(());
+        }
+        self.throttled.clear();
+        self.open_until = Some(now + retry_after);
+        Err(Throttled { retry_after })
+    }
+}
+
+static RETRY_POLICY: std::sync::OnceLock<RetryPolicy> = std::sync::OnceLock::new();
+
+/// Use `timeout`, `max_retries` and `initial_backoff` from flags or config; set once at startup
+pub fn set_retry_policy(policy: RetryPolicy) {
+    let _ = RETRY_POLICY.set(policy);
+}
+
+pub fn retry_policy() -> RetryPolicy {
+    RETRY_POLICY.get().copied().unwrap_or_default()
+}
+
+// Ask Graph for IDs that survive moving a message between folders
+const PREFER_IMMUTABLE_IDS: &str = "IdType=\"ImmutableId\"";
+static IMMUTABLE_IDS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Send `Prefer: IdType="ImmutableId"` on every request; set once at startup
+pub fn set_immutable_ids(enabled: bool) {
+    IMMUTABLE_IDS.store(enabled, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn immutable_ids() -> bool {
+    IMMUTABLE_IDS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+static PROXY: std::sync::OnceLock<reqwest::Proxy> = std::sync::OnceLock::new();
+
+const PROXY_SCHEMES: &[&str] = &["http", "https", "socks4", "socks4a", "socks5", "socks5h"];
+
+/// Check a `proxy` setting: an http(s) or socks URL such as socks5h://127.0.0.1:1080
+pub fn parse_proxy(url: &str) -> Result<reqwest::Proxy> {
+    let parsed = url::Url::parse(url).with_context(|| format!("Invalid proxy URL '{}'", url))?;
+    if !PROXY_SCHEMES.contains(&parsed.scheme()) || parsed.host_str().is_none() {
+        anyhow::bail!(
+            "Invalid proxy URL '{}' (use {}://host:port)",
+            url,
+            PROXY_SCHEMES.join("|")
+        );
+    }
+    Ok(reqwest::Proxy::all(url)?)
+}
+
+/// Route every request through the `proxy` setting; set once at startup
+pub fn set_proxy(url: Option<&str>) -> Result<()> {
+    if let Some(url) = url {
+        let _ = PROXY.set(parse_proxy(url)?);
+    }
+    Ok(())
+}
+
+/// HTTP client builder for Graph and sign-in requests. Uses the `proxy`
+/// setting when there is one; otherwise reqwest honors HTTPS_PROXY,
+/// ALL_PROXY and NO_PROXY from the environment.
+pub fn http_client() -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    match PROXY.get() {
+        Some(proxy) => builder.proxy(proxy.clone()),
+        None => builder,
+    }
+}
+
+fn message_endpoint(id: &str) -> String {
+    format!(
+        "/me/messages/{}?$select=id,subject,from,toRecipients,body,bodyPreview,receivedDateTime,isRead,categories,internetMessageHeaders,parentFolderId",
+        urlencoding::encode(id)
+    )
+}
+
+/// The Graph message `send_mail` sends for these fields
+pub fn mail_message(to: &str, subject: &str, body: &str, from: Option<&str>) -> serde_json::Value {
+    let mut message = serde_json::json!({
+        "subject": subject,
+        "body": { "contentType": "Text", "content": body },
+        "toRecipients": [{ "emailAddress": { "address": to } }],
+    });
+    if let Some(from) = from {
+        message["from"] = serde_json::json!({ "emailAddress": { "address": from } });
+    }
+    message
+}
+
+/// `/me/messages` -> `/users/{mailbox}/messages` when a mailbox is given
+fn mailbox_path(endpoint: &str, mailbox: Option<&str>) -> String {
+    match (mailbox, endpoint.strip_prefix("/me")) {
+        (Some(mailbox), Some(rest)) if rest.is_empty() || rest.starts_with(['/', '?']) => {
+            format!("/users/{}{}", urlencoding::encode(mailbox), rest)
+        }
+        _ => endpoint.to_string(),
+    }
+}
+
+/// Fetches a new access token when Graph rejects the current one
+pub type TokenRefresh = Arc<dyn Fn() -> BoxFuture<'static, Result<String>> + Send + Sync>;
 
 pub struct Client {
     http: reqwest::Client,
-    access_token: String,
+    access_token: RwLock<String>,
+    concurrency: usize,
+    retry: RetryPolicy,
+    breaker: std::sync::Mutex<CircuitBreaker>,
+    refresh: Option<TokenRefresh>,
+    // Parallel requests that all get a 401 share one refresh
+    refresh_lock: tokio::sync::Mutex<()>,
+    base_url: String,
 }
 
-// Message list response
+/// Message list response
 #[derive(Debug, Deserialize)]
 pub struct MessageList {
     pub value: Option<Vec<Message>>,
@@ -22,7 +331,7 @@ pub struct MessageList {
     pub next_link: Option<String>,
 }
 
-// Folder list response
+/// Folder list response
 #[derive(Debug, Deserialize)]
 pub struct FolderList {
     pub value: Option<Vec<Folder>>,
@@ -41,7 +350,7 @@ pub struct Folder {
     pub unread_item_count: Option<i32>,
 }
 
-// Category (Outlook Master Category)
+/// Category (Outlook Master Category)
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Category {
     pub id: Option<String>,
@@ -62,6 +371,8 @@ pub struct Message {
     pub from: Option<Recipient>,
     #[serde(rename = "toRecipients")]
     pub to_recipients: Option<Vec<Recipient>>,
+    #[serde(rename = "ccRecipients")]
+    pub cc_recipients: Option<Vec<Recipient>>,
     pub body: Option<Body>,
     #[serde(rename = "bodyPreview")]
     pub body_preview: Option<String>,
@@ -74,15 +385,34 @@ pub struct Message {
     pub internet_message_headers: Option<Vec<InternetMessageHeader>>,
     #[serde(rename = "parentFolderId")]
     pub parent_folder_id: Option<String>,
+    #[serde(rename = "inferenceClassification")]
+    pub inference_classification: Option<String>,
+    #[serde(rename = "conversationId")]
+    pub conversation_id: Option<String>,
+    #[serde(rename = "hasAttachments")]
+    pub has_attachments: Option<bool>,
+    #[serde(rename = "singleValueExtendedProperties")]
+    pub single_value_extended_properties: Option<Vec<ExtendedProperty>>,
+    #[serde(rename = "webLink")]
+    pub web_link: Option<String>,
+    /// Version of the message when it was read, for If-Match on updates
+    #[serde(rename = "@odata.etag")]
+    pub etag: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+pub struct ExtendedProperty {
+    pub id: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Recipient {
     #[serde(rename = "emailAddress")]
     pub email_address: EmailAddress,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct EmailAddress {
     pub name: Option<String>,
     pub address: Option<String>,
@@ -101,104 +431,730 @@ pub struct InternetMessageHeader {
     pub value: String,
 }
 
-// Move response
+/// File attachment; contentBytes is base64 and only present on file attachments
+#[derive(Debug, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    pub name: Option<String>,
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    pub size: Option<u64>,
+    #[serde(rename = "isInline")]
+    pub is_inline: Option<bool>,
+    #[serde(rename = "contentId")]
+    pub content_id: Option<String>,
+    #[serde(rename = "contentBytes")]
+    pub content_bytes: Option<String>,
+    /// "#microsoft.graph.fileAttachment", "#microsoft.graph.itemAttachment"
+    /// (an attached message or event) or "#microsoft.graph.referenceAttachment"
+    /// (a link to a file in OneDrive or SharePoint)
+    #[serde(rename = "@odata.type")]
+    pub odata_type: Option<String>,
+}
+
+impl Attachment {
+    /// Whether this is a file whose bytes can be downloaded; item and
+    /// reference attachments have no file content
+    pub fn is_file(&self) -> bool {
+        self.odata_type
+            .as_deref()
+            .is_none_or(|t| t == "#microsoft.graph.fileAttachment")
+    }
+
+    /// "item" or "reference" for attachments that aren't files
+    pub fn kind(&self) -> &str {
+        self.odata_type
+            .as_deref()
+            .and_then(|t| t.strip_prefix("#microsoft.graph."))
+            .and_then(|t| t.strip_suffix("Attachment"))
+            .unwrap_or("file")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttachmentList {
+    pub value: Option<Vec<Attachment>>,
+}
+
+/// Move response
 #[derive(Debug, Deserialize)]
 pub struct MoveResponse {
     pub id: String,
+    #[serde(rename = "@odata.etag")]
+    pub etag: Option<String>,
+}
+
+/// Signed-in user
+#[derive(Debug, Deserialize)]
+pub struct User {
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    pub mail: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    pub user_principal_name: String,
+}
+
+impl User {
+    /// The primary SMTP address, falling back to the sign-in name
+    pub fn address(&self) -> &str {
+        self.mail.as_deref().unwrap_or(&self.user_principal_name)
+    }
+}
+
+// A meeting request message with its calendar event expanded
+#[derive(Debug, Deserialize)]
+struct EventMessage {
+    event: Option<EventRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventRef {
+    id: String,
+}
+
+/// Personal contact
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Contact {
+    pub id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ContactList {
+    pub value: Option<Vec<Contact>>,
+}
+
+/// Change-notification subscription
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Subscription {
+    pub id: String,
+    pub resource: String,
+    #[serde(rename = "changeType")]
+    pub change_type: String,
+    #[serde(rename = "notificationUrl")]
+    pub notification_url: String,
+    #[serde(rename = "expirationDateTime")]
+    pub expiration_date_time: String,
+    #[serde(rename = "clientState")]
+    pub client_state: Option<String>,
+}
+
+/// Inbox rule (messageRule)
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct MessageRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<i32>,
+    #[serde(rename = "isEnabled", skip_serializing_if = "Option::is_none")]
+    pub is_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<RuleConditions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<RuleActions>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RuleConditions {
+    #[serde(rename = "senderContains", skip_serializing_if = "Option::is_none")]
+    pub sender_contains: Option<Vec<String>>,
+    #[serde(rename = "fromAddresses", skip_serializing_if = "Option::is_none")]
+    pub from_addresses: Option<Vec<Recipient>>,
+    #[serde(rename = "subjectContains", skip_serializing_if = "Option::is_none")]
+    pub subject_contains: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RuleActions {
+    #[serde(rename = "moveToFolder", skip_serializing_if = "Option::is_none")]
+    pub move_to_folder: Option<String>,
+    #[serde(rename = "assignCategories", skip_serializing_if = "Option::is_none")]
+    pub assign_categories: Option<Vec<String>>,
+    #[serde(rename = "markAsRead", skip_serializing_if = "Option::is_none")]
+    pub mark_as_read: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete: Option<bool>,
+    #[serde(
+        rename = "stopProcessingRules",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop_processing_rules: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageRuleList {
+    pub value: Option<Vec<MessageRule>>,
+}
+
+/// Optional parts of a message listing request
+#[derive(Debug, Default)]
+pub struct ListOptions<'a> {
+    pub filter: Option<&'a str>,
+    pub order_by: Option<&'a str>,
+    /// Properties to $select instead of the usual listing set
+    pub select: Option<&'a str>,
+    /// Expand the MAPI message size property (Graph has no size field)
+    pub include_size: bool,
+}
+
+fn search_endpoint(folder: Option<&str>, query: &str, max_results: u32, select: &str) -> String {
+    let base = match folder {
+        Some(f) => format!("/me/mailFolders/{}/messages", urlencoding::encode(f)),
+        None => "/me/messages".to_string(),
+    };
+    // The whole query is one quoted string, so quotes inside KQL
+    // values (subject:"monthly invoice") must be backslash-escaped
+    let escaped = query.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        "{}?$search=\"{}\"&$top={}&$select={}",
+        base,
+        urlencoding::encode(&escaped),
+        max_results,
+        select
+    )
+}
+
+// $expand clause that adds the MAPI message size to each message
+fn size_expand() -> String {
+    let expand = format!(
+        "singleValueExtendedProperties($filter=id eq '{}')",
+        SIZE_PROPERTY
+    );
+    format!("&$expand={}", urlencoding::encode(&expand))
+}
+
+/// A single request inside a JSON $batch payload
+#[derive(Debug, Clone)]
+pub struct BatchRequest {
+    pub method: reqwest::Method,
+    pub url: String,
+    pub body: Option<serde_json::Value>,
+    /// Only apply the request if the item still has this ETag
+    pub if_match: Option<String>,
+}
+
+impl BatchRequest {
+    pub fn new(method: reqwest::Method, url: String, body: Option<serde_json::Value>) -> Self {
+        Self {
+            method,
+            url,
+            body,
+            if_match: None,
+        }
+    }
+
+    pub fn if_match(mut self, etag: Option<String>) -> Self {
+        self.if_match = etag;
+        self
+    }
+
+    fn to_json(&self, id: usize) -> serde_json::Value {
+        let mut request = serde_json::json!({
+            "id": id.to_string(),
+            "method": self.method.as_str(),
+            "url": mailbox_path(&self.url, mailbox()),
+        });
+        // Batched requests don't inherit headers from the $batch POST
+        let mut headers = serde_json::Map::new();
+        if let Some(body) = &self.body {
+            request["body"] = body.clone();
+            headers.insert("Content-Type".to_string(), "application/json".into());
+        }
+        if immutable_ids() {
+            headers.insert("Prefer".to_string(), PREFER_IMMUTABLE_IDS.into());
+        }
+        if let Some(etag) = &self.if_match {
+            headers.insert("If-Match".to_string(), etag.as_str().into());
+        }
+        if !headers.is_empty() {
+            request["headers"] = headers.into();
+        }
+        request
+    }
+}
+
+// POST /move requests for $batch
+fn move_requests(ids: &[String], destination_folder: &str) -> Vec<BatchRequest> {
+    ids.iter()
+        .map(|id| {
+            BatchRequest::new(
+                reqwest::Method::POST,
+                format!("/me/messages/{}/move", urlencoding::encode(id)),
+                Some(serde_json::json!({ "destinationId": destination_folder })),
+            )
+        })
+        .collect()
+}
+
+/// A message's categories as read, and the ETag they were read at
+#[derive(Debug, Clone)]
+pub struct CategorySnapshot {
+    pub categories: Vec<String>,
+    pub etag: Option<String>,
+}
+
+/// New categories for a message, written only if it still has `etag`
+#[derive(Debug, Clone)]
+pub struct CategoryUpdate {
+    pub id: String,
+    pub categories: Vec<String>,
+    pub etag: Option<String>,
+}
+
+/// One result from translateExchangeIds; targetId is missing when the ID was invalid
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslatedId {
+    pub source_id: String,
+    pub target_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TranslatedIdList {
+    value: Vec<TranslatedId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponseList {
+    responses: Vec<BatchResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponse {
+    id: String,
+    status: u16,
+    headers: Option<std::collections::HashMap<String, String>>,
+    body: Option<serde_json::Value>,
+}
+
+impl BatchResponse {
+    fn retry_after(&self) -> Option<u64> {
+        self.headers
+            .as_ref()?
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Retry-After"))
+            .and_then(|(_, v)| v.parse().ok())
+    }
+
+    fn into_result(self) -> Result<serde_json::Value> {
+        let body = self.body.unwrap_or(serde_json::Value::Null);
+        if (200..300).contains(&self.status) {
+            return Ok(body);
+        }
+        let request_id = self.headers.as_ref().and_then(|headers| {
+            headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("request-id"))
+                .map(|(_, v)| v.clone())
+        });
+        Err(GraphError::from_body(self.status, &body, request_id).into())
+    }
+}
+
+/// Whether Graph refused an If-Match update because the item changed since it was read
+pub fn is_conflict(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<GraphError>())
+        .any(|e| e.status == 412)
+}
+
+/// A request Graph answered with an error status
+#[derive(Debug)]
+pub struct GraphError {
+    pub status: u16,
+    /// Graph's error code, e.g. "ErrorItemNotFound"
+    pub code: Option<String>,
+    pub message: String,
+    /// Graph's `request-id`, for reporting a failure to Microsoft
+    pub request_id: Option<String>,
+}
+
+impl GraphError {
+    fn from_body(status: u16, body: &serde_json::Value, request_id: Option<String>) -> Self {
+        let inner = &body["error"]["innerError"]["request-id"];
+        Self {
+            status,
+            code: body["error"]["code"].as_str().map(str::to_string),
+            request_id: request_id.or_else(|| inner.as_str().map(str::to_string)),
+            // The human-readable part, e.g. why a $filter is invalid
+            message: body["error"]["message"]
+                .as_str()
+                .or(body.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| body.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {} - {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for GraphError {}
+
 impl Client {
-    pub fn new(access_token: &str) -> Self {
+    pub fn new(access_token: &str, retry: RetryPolicy) -> Self {
         Self {
-            http: reqwest::Client::builder()
-                .timeout(Duration::from_secs(30))
+            http: http_client()
+                .timeout(retry.timeout)
                 .build()
                 .expect("Failed to build HTTP client"),
-            access_token: access_token.to_string(),
+            access_token: RwLock::new(access_token.to_string()),
+            concurrency: DEFAULT_CONCURRENCY,
+            retry,
+            breaker: std::sync::Mutex::new(CircuitBreaker::default()),
+            refresh: None,
+            refresh_lock: tokio::sync::Mutex::new(()),
+            base_url: BASE_URL.to_string(),
         }
     }
 
+    /// Send requests to another Graph root than graph.microsoft.com/v1.0,
+    /// e.g. a national cloud, or a mock server in tests
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn graph_url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.base_url, mailbox_path(endpoint, mailbox()))
+    }
+
+    /// Refresh the token and retry once when a request comes back 401
+    pub fn with_token_refresh(mut self, refresh: TokenRefresh) -> Self {
+        self.refresh = Some(refresh);
+        self
+    }
+
+    /// Use `access_token` from now on, e.g. after refreshing it ahead of expiry
+    pub fn set_access_token(&self, access_token: &str) {
+        *self.access_token.write().unwrap() = access_token.to_string();
+    }
+
+    fn token(&self) -> String {
+        self.access_token.read().unwrap().clone()
+    }
+
+    /// Swap in a fresh token unless another request already did
+    async fn refresh_token(&self, stale: &str) -> Result<bool> {
+        let Some(refresh) = &self.refresh else {
+            return Ok(false);
+        };
+        let _guard = self.refresh_lock.lock().await;
+        if self.token() == stale {
+            let token = refresh().await?;
+            *self.access_token.write().unwrap() = token;
+        }
+        Ok(true)
+    }
+
+    /// Limit how many requests (or $batch payloads) bulk operations keep in flight
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
     fn is_retryable_status(status: reqwest::StatusCode) -> bool {
         status == reqwest::StatusCode::TOO_MANY_REQUESTS
             || status.is_server_error()
             || status == reqwest::StatusCode::REQUEST_TIMEOUT
     }
 
+    fn is_throttling_status(status: reqwest::StatusCode) -> bool {
+        status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    // Fails with Throttled once the breaker trips, instead of waiting `delay`
+    fn record_throttled(&self, delay: Duration) -> Result<(), Throttled> {
+        self.breaker
+            .lock()
+            .unwrap()
+            .record(std::time::Instant::now(), delay)
+    }
+
     fn is_retryable_error(err: &reqwest::Error) -> bool {
         err.is_timeout() || err.is_connect() || err.is_request()
     }
 
-    fn get_retry_delay(resp: &reqwest::Response, attempt: u32) -> Duration {
+    fn get_retry_delay(&self, resp: &reqwest::Response, attempt: u32) -> Duration {
         // Check Retry-After header first (Microsoft Graph uses this for rate limits)
-        if let Some(retry_after) = resp.headers().get("Retry-After") {
-            if let Ok(seconds) = retry_after.to_str().unwrap_or("").parse::<u64>() {
-                return Duration::from_secs(seconds);
-            }
+        if let Some(retry_after) = resp.headers().get("Retry-After")
+            && let Ok(seconds) = retry_after.to_str().unwrap_or("").parse::<u64>()
+        {
+            return Duration::from_secs(seconds);
         }
-        // Exponential backoff: 1s, 2s, 4s...
-        Duration::from_millis(INITIAL_BACKOFF_MS * 2u64.pow(attempt))
+        self.retry.backoff(attempt)
     }
 
-    async fn execute_with_retry<F, Fut>(&self, request_fn: F) -> Result<reqwest::Response>
+    async fn execute_with_retry<F>(&self, request_fn: F) -> Result<reqwest::Response>
     where
-        F: Fn() -> Fut,
-        Fut: std::future::Future<Output = Result<reqwest::Response, reqwest::Error>>,
+        F: Fn(String) -> reqwest::RequestBuilder,
     {
-        let mut last_error = None;
-
-        for attempt in 0..=MAX_RETRIES {
-            match request_fn().await {
+        let mut refreshed = false;
+        let max_retries = self.retry.max_retries;
+        let mut attempt = 0;
+
+        loop {
+            self.breaker
+                .lock()
+                .unwrap()
+                .check(std::time::Instant::now())?;
+            let token = self.token();
+            match self.send(request_fn(token.clone())).await {
                 Ok(resp) => {
                     if resp.status().is_success() {
                         return Ok(resp);
                     }
 
-                    if Self::is_retryable_status(resp.status()) && attempt < MAX_RETRIES {
-                        let delay = Self::get_retry_delay(&resp, attempt);
-                        eprintln!(
+                    // One refresh is allowed whatever the retry budget, so
+                    // --max-retries 0 doesn't stop tokens being renewed
+                    if resp.status() == reqwest::StatusCode::UNAUTHORIZED
+                        && !refreshed
+                        && self.refresh_token(&token).await?
+                    {
+                        refreshed = true;
+                        continue;
+                    }
+
+                    let throttling = Self::is_throttling_status(resp.status());
+                    if throttling {
+                        telemetry::record_throttled();
+                    }
+                    if Self::is_retryable_status(resp.status()) && attempt < max_retries {
+                        let delay = self.get_retry_delay(&resp, attempt);
+                        if throttling {
+                            self.record_throttled(delay)?;
+                        }
+                        telemetry::record_retry(if throttling {
+                            "throttled"
+                        } else {
+                            "server_error"
+                        });
+                        note!(
                             "Rate limited ({}), retrying in {:?}...",
                             resp.status(),
                             delay
                         );
                         tokio::time::sleep(delay).await;
+                        attempt += 1;
                         continue;
                     }
 
                     // Non-retryable error or max retries reached
-                    let status = resp.status();
+                    let status = resp.status().as_u16();
+                    let request_id = resp
+                        .headers()
+                        .get("request-id")
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string);
                     let body = resp.text().await.unwrap_or_default();
-                    anyhow::bail!("HTTP {} - {}", status, body);
+                    let body =
+                        serde_json::from_str(&body).unwrap_or(serde_json::Value::String(body));
+                    return Err(GraphError::from_body(status, &body, request_id).into());
                 }
                 Err(e) => {
-                    if Self::is_retryable_error(&e) && attempt < MAX_RETRIES {
-                        let delay = Duration::from_millis(INITIAL_BACKOFF_MS * 2u64.pow(attempt));
-                        eprintln!("Request failed ({}), retrying in {:?}...", e, delay);
+                    if Self::is_retryable_error(&e) && attempt < max_retries {
+                        let delay = self.retry.backoff(attempt);
+                        telemetry::record_retry("network");
+                        note!("Request failed ({}), retrying in {:?}...", e, delay);
                         tokio::time::sleep(delay).await;
-                        last_error = Some(e);
+                        attempt += 1;
                         continue;
                     }
                     return Err(e).context("Failed to send request");
                 }
             }
         }
+    }
+
+    /// Send one request inside a `graph.request` span, recording its latency
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        use tracing::Instrument;
+        use tracing::field::Empty;
+
+        let mut request = request.build()?;
+        if immutable_ids() {
+            request.headers_mut().append(
+                "Prefer",
+                reqwest::header::HeaderValue::from_static(PREFER_IMMUTABLE_IDS),
+            );
+        }
+        if status::dry_run()
+            && let Some(response) = self.dry_run(&request)
+        {
+            return Ok(response);
+        }
+        let method = request.method().to_string();
+        let span = tracing::info_span!(
+            "graph.request",
+            http.request.method = %method,
+            url.path = request.url().path(),
+            http.response.status_code = Empty,
+            request_id = Empty,
+        );
+        let started = std::time::Instant::now();
+        let result = self.send_logged(request).instrument(span.clone()).await;
+        let status = result.as_ref().ok().map(|resp| resp.status().as_u16());
+        if let Ok(resp) = &result {
+            span.record("http.response.status_code", status);
+            if let Some(id) = resp
+                .headers()
+                .get("request-id")
+                .and_then(|v| v.to_str().ok())
+            {
+                span.record("request_id", id);
+            }
+        }
+        telemetry::record_request(&method, status, started.elapsed());
+        result
+    }
+
+    /// Under `--dry-run`, print a write instead of sending it and fake Graph's reply
+    fn dry_run(&self, request: &reqwest::Request) -> Option<reqwest::Response> {
+        let url = request.url().as_str();
+        let path = url
+            .strip_prefix(self.base_url.as_str())
+            .unwrap_or(request.url().path());
+        let body: Option<serde_json::Value> = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .and_then(|bytes| serde_json::from_slice(bytes).ok());
+        let (lines, reply) = dry_run_reply(request.method().as_str(), path, body.as_ref())?;
+        for line in lines {
+            println!("{}", line);
+        }
+        Some(
+            http::Response::builder()
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(reply.to_string())
+                .expect("a default status and a valid header")
+                .into(),
+        )
+    }
+
+    /// Log a request at `--debug`/`-v`, with headers and bodies at `-vv`.
+    /// The bearer token is never printed.
+    async fn send_logged(
+        &self,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let verbosity = status::verbosity();
+        if verbosity == 0 {
+            return self.http.execute(request).await;
+        }
+
+        debug!(1, "> {} {}", request.method(), request.url());
+        if verbosity >= 2 {
+            log_headers(">", request.headers());
+            if let Some(body) = request.body().and_then(reqwest::Body::as_bytes) {
+                log_body(">", body);
+            }
+        }
+        let started = std::time::Instant::now();
+        let resp = match self.http.execute(request).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                debug!(1, "< {} ({} ms)", e, started.elapsed().as_millis());
+                return Err(e);
+            }
+        };
+        debug!(
+            1,
+            "< {} request-id={} ({} ms)",
+            resp.status(),
+            resp.headers()
+                .get("request-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("-"),
+            started.elapsed().as_millis()
+        );
+        if verbosity < 2 {
+            return Ok(resp);
+        }
 
-        Err(last_error.unwrap()).context("Failed after max retries")
+        // Read the body to print it, then hand back an equivalent response
+        log_headers("<", resp.headers());
+        let mut copy = http::Response::builder()
+            .status(resp.status())
+            .version(resp.version());
+        if let Some(headers) = copy.headers_mut() {
+            *headers = resp.headers().clone();
+        }
+        let body = resp.bytes().await?;
+        log_body("<", &body);
+        Ok(copy
+            .body(body)
+            .expect("status and headers came from a valid response")
+            .into())
     }
 
     async fn get<T: serde::de::DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
-        let url = format!("{}{}", BASE_URL, endpoint);
+        self.get_url(&self.graph_url(endpoint)).await
+    }
 
+    // GET an absolute URL, e.g. an @odata.nextLink
+    async fn get_url<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.get_url_prefer(url, None).await
+    }
+
+    // GET with a Prefer header, e.g. to choose the body content type
+    async fn get_url_prefer<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+        prefer: Option<&str>,
+    ) -> Result<T> {
         let resp = self
-            .execute_with_retry(|| self.http.get(&url).bearer_auth(&self.access_token).send())
+            .execute_with_retry(|token| {
+                let request = self.http.get(url).bearer_auth(token);
+                match prefer {
+                    Some(prefer) => request.header("Prefer", prefer),
+                    None => request,
+                }
+            })
             .await?;
 
         resp.json().await.context("Failed to parse JSON response")
     }
 
+    async fn get_bytes(&self, endpoint: &str) -> Result<Vec<u8>> {
+        let url = self.graph_url(endpoint);
+        let resp = self
+            .execute_with_retry(|token| self.http.get(&url).bearer_auth(token))
+            .await?;
+
+        Ok(resp
+            .bytes()
+            .await
+            .context("Failed to read response body")?
+            .to_vec())
+    }
+
     async fn post(&self, endpoint: &str) -> Result<()> {
-        let url = format!("{}{}", BASE_URL, endpoint);
+        let url = self.graph_url(endpoint);
 
-        self.execute_with_retry(|| self.http.post(&url).bearer_auth(&self.access_token).send())
+        self.execute_with_retry(|token| self.http.post(&url).bearer_auth(token))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, endpoint: &str) -> Result<()> {
+        let url = self.graph_url(endpoint);
+
+        self.execute_with_retry(|token| self.http.delete(&url).bearer_auth(token))
             .await?;
 
         Ok(())
@@ -210,13 +1166,12 @@ impl Client {
         endpoint: &str,
         body: &T,
     ) -> Result<reqwest::Response> {
-        let url = format!("{}{}", BASE_URL, endpoint);
-        self.execute_with_retry(|| {
+        let url = self.graph_url(endpoint);
+        self.execute_with_retry(|token| {
             self.http
                 .request(method.clone(), &url)
-                .bearer_auth(&self.access_token)
+                .bearer_auth(token)
                 .json(body)
-                .send()
         })
         .await
     }
@@ -245,12 +1200,164 @@ impl Client {
         Ok(())
     }
 
-    // List mail folders
+    // PATCH that fails with 412 if the item no longer has `etag`
+    async fn patch_json_if_match<T: Serialize + Sync>(
+        &self,
+        endpoint: &str,
+        body: &T,
+        etag: Option<&str>,
+    ) -> Result<()> {
+        let url = self.graph_url(endpoint);
+        self.execute_with_retry(|token| {
+            let request = self.http.patch(&url).bearer_auth(token).json(body);
+            match etag {
+                Some(etag) => request.header(reqwest::header::IF_MATCH, etag),
+                None => request,
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// PATCH a message with `body` only if it still has `etag`. If it changed
+    /// since, read it again and send `update` of the fresh copy, once.
+    async fn patch_message_checked<F>(
+        &self,
+        id: &str,
+        etag: Option<&str>,
+        body: serde_json::Value,
+        update: F,
+    ) -> Result<()>
+    where
+        F: Fn(&Message) -> serde_json::Value,
+    {
+        let endpoint = format!("/me/messages/{}", urlencoding::encode(id));
+        match self.patch_json_if_match(&endpoint, &body, etag).await {
+            Err(e) if is_conflict(&e) => {
+                let fresh = self.get_message(id).await?;
+                self.patch_json_if_match(&endpoint, &update(&fresh), fresh.etag.as_deref())
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    async fn patch_json_with_response<T: Serialize + Sync, R: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        body: &T,
+    ) -> Result<R> {
+        self.send_json(reqwest::Method::PATCH, endpoint, body)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse JSON response")
+    }
+
+    /// Send requests through /$batch in chunks of 20, returning one result per
+    /// request in input order. Up to `concurrency` chunks are in flight at once.
+    /// Throttled items are retried; other per-item failures (including a whole
+    /// chunk failing to send) are returned as errors without failing the rest.
+    pub async fn batch(&self, requests: &[BatchRequest]) -> Result<Vec<Result<serde_json::Value>>> {
+        // Chunks are picked by offset: a closure over `&[BatchRequest]` makes
+        // the future too lifetime-generic to prove Send for tokio::spawn
+        let chunks: Vec<Vec<Result<serde_json::Value>>> =
+            stream::iter((0..requests.len()).step_by(BATCH_LIMIT))
+                .map(|start| async move {
+                    let chunk = &requests[start..requests.len().min(start + BATCH_LIMIT)];
+                    match self.batch_chunk(chunk).await {
+                        Ok(results) => results,
+                        Err(e) => {
+                            let message = format!("{:#}", e);
+                            chunk
+                                .iter()
+                                .map(|_| Err(anyhow::anyhow!("{}", message)))
+                                .collect()
+                        }
+                    }
+                })
+                .buffered(self.concurrency)
+                .collect()
+                .await;
+        Ok(chunks.into_iter().flatten().collect())
+    }
+
+    // Batch requests whose response bodies are not needed
+    async fn batch_unit(&self, requests: &[BatchRequest]) -> Result<Vec<Result<()>>> {
+        Ok(self
+            .batch(requests)
+            .await?
+            .into_iter()
+            .map(|r| r.map(|_| ()))
+            .collect())
+    }
+
+    async fn batch_chunk(
+        &self,
+        requests: &[BatchRequest],
+    ) -> Result<Vec<Result<serde_json::Value>>> {
+        let mut results: Vec<Option<Result<serde_json::Value>>> =
+            requests.iter().map(|_| None).collect();
+        let mut pending: Vec<usize> = (0..requests.len()).collect();
+
+        let max_retries = self.retry.max_retries;
+        for attempt in 0..=max_retries {
+            let payload: Vec<serde_json::Value> =
+                pending.iter().map(|&i| requests[i].to_json(i)).collect();
+            let response: BatchResponseList = self
+                .post_json_with_response("/$batch", &serde_json::json!({ "requests": payload }))
+                .await?;
+
+            let mut throttled = Vec::new();
+            let mut delay = self.retry.backoff(attempt);
+            for item in response.responses {
+                let Ok(index) = item.id.parse::<usize>() else {
+                    continue;
+                };
+                if index >= requests.len() {
+                    continue;
+                }
+                let status = reqwest::StatusCode::from_u16(item.status)
+                    .unwrap_or(reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+                if Self::is_retryable_status(status) && attempt < max_retries {
+                    if let Some(seconds) = item.retry_after() {
+                        delay = delay.max(Duration::from_secs(seconds));
+                    }
+                    throttled.push(index);
+                    continue;
+                }
+                results[index] = Some(item.into_result());
+            }
+
+            if throttled.is_empty() {
+                break;
+            }
+            for _ in &throttled {
+                telemetry::record_throttled();
+                telemetry::record_retry("throttled");
+            }
+            self.record_throttled(delay)?;
+            note!(
+                "{} batched request(s) throttled, retrying in {:?}...",
+                throttled.len(),
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            pending = throttled;
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err(anyhow::anyhow!("No response in batch"))))
+            .collect())
+    }
+
+    /// List mail folders
     pub async fn list_folders(&self) -> Result<FolderList> {
         self.get("/me/mailFolders?$top=100").await
     }
 
-    // Get folder by well-known name or ID
+    /// Get folder by well-known name or ID
     pub async fn get_folder(&self, name_or_id: &str) -> Result<Folder> {
         self.get(&format!(
             "/me/mailFolders/{}",
@@ -259,12 +1366,18 @@ impl Client {
         .await
     }
 
-    // List categories (Outlook master categories)
+    /// Create a top-level mail folder
+    pub async fn create_folder(&self, name: &str) -> Result<Folder> {
+        let body = serde_json::json!({ "displayName": name });
+        self.post_json_with_response("/me/mailFolders", &body).await
+    }
+
+    /// List categories (Outlook master categories)
     pub async fn list_categories(&self) -> Result<CategoryList> {
         self.get("/me/outlook/masterCategories").await
     }
 
-    // Create a master category
+    /// Create a master category
     pub async fn create_category(&self, name: &str, color: Option<&str>) -> Result<Category> {
         let body = serde_json::json!({
             "displayName": name,
@@ -274,7 +1387,16 @@ impl Client {
             .await
     }
 
-    // Ensure a category exists in master list, create if not
+    /// Delete a master category (messages keep the name as a plain tag)
+    pub async fn delete_category(&self, id: &str) -> Result<()> {
+        self.delete(&format!(
+            "/me/outlook/masterCategories/{}",
+            urlencoding::encode(id)
+        ))
+        .await
+    }
+
+    /// Ensure a category exists in master list, create if not
     pub async fn ensure_category(&self, name: &str) -> Result<()> {
         let categories = self.list_categories().await?;
         let exists = categories
@@ -292,45 +1414,525 @@ impl Client {
         Ok(())
     }
 
-    // List messages in a folder
+    /// List messages in a folder
     pub async fn list_messages(
         &self,
         folder: &str,
         filter: Option<&str>,
         max_results: u32,
+    ) -> Result<MessageList> {
+        let options = ListOptions {
+            filter,
+            ..Default::default()
+        };
+        self.list_messages_with(folder, &options, max_results).await
+    }
+
+    /// List messages in a folder with ordering and optional extra properties
+    pub async fn list_messages_with(
+        &self,
+        folder: &str,
+        options: &ListOptions<'_>,
+        max_results: u32,
     ) -> Result<MessageList> {
         let mut endpoint = format!(
-            "/me/mailFolders/{}/messages?$top={}&$select=id,subject,from,receivedDateTime,bodyPreview,isRead,categories",
+            "/me/mailFolders/{}/messages?$top={}&$select={}",
             urlencoding::encode(folder),
-            max_results
+            max_results,
+            options.select.unwrap_or(LIST_SELECT)
         );
 
-        if let Some(f) = filter {
+        if let Some(f) = options.filter {
             endpoint.push_str(&format!("&$filter={}", urlencoding::encode(f)));
         }
+        if let Some(order_by) = options.order_by {
+            endpoint.push_str(&format!("&$orderby={}", urlencoding::encode(order_by)));
+        }
+        if options.include_size {
+            endpoint.push_str(&size_expand());
+        }
 
         self.get(&endpoint).await
     }
 
-    // Search messages across all folders
-    pub async fn search_messages(&self, query: &str, max_results: u32) -> Result<MessageList> {
+    /// Search messages across all folders. $search can't be combined with
+    /// $filter or $orderby, so only `select` and `include_size` apply.
+    pub async fn search_messages(
+        &self,
+        query: &str,
+        options: &ListOptions<'_>,
+        max_results: u32,
+    ) -> Result<MessageList> {
+        let select = options.select.unwrap_or(LIST_SELECT);
+        let mut endpoint = search_endpoint(None, query, max_results, select);
+        if options.include_size {
+            endpoint.push_str(&size_expand());
+        }
+        self.get(&endpoint).await
+    }
+
+    /// Search messages in one folder, or across all folders when none is given
+    pub async fn search_folder(
+        &self,
+        folder: Option<&str>,
+        query: &str,
+        max_results: u32,
+    ) -> Result<MessageList> {
+        let endpoint = search_endpoint(folder, query, max_results, LIST_SELECT);
+        self.get(&endpoint).await
+    }
+
+    /// Follow @odata.nextLink until every page (or `limit` messages) is collected
+    pub async fn collect_pages(
+        &self,
+        mut page: MessageList,
+        limit: Option<usize>,
+    ) -> Result<Vec<Message>> {
+        let mut messages = Vec::new();
+        loop {
+            messages.extend(page.value.take().unwrap_or_default());
+            if let Some(limit) = limit
+                && messages.len() >= limit
+            {
+                messages.truncate(limit);
+                break;
+            }
+            match page.next_link.take() {
+                Some(link) => page = self.get_url(&link).await?,
+                None => break,
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Fetch the page behind an @odata.nextLink
+    pub async fn next_page(&self, link: &str) -> Result<MessageList> {
+        self.get_url(link).await
+    }
+
+    /// Every message in a folder matching an optional OData filter
+    pub async fn list_all_messages(
+        &self,
+        folder: &str,
+        filter: Option<&str>,
+    ) -> Result<Vec<Message>> {
+        self.messages_stream(Some(folder), filter)
+            .try_collect()
+            .await
+    }
+
+    /// Messages in a folder (or the whole mailbox) matching an optional OData
+    /// filter, fetched a page at a time as the stream is polled. Only one page
+    /// is held in memory, so this suits folders of any size.
+    pub fn messages_stream(
+        &self,
+        folder: Option<&str>,
+        filter: Option<&str>,
+    ) -> impl Stream<Item = Result<Message>> + '_ {
+        let base = match folder {
+            Some(folder) => format!("/me/mailFolders/{}/messages", urlencoding::encode(folder)),
+            None => "/me/messages".to_string(),
+        };
+        let mut endpoint = format!("{}?$top={}&$select={}", base, PAGE_SIZE, LIST_SELECT);
+        if let Some(f) = filter {
+            endpoint.push_str(&format!("&$filter={}", urlencoding::encode(f)));
+        }
+        self.message_pages(self.graph_url(&endpoint))
+    }
+
+    // Lazily follow @odata.nextLink from `url`, yielding each message
+    fn message_pages(&self, url: String) -> impl Stream<Item = Result<Message>> + '_ {
+        stream::try_unfold(Some(url), move |url| async move {
+            let Some(url) = url else {
+                return Ok(None);
+            };
+            let page: MessageList = self.get_url(&url).await?;
+            let messages = page.value.unwrap_or_default().into_iter().map(Ok);
+            Ok::<_, anyhow::Error>(Some((stream::iter(messages), page.next_link)))
+        })
+        .try_flatten()
+    }
+
+    /// Every message in a folder matching a filter, including internet headers
+    pub async fn list_all_with_headers(
+        &self,
+        folder: &str,
+        filter: Option<&str>,
+    ) -> Result<Vec<Message>> {
+        let mut endpoint = format!(
+            "/me/mailFolders/{}/messages?$top={}&$select={},internetMessageHeaders",
+            urlencoding::encode(folder),
+            PAGE_SIZE,
+            LIST_SELECT
+        );
+        if let Some(f) = filter {
+            endpoint.push_str(&format!("&$filter={}", urlencoding::encode(f)));
+        }
+        let first = self.get(&endpoint).await?;
+        self.collect_pages(first, None).await
+    }
+
+    /// Visit every message in a folder (or the whole mailbox) with its size
+    pub async fn for_each_sized(&self, folder: Option<&str>, f: impl FnMut(Message)) -> Result<()> {
+        let base = match folder {
+            Some(folder) => format!("/me/mailFolders/{}/messages", urlencoding::encode(folder)),
+            None => "/me/messages".to_string(),
+        };
         let endpoint = format!(
-            "/me/messages?$search=\"{}\"&$top={}&$select=id,subject,from,receivedDateTime,bodyPreview,isRead,categories",
-            urlencoding::encode(query),
-            max_results
+            "{}?$top={}&$select=id,subject,from,receivedDateTime,hasAttachments,parentFolderId{}",
+            base,
+            PAGE_SIZE,
+            size_expand()
         );
+        self.for_each_message(&endpoint, f).await
+    }
+
+    /// Visit every message in any folder matching an OData filter, with just
+    /// its sender and recipients
+    pub async fn for_each_addressed(&self, filter: &str, f: impl FnMut(Message)) -> Result<()> {
+        let endpoint = format!(
+            "/me/messages?$top={}&$select=from,toRecipients,ccRecipients&$filter={}",
+            COUNT_PAGE_SIZE,
+            urlencoding::encode(filter)
+        );
+        self.for_each_message(&endpoint, f).await
+    }
+
+    /// Every message in any folder matching an OData filter
+    pub async fn filter_all_messages(&self, filter: &str) -> Result<Vec<Message>> {
+        let endpoint = format!(
+            "/me/messages?$top={}&$select={}&$filter={}",
+            PAGE_SIZE,
+            LIST_SELECT,
+            urlencoding::encode(filter)
+        );
+        let first = self.get(&endpoint).await?;
+        self.collect_pages(first, None).await
+    }
+
+    /// Count messages in any folder matching an OData filter, per parentFolderId.
+    /// Pages through bare IDs, since Graph has no grouped count.
+    pub async fn count_by_folder(
+        &self,
+        filter: &str,
+    ) -> Result<std::collections::HashMap<String, usize>> {
+        let endpoint = format!(
+            "/me/messages?$top={}&$select=parentFolderId&$filter={}",
+            COUNT_PAGE_SIZE,
+            urlencoding::encode(filter)
+        );
+        let mut counts = std::collections::HashMap::new();
+        self.for_each_message(&endpoint, |msg| {
+            *counts
+                .entry(msg.parent_folder_id.unwrap_or_default())
+                .or_insert(0) += 1;
+        })
+        .await?;
+        Ok(counts)
+    }
+
+    /// Every distinct category name on any message, in any folder
+    pub async fn used_categories(&self) -> Result<Vec<String>> {
+        let endpoint = format!("/me/messages?$top={}&$select=categories", COUNT_PAGE_SIZE);
+        let mut seen = std::collections::HashSet::new();
+        let mut names = Vec::new();
+        self.for_each_message(&endpoint, |msg| {
+            for name in msg.categories.unwrap_or_default() {
+                if seen.insert(name.to_lowercase()) {
+                    names.push(name);
+                }
+            }
+        })
+        .await?;
+        Ok(names)
+    }
+
+    /// Every message carrying at least one category, in one folder or all of them
+    pub async fn list_categorized(&self, folder: Option<&str>) -> Result<Vec<Message>> {
+        let base = match folder {
+            Some(f) => format!("/me/mailFolders/{}/messages", urlencoding::encode(f)),
+            None => "/me/messages".to_string(),
+        };
+        let endpoint = format!(
+            "{}?$top={}&$select=subject,categories,parentFolderId",
+            base, COUNT_PAGE_SIZE
+        );
+        let mut messages = Vec::new();
+        self.for_each_message(&endpoint, |msg| {
+            if msg.categories.as_ref().is_some_and(|c| !c.is_empty()) {
+                messages.push(msg);
+            }
+        })
+        .await?;
+        Ok(messages)
+    }
+
+    // Walk every page of a message listing without keeping the pages
+    async fn for_each_message(&self, endpoint: &str, mut f: impl FnMut(Message)) -> Result<()> {
+        let mut messages = std::pin::pin!(self.message_pages(self.graph_url(endpoint)));
+        while let Some(msg) = messages.try_next().await? {
+            f(msg);
+        }
+        Ok(())
+    }
+
+    /// Every message matching a search query
+    pub async fn search_all_messages(
+        &self,
+        folder: Option<&str>,
+        query: &str,
+    ) -> Result<Vec<Message>> {
+        let first = self.search_folder(folder, query, PAGE_SIZE).await?;
+        self.collect_pages(first, None).await
+    }
+
+    /// Get a message with only the given properties, and optionally its size
+    pub async fn get_message_select(
+        &self,
+        id: &str,
+        select: &str,
+        include_size: bool,
+    ) -> Result<Message> {
+        let mut endpoint = format!(
+            "/me/messages/{}?$select={}",
+            urlencoding::encode(id),
+            select
+        );
+        if include_size {
+            endpoint.push_str(&size_expand());
+        }
         self.get(&endpoint).await
     }
 
-    // Get a specific message with full body and headers
+    /// Get a specific message with full body and headers
     pub async fn get_message(&self, id: &str) -> Result<Message> {
-        self.get(&format!(
-            "/me/messages/{}?$select=id,subject,from,toRecipients,body,bodyPreview,receivedDateTime,isRead,categories,internetMessageHeaders,parentFolderId",
-            urlencoding::encode(id)
-        )).await
+        self.get(&message_endpoint(id)).await
+    }
+
+    /// Same as get_message, with the body converted to plain text by Graph
+    pub async fn get_message_text(&self, id: &str) -> Result<Message> {
+        self.get_url_prefer(
+            &self.graph_url(&message_endpoint(id)),
+            Some(PREFER_TEXT_BODY),
+        )
+        .await
+    }
+
+    /// Full RFC 822 source of a message (not necessarily valid UTF-8)
+    pub async fn get_mime(&self, id: &str) -> Result<Vec<u8>> {
+        self.get_bytes(&format!("/me/messages/{}/$value", urlencoding::encode(id)))
+            .await
+    }
+
+    /// List a message's attachments, including their content
+    pub async fn list_attachments(&self, id: &str) -> Result<Vec<Attachment>> {
+        let list: AttachmentList = self
+            .get(&format!(
+                "/me/messages/{}/attachments",
+                urlencoding::encode(id)
+            ))
+            .await?;
+        Ok(list.value.unwrap_or_default())
+    }
+
+    /// List a message's attachments without their content
+    pub async fn list_attachment_info(&self, id: &str) -> Result<Vec<Attachment>> {
+        let list: AttachmentList = self
+            .get(&format!(
+                "/me/messages/{}/attachments?$select=id,name,contentType,size,isInline",
+                urlencoding::encode(id)
+            ))
+            .await?;
+        Ok(list.value.unwrap_or_default())
+    }
+
+    /// Raw content of one file attachment
+    pub async fn get_attachment_content(&self, id: &str, attachment_id: &str) -> Result<Vec<u8>> {
+        self.get_bytes(&format!(
+            "/me/messages/{}/attachments/{}/$value",
+            urlencoding::encode(id),
+            urlencoding::encode(attachment_id)
+        ))
+        .await
+    }
+
+    /// Send a plain-text email from the signed-in mailbox, optionally as an
+    /// alias or mailbox it has SendAs/SendOnBehalf rights on
+    pub async fn send_mail(
+        &self,
+        to: &str,
+        subject: &str,
+        body: &str,
+        from: Option<&str>,
+    ) -> Result<()> {
+        let message = serde_json::json!({
+            "message": mail_message(to, subject, body, from),
+            "saveToSentItems": true,
+        });
+        self.post_json("/me/sendMail", &message).await.map_err(|e| {
+            let denied = e
+                .chain()
+                .filter_map(|c| c.downcast_ref::<GraphError>())
+                .any(|g| g.code.as_deref() == Some("ErrorSendAsDenied"));
+            match from {
+                Some(from) if denied => e.context(format!(
+                    "This mailbox has no SendAs or SendOnBehalf permission for {}",
+                    from
+                )),
+                _ => e,
+            }
+        })
+    }
+
+    /// Reply to a message's sender, quoting it below `comment`
+    pub async fn reply(&self, id: &str, comment: &str) -> Result<()> {
+        let body = serde_json::json!({ "comment": comment });
+        self.post_json(
+            &format!("/me/messages/{}/reply", urlencoding::encode(id)),
+            &body,
+        )
+        .await
+    }
+
+    /// Convert message IDs between the regular REST format and immutable IDs
+    pub async fn translate_ids(
+        &self,
+        ids: &[String],
+        to_immutable: bool,
+    ) -> Result<Vec<TranslatedId>> {
+        let (source, target) = if to_immutable {
+            ("restId", "restImmutableEntryId")
+        } else {
+            ("restImmutableEntryId", "restId")
+        };
+        let mut translated = Vec::new();
+        // Graph converts at most 1000 IDs per call
+        for chunk in ids.chunks(1000) {
+            let response: TranslatedIdList = self
+                .post_json_with_response(
+                    "/me/translateExchangeIds",
+                    &serde_json::json!({
+                        "inputIds": chunk,
+                        "sourceIdType": source,
+                        "targetIdType": target,
+                    }),
+                )
+                .await?;
+            translated.extend(response.value);
+        }
+        Ok(translated)
+    }
+
+    /// The signed-in account itself, even when --mailbox points elsewhere
+    pub async fn signed_in_user(&self) -> Result<User> {
+        self.get_url(&format!(
+            "{}/me?$select=displayName,mail,userPrincipalName",
+            self.base_url
+        ))
+        .await
+    }
+
+    /// Invalidate every refresh token issued to the signed-in user, on all devices
+    pub async fn revoke_sign_in_sessions(&self) -> Result<()> {
+        self.post("/me/revokeSignInSessions").await
+    }
+
+    /// The signed-in user
+    pub async fn me(&self) -> Result<User> {
+        self.get("/me?$select=displayName,mail,userPrincipalName")
+            .await
+    }
+
+    /// Submit a message to Microsoft's filtering as spam/phishing (block) or
+    /// as a false positive (unblock). Needs ThreatAssessment.ReadWrite.All.
+    pub async fn report_message(
+        &self,
+        id: &str,
+        recipient: &str,
+        category: &str,
+        block: bool,
+    ) -> Result<()> {
+        let body = serde_json::json!({
+            "@odata.type": "#microsoft.graph.mailAssessmentRequest",
+            "recipientEmail": recipient,
+            "expectedAssessment": if block { "block" } else { "unblock" },
+            "category": category,
+            "messageUri": format!(
+                "{}/users/{}/messages/{}",
+                self.base_url,
+                urlencoding::encode(recipient),
+                urlencoding::encode(id)
+            ),
+        });
+        self.post_json("/informationProtection/threatAssessmentRequests", &body)
+            .await
+    }
+
+    /// The calendar event behind a meeting request message
+    pub async fn get_invite_event_id(&self, id: &str) -> Result<String> {
+        let expand = urlencoding::encode("microsoft.graph.eventMessage/event($select=id)");
+        let msg: EventMessage = self
+            .get(&format!(
+                "/me/messages/{}?$select=id&$expand={}",
+                urlencoding::encode(id),
+                expand
+            ))
+            .await?;
+        msg.event
+            .map(|e| e.id)
+            .ok_or_else(|| anyhow::anyhow!("Message is not a meeting invitation"))
+    }
+
+    /// Answer a meeting: `response` is accept, tentativelyAccept or decline
+    pub async fn respond_to_event(
+        &self,
+        event_id: &str,
+        response: &str,
+        comment: Option<&str>,
+    ) -> Result<()> {
+        let body = serde_json::json!({
+            "comment": comment.unwrap_or(""),
+            "sendResponse": true,
+        });
+        self.post_json(
+            &format!("/me/events/{}/{}", urlencoding::encode(event_id), response),
+            &body,
+        )
+        .await
+    }
+
+    /// A contact with this email address, if there is one
+    pub async fn find_contact(&self, address: &str) -> Result<Option<Contact>> {
+        let filter = format!(
+            "emailAddresses/any(a:a/address eq '{}')",
+            address.replace('\'', "''")
+        );
+        let list: ContactList = self
+            .get(&format!(
+                "/me/contacts?$top=1&$select=id,displayName&$filter={}",
+                urlencoding::encode(&filter)
+            ))
+            .await?;
+        Ok(list.value.unwrap_or_default().into_iter().next())
+    }
+
+    /// Create a contact from a display name and email address
+    pub async fn create_contact(&self, name: &str, address: &str) -> Result<Contact> {
+        // "Ada King Lovelace" -> given "Ada King", surname "Lovelace"
+        let (given, surname) = match name.trim().rsplit_once(' ') {
+            Some((given, surname)) => (given.trim(), surname),
+            None => (name.trim(), ""),
+        };
+        let body = serde_json::json!({
+            "displayName": name,
+            "givenName": given,
+            "surname": surname,
+            "emailAddresses": [{ "address": address, "name": name }],
+        });
+        self.post_json_with_response("/me/contacts", &body).await
     }
 
-    // Move message to a folder
+    /// Move message to a folder
     pub async fn move_message(&self, id: &str, destination_folder: &str) -> Result<MoveResponse> {
         let body = serde_json::json!({
             "destinationId": destination_folder
@@ -342,75 +1944,510 @@ impl Client {
         .await
     }
 
-    // Archive message (move to archive folder)
+    /// Archive message (move to archive folder)
     pub async fn archive(&self, id: &str) -> Result<()> {
         self.move_message(id, "archive").await?;
         Ok(())
     }
 
-    // Mark as spam (move to junk folder)
+    /// Mark as spam (move to junk folder)
     pub async fn mark_spam(&self, id: &str) -> Result<()> {
         self.move_message(id, "junkemail").await?;
         Ok(())
     }
 
-    // Unspam (move from junk to inbox)
+    /// Unspam (move from junk to inbox)
     pub async fn unspam(&self, id: &str) -> Result<()> {
         self.move_message(id, "inbox").await?;
         Ok(())
     }
 
-    // Move to trash (deleted items)
+    /// Move to trash (deleted items)
     pub async fn trash(&self, id: &str) -> Result<()> {
         self.move_message(id, "deleteditems").await?;
         Ok(())
     }
 
-    // Update message categories
-    pub async fn update_categories(&self, id: &str, categories: &[String]) -> Result<()> {
-        let body = serde_json::json!({
-            "categories": categories
-        });
-        self.patch_json(&format!("/me/messages/{}", urlencoding::encode(id)), &body)
+    /// Move many messages to a folder via $batch
+    pub async fn move_messages(
+        &self,
+        ids: &[String],
+        destination_folder: &str,
+    ) -> Result<Vec<Result<()>>> {
+        self.batch_unit(&move_requests(ids, destination_folder))
             .await
     }
 
-    // Add a category to a message
-    pub async fn add_category(&self, id: &str, category: &str) -> Result<()> {
-        let msg = self.get_message(id).await?;
-        let mut categories = msg.categories.unwrap_or_default();
-        if !categories.iter().any(|c| c.eq_ignore_ascii_case(category)) {
-            categories.push(category.to_string());
-            self.update_categories(id, &categories).await?;
-        }
-        Ok(())
+    /// Move many messages to a folder via $batch, returning each moved copy
+    pub async fn move_messages_returning(
+        &self,
+        ids: &[String],
+        destination_folder: &str,
+    ) -> Result<Vec<Result<Message>>> {
+        Ok(self
+            .batch(&move_requests(ids, destination_folder))
+            .await?
+            .into_iter()
+            .map(|r| {
+                r.and_then(|body| {
+                    serde_json::from_value(body).context("Failed to parse moved message")
+                })
+            })
+            .collect())
     }
 
-    // Remove a category from a message
-    pub async fn remove_category(&self, id: &str, category: &str) -> Result<()> {
-        let msg = self.get_message(id).await?;
-        let categories: Vec<String> = msg
-            .categories
-            .unwrap_or_default()
+    /// Delete many messages via $batch (from Deleted Items this empties them
+    /// out to Recoverable Items)
+    pub async fn delete_messages(&self, ids: &[String]) -> Result<Vec<Result<()>>> {
+        let requests: Vec<BatchRequest> = ids
+            .iter()
+            .map(|id| {
+                BatchRequest::new(
+                    reqwest::Method::DELETE,
+                    format!("/me/messages/{}", urlencoding::encode(id)),
+                    None,
+                )
+            })
+            .collect();
+        self.batch_unit(&requests).await
+    }
+
+    /// Apply a PATCH body per message via $batch
+    pub async fn update_messages(
+        &self,
+        updates: &[(String, serde_json::Value)],
+    ) -> Result<Vec<Result<()>>> {
+        let requests: Vec<BatchRequest> = updates
+            .iter()
+            .map(|(id, body)| {
+                BatchRequest::new(
+                    reqwest::Method::PATCH,
+                    format!("/me/messages/{}", urlencoding::encode(id)),
+                    Some(body.clone()),
+                )
+            })
+            .collect();
+        self.batch_unit(&requests).await
+    }
+
+    /// Set the read state of many messages via $batch
+    pub async fn set_read_many(&self, ids: &[String], is_read: bool) -> Result<Vec<Result<()>>> {
+        let requests: Vec<BatchRequest> = ids
+            .iter()
+            .map(|id| {
+                BatchRequest::new(
+                    reqwest::Method::PATCH,
+                    format!("/me/messages/{}", urlencoding::encode(id)),
+                    Some(serde_json::json!({ "isRead": is_read })),
+                )
+            })
+            .collect();
+        self.batch_unit(&requests).await
+    }
+
+    /// Set categories on many messages via $batch
+    pub async fn update_categories_many(
+        &self,
+        ids: &[String],
+        categories: &[String],
+    ) -> Result<Vec<Result<()>>> {
+        let requests: Vec<BatchRequest> = ids
+            .iter()
+            .map(|id| {
+                BatchRequest::new(
+                    reqwest::Method::PATCH,
+                    format!("/me/messages/{}", urlencoding::encode(id)),
+                    Some(serde_json::json!({ "categories": categories })),
+                )
+            })
+            .collect();
+        self.batch_unit(&requests).await
+    }
+
+    /// Current categories of many messages, with their ETags, via $batch
+    pub async fn get_categories_many(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<Result<CategorySnapshot>>> {
+        let requests: Vec<BatchRequest> = ids
+            .iter()
+            .map(|id| {
+                BatchRequest::new(
+                    reqwest::Method::GET,
+                    format!(
+                        "/me/messages/{}?$select=categories",
+                        urlencoding::encode(id)
+                    ),
+                    None,
+                )
+            })
+            .collect();
+        Ok(self
+            .batch(&requests)
+            .await?
+            .into_iter()
+            .map(|r| {
+                r.map(|body| CategorySnapshot {
+                    categories: serde_json::from_value(body["categories"].clone())
+                        .unwrap_or_default(),
+                    etag: body["@odata.etag"].as_str().map(String::from),
+                })
+            })
+            .collect())
+    }
+
+    /// Folder, read state and categories of many messages via $batch
+    pub async fn get_message_states(&self, ids: &[String]) -> Result<Vec<Result<Message>>> {
+        let requests: Vec<BatchRequest> = ids
+            .iter()
+            .map(|id| {
+                BatchRequest::new(
+                    reqwest::Method::GET,
+                    format!(
+                        "/me/messages/{}?$select=parentFolderId,isRead,categories",
+                        urlencoding::encode(id)
+                    ),
+                    None,
+                )
+            })
+            .collect();
+        Ok(self
+            .batch(&requests)
+            .await?
             .into_iter()
-            .filter(|c| !c.eq_ignore_ascii_case(category))
+            .map(|r| {
+                r.and_then(|body| {
+                    serde_json::from_value(body).context("Failed to parse message state")
+                })
+            })
+            .collect())
+    }
+
+    /// Give each message its own set of categories via $batch
+    /// Items fail with 412 (see is_conflict) when their ETag no longer matches
+    pub async fn update_categories_each(
+        &self,
+        updates: &[CategoryUpdate],
+    ) -> Result<Vec<Result<()>>> {
+        let requests: Vec<BatchRequest> = updates
+            .iter()
+            .map(|update| {
+                BatchRequest::new(
+                    reqwest::Method::PATCH,
+                    format!("/me/messages/{}", urlencoding::encode(&update.id)),
+                    Some(serde_json::json!({ "categories": update.categories })),
+                )
+                .if_match(update.etag.clone())
+            })
+            .collect();
+        self.batch_unit(&requests).await
+    }
+
+    /// Create many master categories via $batch
+    pub async fn create_categories(&self, names: &[String]) -> Result<Vec<Result<()>>> {
+        let requests: Vec<BatchRequest> = names
+            .iter()
+            .map(|name| {
+                BatchRequest::new(
+                    reqwest::Method::POST,
+                    "/me/outlook/masterCategories".to_string(),
+                    Some(serde_json::json!({ "displayName": name, "color": "preset0" })),
+                )
+            })
             .collect();
-        self.update_categories(id, &categories).await
+        self.batch_unit(&requests).await
+    }
+
+    /// Read the categories of many messages, then write `edit` of them with
+    /// If-Match (see `write_categories`), one result per message
+    pub async fn edit_categories(
+        &self,
+        ids: &[String],
+        edit: impl Fn(&mut Vec<String>),
+    ) -> Result<Vec<Result<()>>> {
+        let mut results = Vec::with_capacity(ids.len());
+        let mut current = Vec::new();
+        let mut positions = Vec::new();
+        for (id, snapshot) in ids.iter().zip(self.get_categories_many(ids).await?) {
+            match snapshot {
+                Ok(snapshot) => {
+                    positions.push(results.len());
+                    current.push((id.clone(), snapshot));
+                    results.push(Ok(()));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        let written = self.write_categories(current, edit).await?;
+        for (position, result) in positions.into_iter().zip(written) {
+            results[position] = result;
+        }
+        Ok(results)
+    }
+
+    /// Write edited categories with If-Match, one result per message. Messages
+    /// that changed since they were read are read again and edited once more,
+    /// so a concurrent change isn't silently overwritten.
+    pub async fn write_categories(
+        &self,
+        current: Vec<(String, CategorySnapshot)>,
+        edit: impl Fn(&mut Vec<String>),
+    ) -> Result<Vec<Result<()>>> {
+        let mut results: Vec<Result<()>> = current.iter().map(|_| Ok(())).collect();
+        let mut pending: Vec<(usize, CategorySnapshot)> =
+            current.iter().map(|(_, s)| s.clone()).enumerate().collect();
+        for retry in [false, true] {
+            let mut updates = Vec::new();
+            let mut positions = Vec::new();
+            for (position, snapshot) in pending {
+                let mut edited = snapshot.categories.clone();
+                edit(&mut edited);
+                if edited != snapshot.categories {
+                    positions.push(position);
+                    updates.push(CategoryUpdate {
+                        id: current[position].0.clone(),
+                        categories: edited,
+                        etag: snapshot.etag,
+                    });
+                }
+            }
+            let written = self.update_categories_each(&updates).await?;
+            let mut conflicts = Vec::new();
+            for (position, result) in positions.into_iter().zip(written) {
+                match result {
+                    Err(e) if !retry && is_conflict(&e) => conflicts.push(position),
+                    result => results[position] = result,
+                }
+            }
+            if conflicts.is_empty() {
+                break;
+            }
+            let ids: Vec<String> = conflicts.iter().map(|&p| current[p].0.clone()).collect();
+            pending = Vec::new();
+            for (position, snapshot) in conflicts
+                .into_iter()
+                .zip(self.get_categories_many(&ids).await?)
+            {
+                match snapshot {
+                    Ok(snapshot) => pending.push((position, snapshot)),
+                    Err(e) => results[position] = Err(e),
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Add a category to a message
+    pub async fn add_category(&self, id: &str, category: &str) -> Result<()> {
+        self.edit_category(id, |categories| {
+            if !categories.iter().any(|c| c.eq_ignore_ascii_case(category)) {
+                categories.push(category.to_string());
+            }
+        })
+        .await
+    }
+
+    /// Remove a category from a message
+    pub async fn remove_category(&self, id: &str, category: &str) -> Result<()> {
+        self.edit_category(id, |categories| {
+            categories.retain(|c| !c.eq_ignore_ascii_case(category))
+        })
+        .await
     }
 
-    // Mark message as read
-    pub async fn mark_read(&self, id: &str) -> Result<()> {
-        let body = serde_json::json!({ "isRead": true });
+    async fn edit_category(&self, id: &str, edit: impl Fn(&mut Vec<String>)) -> Result<()> {
+        self.edit_categories(&[id.to_string()], edit)
+            .await?
+            .pop()
+            .unwrap_or_else(|| Err(anyhow::anyhow!("No response in batch")))
+    }
+
+    /// Move a message to the Focused or Other tab
+    pub async fn set_classification(&self, id: &str, classification: &str) -> Result<()> {
+        let body = serde_json::json!({ "inferenceClassification": classification });
         self.patch_json(&format!("/me/messages/{}", urlencoding::encode(id)), &body)
             .await
     }
 
-    // Mark message as unread
-    pub async fn mark_unread(&self, id: &str) -> Result<()> {
-        let body = serde_json::json!({ "isRead": false });
-        self.patch_json(&format!("/me/messages/{}", urlencoding::encode(id)), &body)
+    /// Always classify mail from a sender as Focused or Other
+    pub async fn create_classification_override(
+        &self,
+        sender: &EmailAddress,
+        classification: &str,
+    ) -> Result<()> {
+        let body = serde_json::json!({
+            "classifyAs": classification,
+            "senderEmailAddress": {
+                "name": sender.name.as_deref().unwrap_or_default(),
+                "address": sender.address.as_deref().unwrap_or_default()
+            }
+        });
+        self.post_json("/me/inferenceClassification/overrides", &body)
             .await
     }
+
+    /// Mark message as read
+    /// `etag` is the message's version when it was read, if known
+    pub async fn mark_read(&self, id: &str, etag: Option<&str>) -> Result<()> {
+        self.set_read(id, etag, true).await
+    }
+
+    /// Mark message as unread
+    pub async fn mark_unread(&self, id: &str, etag: Option<&str>) -> Result<()> {
+        self.set_read(id, etag, false).await
+    }
+
+    async fn set_read(&self, id: &str, etag: Option<&str>, is_read: bool) -> Result<()> {
+        let body = serde_json::json!({ "isRead": is_read });
+        self.patch_message_checked(id, etag, body.clone(), |_| body.clone())
+            .await
+    }
+
+    /// List inbox rules
+    pub async fn list_rules(&self) -> Result<MessageRuleList> {
+        self.get("/me/mailFolders/inbox/messageRules").await
+    }
+
+    /// Create an inbox rule
+    pub async fn create_rule(&self, rule: &MessageRule) -> Result<MessageRule> {
+        self.post_json_with_response("/me/mailFolders/inbox/messageRules", rule)
+            .await
+    }
+
+    /// Update fields of an inbox rule
+    pub async fn update_rule<T: Serialize + Sync>(&self, id: &str, changes: &T) -> Result<()> {
+        self.patch_json(
+            &format!(
+                "/me/mailFolders/inbox/messageRules/{}",
+                urlencoding::encode(id)
+            ),
+            changes,
+        )
+        .await
+    }
+
+    /// Delete an inbox rule
+    pub async fn delete_rule(&self, id: &str) -> Result<()> {
+        self.delete(&format!(
+            "/me/mailFolders/inbox/messageRules/{}",
+            urlencoding::encode(id)
+        ))
+        .await
+    }
+
+    /// Add a sender address or domain to the junk-blocking inbox rule, creating
+    /// the rule on first use. Returns false if the sender was already blocked.
+    pub async fn block_sender(&self, sender: &str) -> Result<bool> {
+        let sender = &block_pattern(sender);
+        let rules = self.list_rules().await?.value.unwrap_or_default();
+        let existing = rules
+            .into_iter()
+            .find(|r| r.display_name == BLOCK_RULE_NAME);
+
+        if let Some(rule) = existing {
+            let mut senders = rule
+                .conditions
+                .and_then(|c| c.sender_contains)
+                .unwrap_or_default();
+            if senders.iter().any(|s| s.eq_ignore_ascii_case(sender)) {
+                return Ok(false);
+            }
+            senders.push(sender.to_string());
+            let id = rule.id.unwrap_or_default();
+            self.update_rule(
+                &id,
+                &serde_json::json!({ "conditions": { "senderContains": senders } }),
+            )
+            .await?;
+            return Ok(true);
+        }
+
+        let junk = self.get_folder("junkemail").await?;
+        let rule = MessageRule {
+            display_name: BLOCK_RULE_NAME.to_string(),
+            sequence: Some(1),
+            is_enabled: Some(true),
+            conditions: Some(RuleConditions {
+                sender_contains: Some(vec![sender.to_string()]),
+                ..Default::default()
+            }),
+            actions: Some(RuleActions {
+                move_to_folder: Some(junk.id),
+                stop_processing_rules: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.create_rule(&rule).await?;
+        Ok(true)
+    }
+
+    /// Remove a sender from the junk-blocking inbox rule. Returns false if the
+    /// sender wasn't blocked.
+    pub async fn unblock_sender(&self, sender: &str) -> Result<bool> {
+        let pattern = block_pattern(sender);
+        let rules = self.list_rules().await?.value.unwrap_or_default();
+        let Some(rule) = rules
+            .into_iter()
+            .find(|r| r.display_name == BLOCK_RULE_NAME)
+        else {
+            return Ok(false);
+        };
+
+        let senders = rule
+            .conditions
+            .and_then(|c| c.sender_contains)
+            .unwrap_or_default();
+        let remaining: Vec<String> = senders
+            .iter()
+            // Bare domains are what blocks made before they got an `@`
+            .filter(|s| !s.eq_ignore_ascii_case(&pattern) && !s.eq_ignore_ascii_case(sender))
+            .cloned()
+            .collect();
+        if remaining.len() == senders.len() {
+            return Ok(false);
+        }
+
+        let id = rule.id.unwrap_or_default();
+        if remaining.is_empty() {
+            // A rule without conditions would match every message
+            self.delete_rule(&id).await?;
+        } else {
+            self.update_rule(
+                &id,
+                &serde_json::json!({ "conditions": { "senderContains": remaining } }),
+            )
+            .await?;
+        }
+        Ok(true)
+    }
+
+    /// Subscribe to new messages arriving in the inbox
+    pub async fn create_subscription(
+        &self,
+        notification_url: &str,
+        expiration: &str,
+        client_state: &str,
+    ) -> Result<Subscription> {
+        let body = serde_json::json!({
+            "changeType": "created",
+            "notificationUrl": notification_url,
+            "resource": mailbox_path("/me/mailFolders('inbox')/messages", mailbox()),
+            "expirationDateTime": expiration,
+            "clientState": client_state
+        });
+        self.post_json_with_response("/subscriptions", &body).await
+    }
+
+    /// Extend a subscription's expiration time
+    pub async fn renew_subscription(&self, id: &str, expiration: &str) -> Result<Subscription> {
+        let body = serde_json::json!({ "expirationDateTime": expiration });
+        self.patch_json_with_response(
+            &format!("/subscriptions/{}", urlencoding::encode(id)),
+            &body,
+        )
+        .await
+    }
 }
 
 impl Message {
@@ -424,6 +2461,24 @@ impl Message {
         })
     }
 
+    pub fn get_from_address(&self) -> Option<&str> {
+        self.from.as_ref()?.email_address.address.as_deref()
+    }
+
+    /// True if sent by `sender`, either an exact address or a domain (subdomains included)
+    pub fn is_from(&self, sender: &str) -> bool {
+        let Some(address) = self.get_from_address() else {
+            return false;
+        };
+        let address = address.to_lowercase();
+        let sender = sender.to_lowercase();
+        if sender.contains('@') {
+            return address == sender;
+        }
+        let domain = address.rsplit('@').next().unwrap_or("");
+        domain == sender || domain.ends_with(&format!(".{}", sender))
+    }
+
     pub fn get_to(&self) -> Option<String> {
         self.to_recipients.as_ref().map(|recipients| {
             recipients
@@ -438,6 +2493,35 @@ impl Message {
         self.body.as_ref().and_then(|b| b.content.clone())
     }
 
+    pub fn is_html(&self) -> bool {
+        self.body
+            .as_ref()
+            .and_then(|b| b.content_type.as_deref())
+            .is_some_and(|t| t.eq_ignore_ascii_case("html"))
+    }
+
+    /// Body as plain text, wrapping HTML at `width` columns with links as footnotes
+    pub fn get_body_plain(&self, width: usize) -> Option<String> {
+        let content = self.get_body_text()?;
+        if !self.is_html() {
+            return Some(content);
+        }
+        html2text::config::plain()
+            .link_footnotes(true)
+            .string_from_read(content.as_bytes(), width)
+            .ok()
+            .or(Some(content))
+    }
+
+    /// Message size in bytes, when listed with `include_size`
+    pub fn get_size(&self) -> Option<u64> {
+        self.single_value_extended_properties
+            .as_ref()?
+            .iter()
+            .find(|p| p.id.eq_ignore_ascii_case(SIZE_PROPERTY))
+            .and_then(|p| p.value.parse().ok())
+    }
+
     pub fn get_header(&self, name: &str) -> Option<&str> {
         self.internet_message_headers
             .as_ref()?
@@ -468,18 +2552,118 @@ impl Message {
         }
         None
     }
+
+    /// The List-Unsubscribe https URL when the sender supports RFC 8058
+    /// one-click unsubscribe (List-Unsubscribe-Post: List-Unsubscribe=One-Click)
+    pub fn get_one_click_url(&self) -> Option<String> {
+        let post = self.get_header("List-Unsubscribe-Post")?;
+        if !post
+            .trim()
+            .eq_ignore_ascii_case("List-Unsubscribe=One-Click")
+        {
+            return None;
+        }
+        // The first https URI, even when a plain http one comes before it
+        self.get_header("List-Unsubscribe")?
+            .split(',')
+            .filter_map(|part| part.trim().strip_prefix('<')?.strip_suffix('>'))
+            .find(|url| url.starts_with("https://"))
+            .map(String::from)
+    }
+
+    /// Unique http(s) links in the body, in order of appearance: href
+    /// attributes of HTML bodies plus bare URLs in the text
+    pub fn get_links(&self) -> Vec<String> {
+        let Some(body) = self.get_body_text() else {
+            return Vec::new();
+        };
+        let href = regex::Regex::new(r#"(?i)href\s*=\s*["'](https?://[^"']+)["']"#).unwrap();
+        let bare = regex::Regex::new(r#"(?i)https?://[^\s<>"'()\[\]]+"#).unwrap();
+
+        let mut links: Vec<String> = Vec::new();
+        let found = href
+            .captures_iter(&body)
+            .map(|c| c[1].to_string())
+            .chain(bare.find_iter(&body).map(|m| m.as_str().to_string()));
+        for link in found {
+            let link = link
+                .replace("&amp;", "&")
+                .trim_end_matches(['.', ',', ';', ':', '!', '?'])
+                .to_string();
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+        links
+    }
+}
+
+/// The `senderContains` entry for a sender. The condition is a substring
+/// match, so a domain is stored as `@example.com` to keep it from also
+/// matching `notexample.com` (a lookalike such as `example.com.evil.org`
+/// still matches).
+fn block_pattern(sender: &str) -> String {
+    if sender.contains('@') {
+        sender.to_string()
+    } else {
+        format!("@{}", sender)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(250),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..20 {
+            let first = policy.backoff(0);
+            assert!(first >= Duration::from_millis(125) && first < Duration::from_millis(375));
+            let fourth = policy.backoff(3);
+            assert!(fourth >= Duration::from_secs(1) && fourth < Duration::from_secs(3));
+        }
+        // Absurd retry counts saturate instead of overflowing
+        assert!(policy.backoff(100) > Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let start = std::time::Instant::now();
+        let wait = Duration::from_secs(30);
+        let mut breaker = CircuitBreaker::default();
+        for i in 0..BREAKER_THRESHOLD - 1 {
+            breaker
+                .record(start + Duration::from_secs(i as u64), wait)
+                .unwrap();
+        }
+        // The oldest one has aged out of the window
+        breaker.record(start + BREAKER_WINDOW, wait).unwrap();
+        let at = start + BREAKER_WINDOW;
+        let err = breaker.record(at, wait).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Throttled by Microsoft Graph, retry after 30s"
+        );
+
+        let retry_after = breaker
+            .check(at + Duration::from_secs(10))
+            .unwrap_err()
+            .retry_after;
+        assert_eq!(retry_after, Duration::from_secs(20));
+        breaker.check(at + wait).unwrap();
+    }
+
     fn make_message(from: Option<Recipient>, body: Option<Body>) -> Message {
         Message {
             id: "test123".to_string(),
             subject: Some("Test Subject".to_string()),
             from,
             to_recipients: None,
+            cc_recipients: None,
             body,
             body_preview: Some("preview".to_string()),
             received_date_time: None,
@@ -487,6 +2671,12 @@ mod tests {
             categories: None,
             internet_message_headers: None,
             parent_folder_id: None,
+            inference_classification: None,
+            conversation_id: None,
+            has_attachments: None,
+            single_value_extended_properties: None,
+            web_link: None,
+            etag: None,
         }
     }
 
@@ -521,6 +2711,57 @@ mod tests {
         assert_eq!(msg.get_from(), Some("john@example.com".to_string()));
     }
 
+    #[test]
+    fn test_get_body_plain() {
+        let html = r#"<html><body><p>Hello <b>there</b></p><a href="https://example.com">site</a></body></html>"#;
+        let msg = make_message(
+            None,
+            Some(Body {
+                content_type: Some("html".to_string()),
+                content: Some(html.to_string()),
+            }),
+        );
+        let text = msg.get_body_plain(80).unwrap();
+        assert!(text.starts_with("Hello **there**"));
+        assert!(text.contains("[site][1]"));
+        assert!(text.contains("[1]: https://example.com"));
+    }
+
+    #[test]
+    fn test_get_links() {
+        let html = r#"<a href="https://example.com/track?a=1&amp;b=2">Track</a>
+            Visit https://example.com/track?a=1&amp;b=2. or <a href='http://x.org/'>x</a>"#;
+        let msg = make_message(
+            None,
+            Some(Body {
+                content_type: Some("html".to_string()),
+                content: Some(html.to_string()),
+            }),
+        );
+        assert_eq!(
+            msg.get_links(),
+            vec!["https://example.com/track?a=1&b=2", "http://x.org/"]
+        );
+    }
+
+    #[test]
+    fn test_is_from() {
+        let msg = make_message(
+            Some(Recipient {
+                email_address: EmailAddress {
+                    name: None,
+                    address: Some("News@mail.Example.com".to_string()),
+                },
+            }),
+            None,
+        );
+        assert!(msg.is_from("news@mail.example.com"));
+        assert!(msg.is_from("example.com"));
+        assert!(msg.is_from("mail.example.com"));
+        assert!(!msg.is_from("other@example.com"));
+        assert!(!msg.is_from("ample.com"));
+    }
+
     #[test]
     fn test_get_body_text() {
         let msg = make_message(
@@ -546,6 +2787,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_one_click_url() {
+        let header = |name: &str, value: &str| InternetMessageHeader {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        let mut msg = make_message(None, None);
+        msg.internet_message_headers = Some(vec![header(
+            "List-Unsubscribe",
+            "<mailto:unsub@example.com>, <https://example.com/unsub>",
+        )]);
+        assert_eq!(msg.get_one_click_url(), None);
+
+        msg.internet_message_headers.as_mut().unwrap().push(header(
+            "List-Unsubscribe-Post",
+            "List-Unsubscribe=One-Click",
+        ));
+        assert_eq!(
+            msg.get_one_click_url(),
+            Some("https://example.com/unsub".to_string())
+        );
+
+        msg.internet_message_headers.as_mut().unwrap()[0].value =
+            "<http://example.com/plain>, <https://example.com/unsub>".to_string();
+        assert_eq!(
+            msg.get_one_click_url(),
+            Some("https://example.com/unsub".to_string())
+        );
+    }
+
     #[test]
     fn test_get_unsubscribe_url_mailto_only() {
         let mut msg = make_message(None, None);
@@ -558,4 +2829,146 @@ mod tests {
             Some("mailto:unsub@example.com".to_string())
         );
     }
+
+    #[test]
+    fn test_mailbox_path() {
+        assert_eq!(
+            mailbox_path("/me/messages?$top=1", None),
+            "/me/messages?$top=1"
+        );
+        assert_eq!(
+            mailbox_path("/me/messages?$top=1", Some("help@contoso.com")),
+            "/users/help%40contoso.com/messages?$top=1"
+        );
+        assert_eq!(
+            mailbox_path("/me?$select=mail", Some("help@contoso.com")),
+            "/users/help%40contoso.com?$select=mail"
+        );
+        assert_eq!(mailbox_path("/$batch", Some("help@contoso.com")), "/$batch");
+        assert_eq!(mailbox_path("/meetings", Some("x@y.com")), "/meetings");
+    }
+
+    #[test]
+    fn test_attachment_kind() {
+        let attachment =
+            |json: serde_json::Value| -> Attachment { serde_json::from_value(json).unwrap() };
+        let file = attachment(serde_json::json!({
+            "@odata.type": "#microsoft.graph.fileAttachment",
+            "id": "a",
+        }));
+        assert!(file.is_file());
+        let item = attachment(serde_json::json!({
+            "@odata.type": "#microsoft.graph.itemAttachment",
+            "id": "b",
+        }));
+        assert!(!item.is_file());
+        assert_eq!(item.kind(), "item");
+    }
+
+    #[test]
+    fn test_batch_request_json() {
+        let req = BatchRequest::new(
+            reqwest::Method::PATCH,
+            "/me/messages/abc".to_string(),
+            Some(serde_json::json!({ "isRead": true })),
+        );
+        let json = req.to_json(3);
+        assert_eq!(json["id"], "3");
+        assert_eq!(json["method"], "PATCH");
+        assert_eq!(json["headers"]["Content-Type"], "application/json");
+        assert!(json["headers"].get("Prefer").is_none());
+
+        let get = BatchRequest::new(reqwest::Method::GET, "/me/messages/abc".to_string(), None);
+        assert!(get.to_json(0).get("headers").is_none());
+
+        let checked =
+            BatchRequest::new(reqwest::Method::PATCH, "/me/messages/abc".to_string(), None)
+                .if_match(Some("W/\"CQAAABYAAAA\"".to_string()));
+        assert_eq!(
+            checked.to_json(0)["headers"]["If-Match"],
+            "W/\"CQAAABYAAAA\""
+        );
+    }
+
+    #[test]
+    fn test_dry_run_reply() {
+        assert!(dry_run_reply("GET", "/me/messages?$top=10", None).is_none());
+        assert!(dry_run_reply("POST", "/me/translateExchangeIds", None).is_none());
+
+        let body = serde_json::json!({ "displayName": "Receipts" });
+        let (lines, reply) = dry_run_reply("POST", "/me/mailFolders", Some(&body)).unwrap();
+        assert_eq!(
+            lines,
+            [r#"Would POST /me/mailFolders {"displayName":"Receipts"}"#]
+        );
+        assert_eq!(reply["displayName"], "Receipts");
+        assert_eq!(reply["id"], "dry-run");
+        let (lines, _) = dry_run_reply("DELETE", "/me/messages/abc", None).unwrap();
+        assert_eq!(lines, ["Would DELETE /me/messages/abc"]);
+
+        let long = serde_json::json!({ "body": "x".repeat(500) });
+        let (lines, _) = dry_run_reply("POST", "/me/sendMail", Some(&long)).unwrap();
+        assert!(lines[0].ends_with("xxx..."));
+        assert!(lines[0].len() < 250);
+
+        let reads = serde_json::json!({ "requests": [
+            BatchRequest::new(reqwest::Method::GET, "/me/messages/a".to_string(), None).to_json(0),
+        ]});
+        assert!(dry_run_reply("POST", "/$batch", Some(&reads)).is_none());
+        let moves = serde_json::json!({ "requests": move_requests(&["a".into(), "b".into()], "archive")
+            .iter()
+            .enumerate()
+            .map(|(i, r)| r.to_json(i))
+            .collect::<Vec<_>>() });
+        let (lines, reply) = dry_run_reply("POST", "/$batch", Some(&moves)).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("Would POST /me/messages/b/move"));
+        let reply: BatchResponseList = serde_json::from_value(reply).unwrap();
+        assert_eq!(reply.responses[1].id, "1");
+        assert!(reply.responses[1].status == 200);
+    }
+
+    #[test]
+    fn test_block_pattern() {
+        assert_eq!(block_pattern("example.com"), "@example.com");
+        assert_eq!(block_pattern("@example.com"), "@example.com");
+        assert_eq!(block_pattern("bob@example.com"), "bob@example.com");
+    }
+
+    #[test]
+    fn test_etag_conflict() {
+        let msg: Message = serde_json::from_value(serde_json::json!({
+            "@odata.etag": "W/\"CQAAABYAAAA\"",
+            "id": "m1",
+        }))
+        .unwrap();
+        assert_eq!(msg.etag.as_deref(), Some("W/\"CQAAABYAAAA\""));
+
+        let changed = serde_json::json!({ "error": { "code": "ErrorIrresolvableConflict", "message": "Changed" } });
+        let err = anyhow::Error::new(GraphError::from_body(412, &changed, None)).context("Failed");
+        assert!(is_conflict(&err));
+        let missing = anyhow::Error::new(GraphError::from_body(404, &changed, None));
+        assert!(!is_conflict(&missing));
+    }
+
+    #[test]
+    fn test_batch_response_error() {
+        let resp = BatchResponse {
+            id: "0".to_string(),
+            status: 404,
+            headers: None,
+            body: Some(serde_json::json!({
+                "error": {
+                    "code": "ErrorItemNotFound",
+                    "message": "Not found",
+                    "innerError": { "request-id": "abc-123" }
+                }
+            })),
+        };
+        let err = resp.into_result().unwrap_err();
+        assert_eq!(err.to_string(), "HTTP 404 - Not found");
+        let graph = err.downcast_ref::<GraphError>().unwrap();
+        assert_eq!(graph.code.as_deref(), Some("ErrorItemNotFound"));
+        assert_eq!(graph.request_id.as_deref(), Some("abc-123"));
+    }
 }
@@ -1,21 +1,86 @@
 use anyhow::{Context, Result};
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use oauth2::basic::BasicClient;
 use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, CsrfToken, PkceCodeChallenge, RedirectUrl, RefreshToken,
     Scope, TokenResponse, TokenUrl,
 };
+use rsa::RsaPrivateKey;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::sha2::Sha256;
+use rsa::signature::{SignatureEncoding, Signer};
 use serde::Deserialize;
+use sha1::{Digest, Sha1};
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpListener;
+use std::path::Path;
 use std::time::Duration;
 use url::Url;
 
 use crate::config::{self, Tokens};
+use crate::status::info;
+
+// Microsoft identity platform (common = any Azure AD or personal Microsoft account)
+const LOGIN_HOST: &str = "https://login.microsoftonline.com";
+pub const DEFAULT_TENANT: &str = "common";
+// Permissions requested at login:
+// Mail.ReadWrite for reading/moving/deleting messages
+// Mail.Send for unsubscribe emails
+// MailboxSettings.ReadWrite for reading and creating categories
+// offline_access for refresh token
+pub const SCOPES: &[&str] = &[
+    "Mail.ReadWrite",
+    "Mail.Send",
+    "MailboxSettings.ReadWrite",
+    "offline_access",
+];
+
+/// Requested only when a command first needs them, so a plain `login`
+/// doesn't ask every user (or their admin) to consent to them:
+/// ThreatAssessment.ReadWrite.All for `report`,
+/// Contacts.ReadWrite for `contacts add`,
+/// Calendars.ReadWrite for `rsvp`,
+/// User.RevokeSessions.All for `logout --revoke`
+pub const INCREMENTAL_SCOPES: &[&str] = &[
+    "ThreatAssessment.ReadWrite.All",
+    "Contacts.ReadWrite",
+    "Calendars.ReadWrite",
+    "User.RevokeSessions.All",
+];
+
+/// Requested instead of SCOPES in read-only mode
+pub const READ_ONLY_SCOPES: &[&str] = &["Mail.Read", "MailboxSettings.Read", "offline_access"];
+
+/// Whether a granted permission covers a required one: equal, or the
+/// ReadWrite form of a Read permission (Mail.ReadWrite covers Mail.Read)
+pub fn grants(granted: &str, required: &str) -> bool {
+    granted.eq_ignore_ascii_case(required)
+        || required
+            .strip_suffix(".Read")
+            .is_some_and(|base| granted.eq_ignore_ascii_case(&format!("{}.ReadWrite", base)))
+        || required
+            .strip_suffix(".Read.Shared")
+            .is_some_and(|base| granted.eq_ignore_ascii_case(&format!("{}.ReadWrite.Shared", base)))
+}
+
+/// Also requested when a shared or delegated mailbox is configured
+pub const SHARED_SCOPES: &[&str] = &["Mail.ReadWrite.Shared", "Mail.Send.Shared"];
+
+/// Password for the `login --client-cert` file, if it has one
+pub const CERT_PASSWORD_ENV: &str = "OUTLOOK_CERT_PASSWORD";
+const ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+// How long a client assertion stays valid
+const ASSERTION_LIFETIME_SECS: i64 = 600;
+
+// Azure Instance Metadata Service, for VMs with a managed identity
+const IMDS_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const GRAPH_RESOURCE: &str = "https://graph.microsoft.com";
+/// `managed_identity` value for the system-assigned identity
+pub const SYSTEM_IDENTITY: &str = "system";
 
-// Microsoft identity platform endpoints (common = any Azure AD or personal Microsoft account)
-const AUTH_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/authorize";
-const TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
-const DEVICE_CODE_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode";
 const LOGIN_MAX_RETRIES: u32 = 3;
 const CALLBACK_TIMEOUT_SECS: u64 = 120;
 
@@ -32,6 +97,14 @@ struct DeviceCodeResponse {
 struct DeviceTokenResponse {
     access_token: String,
     refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ManagedIdentityToken {
+    access_token: String,
+    /// Unix seconds; a string from IMDS, sometimes a number elsewhere
+    expires_on: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize)]
@@ -39,14 +112,161 @@ struct DeviceTokenError {
     error: String,
 }
 
+/// Check a `--tenant` value: a tenant GUID, a domain such as contoso.com, or
+/// one of common/organizations/consumers
+pub fn parse_tenant(tenant: &str) -> Result<String> {
+    let tenant = tenant.trim();
+    let valid = !tenant.is_empty()
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    if !valid {
+        anyhow::bail!("Invalid tenant '{}' (use a tenant ID or domain)", tenant);
+    }
+    Ok(tenant.to_lowercase())
+}
+
+// An OAuth endpoint (authorize, token, devicecode, logout) for a tenant
+pub fn oauth_url(tenant: &str, endpoint: &str) -> String {
+    format!("{}/{}/oauth2/v2.0/{}", LOGIN_HOST, tenant, endpoint)
+}
+
+/// What an access token says about the session
+#[derive(Debug, Default, PartialEq)]
+pub struct TokenClaims {
+    pub tenant: Option<String>,
+    /// Delegated scopes, or the application roles of an app-only token
+    pub scopes: Vec<String>,
+    pub expires: Option<DateTime<Utc>>,
+    /// The app an app-only token (managed identity, client credentials)
+    /// was issued to; None for tokens acting as a signed-in user
+    pub app: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtPayload {
+    tid: Option<String>,
+    scp: Option<String>,
+    roles: Option<Vec<String>>,
+    exp: Option<i64>,
+    app_displayname: Option<String>,
+    appid: Option<String>,
+}
+
+/// Read the claims of a JWT access token without verifying it. Tokens for
+/// personal Microsoft accounts are opaque, so this returns None for them.
+pub fn decode_claims(token: &str) -> Option<TokenClaims> {
+    let payload = token.split('.').nth(1)?;
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let jwt: JwtPayload = serde_json::from_slice(&bytes).ok()?;
+    // Only delegated tokens carry scp
+    let (scopes, app) = match jwt.scp {
+        Some(scp) => (scp.split_whitespace().map(String::from).collect(), None),
+        None => (
+            jwt.roles.unwrap_or_default(),
+            jwt.app_displayname.or(jwt.appid),
+        ),
+    };
+    Some(TokenClaims {
+        tenant: jwt.tid,
+        scopes,
+        expires: jwt.exp.and_then(|exp| DateTime::from_timestamp(exp, 0)),
+        app,
+    })
+}
+
+/// RSA key and certificate thumbprint from a PKCS#12 (.pfx) file, for app
+/// registrations that authenticate with a certificate instead of a secret
+pub struct ClientCert {
+    key: RsaPrivateKey,
+    thumbprint: Vec<u8>,
+}
+
+impl ClientCert {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let password = std::env::var(CERT_PASSWORD_ENV).unwrap_or_default();
+        let store = p12_keystore::KeyStore::from_pkcs12(&data, &password).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to open {} ({}); set {} if it has a password",
+                path.display(),
+                e,
+                CERT_PASSWORD_ENV
+            )
+        })?;
+        let (_, chain) = store
+            .private_key_chain()
+            .ok_or_else(|| anyhow::anyhow!("{} has no private key", path.display()))?;
+        let cert = chain
+            .chain()
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("{} has no certificate", path.display()))?;
+        let key = RsaPrivateKey::from_pkcs8_der(chain.key())
+            .or_else(|_| RsaPrivateKey::from_pkcs1_der(chain.key()))
+            .map_err(|_| anyhow::anyhow!("Only RSA certificate keys are supported"))?;
+        Ok(ClientCert {
+            key,
+            thumbprint: Sha1::digest(cert.as_der()).to_vec(),
+        })
+    }
+
+    /// A signed JWT standing in for a client secret in one token request
+    pub fn assertion(&self, client_id: &str, token_url: &str) -> String {
+        let encode = |value: serde_json::Value| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(value.to_string())
+        };
+        let now = Utc::now().timestamp();
+        let header = serde_json::json!({
+            "alg": "RS256",
+            "typ": "JWT",
+            "x5t": base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&self.thumbprint),
+        });
+        let claims = serde_json::json!({
+            "aud": token_url,
+            "iss": client_id,
+            "sub": client_id,
+            "jti": CsrfToken::new_random().secret(),
+            "nbf": now,
+            "iat": now,
+            "exp": now + ASSERTION_LIFETIME_SECS,
+        });
+        let signing_input = format!("{}.{}", encode(header), encode(claims));
+        let signature = SigningKey::<Sha256>::new(self.key.clone()).sign(signing_input.as_bytes());
+        format!(
+            "{}.{}",
+            signing_input,
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+}
+
+// Extra token request fields when config names a client certificate
+fn assertion_params(client_id: &str, tenant: &str) -> Result<Vec<(&'static str, String)>> {
+    let Some(path) = config::load_config()?.client_cert else {
+        return Ok(Vec::new());
+    };
+    let cert = ClientCert::load(&path)?;
+    Ok(vec![
+        ("client_assertion_type", ASSERTION_TYPE.to_string()),
+        (
+            "client_assertion",
+            cert.assertion(client_id, &oauth_url(tenant, "token")),
+        ),
+    ])
+}
+
 fn create_http_client() -> reqwest::Client {
-    reqwest::Client::builder()
+    crate::api::http_client()
         .redirect(reqwest::redirect::Policy::none())
         .build()
         .expect("Client should build")
 }
 
-pub async fn login(client_id: &str) -> Result<Tokens> {
+#[tracing::instrument(name = "auth.login", skip_all, fields(tenant = %tenant))]
+pub async fn login(client_id: &str, tenant: &str, scopes: &[&str]) -> Result<Tokens> {
     let mut last_error = None;
 
     for attempt in 0..LOGIN_MAX_RETRIES {
@@ -58,7 +278,7 @@ pub async fn login(client_id: &str) -> Result<Tokens> {
             );
         }
 
-        match try_login(client_id).await {
+        match try_login(client_id, tenant, scopes).await {
             Ok(tokens) => return Ok(tokens),
             Err(e) => {
                 eprintln!("Login failed: {}", e);
@@ -71,7 +291,7 @@ pub async fn login(client_id: &str) -> Result<Tokens> {
         .unwrap_or_else(|| anyhow::anyhow!("Login failed after {} attempts", LOGIN_MAX_RETRIES)))
 }
 
-async fn try_login(client_id: &str) -> Result<Tokens> {
+async fn try_login(client_id: &str, tenant: &str, scopes: &[&str]) -> Result<Tokens> {
     // Bind to port 0 to get an OS-assigned available port (prevents port squatting)
     let listener = TcpListener::bind("127.0.0.1:0").context("Failed to bind to local port")?;
     let port = listener.local_addr()?.port();
@@ -81,8 +301,8 @@ async fn try_login(client_id: &str) -> Result<Tokens> {
 
     // Public client - no client_secret needed, PKCE provides security
     let client = BasicClient::new(ClientId::new(client_id.to_string()))
-        .set_auth_uri(AuthUrl::new(AUTH_URL.to_string())?)
-        .set_token_uri(TokenUrl::new(TOKEN_URL.to_string())?)
+        .set_auth_uri(AuthUrl::new(oauth_url(tenant, "authorize"))?)
+        .set_token_uri(TokenUrl::new(oauth_url(tenant, "token"))?)
         .set_redirect_uri(RedirectUrl::new(format!("http://localhost:{}", port))?);
 
     let http_client = create_http_client();
@@ -91,14 +311,7 @@ async fn try_login(client_id: &str) -> Result<Tokens> {
 
     let (auth_url, csrf_token) = client
         .authorize_url(CsrfToken::new_random)
-        // Mail.ReadWrite for reading/moving/deleting messages
-        // Mail.Send for potential future send support
-        // MailboxSettings.ReadWrite for reading and creating categories
-        // offline_access for refresh token
-        .add_scope(Scope::new("Mail.ReadWrite".to_string()))
-        .add_scope(Scope::new("Mail.Send".to_string()))
-        .add_scope(Scope::new("MailboxSettings.ReadWrite".to_string()))
-        .add_scope(Scope::new("offline_access".to_string()))
+        .add_scopes(scopes.iter().map(|s| Scope::new(s.to_string())))
         .set_pkce_challenge(pkce_challenge)
         .url();
 
@@ -109,9 +322,11 @@ async fn try_login(client_id: &str) -> Result<Tokens> {
 
     let code = wait_for_callback_with_timeout(listener, csrf_token, CALLBACK_TIMEOUT_SECS)?;
 
-    let token_result = client
-        .exchange_code(code)
-        .set_pkce_verifier(pkce_verifier)
+    let mut request = client.exchange_code(code).set_pkce_verifier(pkce_verifier);
+    for (name, value) in assertion_params(client_id, tenant)? {
+        request = request.add_extra_param(name, value);
+    }
+    let token_result = request
         .request_async(&http_client)
         .await
         .context("Failed to exchange code for token")?;
@@ -122,12 +337,18 @@ async fn try_login(client_id: &str) -> Result<Tokens> {
             .refresh_token()
             .map(|t| t.secret().to_string())
             .ok_or_else(|| anyhow::anyhow!("No refresh token received"))?,
+        expires_at: expires_at(token_result.expires_in()),
     };
 
     config::save_tokens(&tokens)?;
     Ok(tokens)
 }
 
+// Absolute expiry from a token response's expires_in
+fn expires_at(expires_in: Option<Duration>) -> Option<i64> {
+    expires_in.map(|d| chrono::Utc::now().timestamp() + d.as_secs() as i64)
+}
+
 fn wait_for_callback_with_timeout(
     listener: TcpListener,
     expected_csrf: CsrfToken,
@@ -192,16 +413,30 @@ fn wait_for_callback_with_timeout(
     Ok(code)
 }
 
-pub async fn refresh_token(client_id: &str, refresh: &str) -> Result<Tokens> {
+/// Redeem a refresh token. Non-empty `scopes` ask for those permissions
+/// instead of the ones granted at login, which fails unless they were consented to.
+#[tracing::instrument(name = "auth.refresh", skip_all, fields(tenant = %tenant))]
+pub async fn refresh_token(
+    client_id: &str,
+    tenant: &str,
+    refresh: &str,
+    scopes: &[&str],
+) -> Result<Tokens> {
     // Public client - no client_secret needed
     let client = BasicClient::new(ClientId::new(client_id.to_string()))
-        .set_auth_uri(AuthUrl::new(AUTH_URL.to_string())?)
-        .set_token_uri(TokenUrl::new(TOKEN_URL.to_string())?);
+        .set_auth_uri(AuthUrl::new(oauth_url(tenant, "authorize"))?)
+        .set_token_uri(TokenUrl::new(oauth_url(tenant, "token"))?);
 
     let http_client = create_http_client();
 
-    let token_result = client
-        .exchange_refresh_token(&RefreshToken::new(refresh.to_string()))
+    let refresh_token = RefreshToken::new(refresh.to_string());
+    let mut request = client
+        .exchange_refresh_token(&refresh_token)
+        .add_scopes(scopes.iter().map(|s| Scope::new(s.to_string())));
+    for (name, value) in assertion_params(client_id, tenant)? {
+        request = request.add_extra_param(name, value);
+    }
+    let token_result = request
         .request_async(&http_client)
         .await
         .context("Failed to refresh token")?;
@@ -212,21 +447,304 @@ pub async fn refresh_token(client_id: &str, refresh: &str) -> Result<Tokens> {
             .refresh_token()
             .map(|t| t.secret().to_string())
             .unwrap_or_else(|| refresh.to_string()),
+        expires_at: expires_at(token_result.expires_in()),
+    };
+
+    config::save_tokens(&tokens)?;
+    Ok(tokens)
+}
+
+type RefreshError = oauth2::basic::BasicRequestTokenError<oauth2::HttpClientError<reqwest::Error>>;
+
+/// Whether a `refresh_token` or `broker_token` error is the identity
+/// platform refusing to renew the session (invalid_grant: expired, revoked
+/// or consent withdrawn), rather than a network failure
+pub fn refresh_rejected(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.is::<InteractionRequired>()
+            || matches!(
+                e.downcast_ref::<RefreshError>(),
+                Some(oauth2::RequestTokenError::ServerResponse(_))
+            )
+    })
+}
+
+/// Get an app-only Graph token from the Azure managed identity endpoint: the
+/// one App Service and Container Apps advertise, or IMDS on a VM. `identity`
+/// is SYSTEM_IDENTITY or the client ID of a user-assigned identity.
+#[tracing::instrument(name = "auth.managed_identity", skip_all)]
+pub async fn managed_identity_token(identity: &str) -> Result<Tokens> {
+    // Both endpoints are local to the host, so never go through a proxy
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .no_proxy()
+        .build()?;
+    let mut query = vec![("resource", GRAPH_RESOURCE)];
+    if identity != SYSTEM_IDENTITY {
+        query.push(("client_id", identity));
+    }
+
+    let request = match (
+        std::env::var("IDENTITY_ENDPOINT"),
+        std::env::var("IDENTITY_HEADER"),
+    ) {
+        (Ok(endpoint), Ok(secret)) => {
+            query.push(("api-version", "2019-08-01"));
+            http_client
+                .get(endpoint)
+                .header("X-IDENTITY-HEADER", secret)
+        }
+        _ => {
+            query.push(("api-version", "2018-02-01"));
+            http_client.get(IMDS_ENDPOINT).header("Metadata", "true")
+        }
     };
+    let response = request.query(&query).send().await.context(
+        "No managed identity endpoint (is this running on Azure with an identity assigned?)",
+    )?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!(
+            "Managed identity token request failed ({}): {}",
+            status,
+            body
+        );
+    }
+    let token: ManagedIdentityToken = response
+        .json()
+        .await
+        .context("Failed to parse managed identity token")?;
 
+    let tokens = Tokens {
+        access_token: token.access_token,
+        // Managed identities have no refresh token; the endpoint is asked again
+        refresh_token: String::new(),
+        expires_at: token.expires_on.as_ref().and_then(parse_expires_on),
+    };
     config::save_tokens(&tokens)?;
     Ok(tokens)
 }
 
+fn parse_expires_on(value: &serde_json::Value) -> Option<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+}
+
+/// Redirect URI the app registration needs for broker sign-in
+pub fn broker_redirect_uri(client_id: &str) -> String {
+    format!("ms-appx-web://microsoft.aad.brokerplugin/{}", client_id)
+}
+
+/// The Windows broker can't renew a token without the user, e.g. after a
+/// password change or a new conditional access policy
+#[derive(Debug)]
+pub struct InteractionRequired;
+
+impl std::fmt::Display for InteractionRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Windows needs you to sign in again")
+    }
+}
+
+impl std::error::Error for InteractionRequired {}
+
+/// Sign in through the Windows Web Account Manager (WAM) broker, which
+/// signs in with the Windows account and proves the device to conditional
+/// access. Returns the broker's ID for the account, for `broker_token`.
+#[tracing::instrument(name = "auth.broker", skip_all, fields(tenant = %tenant))]
+pub async fn broker_login(client_id: &str, tenant: &str, scopes: &[&str]) -> Result<String> {
+    #[cfg(windows)]
+    {
+        let (client_id, tenant) = (client_id.to_string(), tenant.to_string());
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        let (tokens, account) =
+            tokio::task::spawn_blocking(move || wam::login(&client_id, &tenant, &scopes)).await??;
+        config::save_tokens(&tokens)?;
+        Ok(account)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (client_id, tenant, scopes);
+        anyhow::bail!("Signing in through the Windows broker only works on Windows")
+    }
+}
+
+/// A new access token for an account signed in with `broker_login`, without
+/// prompting. The broker keeps the refresh token; there is none to save.
+pub async fn broker_token(
+    client_id: &str,
+    tenant: &str,
+    account: &str,
+    scopes: &[&str],
+) -> Result<Tokens> {
+    #[cfg(windows)]
+    {
+        let (client_id, tenant) = (client_id.to_string(), tenant.to_string());
+        let account = account.to_string();
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        let tokens =
+            tokio::task::spawn_blocking(move || wam::token(&client_id, &tenant, &account, &scopes))
+                .await??;
+        config::save_tokens(&tokens)?;
+        Ok(tokens)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (client_id, tenant, account, scopes);
+        anyhow::bail!("Signing in through the Windows broker only works on Windows")
+    }
+}
+
+// WinRT calls block on their async operations, so they run on a blocking
+// thread rather than the async runtime
+#[cfg(windows)]
+mod wam {
+    use anyhow::{Context, Result};
+    use windows::Security::Authentication::Web::Core::{
+        WebAuthenticationCoreManager, WebTokenRequest, WebTokenRequestResult, WebTokenRequestStatus,
+    };
+    use windows::Security::Credentials::WebAccountProvider;
+    use windows::Win32::System::Console::GetConsoleWindow;
+    use windows::Win32::System::WinRT::{
+        IWebAuthenticationCoreManagerInterop, RO_INIT_MULTITHREADED, RoInitialize,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{GA_ROOTOWNER, GetAncestor};
+    use windows::core::HSTRING;
+    use windows_future::IAsyncOperation;
+
+    use super::{DEFAULT_TENANT, InteractionRequired, LOGIN_HOST, decode_claims};
+    use crate::config::Tokens;
+
+    // Windows' provider for work and school accounts
+    const PROVIDER_ID: &str = "https://login.microsoft.com";
+    const PROVIDER_AUTHORITY: &str = "organizations";
+
+    /// Ask for a token interactively, with the broker's dialog in front of
+    /// the console window
+    pub fn login(client_id: &str, tenant: &str, scopes: &[String]) -> Result<(Tokens, String)> {
+        let request = token_request(client_id, tenant, scopes)?;
+        let interop = windows::core::factory::<
+            WebAuthenticationCoreManager,
+            IWebAuthenticationCoreManagerInterop,
+        >()?;
+        // SAFETY: both calls only look up window handles. Under Windows
+        // Terminal the console window is hidden, and its root owner is the
+        // visible terminal window.
+        let window = unsafe { GetAncestor(GetConsoleWindow(), GA_ROOTOWNER) };
+        // SAFETY: `request` is a live WebTokenRequest, and the IID requested
+        // matches the IAsyncOperation type the result is read as
+        let operation: IAsyncOperation<WebTokenRequestResult> =
+            unsafe { interop.RequestTokenForWindowAsync(window, &request)? };
+        read_result(&operation.join()?)
+    }
+
+    /// Renew a token for a signed-in account without showing anything
+    pub fn token(
+        client_id: &str,
+        tenant: &str,
+        account: &str,
+        scopes: &[String],
+    ) -> Result<Tokens> {
+        let request = token_request(client_id, tenant, scopes)?;
+        let account = WebAuthenticationCoreManager::FindAccountAsync(
+            &request.WebAccountProvider()?,
+            &HSTRING::from(account),
+        )?
+        .join()
+        .map_err(|_| InteractionRequired)
+        .context("The Windows account used to sign in is no longer available")?;
+        let result =
+            WebAuthenticationCoreManager::GetTokenSilentlyWithWebAccountAsync(&request, &account)?
+                .join()?;
+        read_result(&result).map(|(tokens, _)| tokens)
+    }
+
+    fn token_request(client_id: &str, tenant: &str, scopes: &[String]) -> Result<WebTokenRequest> {
+        init_thread();
+        let provider: WebAccountProvider =
+            WebAuthenticationCoreManager::FindAccountProviderWithAuthorityAsync(
+                &HSTRING::from(PROVIDER_ID),
+                &HSTRING::from(PROVIDER_AUTHORITY),
+            )?
+            .join()
+            .context("Windows has no provider for work or school accounts")?;
+        // The broker keeps its own refresh token
+        let scope: Vec<&str> = scopes
+            .iter()
+            .map(String::as_str)
+            .filter(|s| *s != "offline_access")
+            .collect();
+        let request = WebTokenRequest::Create(
+            &provider,
+            &HSTRING::from(scope.join(" ")),
+            &HSTRING::from(client_id),
+        )?;
+        // The broker's Azure AD plugin takes "organizations" where the
+        // browser flow takes "common"
+        let tenant = match tenant {
+            DEFAULT_TENANT => PROVIDER_AUTHORITY,
+            tenant => tenant,
+        };
+        let properties = request.Properties()?;
+        // v2.0 scopes such as Mail.ReadWrite rather than a v1 resource
+        properties.Insert(&HSTRING::from("wam_compat"), &HSTRING::from("2.0"))?;
+        properties.Insert(
+            &HSTRING::from("authority"),
+            &HSTRING::from(format!("{}/{}", LOGIN_HOST, tenant)),
+        )?;
+        Ok(request)
+    }
+
+    fn read_result(result: &WebTokenRequestResult) -> Result<(Tokens, String)> {
+        let status = result.ResponseStatus()?;
+        if status == WebTokenRequestStatus::Success {
+            let response = result.ResponseData()?.GetAt(0)?;
+            let access_token = response.Token()?.to_string();
+            let account = response.WebAccount()?.Id()?.to_string();
+            let expires_at = decode_claims(&access_token)
+                .and_then(|claims| claims.expires)
+                .map(|expires| expires.timestamp());
+            let tokens = Tokens {
+                access_token,
+                refresh_token: String::new(),
+                expires_at,
+            };
+            return Ok((tokens, account));
+        }
+        if status == WebTokenRequestStatus::UserCancel {
+            anyhow::bail!("Sign-in was cancelled");
+        }
+        if status == WebTokenRequestStatus::UserInteractionRequired {
+            return Err(InteractionRequired.into());
+        }
+        let error = result.ResponseError()?;
+        anyhow::bail!(
+            "Windows sign-in failed: {} (error {:#x})",
+            error.ErrorMessage()?,
+            error.ErrorCode()?
+        )
+    }
+
+    // WinRT needs the thread in an apartment. A thread that already is
+    // returns an error that is safe to ignore.
+    fn init_thread() {
+        // SAFETY: no COM objects exist on this thread yet
+        let _ = unsafe { RoInitialize(RO_INIT_MULTITHREADED) };
+    }
+}
+
 /// Device code flow - works with first-party Microsoft app IDs without redirect URI
-pub async fn login_device_code(client_id: &str) -> Result<Tokens> {
+#[tracing::instrument(name = "auth.device_code", skip_all, fields(tenant = %tenant))]
+pub async fn login_device_code(client_id: &str, tenant: &str, scopes: &[&str]) -> Result<Tokens> {
     let http_client = create_http_client();
-    let scopes = "Mail.ReadWrite Mail.Send MailboxSettings.ReadWrite offline_access";
+    let scopes = scopes.join(" ");
 
     // Step 1: Request device code
     let device_response = http_client
-        .post(DEVICE_CODE_URL)
-        .form(&[("client_id", client_id), ("scope", scopes)])
+        .post(oauth_url(tenant, "devicecode"))
+        .form(&[("client_id", client_id), ("scope", scopes.as_str())])
         .send()
         .await
         .context("Failed to request device code")?
@@ -251,13 +769,18 @@ pub async fn login_device_code(client_id: &str) -> Result<Tokens> {
 
         tokio::time::sleep(interval).await;
 
+        let mut form = vec![
+            ("client_id", client_id.to_string()),
+            ("device_code", device_response.device_code.clone()),
+            (
+                "grant_type",
+                "urn:ietf:params:oauth:grant-type:device_code".to_string(),
+            ),
+        ];
+        form.extend(assertion_params(client_id, tenant)?);
         let response = http_client
-            .post(TOKEN_URL)
-            .form(&[
-                ("client_id", client_id),
-                ("device_code", &device_response.device_code),
-                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
-            ])
+            .post(oauth_url(tenant, "token"))
+            .form(&form)
             .send()
             .await
             .context("Failed to poll for token")?;
@@ -271,9 +794,10 @@ pub async fn login_device_code(client_id: &str) -> Result<Tokens> {
                 refresh_token: token_response
                     .refresh_token
                     .ok_or_else(|| anyhow::anyhow!("No refresh token received"))?,
+                expires_at: expires_at(token_response.expires_in.map(Duration::from_secs)),
             };
             config::save_tokens(&tokens)?;
-            println!("Authentication successful!");
+            info!("Authentication successful!");
             return Ok(tokens);
         }
 
@@ -290,3 +814,98 @@ pub async fn login_device_code(client_id: &str) -> Result<Tokens> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_claims() {
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"tid":"t-1","scp":"Mail.Read offline_access","exp":1700000000}"#);
+        let claims = decode_claims(&format!("eyJhbGciOiJub25lIn0.{}.sig", payload)).unwrap();
+        assert_eq!(claims.tenant.as_deref(), Some("t-1"));
+        assert_eq!(claims.scopes, vec!["Mail.Read", "offline_access"]);
+        assert_eq!(claims.expires.unwrap().timestamp(), 1700000000);
+        assert_eq!(claims.app, None);
+        assert_eq!(decode_claims("EwBwA8l6BAAU-opaque"), None);
+
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"tid":"t-1","roles":["Mail.ReadWrite"],"appid":"a-1"}"#);
+        let claims = decode_claims(&format!("eyJhbGciOiJub25lIn0.{}.sig", payload)).unwrap();
+        assert_eq!(claims.scopes, vec!["Mail.ReadWrite"]);
+        assert_eq!(claims.app.as_deref(), Some("a-1"));
+    }
+
+    #[test]
+    fn test_client_assertion() {
+        use rsa::pkcs1v15::{Signature, VerifyingKey};
+        use rsa::signature::Verifier;
+
+        let key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+        let cert = ClientCert {
+            key: key.clone(),
+            thumbprint: vec![0xab; 20],
+        };
+        let jwt = cert.assertion("app-1", "https://login.example/token");
+        let parts: Vec<&str> = jwt.split('.').collect();
+        assert_eq!(parts.len(), 3);
+
+        let decode = |part: &str| -> serde_json::Value {
+            let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(part)
+                .unwrap();
+            serde_json::from_slice(&bytes).unwrap()
+        };
+        let header = decode(parts[0]);
+        assert_eq!(header["alg"], "RS256");
+        assert_eq!(header["x5t"], "q6urq6urq6urq6urq6urq6urq6s");
+        let claims = decode(parts[1]);
+        assert_eq!(claims["aud"], "https://login.example/token");
+        assert_eq!(claims["iss"], "app-1");
+        assert_eq!(claims["sub"], "app-1");
+
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(parts[2])
+            .unwrap();
+        let signature = Signature::try_from(signature.as_slice()).unwrap();
+        VerifyingKey::<Sha256>::new(key.to_public_key())
+            .verify(format!("{}.{}", parts[0], parts[1]).as_bytes(), &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_expires_on() {
+        let parse = |json: &str| parse_expires_on(&serde_json::from_str(json).unwrap());
+        assert_eq!(parse(r#""1506484173""#), Some(1506484173));
+        assert_eq!(parse("1506484173"), Some(1506484173));
+        assert_eq!(parse(r#""soon""#), None);
+    }
+
+    #[test]
+    fn test_grants() {
+        assert!(grants("Mail.Read", "Mail.Read"));
+        assert!(grants("mail.readwrite", "Mail.Read"));
+        assert!(grants("Mail.ReadWrite.Shared", "Mail.Read.Shared"));
+        assert!(!grants("Mail.Read", "Mail.ReadWrite"));
+        assert!(!grants("Mail.ReadWrite", "Mail.Send"));
+    }
+
+    #[test]
+    fn test_parse_tenant() {
+        assert_eq!(
+            parse_tenant("Contoso.onmicrosoft.com").unwrap(),
+            "contoso.onmicrosoft.com"
+        );
+        assert_eq!(
+            parse_tenant("72f988bf-86f1-41af-91ab-2d7cd011db47").unwrap(),
+            "72f988bf-86f1-41af-91ab-2d7cd011db47"
+        );
+        assert!(parse_tenant("common/../x").is_err());
+        assert!(parse_tenant("").is_err());
+        assert_eq!(
+            oauth_url("contoso.com", "token"),
+            "https://login.microsoftonline.com/contoso.com/oauth2/v2.0/token"
+        );
+    }
+}
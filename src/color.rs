@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Outlook category presets: (preset, name, closest xterm-256 color)
+pub const PRESETS: &[(&str, &str, u8)] = &[
+    ("preset0", "red", 160),
+    ("preset1", "orange", 208),
+    ("preset2", "brown", 130),
+    ("preset3", "yellow", 178),
+    ("preset4", "green", 34),
+    ("preset5", "teal", 30),
+    ("preset6", "olive", 100),
+    ("preset7", "blue", 33),
+    ("preset8", "purple", 98),
+    ("preset9", "cranberry", 125),
+    ("preset10", "steel", 110),
+    ("preset11", "darksteel", 67),
+    ("preset12", "gray", 246),
+    ("preset13", "darkgray", 240),
+    ("preset14", "black", 236),
+    ("preset15", "darkred", 88),
+    ("preset16", "darkorange", 166),
+    ("preset17", "darkbrown", 94),
+    ("preset18", "darkyellow", 136),
+    ("preset19", "darkgreen", 22),
+    ("preset20", "darkteal", 23),
+    ("preset21", "darkolive", 58),
+    ("preset22", "darkblue", 19),
+    ("preset23", "darkpurple", 54),
+    ("preset24", "darkcranberry", 89),
+];
+
+/// Category name (lowercase) -> preset, from the master category list
+pub type Palette = HashMap<String, String>;
+
+/// A category color from a preset ("preset7") or its name ("blue"), or "none"
+pub fn parse_preset(input: &str) -> anyhow::Result<String> {
+    let wanted = input.trim().to_lowercase();
+    if wanted == "none" {
+        return Ok(wanted);
+    }
+    PRESETS
+        .iter()
+        .find(|(preset, name, _)| *preset == wanted || *name == wanted)
+        .map(|(preset, _, _)| preset.to_string())
+        .ok_or_else(|| {
+            let names: Vec<&str> = PRESETS.iter().map(|(_, name, _)| *name).collect();
+            anyhow::anyhow!(
+                "Unknown color '{}' (use preset0-preset24, none, or {})",
+                input,
+                names.join(", ")
+            )
+        })
+}
+
+/// The color name for a preset, e.g. "preset7" -> "blue"
+pub fn preset_name(preset: &str) -> Option<&'static str> {
+    PRESETS
+        .iter()
+        .find(|(p, _, _)| *p == preset)
+        .map(|(_, name, _)| *name)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether `--color auto` should color: a terminal, and NO_COLOR unset
+pub fn auto() -> bool {
+    use std::io::IsTerminal;
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+fn paint(text: &str, sgr: &str) -> String {
+    if enabled() && !text.is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", sgr, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn bold(text: &str) -> String {
+    paint(text, "1")
+}
+
+pub fn dim(text: &str) -> String {
+    paint(text, "2")
+}
+
+/// A category as `[Name]`, in its Outlook color when it has one
+pub fn category(name: &str, palette: &Palette) -> String {
+    let label = format!("[{}]", name);
+    let code = palette
+        .get(&name.to_lowercase())
+        .and_then(|preset| PRESETS.iter().find(|(p, _, _)| p == preset))
+        .map(|(_, _, code)| *code);
+    match code {
+        Some(code) => paint(&label, &format!("38;5;{}", code)),
+        None => label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preset() {
+        assert_eq!(parse_preset("Teal").unwrap(), "preset5");
+        assert_eq!(parse_preset("preset19").unwrap(), "preset19");
+        assert_eq!(parse_preset("none").unwrap(), "none");
+        assert!(parse_preset("preset25").is_err());
+        assert_eq!(preset_name("preset7"), Some("blue"));
+    }
+
+    #[test]
+    fn test_category_color() {
+        let palette = Palette::from([("work".to_string(), "preset7".to_string())]);
+        set_enabled(false);
+        assert_eq!(category("Work", &palette), "[Work]");
+        set_enabled(true);
+        assert_eq!(category("Work", &palette), "\x1b[38;5;33m[Work]\x1b[0m");
+        assert_eq!(category("Other", &palette), "[Other]");
+        set_enabled(false);
+    }
+}
@@ -0,0 +1,186 @@
+// Shell completion. `outlook completions <shell>` prints a script that calls
+// back into outlook as you type, so besides commands and flags, folder and
+// category arguments complete from a small cache of the mailbox's own names.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::Shells;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+
+use crate::{api, config};
+
+/// Environment variable the generated scripts set when asking for completions
+pub const COMPLETE_VAR: &str = "OUTLOOK_COMPLETE";
+const CACHE_FILE: &str = "completions.json";
+// Refetch folder and category names at most this often
+const CACHE_MAX_AGE_HOURS: i64 = 24;
+
+// Names every folder argument accepts, whatever the mailbox language
+const WELL_KNOWN_FOLDERS: &[(&str, &str)] = &[
+    ("inbox", "Inbox"),
+    ("sent", "Sent Items"),
+    ("drafts", "Drafts"),
+    ("archive", "Archive"),
+    ("trash", "Deleted Items"),
+    ("spam", "Junk Email"),
+    ("outbox", "Outbox"),
+];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    /// Last time the names were fetched, or a fetch was attempted
+    checked: Option<DateTime<Utc>>,
+    folders: Vec<String>,
+    categories: Vec<String>,
+}
+
+impl Cache {
+    fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        self.checked
+            .is_none_or(|at| now - at > Duration::hours(CACHE_MAX_AGE_HOURS))
+    }
+}
+
+/// Shells `completions` can write a script for
+pub fn shells() -> Vec<&'static str> {
+    Shells::builtins().names().collect()
+}
+
+/// Write the completion script for `shell`
+pub fn write_script(shell: &str, out: &mut dyn std::io::Write) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .with_context(|| format!("Unknown shell: {}", shell))?;
+    completer.write_registration(COMPLETE_VAR, "outlook", "outlook", "outlook", out)?;
+    Ok(())
+}
+
+/// Well-known folder names, for arguments that take nothing else
+pub fn well_known_folders() -> Vec<CompletionCandidate> {
+    WELL_KNOWN_FOLDERS
+        .iter()
+        .map(|(name, help)| CompletionCandidate::new(*name).help(Some((*help).into())))
+        .collect()
+}
+
+/// Well-known folder names plus the mailbox's own folders
+pub fn folders() -> Vec<CompletionCandidate> {
+    let cache = cached();
+    let mut candidates = well_known_folders();
+    candidates.extend(
+        custom_folders(&cache)
+            .into_iter()
+            .map(CompletionCandidate::new),
+    );
+    candidates
+}
+
+/// The mailbox's categories
+pub fn categories() -> Vec<CompletionCandidate> {
+    cached()
+        .categories
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Folders not already offered under a well-known name
+fn custom_folders(cache: &Cache) -> Vec<String> {
+    cache
+        .folders
+        .iter()
+        .filter(|name| {
+            !WELL_KNOWN_FOLDERS
+                .iter()
+                .any(|(_, display)| display.eq_ignore_ascii_case(name))
+        })
+        .cloned()
+        .collect()
+}
+
+/// The cached names, starting a background refresh when they're old.
+/// Completion runs on every Tab, so it never waits for Graph itself.
+fn cached() -> Cache {
+    // --account isn't parsed while completing, so use the default account
+    config::set_account(config::default_account().ok().flatten());
+    let mut cache: Cache = config::load_state(CACHE_FILE).unwrap_or_default();
+    if cache.is_stale(Utc::now()) {
+        // Record the attempt first so a failing refresh isn't retried on every Tab
+        cache.checked = Some(Utc::now());
+        if config::save_state(CACHE_FILE, &cache).is_ok() {
+            spawn_refresh();
+        }
+    }
+    cache
+}
+
+fn spawn_refresh() {
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+    let _ = std::process::Command::new(exe)
+        .args(["completions", "--refresh"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
+
+/// Fetch folder and category names into the cache
+pub async fn refresh(client: &api::Client) -> Result<()> {
+    let folders = client.list_folders().await?.value.unwrap_or_default();
+    let categories = client.list_categories().await?.value.unwrap_or_default();
+    let cache = Cache {
+        checked: Some(Utc::now()),
+        folders: folders.into_iter().map(|f| f.display_name).collect(),
+        categories: categories.into_iter().map(|c| c.display_name).collect(),
+    };
+    config::save_state(CACHE_FILE, &cache)
+}
+
+/// Update the cached folder names from a listing (best effort)
+pub fn remember_folders(folders: &[api::Folder]) {
+    let mut cache: Cache = config::load_state(CACHE_FILE).unwrap_or_default();
+    cache.folders = folders.iter().map(|f| f.display_name.clone()).collect();
+    let _ = config::save_state(CACHE_FILE, &cache);
+}
+
+/// Update the cached category names from a listing (best effort)
+pub fn remember_categories(categories: &[api::Category]) {
+    let mut cache: Cache = config::load_state(CACHE_FILE).unwrap_or_default();
+    cache.categories = categories.iter().map(|c| c.display_name.clone()).collect();
+    let _ = config::save_state(CACHE_FILE, &cache);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let now = Utc::now();
+        let mut cache = Cache {
+            checked: None,
+            folders: ["Inbox", "Receipts", "junk email"].map(String::from).into(),
+            categories: Vec::new(),
+        };
+        assert!(cache.is_stale(now));
+        cache.checked = Some(now - Duration::hours(1));
+        assert!(!cache.is_stale(now));
+        cache.checked = Some(now - Duration::hours(CACHE_MAX_AGE_HOURS + 1));
+        assert!(cache.is_stale(now));
+
+        assert_eq!(custom_folders(&cache), ["Receipts"]);
+    }
+
+    #[test]
+    fn test_write_script() {
+        let mut script = Vec::new();
+        write_script("bash", &mut script).unwrap();
+        assert!(String::from_utf8(script).unwrap().contains(COMPLETE_VAR));
+        assert!(write_script("tcsh", &mut Vec::new()).is_err());
+    }
+}
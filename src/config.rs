@@ -1,85 +1,958 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fs::{self, Permissions};
-use std::io::Write;
-use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+use crate::secure;
+use crate::status::note;
 
 // Microsoft Graph CLI public client ID - works with localhost redirect + PKCE
 pub const DEFAULT_CLIENT_ID: &str = "14d82eec-204b-4c2f-b7e8-296a70dab67e";
 
+// Service name for tokens kept in the OS keyring
+const KEYRING_SERVICE: &str = "outlook-cli";
+
+// Passphrase for the encrypted token file; prompted for when unset
+pub const PASSPHRASE_ENV: &str = "OUTLOOK_TOKEN_PASSPHRASE";
+
+// Settings can also come from OUTLOOK_CLI_<KEY> (e.g. OUTLOOK_CLI_CLIENT_ID),
+// which override the config file but not command-line flags
+pub const ENV_PREFIX: &str = "OUTLOOK_CLI_";
+
+// Replaces the config directory, for containers and CI without a usable $HOME
+pub const CONFIG_DIR_ENV: &str = "OUTLOOK_CLI_CONFIG_DIR";
+
+/// Where login tokens are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenStore {
+    /// tokens.json in the config directory (mode 0600)
+    #[default]
+    File,
+    /// Secret Service, macOS Keychain or Windows Credential Manager
+    Keyring,
+    /// tokens.json encrypted with ChaCha20-Poly1305 under a passphrase
+    Encrypted,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Config {
     pub client_id: Option<String>,
+    /// Mark messages read when `outlook read` shows them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_marks_read: Option<bool>,
+    /// Page `list` and `read` output when printing to a terminal (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pager: Option<bool>,
+    /// strftime pattern for dates shown in local time (default "%Y-%m-%d %H:%M")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    /// Have Graph convert bodies to plain text for `read` instead of rendering HTML locally
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_body: Option<bool>,
+    /// Use immutable message IDs, which don't change when a message moves folders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub immutable_ids: Option<bool>,
+    /// Refuse commands that change the mailbox, and log in with read permissions only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// Permissions granted at `login --scopes` (default: everything the CLI uses)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    /// Azure AD tenant to sign in to (default "common")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Shared or delegated mailbox to use by default (`--mailbox` overrides it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mailbox: Option<String>,
+    /// Account profile used without `--account` (only read from the top-level config)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_account: Option<String>,
+    /// "file" (default), "keyring" or "encrypted"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_store: Option<TokenStore>,
+    /// PKCS#12 file whose certificate authenticates a confidential client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    /// Get app-only tokens from Azure: "system" or a user-assigned identity's client ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_identity: Option<String>,
+    /// Windows account `login --broker` signed in with, renewed through WAM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broker_account: Option<String>,
+    /// Folder `list` shows without --label (default "inbox")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Messages `list` shows without -n (default 100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<u32>,
+    /// Output format without --output (one of OUTPUT_FORMATS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Color without --color: "auto", "always" or "never"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Seconds before a Graph request gives up (default 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Retries for failed or throttled Graph requests (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    /// Milliseconds before the first retry, doubled each time (default 1000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_backoff: Option<u64>,
+    /// HTTP(S) or SOCKS proxy URL for every request, ahead of HTTPS_PROXY/ALL_PROXY
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Shell command run with each new message's JSON on stdin (`outlook serve`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_new_message: Option<String>,
+    /// Shell command run with each archived message's JSON on stdin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_archive: Option<String>,
+    /// Shell command run with outgoing mail's JSON on stdin; failing cancels the send
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_send: Option<String>,
 }
 
+/// Settings `outlook config` reads and writes, with what each one holds
+pub const KEYS: &[(&str, &str)] = &[
+    ("client_id", "Application ID of your own app registration"),
+    ("tenant", "Tenant ID or domain to sign in to"),
+    ("account", "Default account profile"),
+    ("mailbox", "Shared or delegated mailbox to use by default"),
+    ("folder", "Folder `list` shows by default"),
+    ("page_size", "Messages `list` shows by default"),
+    ("output", "Default output format"),
+    ("color", "auto, always or never"),
+    ("proxy", "http(s):// or socks5:// proxy URL"),
+    ("timeout", "Seconds before a request gives up"),
+    ("max_retries", "Retries for failed or throttled requests"),
+    ("initial_backoff", "Milliseconds before the first retry"),
+    ("pager", "Page `list` and `read` output (true/false)"),
+    ("date_format", "strftime pattern for dates"),
+    ("read_marks_read", "`read` marks messages read (true/false)"),
+    (
+        "text_body",
+        "`read` asks Graph for plain-text bodies (true/false)",
+    ),
+    (
+        "immutable_ids",
+        "Message IDs that survive folder moves (true/false)",
+    ),
+    (
+        "read_only",
+        "Refuse commands that change the mailbox (true/false)",
+    ),
+    ("scopes", "Permissions requested at login, space-separated"),
+    ("token_store", "file, keyring or encrypted"),
+    (
+        "client_cert",
+        "PKCS#12 file for certificate client authentication",
+    ),
+    (
+        "managed_identity",
+        "system, or a user-assigned identity's client ID",
+    ),
+    (
+        "broker_account",
+        "Windows account signed in through WAM (set by login --broker)",
+    ),
+    ("on_new_message", "Command run for each new message"),
+    ("post_archive", "Command run for each archived message"),
+    ("pre_send", "Command that can veto outgoing mail"),
+];
+
+pub const OUTPUT_FORMATS: &[&str] = &["text", "table", "csv", "tsv", "json", "ndjson"];
+const COLOR_CHOICES: &[&str] = &["auto", "always", "never"];
+
 impl Config {
     pub fn client_id(&self) -> &str {
         self.client_id.as_deref().unwrap_or(DEFAULT_CLIENT_ID)
     }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.unwrap_or(false)
+    }
+
+    pub fn tenant(&self) -> &str {
+        self.tenant
+            .as_deref()
+            .unwrap_or(crate::auth::DEFAULT_TENANT)
+    }
+
+    /// A setting as text, or None when unset
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let text = |value: &Option<String>| value.clone();
+        let flag = |value: Option<bool>| value.map(|v| v.to_string());
+        Ok(match check_key(key)? {
+            "client_id" => text(&self.client_id),
+            "tenant" => text(&self.tenant),
+            "account" => text(&self.default_account),
+            "mailbox" => text(&self.mailbox),
+            "folder" => text(&self.folder),
+            "page_size" => self.page_size.map(|n| n.to_string()),
+            "output" => text(&self.output),
+            "color" => text(&self.color),
+            "proxy" => text(&self.proxy),
+            "timeout" => self.timeout.map(|n| n.to_string()),
+            "max_retries" => self.max_retries.map(|n| n.to_string()),
+            "initial_backoff" => self.initial_backoff.map(|n| n.to_string()),
+            "pager" => flag(self.pager),
+            "date_format" => text(&self.date_format),
+            "read_marks_read" => flag(self.read_marks_read),
+            "text_body" => flag(self.text_body),
+            "immutable_ids" => flag(self.immutable_ids),
+            "read_only" => flag(self.read_only),
+            "scopes" => self.scopes.as_ref().map(|s| s.join(" ")),
+            "token_store" => self.token_store.map(|store| {
+                serde_json::to_value(store)
+                    .ok()
+                    .and_then(|v| v.as_str().map(String::from))
+                    .unwrap_or_default()
+            }),
+            "client_cert" => self.client_cert.as_ref().map(|p| p.display().to_string()),
+            "managed_identity" => text(&self.managed_identity),
+            "broker_account" => text(&self.broker_account),
+            "on_new_message" => text(&self.on_new_message),
+            "post_archive" => text(&self.post_archive),
+            "pre_send" => text(&self.pre_send),
+            _ => unreachable!("check_key accepted an unhandled key"),
+        })
+    }
+
+    /// Validate and store a setting given as text
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        if value.is_empty() {
+            anyhow::bail!(
+                "Empty value for '{}'; use `outlook config unset {}`",
+                key,
+                key
+            );
+        }
+        match check_key(key)? {
+            "client_id" => {
+                if !is_guid(value) {
+                    anyhow::bail!("client_id must be an application ID (a GUID)");
+                }
+                self.client_id = Some(value.to_lowercase());
+            }
+            "tenant" => self.tenant = Some(crate::auth::parse_tenant(value)?),
+            "account" => {
+                let name = parse_account_name(value)?;
+                if !account_exists(&name) {
+                    anyhow::bail!(
+                        "No account named '{}'. Add it with `outlook account add {}`.",
+                        name,
+                        name
+                    );
+                }
+                self.default_account = Some(name);
+            }
+            "mailbox" => self.mailbox = Some(value.to_string()),
+            "folder" => self.folder = Some(value.to_string()),
+            "page_size" => {
+                let size: u32 = value
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("page_size must be a positive number"))?;
+                self.page_size = Some(size);
+            }
+            "output" => self.output = Some(one_of(key, value, OUTPUT_FORMATS)?),
+            "color" => self.color = Some(one_of(key, value, COLOR_CHOICES)?),
+            "proxy" => {
+                crate::api::parse_proxy(value)?;
+                self.proxy = Some(value.to_string());
+            }
+            "timeout" => self.timeout = Some(parse_number(key, value, 1)?),
+            "max_retries" => self.max_retries = Some(parse_number(key, value, 0)?),
+            "initial_backoff" => self.initial_backoff = Some(parse_number(key, value, 0)?),
+            "pager" => self.pager = Some(parse_bool(key, value)?),
+            "date_format" => {
+                use chrono::format::{Item, StrftimeItems};
+                if StrftimeItems::new(value).any(|item| matches!(item, Item::Error)) {
+                    anyhow::bail!(
+                        "Invalid date_format '{}' (use strftime, e.g. %d %b %H:%M)",
+                        value
+                    );
+                }
+                self.date_format = Some(value.to_string());
+            }
+            "read_marks_read" => self.read_marks_read = Some(parse_bool(key, value)?),
+            "text_body" => self.text_body = Some(parse_bool(key, value)?),
+            "immutable_ids" => self.immutable_ids = Some(parse_bool(key, value)?),
+            "read_only" => self.read_only = Some(parse_bool(key, value)?),
+            "scopes" => self.scopes = Some(value.split_whitespace().map(String::from).collect()),
+            "token_store" => {
+                let store = one_of(key, value, &["file", "keyring", "encrypted"])?;
+                self.token_store = Some(serde_json::from_value(serde_json::Value::String(store))?);
+            }
+            "client_cert" => {
+                let path = PathBuf::from(value);
+                if !path.is_file() {
+                    anyhow::bail!("No such file: {}", path.display());
+                }
+                self.client_cert = Some(std::path::absolute(path)?);
+            }
+            "managed_identity" => self.managed_identity = Some(value.to_string()),
+            "broker_account" => self.broker_account = Some(value.to_string()),
+            "on_new_message" => self.on_new_message = Some(value.to_string()),
+            "post_archive" => self.post_archive = Some(value.to_string()),
+            "pre_send" => self.pre_send = Some(value.to_string()),
+            _ => unreachable!("check_key accepted an unhandled key"),
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        match check_key(key)? {
+            "client_id" => self.client_id = None,
+            "tenant" => self.tenant = None,
+            "account" => self.default_account = None,
+            "mailbox" => self.mailbox = None,
+            "folder" => self.folder = None,
+            "page_size" => self.page_size = None,
+            "output" => self.output = None,
+            "color" => self.color = None,
+            "proxy" => self.proxy = None,
+            "timeout" => self.timeout = None,
+            "max_retries" => self.max_retries = None,
+            "initial_backoff" => self.initial_backoff = None,
+            "pager" => self.pager = None,
+            "date_format" => self.date_format = None,
+            "read_marks_read" => self.read_marks_read = None,
+            "text_body" => self.text_body = None,
+            "immutable_ids" => self.immutable_ids = None,
+            "read_only" => self.read_only = None,
+            "scopes" => self.scopes = None,
+            "token_store" => self.token_store = None,
+            "client_cert" => self.client_cert = None,
+            "managed_identity" => self.managed_identity = None,
+            "broker_account" => self.broker_account = None,
+            "on_new_message" => self.on_new_message = None,
+            "post_archive" => self.post_archive = None,
+            "pre_send" => self.pre_send = None,
+            _ => unreachable!("check_key accepted an unhandled key"),
+        }
+        Ok(())
+    }
+
+    /// Apply `OUTLOOK_CLI_<KEY>` overrides, validated like `config set`.
+    /// The default account is resolved separately by `default_account`.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        for (key, _) in KEYS.iter().filter(|(key, _)| *key != "account") {
+            let name = env_var_name(key);
+            if let Some(value) = var(&name).filter(|v| !v.trim().is_empty()) {
+                self.set(key, &value)
+                    .with_context(|| format!("Invalid {}", name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Environment variable that overrides a setting: "client_id" -> OUTLOOK_CLI_CLIENT_ID
+pub fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn check_key(key: &str) -> Result<&'static str> {
+    KEYS.iter()
+        .map(|(name, _)| *name)
+        .find(|name| name.eq_ignore_ascii_case(key))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown setting '{}'. `outlook config list` shows them all.",
+                key
+            )
+        })
+}
+
+fn one_of(key: &str, value: &str, allowed: &[&str]) -> Result<String> {
+    let value = value.to_lowercase();
+    if !allowed.contains(&value.as_str()) {
+        anyhow::bail!("{} must be one of: {}", key, allowed.join(", "));
+    }
+    Ok(value)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => anyhow::bail!("{} must be true or false", key),
+    }
+}
+
+fn parse_number<T>(key: &str, value: &str, min: u8) -> Result<T>
+where
+    T: std::str::FromStr + PartialOrd + From<u8>,
+{
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n >= T::from(min))
+        .ok_or_else(|| anyhow::anyhow!("{} must be a whole number of at least {}", key, min))
+}
+
+fn is_guid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Tokens {
     pub access_token: String,
     pub refresh_token: String,
+    /// When the access token expires (Unix seconds), if the server said
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
 }
 
-pub fn config_dir() -> PathBuf {
+impl Tokens {
+    /// Whether the access token expires within `margin` seconds. Unknown
+    /// expiry counts as fresh; a 401 triggers the refresh instead.
+    pub fn expires_within(&self, margin: i64) -> bool {
+        self.expires_at
+            .is_some_and(|at| at - chrono::Utc::now().timestamp() <= margin)
+    }
+}
+
+// tokens.json when token_store is "encrypted": the key comes from the
+// passphrase through Argon2id with a fresh salt on every save
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedTokens {
+    cipher: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+// Either form loads, so switching token_store doesn't need a new login
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenFile {
+    Encrypted(EncryptedTokens),
+    Plain(Tokens),
+}
+
+// Account profile in use; None for the top-level (unnamed) profile
+static ACCOUNT: RwLock<Option<String>> = RwLock::new(None);
+
+/// Point config, tokens and state at an account profile
+pub fn set_account(name: Option<String>) {
+    *ACCOUNT.write().unwrap() = name;
+}
+
+pub fn account() -> Option<String> {
+    ACCOUNT.read().unwrap().clone()
+}
+
+// "default" is the top-level profile's keyring entry, and `account switch
+// none` clears the default account
+const RESERVED_ACCOUNT_NAMES: &[&str] = &["default", "none"];
+
+/// Account names become directory names, so keep them simple
+pub fn parse_account_name(name: &str) -> Result<String> {
+    if RESERVED_ACCOUNT_NAMES.contains(&name.to_lowercase().as_str()) {
+        anyhow::bail!("'{}' is reserved; pick another account name", name);
+    }
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        anyhow::bail!(
+            "Invalid account name '{}' (use letters, digits, '-' and '_')",
+            name
+        );
+    }
+    Ok(name.to_string())
+}
+
+fn base_dir() -> PathBuf {
+    if let Some(dir) = env_var(CONFIG_DIR_ENV).filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
     dirs::config_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join("outlook-cli")
 }
 
+fn accounts_dir() -> PathBuf {
+    base_dir().join("accounts")
+}
+
+/// Directory holding config, tokens and state for the current account
+pub fn config_dir() -> PathBuf {
+    match account() {
+        Some(name) => accounts_dir().join(name),
+        None => base_dir(),
+    }
+}
+
 fn config_path() -> PathBuf {
-    config_dir().join("config.json")
+    config_file(&config_dir())
+}
+
+/// config.toml when the directory has one, otherwise config.json
+fn config_file(dir: &Path) -> PathBuf {
+    let toml = dir.join("config.toml");
+    if toml.exists() {
+        toml
+    } else {
+        dir.join("config.json")
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+pub fn account_exists(name: &str) -> bool {
+    accounts_dir().join(name).is_dir()
+}
+
+/// Names of every account profile, sorted
+pub fn list_accounts() -> Result<Vec<String>> {
+    let dir = accounts_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir()
+            && let Some(name) = entry.file_name().to_str()
+        {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Whether an account profile has saved tokens
+pub fn account_logged_in(name: &str) -> bool {
+    let dir = accounts_dir().join(name);
+    if dir.join("tokens.json").exists() {
+        return true;
+    }
+    let store = read_config(&config_file(&dir))
+        .ok()
+        .and_then(|c| c.token_store);
+    store == Some(TokenStore::Keyring)
+        && keyring_call(move || keyring_entry(Some(name))?.get_password()).is_ok()
+}
+
+/// OUTLOOK_CLI_ACCOUNT, or the `default_account` setting from the top-level config
+pub fn default_account() -> Result<Option<String>> {
+    let name = env_var_name("account");
+    if let Some(value) = env_var(&name).filter(|v| !v.trim().is_empty()) {
+        return parse_account_name(value.trim())
+            .map(Some)
+            .with_context(|| format!("Invalid {}", name));
+    }
+    Ok(read_config(&config_file(&base_dir()))?.default_account)
+}
+
+pub fn set_default_account(name: Option<String>) -> Result<()> {
+    let path = config_file(&base_dir());
+    let config = Config {
+        default_account: name,
+        ..read_config(&path)?
+    };
+    ensure_dir(&base_dir())?;
+    write_config(&path, &config)
 }
 
 pub fn tokens_path() -> PathBuf {
     config_dir().join("tokens.json")
 }
 
-fn write_secure(path: &PathBuf, content: &str) -> Result<()> {
-    let mut file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .mode(0o600)
-        .open(path)?;
-    file.write_all(content.as_bytes())?;
-    Ok(())
+/// Exclusive advisory lock on the saved tokens, released on drop
+pub struct TokenLock {
+    _file: fs::File,
+}
+
+/// Wait until no other `outlook` process is refreshing this account's tokens
+pub fn lock_tokens() -> Result<TokenLock> {
+    ensure_config_dir()?;
+    let file = secure::open(&config_dir().join("tokens.lock"), false)?;
+    file.lock().context("Failed to lock tokens")?;
+    Ok(TokenLock { _file: file })
 }
 
+fn write_secure(path: &Path, content: &str) -> Result<()> {
+    Ok(secure::write(path, content.as_bytes())?)
+}
+
+/// Settings in effect: the config file with environment overrides applied
 pub fn load_config() -> Result<Config> {
-    let path = config_path();
-    if path.exists() {
-        let content = fs::read_to_string(&path)?;
-        return Ok(serde_json::from_str(&content)?);
+    let mut config = load_saved_config()?;
+    config.apply_env(env_var)?;
+    Ok(config)
+}
+
+/// The config file alone, for changes that are saved back to it
+pub fn load_saved_config() -> Result<Config> {
+    read_config(&config_path())
+}
+
+fn read_config(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
     }
-    Ok(Config::default())
+    let content = fs::read_to_string(path)?;
+    let config = if is_toml(path) {
+        toml::from_str(&content).map_err(anyhow::Error::from)
+    } else {
+        serde_json::from_str(&content).map_err(anyhow::Error::from)
+    };
+    config.with_context(|| format!("Invalid config file {}", path.display()))
+}
+
+// Keeps whichever format the file is already in
+fn write_config(path: &Path, config: &Config) -> Result<()> {
+    let content = if is_toml(path) {
+        toml::to_string_pretty(config)?
+    } else {
+        serde_json::to_string_pretty(config)?
+    };
+    write_secure(path, &content)
 }
 
-fn ensure_config_dir() -> Result<PathBuf> {
+pub fn ensure_config_dir() -> Result<PathBuf> {
     let dir = config_dir();
-    if !dir.exists() {
-        fs::create_dir_all(&dir)?;
-        fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
-    }
+    ensure_dir(&dir)?;
     Ok(dir)
 }
 
+fn ensure_dir(dir: &Path) -> Result<()> {
+    Ok(secure::create_dir(dir)?)
+}
+
+/// Create the directory for an account profile
+pub fn create_account(name: &str) -> Result<()> {
+    ensure_dir(&accounts_dir())?;
+    ensure_dir(&accounts_dir().join(name))
+}
+
 pub fn save_config(config: &Config) -> Result<()> {
     ensure_config_dir()?;
-    write_secure(&config_path(), &serde_json::to_string_pretty(config)?)
+    write_config(&config_path(), config)
+}
+
+fn token_store() -> TokenStore {
+    load_config()
+        .ok()
+        .and_then(|c| c.token_store)
+        .unwrap_or_default()
+}
+
+// One keyring entry per account profile
+fn keyring_entry(account: Option<&str>) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, account.unwrap_or("default"))
+}
+
+// The Secret Service backend runs its own Tokio runtime, which can't start
+// on a thread that is already driving ours
+fn keyring_call<T: Send>(f: impl FnOnce() -> keyring::Result<T> + Send) -> keyring::Result<T> {
+    std::thread::scope(|s| s.spawn(f).join().expect("keyring thread panicked"))
 }
 
 pub fn load_tokens() -> Result<Tokens> {
+    if token_store() == TokenStore::Keyring {
+        let account = account();
+        match keyring_call(|| keyring_entry(account.as_deref())?.get_password()) {
+            Ok(secret) => return Ok(serde_json::from_str(&secret)?),
+            // Tokens saved before the switch, or while the keyring was unavailable
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => note!("Keyring unavailable ({}), trying the token file", e),
+        }
+    }
     let path = tokens_path();
     let content = fs::read_to_string(&path)?;
-    Ok(serde_json::from_str(&content)?)
+    match serde_json::from_str(&content)? {
+        TokenFile::Plain(tokens) => Ok(tokens),
+        TokenFile::Encrypted(encrypted) => decrypt_tokens(&encrypted, passphrase()?),
+    }
 }
 
 pub fn save_tokens(tokens: &Tokens) -> Result<()> {
+    let content = serde_json::to_string_pretty(tokens)?;
+    if token_store() == TokenStore::Keyring {
+        let account = account();
+        match keyring_call(|| keyring_entry(account.as_deref())?.set_password(&content)) {
+            Ok(()) => {
+                // Don't leave a stale plaintext copy behind
+                remove_file(&tokens_path())?;
+                return Ok(());
+            }
+            Err(e) => note!("Keyring unavailable ({}), saving tokens to a file", e),
+        }
+    }
+    let content = match token_store() {
+        TokenStore::Encrypted => {
+            serde_json::to_string_pretty(&encrypt_tokens(&content, passphrase()?)?)?
+        }
+        _ => content,
+    };
     ensure_config_dir()?;
-    write_secure(&tokens_path(), &serde_json::to_string_pretty(tokens)?)
+    write_secure(&tokens_path(), &content)
+}
+
+// Asked for at most once per run, so a token refresh doesn't prompt again
+fn passphrase() -> Result<&'static str> {
+    static PASSPHRASE: OnceLock<String> = OnceLock::new();
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase);
+    }
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) if std::io::IsTerminal::is_terminal(&std::io::stdin()) => {
+            rpassword::prompt_password("Token passphrase: ")?
+        }
+        Err(_) => anyhow::bail!(
+            "Tokens are encrypted; set {} to the passphrase",
+            PASSPHRASE_ENV
+        ),
+    };
+    if passphrase.is_empty() {
+        anyhow::bail!("The token passphrase can't be empty");
+    }
+    Ok(PASSPHRASE.get_or_init(|| passphrase))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+fn encrypt_tokens(plaintext: &str, passphrase: &str) -> Result<EncryptedTokens> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt tokens"))?;
+    Ok(EncryptedTokens {
+        cipher: "chacha20poly1305".to_string(),
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+fn decrypt_tokens(encrypted: &EncryptedTokens, passphrase: &str) -> Result<Tokens> {
+    if encrypted.cipher != "chacha20poly1305" {
+        anyhow::bail!("Unsupported token encryption '{}'", encrypted.cipher);
+    }
+    let salt = BASE64
+        .decode(&encrypted.salt)
+        .context("Corrupt token file")?;
+    let nonce = BASE64
+        .decode(&encrypted.nonce)
+        .context("Corrupt token file")?;
+    let ciphertext = BASE64
+        .decode(&encrypted.ciphertext)
+        .context("Corrupt token file")?;
+    if nonce.len() != 12 {
+        anyhow::bail!("Corrupt token file");
+    }
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow::anyhow!("Wrong token passphrase, or the token file is corrupt"))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+/// Whether a `load_tokens` error means there are no saved tokens at all,
+/// rather than tokens that couldn't be read or decrypted
+pub fn tokens_missing(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
+/// Delete the saved tokens; returns false if there were none
+pub fn remove_tokens() -> Result<bool> {
+    let mut removed = false;
+    if token_store() == TokenStore::Keyring {
+        let account = account();
+        match keyring_call(|| keyring_entry(account.as_deref())?.delete_credential()) {
+            Ok(()) => removed = true,
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => note!("Keyring unavailable ({}), tokens there were kept", e),
+        }
+    }
+    Ok(remove_file(&tokens_path())? || removed)
+}
+
+/// Delete a state file; returns false if it didn't exist
+pub fn remove_state(name: &str) -> Result<bool> {
+    remove_file(&config_dir().join(name))
+}
+
+fn remove_file(path: &PathBuf) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn load_state<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    let path = config_dir().join(name);
+    if path.exists() {
+        let content = fs::read_to_string(&path)?;
+        return Ok(serde_json::from_str(&content)?);
+    }
+    Ok(T::default())
+}
+
+pub fn save_state<T: Serialize>(name: &str, state: &T) -> Result<()> {
+    ensure_config_dir()?;
+    write_secure(
+        &config_dir().join(name),
+        &serde_json::to_string_pretty(state)?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_account_name() {
+        assert_eq!(parse_account_name("work_2").unwrap(), "work_2");
+        assert!(parse_account_name("").is_err());
+        assert!(parse_account_name("../tokens").is_err());
+        assert!(parse_account_name("a b").is_err());
+        assert!(parse_account_name("default").is_err());
+        assert!(parse_account_name("None").is_err());
+    }
+
+    #[test]
+    fn test_config_keys() {
+        let mut config = Config::default();
+        config.set("page_size", "25").unwrap();
+        config.set("Output", "CSV").unwrap();
+        config.set("pager", "off").unwrap();
+        config.set("token_store", "keyring").unwrap();
+        assert_eq!(config.get("page_size").unwrap().as_deref(), Some("25"));
+        assert_eq!(config.get("output").unwrap().as_deref(), Some("csv"));
+        assert_eq!(config.get("pager").unwrap().as_deref(), Some("false"));
+        assert_eq!(
+            config.get("token_store").unwrap().as_deref(),
+            Some("keyring")
+        );
+        assert_eq!(config.get("folder").unwrap(), None);
+
+        assert!(config.set("page_size", "0").is_err());
+        assert!(config.set("color", "sometimes").is_err());
+        assert!(config.set("client_id", "not-a-guid").is_err());
+        assert!(config.set("date_format", "%Q").is_err());
+        assert!(config.set("nonsense", "1").is_err());
+        assert!(config.set("proxy", "ftp://proxy:21").is_err());
+        assert!(config.set("proxy", "proxy.corp:8080").is_err());
+        config.set("proxy", "socks5h://127.0.0.1:1080").unwrap();
+        assert!(config.set("timeout", "0").is_err());
+        assert!(config.set("max_retries", "-1").is_err());
+        config.set("max_retries", "0").unwrap();
+        assert_eq!(config.max_retries, Some(0));
+
+        config.unset("page_size").unwrap();
+        assert_eq!(config.page_size, None);
+        // Every key is handled, so none of them can hit unreachable!
+        for (key, _) in KEYS {
+            config.get(key).unwrap();
+            config.unset(key).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config: Config = toml::from_str("page_size = 20\noutput = \"csv\"").unwrap();
+        let env = |name: &str| match name {
+            "OUTLOOK_CLI_OUTPUT" => Some("json".to_string()),
+            "OUTLOOK_CLI_PAGER" => Some("no".to_string()),
+            "OUTLOOK_CLI_FOLDER" => Some(" ".to_string()),
+            _ => None,
+        };
+        config.apply_env(env).unwrap();
+        assert_eq!(config.page_size, Some(20));
+        assert_eq!(config.output.as_deref(), Some("json"));
+        assert_eq!(config.pager, Some(false));
+        assert_eq!(config.folder, None);
+
+        let bad = |name: &str| (name == "OUTLOOK_CLI_PAGE_SIZE").then(|| "lots".to_string());
+        let err = config.apply_env(bad).unwrap_err();
+        assert!(err.to_string().contains("OUTLOOK_CLI_PAGE_SIZE"));
+        assert_eq!(env_var_name("client_id"), "OUTLOOK_CLI_CLIENT_ID");
+    }
+
+    #[test]
+    fn test_expires_within() {
+        let now = chrono::Utc::now().timestamp();
+        let tokens = |expires_at| Tokens {
+            access_token: "a".to_string(),
+            refresh_token: "r".to_string(),
+            expires_at,
+        };
+        assert!(tokens(Some(now + 60)).expires_within(300));
+        assert!(tokens(Some(now - 60)).expires_within(300));
+        assert!(!tokens(Some(now + 3600)).expires_within(300));
+        assert!(!tokens(None).expires_within(300));
+    }
+
+    #[test]
+    fn test_encrypt_tokens() {
+        let plaintext = r#"{"access_token":"a","refresh_token":"r"}"#;
+        let encrypted = encrypt_tokens(plaintext, "correct horse").unwrap();
+        assert!(!encrypted.ciphertext.contains("refresh"));
+
+        let json = serde_json::to_string(&encrypted).unwrap();
+        let TokenFile::Encrypted(encrypted) = serde_json::from_str(&json).unwrap() else {
+            panic!("expected the encrypted form");
+        };
+        let tokens = decrypt_tokens(&encrypted, "correct horse").unwrap();
+        assert_eq!(tokens.refresh_token, "r");
+        assert!(decrypt_tokens(&encrypted, "wrong").is_err());
+    }
+
+    #[test]
+    fn test_tokens_missing() {
+        let missing = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(tokens_missing(&missing));
+        let denied = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(!tokens_missing(&denied));
+
+        let encrypted = encrypt_tokens(r#"{"access_token":"a"}"#, "correct horse").unwrap();
+        let wrong = decrypt_tokens(&encrypted, "wrong").unwrap_err();
+        assert!(!tokens_missing(&wrong));
+    }
 }
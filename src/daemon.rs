@@ -0,0 +1,252 @@
+// `outlook daemon`: JSON-RPC 2.0 over a unix socket, one request per line.
+// The daemon holds a signed-in Graph client, so callers such as editor
+// plugins and status bars skip sign-in and TLS setup on every query.
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::api;
+use crate::config;
+
+const SOCKET_FILE: &str = "daemon.sock";
+
+// Standard JSON-RPC error codes, plus one for failed Graph requests
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const GRAPH_ERROR: i64 = -32000;
+
+/// Default socket: daemon.sock in the (account's) config directory, which
+/// only the current user can enter
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(config::ensure_config_dir()?.join(SOCKET_FILE))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    jsonrpc: Option<String>,
+    /// Absent for notifications, which get no response
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl Request {
+    /// Deserialize the params, or fail with INVALID_PARAMS
+    pub fn params<T: serde::de::DeserializeOwned>(&self) -> Result<T, RpcError> {
+        let params = match &self.params {
+            Value::Null => Value::Object(Default::default()),
+            params => params.clone(),
+        };
+        serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut error = serde_json::json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        error
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        let graph = err
+            .chain()
+            .find_map(|e| e.downcast_ref::<api::GraphError>());
+        Self {
+            code: GRAPH_ERROR,
+            message: format!("{:#}", err),
+            data: graph.map(|e| {
+                serde_json::json!({
+                    "status": e.status,
+                    "code": e.code,
+                    "requestId": e.request_id,
+                })
+            }),
+        }
+    }
+}
+
+/// Answers one request
+pub type Handler =
+    Arc<dyn Fn(Request) -> BoxFuture<'static, Result<Value, RpcError>> + Send + Sync>;
+
+/// The response line for one request line, or None for a notification
+pub async fn respond(line: &str, handler: &Handler) -> Option<Value> {
+    let request: Request = match serde_json::from_str::<Value>(line) {
+        Err(e) => {
+            return Some(reply(
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, e.to_string())),
+            ));
+        }
+        Ok(value) => {
+            let id = value.get("id").cloned().unwrap_or(Value::Null);
+            match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => {
+                    return Some(reply(
+                        id,
+                        Err(RpcError::new(INVALID_REQUEST, e.to_string())),
+                    ));
+                }
+            }
+        }
+    };
+    if request.jsonrpc.as_deref() != Some("2.0") {
+        let id = request.id.unwrap_or(Value::Null);
+        return Some(reply(
+            id,
+            Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+        ));
+    }
+    let id = request.id.clone();
+    let result = handler(request).await;
+    id.map(|id| reply(id, result))
+}
+
+fn reply(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": e.to_json() }),
+    }
+}
+
+/// Listen on `path` until killed, answering each connection's requests in order
+#[cfg(unix)]
+pub async fn serve(path: &Path, handler: Handler) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", path.display());
+        }
+        if UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!("A daemon is already listening on {}", path.display());
+        }
+        // Left behind by a daemon that didn't shut down cleanly
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    // Create the socket as 0600 rather than tightening it after bind, which
+    // would leave it open to other users for a moment
+    // SAFETY: umask only swaps the process file mode mask
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    // SAFETY: as above, restoring the previous mask
+    unsafe { libc::umask(umask) };
+    let listener = listener.with_context(|| format!("Failed to listen on {}", path.display()))?;
+    eprintln!("Listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(response) = respond(&line, &handler).await {
+                    let mut out = response.to_string();
+                    out.push('\n');
+                    if writer.write_all(out.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn serve(_path: &Path, _handler: Handler) -> Result<()> {
+    anyhow::bail!("outlook daemon needs unix sockets, which this platform lacks")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo() -> Handler {
+        Arc::new(|request: Request| {
+            Box::pin(async move {
+                match request.method.as_str() {
+                    "echo" => Ok(request.params),
+                    "fail" => Err(anyhow::anyhow!("boom").into()),
+                    method => Err(RpcError::new(METHOD_NOT_FOUND, method)),
+                }
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_respond() {
+        let handler = echo();
+        let ok = respond(
+            r#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[7]}"#,
+            &handler,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ok,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": [7] })
+        );
+
+        let missing = respond(r#"{"jsonrpc":"2.0","id":"a","method":"nope"}"#, &handler)
+            .await
+            .unwrap();
+        assert_eq!(missing["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(missing["id"], "a");
+
+        let failed = respond(r#"{"jsonrpc":"2.0","id":2,"method":"fail"}"#, &handler)
+            .await
+            .unwrap();
+        assert_eq!(failed["error"]["code"], GRAPH_ERROR);
+
+        let garbled = respond("{not json", &handler).await.unwrap();
+        assert_eq!(garbled["error"]["code"], PARSE_ERROR);
+        assert_eq!(garbled["id"], Value::Null);
+
+        let old = respond(r#"{"id":3,"method":"echo"}"#, &handler)
+            .await
+            .unwrap();
+        assert_eq!(old["error"]["code"], INVALID_REQUEST);
+
+        // Notifications get no response
+        assert!(
+            respond(r#"{"jsonrpc":"2.0","method":"echo"}"#, &handler)
+                .await
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use anyhow::Result;
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, SecondsFormat, TimeZone, Utc,
+    Weekday,
+};
+use std::sync::OnceLock;
+
+// Time of day used when only a day is given ("tomorrow", "monday")
+const DEFAULT_HOUR: u32 = 9;
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// How timestamps are shown: `relative` ("2h ago"), or local time in a
+/// strftime `format`
+pub struct Display {
+    pub relative: bool,
+    pub format: String,
+}
+
+static DISPLAY: OnceLock<Display> = OnceLock::new();
+
+pub fn set_display(display: Display) {
+    let _ = DISPLAY.set(display);
+}
+
+/// Parse an age such as "12h", "30d", "2w" or "1y" into a duration
+pub fn parse_age(age: &str) -> Result<Duration> {
+    let age = age.trim();
+    let split = age
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow::anyhow!("Missing unit in age '{}' (use h, d, w or y)", age))?;
+    let (number, unit) = age.split_at(split);
+    let n: i64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid age '{}'", age))?;
+
+    match unit {
+        "h" => Ok(Duration::hours(n)),
+        "d" => Ok(Duration::days(n)),
+        "w" => Ok(Duration::weeks(n)),
+        "y" => Ok(Duration::days(n * 365)),
+        _ => anyhow::bail!("Unknown unit in age '{}' (use h, d, w or y)", age),
+    }
+}
+
+/// The point in time `age` ago
+pub fn cutoff(age: &str) -> Result<DateTime<Utc>> {
+    Ok(Utc::now() - parse_age(age)?)
+}
+
+/// Format a timestamp for use in OData filters
+pub fn odata_timestamp(dt: DateTime<Utc>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Parse a Graph timestamp such as `receivedDateTime`
+pub fn parse_graph_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// A timestamp for people: relative or in the configured local format
+pub fn display(dt: DateTime<Utc>) -> String {
+    match DISPLAY.get() {
+        Some(d) if d.relative => relative(dt),
+        Some(d) => format_local(dt, &d.format),
+        None => format_local(dt, DEFAULT_FORMAT),
+    }
+}
+
+/// Like `display`, for a Graph timestamp; unparseable values pass through
+pub fn display_graph(value: &str) -> String {
+    parse_graph_time(value).map_or_else(|| value.to_string(), display)
+}
+
+fn format_local(dt: DateTime<Utc>, format: &str) -> String {
+    use std::fmt::Write;
+    // An invalid pattern makes Display fail; fall back to the default
+    let local = dt.with_timezone(&Local);
+    let mut out = String::new();
+    match write!(out, "{}", local.format(format)) {
+        Ok(()) => out,
+        Err(_) => local.format(DEFAULT_FORMAT).to_string(),
+    }
+}
+
+/// A time relative to now, such as "3h ago" or "in 52m"
+pub fn relative(dt: DateTime<Utc>) -> String {
+    relative_from(dt, Local::now())
+}
+
+/// "just now", "5m ago", "3h ago", "yesterday", "4 days ago", then a date;
+/// future times read "in 5m", "in 3h", "tomorrow", "in 4 days"
+fn relative_from<Tz: TimeZone>(dt: DateTime<Utc>, now: DateTime<Tz>) -> String {
+    let tz = now.timezone();
+    let local = dt.with_timezone(&tz);
+    let delta = now.clone().with_timezone(&Utc) - dt;
+    let future = delta < Duration::zero();
+    let span = delta.abs();
+    let days = (now.date_naive() - local.date_naive()).num_days();
+
+    let text = if span < Duration::minutes(1) {
+        return "just now".to_string();
+    } else if span < Duration::hours(1) {
+        format!("{}m", span.num_minutes())
+    } else if span < Duration::hours(12) || days == 0 {
+        format!("{}h", span.num_hours())
+    } else if days == 1 {
+        return "yesterday".to_string();
+    } else if days == -1 {
+        return "tomorrow".to_string();
+    } else if days.abs() < 7 {
+        format!("{} days", days.abs())
+    } else {
+        return local.date_naive().format("%Y-%m-%d").to_string();
+    };
+    if future {
+        format!("in {}", text)
+    } else {
+        format!("{} ago", text)
+    }
+}
+
+/// Parse a past point in time: "7d", "today", "yesterday", "monday",
+/// "last monday", "last week", "2024-06-01" or an RFC 3339 timestamp.
+/// Days resolve to local midnight.
+pub fn parse_past(input: &str) -> Result<DateTime<Utc>> {
+    parse_past_from(input, Local::now())
+}
+
+fn parse_past_from<Tz: TimeZone>(input: &str, now: DateTime<Tz>) -> Result<DateTime<Utc>> {
+    let input = input.trim().to_lowercase();
+    if let Ok(age) = parse_age(&input) {
+        return Ok((now - age).with_timezone(&Utc));
+    }
+    if let Some(dt) = parse_graph_time(&input.to_uppercase()) {
+        return Ok(dt);
+    }
+
+    let today = now.date_naive();
+    let day = input.strip_prefix("last ").unwrap_or(&input);
+    let date = match day {
+        "today" => today,
+        "yesterday" => today - Duration::days(1),
+        "week" if input != day => {
+            let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+            monday - Duration::days(7)
+        }
+        _ => match day.parse::<Weekday>() {
+            Ok(weekday) => previous_weekday(today, weekday),
+            Err(_) => NaiveDate::parse_from_str(day, "%Y-%m-%d")
+                .map_err(|_| anyhow::anyhow!("Unrecognized date '{}'", input))?,
+        },
+    };
+    at_time(&now.timezone(), date, NaiveTime::MIN)
+}
+
+/// Parse a future point in time: "2h", "3d", "tomorrow 9am", "monday",
+/// "friday 17:30", "next week", "2024-06-01 8am" or an RFC 3339 timestamp
+pub fn parse_when(input: &str) -> Result<DateTime<Utc>> {
+    parse_when_from(input, Local::now())
+}
+
+fn parse_when_from<Tz: TimeZone>(input: &str, now: DateTime<Tz>) -> Result<DateTime<Utc>> {
+    let input = input.trim().to_lowercase();
+    if let Ok(age) = parse_age(&input) {
+        return Ok((now + age).with_timezone(&Utc));
+    }
+    if let Some(dt) = parse_graph_time(&input.to_uppercase()) {
+        return Ok(dt);
+    }
+
+    let (day, time) = match input.split_once(' ') {
+        Some((day, time)) => (day, Some(time)),
+        None => (input.as_str(), None),
+    };
+    let today = now.date_naive();
+
+    let date = match day {
+        "today" => Some(today),
+        "tomorrow" => Some(today + Duration::days(1)),
+        "next" if time == Some("week") => {
+            let monday = today + Duration::days(7 - today.weekday().num_days_from_monday() as i64);
+            return at_time(&now.timezone(), monday, default_time());
+        }
+        _ => match day.parse::<Weekday>() {
+            Ok(weekday) => Some(next_weekday(today, weekday)),
+            Err(_) => NaiveDate::parse_from_str(day, "%Y-%m-%d").ok(),
+        },
+    };
+
+    match (date, time) {
+        (Some(date), time) => {
+            let time = match time {
+                Some(t) => parse_time_of_day(t)?,
+                None => default_time(),
+            };
+            at_time(&now.timezone(), date, time)
+        }
+        // A bare time of day means its next occurrence
+        (None, None) => {
+            let time = parse_time_of_day(day)?;
+            let when = at_time(&now.timezone(), today, time)?;
+            if when > now.with_timezone(&Utc) {
+                Ok(when)
+            } else {
+                at_time(&now.timezone(), today + Duration::days(1), time)
+            }
+        }
+        (None, Some(_)) => anyhow::bail!("Unrecognized time '{}'", input),
+    }
+}
+
+fn default_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(DEFAULT_HOUR, 0, 0).unwrap()
+}
+
+fn next_weekday(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let ahead = (weekday.num_days_from_monday() as i64
+        - from.weekday().num_days_from_monday() as i64)
+        .rem_euclid(7);
+    from + Duration::days(if ahead == 0 { 7 } else { ahead })
+}
+
+fn previous_weekday(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let behind = (from.weekday().num_days_from_monday() as i64
+        - weekday.num_days_from_monday() as i64)
+        .rem_euclid(7);
+    from - Duration::days(if behind == 0 { 7 } else { behind })
+}
+
+/// Parse "9am", "9:30pm", "17:00" or "17"
+fn parse_time_of_day(input: &str) -> Result<NaiveTime> {
+    let input = input.trim();
+    let (clock, offset) = if let Some(t) = input.strip_suffix("am") {
+        (t, Some(0))
+    } else if let Some(t) = input.strip_suffix("pm") {
+        (t, Some(12))
+    } else {
+        (input, None)
+    };
+    let (hour, minute) = match clock.trim().split_once(':') {
+        Some((h, m)) => (h.parse::<u32>()?, m.parse::<u32>()?),
+        None => (clock.trim().parse::<u32>()?, 0),
+    };
+    let hour = match offset {
+        Some(_) if hour == 0 || hour > 12 => anyhow::bail!("Invalid time '{}'", input),
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid time '{}'", input))
+}
+
+fn at_time<Tz: TimeZone>(tz: &Tz, date: NaiveDate, time: NaiveTime) -> Result<DateTime<Utc>> {
+    tz.from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok_or_else(|| anyhow::anyhow!("Time does not exist in the local timezone"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wednesday 2024-05-15 10:00 UTC
+    fn now() -> DateTime<Utc> {
+        parse_graph_time("2024-05-15T10:00:00Z").unwrap()
+    }
+
+    fn when(input: &str) -> String {
+        odata_timestamp(parse_when_from(input, now()).unwrap())
+    }
+
+    #[test]
+    fn test_relative() {
+        let rel = |value: &str| relative_from(parse_graph_time(value).unwrap(), now());
+        assert_eq!(rel("2024-05-15T09:59:30Z"), "just now");
+        assert_eq!(rel("2024-05-15T09:15:00Z"), "45m ago");
+        assert_eq!(rel("2024-05-15T07:00:00Z"), "3h ago");
+        assert_eq!(rel("2024-05-15T12:00:00Z"), "in 2h");
+        assert_eq!(rel("2024-05-14T08:00:00Z"), "yesterday");
+        assert_eq!(rel("2024-05-11T10:00:00Z"), "4 days ago");
+        assert_eq!(rel("2024-05-17T10:00:00Z"), "in 2 days");
+        assert_eq!(rel("2024-04-01T10:00:00Z"), "2024-04-01");
+    }
+
+    #[test]
+    fn test_parse_when() {
+        assert_eq!(when("2h"), "2024-05-15T12:00:00Z");
+        assert_eq!(when("tomorrow 9am"), "2024-05-16T09:00:00Z");
+        assert_eq!(when("tomorrow"), "2024-05-16T09:00:00Z");
+        assert_eq!(when("friday 5:30pm"), "2024-05-17T17:30:00Z");
+        assert_eq!(when("wednesday"), "2024-05-22T09:00:00Z");
+        assert_eq!(when("next week"), "2024-05-20T09:00:00Z");
+        assert_eq!(when("8am"), "2024-05-16T08:00:00Z");
+        assert_eq!(when("17:00"), "2024-05-15T17:00:00Z");
+        assert_eq!(when("2024-06-01 8am"), "2024-06-01T08:00:00Z");
+        assert!(parse_when_from("someday", now()).is_err());
+    }
+
+    fn past(input: &str) -> String {
+        odata_timestamp(parse_past_from(input, now()).unwrap())
+    }
+
+    #[test]
+    fn test_parse_past() {
+        assert_eq!(past("7d"), "2024-05-08T10:00:00Z");
+        assert_eq!(past("today"), "2024-05-15T00:00:00Z");
+        assert_eq!(past("yesterday"), "2024-05-14T00:00:00Z");
+        assert_eq!(past("last monday"), "2024-05-13T00:00:00Z");
+        assert_eq!(past("wednesday"), "2024-05-08T00:00:00Z");
+        assert_eq!(past("last week"), "2024-05-06T00:00:00Z");
+        assert_eq!(past("2024-01-31"), "2024-01-31T00:00:00Z");
+        assert!(parse_past_from("whenever", now()).is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_age("2w").unwrap(), Duration::weeks(2));
+        assert_eq!(parse_age("12h").unwrap(), Duration::hours(12));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("5x").is_err());
+    }
+
+    #[test]
+    fn test_odata_timestamp() {
+        let dt = parse_graph_time("2024-01-02T03:04:05Z").unwrap();
+        assert_eq!(odata_timestamp(dt), "2024-01-02T03:04:05Z");
+    }
+}
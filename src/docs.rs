@@ -0,0 +1,241 @@
+// `outlook docs`: man pages and markdown generated from the clap definitions,
+// one page per command, so packaged documentation can't drift from --help.
+
+use anyhow::{Context, Result};
+use clap::{Arg, Command};
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Every visible command, parents before children, with display names
+/// such as "outlook-labels-list" filled in
+fn pages(cmd: Command) -> Vec<Command> {
+    fn walk(cmd: Command, pages: &mut Vec<Command>) {
+        let children: Vec<Command> = cmd
+            .get_subcommands()
+            .filter(|s| !s.is_hide_set())
+            .cloned()
+            .collect();
+        pages.push(cmd);
+        for child in children {
+            walk(child, pages);
+        }
+    }
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    let mut pages = Vec::new();
+    walk(cmd, &mut pages);
+    pages
+}
+
+fn file_stem(cmd: &Command) -> &str {
+    cmd.get_display_name().unwrap_or(cmd.get_name())
+}
+
+/// Write a man page per command into `dir`; returns how many
+pub fn write_man_pages(cmd: Command, dir: &Path) -> Result<usize> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let pages = pages(cmd);
+    for page in &pages {
+        man(page.clone()).generate_to(dir)?;
+    }
+    Ok(pages.len())
+}
+
+/// The top-level man page, e.g. for `outlook docs man | man -l -`
+pub fn render_man_page(cmd: Command, out: &mut dyn std::io::Write) -> Result<()> {
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    man(cmd).render(out)?;
+    Ok(())
+}
+
+fn man(cmd: Command) -> clap_mangen::Man {
+    clap_mangen::Man::new(cmd).source(format!("outlook {}", env!("CARGO_PKG_VERSION")))
+}
+
+/// Write a markdown page per command into `dir`; returns how many
+pub fn write_markdown_pages(cmd: Command, dir: &Path) -> Result<usize> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let pages = pages(cmd);
+    for page in &pages {
+        let path = dir.join(format!("{}.md", file_stem(page)));
+        std::fs::write(&path, markdown(page))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(pages.len())
+}
+
+/// Every command's markdown page, one after another
+pub fn render_markdown(cmd: Command) -> String {
+    pages(cmd)
+        .iter()
+        .map(markdown)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn markdown(cmd: &Command) -> String {
+    let mut md = String::new();
+    let title = file_stem(cmd).replace('-', " ");
+    let _ = writeln!(md, "# {}\n", title);
+    if let Some(about) = cmd.get_long_about().or(cmd.get_about()) {
+        let _ = writeln!(md, "{}\n", about);
+    }
+    let usage = cmd.clone().render_usage().to_string();
+    let usage = usage.strip_prefix("Usage: ").unwrap_or(&usage);
+    let _ = writeln!(md, "## Usage\n\n```\n{}\n```\n", usage);
+
+    // Global options are documented once, on the top-level page
+    let top_level = cmd
+        .get_display_name()
+        .is_none_or(|name| name == cmd.get_name());
+    let visible = |arg: &&Arg| {
+        !arg.is_hide_set()
+            && (top_level || !arg.is_global_set())
+            && !matches!(arg.get_id().as_str(), "help" | "version")
+    };
+    let positionals: Vec<&Arg> = cmd.get_positionals().filter(visible).collect();
+    if !positionals.is_empty() {
+        md.push_str("## Arguments\n\n");
+        for arg in positionals {
+            let name = arg
+                .get_value_names()
+                .map(|names| names.join(" "))
+                .unwrap_or_else(|| arg.get_id().to_string());
+            let _ = writeln!(md, "- `<{}>`{}", name, describe(arg));
+        }
+        md.push('\n');
+    }
+    let options: Vec<&Arg> = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_positional())
+        .filter(visible)
+        .collect();
+    if !options.is_empty() {
+        md.push_str("## Options\n\n");
+        for arg in options {
+            let _ = writeln!(md, "- `{}`{}", option_name(arg), describe(arg));
+        }
+        md.push('\n');
+    }
+    let subcommands: Vec<&Command> = cmd.get_subcommands().filter(|s| !s.is_hide_set()).collect();
+    if !subcommands.is_empty() {
+        md.push_str("## Commands\n\n");
+        for sub in subcommands {
+            let _ = writeln!(
+                md,
+                "- [`{}`]({}.md){}",
+                file_stem(sub).replace('-', " "),
+                file_stem(sub),
+                sub.get_about()
+                    .map(|about| format!(": {}", about))
+                    .unwrap_or_default()
+            );
+        }
+        md.push('\n');
+    }
+    md
+}
+
+fn option_name(arg: &Arg) -> String {
+    let mut name = match (arg.get_short(), arg.get_long()) {
+        (Some(short), Some(long)) => format!("-{}, --{}", short, long),
+        (Some(short), None) => format!("-{}", short),
+        (None, Some(long)) => format!("--{}", long),
+        (None, None) => arg.get_id().to_string(),
+    };
+    if arg.get_action().takes_values() {
+        let value = arg
+            .get_value_names()
+            .and_then(|names| names.first())
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| arg.get_id().to_string());
+        let _ = write!(name, " <{}>", value);
+    }
+    name
+}
+
+/// ": help [default: x] [possible values: a, b]", or nothing without help
+fn describe(arg: &Arg) -> String {
+    let mut text = arg
+        .get_long_help()
+        .or(arg.get_help())
+        .map(|help| help.to_string().replace('\n', " "))
+        .unwrap_or_default();
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|v| v.to_string_lossy().into_owned())
+        .collect();
+    if !defaults.is_empty() && arg.get_action().takes_values() {
+        let _ = write!(text, " [default: {}]", defaults.join(", "));
+    }
+    let possible: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect();
+    if !possible.is_empty() && arg.get_action().takes_values() {
+        let _ = write!(text, " [possible values: {}]", possible.join(", "));
+    }
+    let text = text.trim();
+    if text.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli() -> Command {
+        Command::new("tool")
+            .about("Does things")
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .global(true)
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Say more"),
+            )
+            .subcommand(
+                Command::new("fetch")
+                    .about("Fetch items")
+                    .arg(Arg::new("id").required(true).help("Item ID"))
+                    .arg(
+                        Arg::new("format")
+                            .long("format")
+                            .short('f')
+                            .value_parser(["json", "text"])
+                            .default_value("text")
+                            .help("Output format"),
+                    ),
+            )
+            .subcommand(Command::new("secret").hide(true))
+    }
+
+    #[test]
+    fn test_markdown_pages() {
+        let pages = pages(cli());
+        let names: Vec<&str> = pages.iter().map(file_stem).collect();
+        assert_eq!(names, ["tool", "tool-fetch"]);
+
+        let top = markdown(&pages[0]);
+        assert!(top.starts_with("# tool\n\nDoes things\n"));
+        assert!(top.contains("- `--verbose`: Say more"));
+        assert!(top.contains("- [`tool fetch`](tool-fetch.md): Fetch items"));
+        assert!(!top.contains("secret"));
+
+        let fetch = markdown(&pages[1]);
+        assert!(fetch.contains("```\ntool fetch [OPTIONS] <id>\n```"));
+        assert!(fetch.contains("- `<id>`: Item ID"));
+        assert!(fetch.contains(
+            "- `-f, --format <format>`: Output format [default: text] [possible values: json, text]"
+        ));
+        // Globals are only listed on the top-level page
+        assert!(!fetch.contains("--verbose"));
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::api::Message;
+use crate::config;
+
+const FILTERS_FILE: &str = "filters.toml";
+const STATE_FILE: &str = "filters_state.json";
+
+// One [[filter]] table in filters.toml. Every condition given must match.
+#[derive(Debug, Deserialize)]
+pub struct Filter {
+    pub name: String,
+    /// Sender address or domain
+    pub from: Option<String>,
+    /// Regex matched against the subject
+    pub subject: Option<String>,
+    /// Text contained in the List-Id header
+    pub list_id: Option<String>,
+    /// Header name -> regex matched against its value
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    #[serde(default)]
+    pub label: Vec<String>,
+    #[serde(default)]
+    pub mark_read: bool,
+    #[serde(default)]
+    pub archive: bool,
+    pub move_to: Option<String>,
+    /// Skip later filters once this one matches
+    #[serde(default)]
+    pub stop: bool,
+}
+
+// Scheduled cleanups run by `outlook triage`, from the [triage] table
+#[derive(Debug, Default, Deserialize)]
+pub struct TriageConfig {
+    /// Move Junk mail older than this to Deleted Items (e.g. "30d")
+    pub expire_junk: Option<String>,
+    /// Archive read inbox mail older than this (e.g. "14d")
+    pub archive_read: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FilterFile {
+    #[serde(default, rename = "filter")]
+    filters: Vec<Filter>,
+    #[serde(default)]
+    triage: TriageConfig,
+    #[serde(default)]
+    expire: BTreeMap<String, String>,
+}
+
+pub struct FilterConfig {
+    pub filters: Vec<CompiledFilter>,
+    pub triage: TriageConfig,
+    /// Folder -> maximum age, from the [expire] table
+    pub expire: BTreeMap<String, String>,
+}
+
+pub struct CompiledFilter {
+    pub filter: Filter,
+    subject: Option<Regex>,
+    headers: Vec<(String, Regex)>,
+}
+
+// Combined outcome of every filter matching one message
+#[derive(Debug, Default, PartialEq)]
+pub struct Actions {
+    pub matched: Vec<String>,
+    pub labels: Vec<String>,
+    pub mark_read: bool,
+    /// Folder name or ID; "archive" for archive actions
+    pub destination: Option<String>,
+}
+
+// What a `filter run` did, for scripts and the triage report
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub scanned: usize,
+    pub matched: usize,
+    pub updated: usize,
+    pub moved: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FilterState {
+    pub last_run: Option<String>,
+}
+
+pub fn filters_path() -> PathBuf {
+    config::config_dir().join(FILTERS_FILE)
+}
+
+/// filters.toml, or no filters and cleanups when there isn't one
+pub fn load() -> Result<FilterConfig> {
+    let path = filters_path();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    parse(&content)
+}
+
+pub fn parse(content: &str) -> Result<FilterConfig> {
+    let file: FilterFile = toml::from_str(content).context("Invalid filters file")?;
+    Ok(FilterConfig {
+        filters: file
+            .filters
+            .into_iter()
+            .map(compile)
+            .collect::<Result<_>>()?,
+        triage: file.triage,
+        expire: file.expire,
+    })
+}
+
+fn compile(filter: Filter) -> Result<CompiledFilter> {
+    if filter.from.is_none()
+        && filter.subject.is_none()
+        && filter.list_id.is_none()
+        && filter.headers.is_empty()
+    {
+        anyhow::bail!("Filter '{}' has no conditions", filter.name);
+    }
+    if filter.label.is_empty() && !filter.mark_read && !filter.archive && filter.move_to.is_none() {
+        anyhow::bail!("Filter '{}' has no actions", filter.name);
+    }
+
+    let subject = filter
+        .subject
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .with_context(|| format!("Invalid subject regex in filter '{}'", filter.name))?;
+    let headers = filter
+        .headers
+        .iter()
+        .map(|(name, pattern)| Ok((name.clone(), Regex::new(pattern)?)))
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("Invalid header regex in filter '{}'", filter.name))?;
+
+    Ok(CompiledFilter {
+        filter,
+        subject,
+        headers,
+    })
+}
+
+impl Actions {
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        for label in &self.labels {
+            parts.push(format!("label {}", label));
+        }
+        if self.mark_read {
+            parts.push("mark read".to_string());
+        }
+        if let Some(dest) = &self.destination {
+            parts.push(format!("move to {}", dest));
+        }
+        parts.join(", ")
+    }
+}
+
+impl CompiledFilter {
+    pub fn matches(&self, msg: &Message) -> bool {
+        if let Some(from) = &self.filter.from
+            && !msg.is_from(from)
+        {
+            return false;
+        }
+        if let Some(re) = &self.subject
+            && !re.is_match(msg.subject.as_deref().unwrap_or(""))
+        {
+            return false;
+        }
+        if let Some(list_id) = &self.filter.list_id {
+            let header = msg.get_header("List-Id").unwrap_or("").to_lowercase();
+            if !header.contains(&list_id.to_lowercase()) {
+                return false;
+            }
+        }
+        self.headers
+            .iter()
+            .all(|(name, re)| msg.get_header(name).is_some_and(|v| re.is_match(v)))
+    }
+}
+
+/// Run every filter against a message, merging the actions of all matches
+pub fn evaluate(filters: &[CompiledFilter], msg: &Message) -> Actions {
+    let mut actions = Actions::default();
+    for compiled in filters {
+        if !compiled.matches(msg) {
+            continue;
+        }
+        let filter = &compiled.filter;
+        actions.matched.push(filter.name.clone());
+        for label in &filter.label {
+            if !actions.labels.iter().any(|l| l.eq_ignore_ascii_case(label)) {
+                actions.labels.push(label.clone());
+            }
+        }
+        actions.mark_read |= filter.mark_read;
+        if actions.destination.is_none() {
+            actions.destination = if filter.archive {
+                Some("archive".to_string())
+            } else {
+                filter.move_to.clone()
+            };
+        }
+        if filter.stop {
+            break;
+        }
+    }
+    actions
+}
+
+pub fn load_state() -> Result<FilterState> {
+    config::load_state(STATE_FILE)
+}
+
+pub fn save_state(state: &FilterState) -> Result<()> {
+    config::save_state(STATE_FILE, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, subject: &str, list_id: Option<&str>) -> Message {
+        let headers: Vec<serde_json::Value> = list_id
+            .map(|id| vec![serde_json::json!({ "name": "List-Id", "value": id })])
+            .unwrap_or_default();
+        serde_json::from_value(serde_json::json!({
+            "id": "m1",
+            "subject": subject,
+            "from": { "emailAddress": { "address": from } },
+            "internetMessageHeaders": headers,
+        }))
+        .unwrap()
+    }
+
+    const FILTERS: &str = r#"
+[[filter]]
+name = "jira"
+from = "jira@example.com"
+subject = "^\\[JIRA\\]"
+label = ["Jira"]
+mark_read = true
+
+[[filter]]
+name = "lists"
+list_id = "dev.lists.example.com"
+archive = true
+stop = true
+
+[[filter]]
+name = "never"
+list_id = "dev.lists.example.com"
+move_to = "Other"
+"#;
+
+    #[test]
+    fn test_evaluate_merges_matches() {
+        let filters = parse(FILTERS).unwrap().filters;
+        let msg = message(
+            "jira@example.com",
+            "[JIRA] PROJ-1 updated",
+            Some("<dev.lists.example.com>"),
+        );
+        let actions = evaluate(&filters, &msg);
+        assert_eq!(actions.matched, vec!["jira", "lists"]);
+        assert_eq!(actions.labels, vec!["Jira"]);
+        assert!(actions.mark_read);
+        assert_eq!(actions.destination.as_deref(), Some("archive"));
+    }
+
+    #[test]
+    fn test_evaluate_no_match() {
+        let filters = parse(FILTERS).unwrap().filters;
+        let msg = message("jira@example.com", "Weekly digest", None);
+        assert_eq!(evaluate(&filters, &msg), Actions::default());
+    }
+
+    #[test]
+    fn test_parse_triage() {
+        // What load() uses when there's no filters.toml
+        let empty = parse("").unwrap();
+        assert!(empty.filters.is_empty() && empty.expire.is_empty());
+        assert_eq!(empty.triage.expire_junk, None);
+
+        let config = parse("[triage]\nexpire_junk = \"30d\"\n").unwrap();
+        assert!(config.filters.is_empty());
+        assert_eq!(config.triage.expire_junk.as_deref(), Some("30d"));
+        assert_eq!(config.triage.archive_read, None);
+
+        let config = parse("[expire]\njunk = \"14d\"\ntrash = \"30d\"\n").unwrap();
+        let expire: Vec<_> = config.expire.into_iter().collect();
+        assert_eq!(
+            expire,
+            vec![
+                ("junk".to_string(), "14d".to_string()),
+                ("trash".to_string(), "30d".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter_requires_condition() {
+        let err = parse("[[filter]]\nname = \"x\"\narchive = true\n")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no conditions"));
+    }
+}
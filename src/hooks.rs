@@ -0,0 +1,144 @@
+// User commands run at points in a message's life: when one arrives
+// (`serve`), after it's archived, and before the CLI sends mail. Each runs
+// through the shell once per message, with the message as JSON on stdin.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Config;
+use crate::status;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    /// A new inbox message was announced by a push notification
+    OnNewMessage,
+    /// A message was moved to the Archive folder
+    PostArchive,
+    /// Mail is about to be sent; a failing command stops it
+    PreSend,
+}
+
+impl Hook {
+    /// The config key holding the hook's command
+    pub fn key(self) -> &'static str {
+        match self {
+            Hook::OnNewMessage => "on_new_message",
+            Hook::PostArchive => "post_archive",
+            Hook::PreSend => "pre_send",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Commands {
+    on_new_message: Option<String>,
+    post_archive: Option<String>,
+    pre_send: Option<String>,
+}
+
+static COMMANDS: OnceLock<Commands> = OnceLock::new();
+
+/// Take hook commands from the config; set once at startup
+pub fn set_commands(config: &Config) {
+    let _ = COMMANDS.set(Commands {
+        on_new_message: config.on_new_message.clone(),
+        post_archive: config.post_archive.clone(),
+        pre_send: config.pre_send.clone(),
+    });
+}
+
+/// The command configured for `hook`, if any. None under `--dry-run`,
+/// where nothing a hook would react to happens.
+pub fn command(hook: Hook) -> Option<&'static str> {
+    if status::dry_run() {
+        return None;
+    }
+    let commands = COMMANDS.get()?;
+    match hook {
+        Hook::OnNewMessage => commands.on_new_message.as_deref(),
+        Hook::PostArchive => commands.post_archive.as_deref(),
+        Hook::PreSend => commands.pre_send.as_deref(),
+    }
+}
+
+/// Run `hook` with `message` as JSON on stdin, if a command is configured.
+/// Fails when the command can't start or exits unsuccessfully.
+pub async fn run(hook: Hook, message: &impl Serialize) -> Result<()> {
+    match command(hook) {
+        Some(command) => exec(hook, command, message).await,
+        None => Ok(()),
+    }
+}
+
+async fn exec(hook: Hook, command: &str, message: &impl Serialize) -> Result<()> {
+    let input = serde_json::to_vec(message)?;
+    let mut child = shell(command)
+        .env("OUTLOOK_HOOK", hook.key())
+        .stdin(Stdio::piped())
+        // Keep the hook's output off stdout, which may be --json output
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("Failed to run {} hook: {}", hook.key(), command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that doesn't read its input closes the pipe early; that's fine
+        let _ = stdin.write_all(&input).await;
+    }
+    let output = child.wait_with_output().await?;
+    eprint!("{}", String::from_utf8_lossy(&output.stdout));
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} hook failed ({}): {}",
+            hook.key(),
+            output.status,
+            command
+        );
+    }
+    Ok(())
+}
+
+/// Run `hook` for each message, reporting failures without stopping
+pub async fn run_each<T: Serialize>(hook: Hook, messages: &[T]) {
+    if command(hook).is_none() {
+        return;
+    }
+    for message in messages {
+        if let Err(e) = run(hook, message).await {
+            eprintln!("{:#}", e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> tokio::process::Command {
+    let mut shell = tokio::process::Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> tokio::process::Command {
+    let mut shell = tokio::process::Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exec() {
+        let message = serde_json::json!({ "id": "AAMk" });
+        let check = r#"[ "$OUTLOOK_HOOK" = pre_send ] && [ "$(cat)" = '{"id":"AAMk"}' ]"#;
+        exec(Hook::PreSend, check, &message).await.unwrap();
+
+        let err = exec(Hook::PreSend, "exit 3", &message).await.unwrap_err();
+        assert!(err.to_string().starts_with("pre_send hook failed"));
+        // Hooks that ignore their input still succeed
+        exec(Hook::PostArchive, "true", &message).await.unwrap();
+    }
+}
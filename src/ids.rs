@@ -0,0 +1,161 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::config;
+
+pub const SHORT_IDS_FILE: &str = "short_ids.json";
+const SHORT_LEN: usize = 8;
+// Aliases not shown for this many newer messages are forgotten
+const MAX_ENTRIES: usize = 2000;
+
+/// Local mapping from short aliases to full Graph message IDs.
+///
+/// Aliases are a hash of the ID, so the same message always gets the same
+/// alias; a colliding alias falls back to the full 16-character hash. Only
+/// the most recently shown MAX_ENTRIES are kept.
+#[derive(Default)]
+pub struct ShortIds {
+    /// (alias, ID) pairs, least recently shown first
+    entries: Vec<(String, String)>,
+    dirty: bool,
+}
+
+impl ShortIds {
+    pub fn load() -> Result<Self> {
+        let entries = match config::load_state(SHORT_IDS_FILE)? {
+            serde_json::Value::Null => Vec::new(),
+            // Written before the cap, as an unordered alias -> ID map
+            value @ serde_json::Value::Object(_) => {
+                serde_json::from_value::<HashMap<String, String>>(value)?
+                    .into_iter()
+                    .collect()
+            }
+            value => serde_json::from_value(value)?,
+        };
+        Ok(Self {
+            entries,
+            dirty: false,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.dirty {
+            config::save_state(SHORT_IDS_FILE, &self.entries)?;
+        }
+        Ok(())
+    }
+
+    /// Alias for a message ID, recording it for later lookups
+    pub fn shorten(&mut self, id: &str) -> String {
+        let hash = format!("{:016x}", fnv1a(id));
+        let short = &hash[..SHORT_LEN];
+        let alias = match self.get(short) {
+            Some(existing) if existing != id => hash.clone(),
+            _ => short.to_string(),
+        };
+        // Move it to the recent end, unless it's already there
+        if self
+            .entries
+            .last()
+            .is_none_or(|(a, i)| *a != alias || i != id)
+        {
+            self.entries.retain(|(a, _)| *a != alias);
+            self.entries.push((alias.clone(), id.to_string()));
+            let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+            self.entries.drain(..excess);
+            self.dirty = true;
+        }
+        alias
+    }
+
+    fn get(&self, alias: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(a, _)| a == alias)
+            .map(|(_, id)| id.as_str())
+    }
+
+    /// Full ID for an alias; anything else is returned unchanged. An
+    /// alias-shaped argument that isn't recorded is an UnknownShortId.
+    pub fn resolve(&self, arg: &str) -> Result<String> {
+        match self.get(&arg.to_lowercase()) {
+            Some(id) => Ok(id.to_string()),
+            None if is_alias(arg) => Err(UnknownShortId(arg.to_string()).into()),
+            None => Ok(arg.to_string()),
+        }
+    }
+}
+
+/// A short ID that no listing has recorded, e.g. from another machine
+#[derive(Debug)]
+pub struct UnknownShortId(pub String);
+
+impl std::fmt::Display for UnknownShortId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unknown short ID '{}'; run 'outlook list' to refresh them",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownShortId {}
+
+// Aliases are 8 or 16 hex digits; Graph IDs are far longer
+fn is_alias(arg: &str) -> bool {
+    (arg.len() == SHORT_LEN || arg.len() == 16) && arg.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// FNV-1a: tiny, and stable across Rust versions unlike DefaultHasher
+fn fnv1a(input: &str) -> u64 {
+    input.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shorten_is_stable() {
+        let mut ids = ShortIds::default();
+        let alias = ids.shorten("AAMkAGI2TG93AAA=");
+        assert_eq!(alias.len(), SHORT_LEN);
+        assert_eq!(ids.shorten("AAMkAGI2TG93AAA="), alias);
+        assert_eq!(
+            ids.resolve(&alias.to_uppercase()).unwrap(),
+            "AAMkAGI2TG93AAA="
+        );
+        assert_eq!(ids.resolve("AAMkOther").unwrap(), "AAMkOther");
+        let unknown = ids.resolve("0123abcd").unwrap_err();
+        assert!(unknown.is::<UnknownShortId>());
+    }
+
+    #[test]
+    fn test_shorten_collision() {
+        let mut ids = ShortIds::default();
+        let alias = ids.shorten("first");
+        ids.entries
+            .push((alias.clone(), "someone-else".to_string()));
+        let fallback = ids.shorten("first");
+        assert_eq!(fallback.len(), 16);
+        assert_eq!(ids.resolve(&fallback).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_keeps_most_recent() {
+        let mut ids = ShortIds::default();
+        let oldest = ids.shorten("message-0");
+        for i in 1..=MAX_ENTRIES {
+            ids.shorten(&format!("message-{}", i));
+        }
+        // Shown again, so it's now the most recent
+        let recent = ids.shorten("message-1");
+        assert!(ids.resolve(&oldest).is_err());
+        assert_eq!(ids.entries.last().unwrap().0, recent);
+        assert_eq!(ids.entries.len(), MAX_ENTRIES);
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::Result;
+
+use crate::dates;
+
+// Message properties Outlook's KQL search understands
+const PROPERTIES: &[&str] = &[
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "participants",
+    "subject",
+    "body",
+    "attachment",
+    "hasattachments",
+    "received",
+    "sent",
+    "importance",
+    "kind",
+    "size",
+    "category",
+];
+
+// Comparison operators, longest first so ">=" wins over ">"
+const OPERATORS: &[&str] = &[">=", "<=", ":", "=", ">", "<"];
+
+/// Check and normalize a search query, e.g.
+/// `from:alice subject:"invoice" hasattachment:yes received>=2024-01-01`.
+///
+/// Free text passes through unchanged, and `word:value` with a word that
+/// isn't a property is searched for as a phrase. Known properties need a
+/// valid value, `hasattachment:yes` becomes `hasattachments:true`, and date
+/// values accept the same forms as `--since` ("7d", "last monday").
+pub fn normalize(query: &str) -> Result<String> {
+    let terms = tokenize(query)?
+        .into_iter()
+        .map(|token| normalize_term(&token))
+        .collect::<Result<Vec<_>>>()?;
+    if terms.is_empty() {
+        anyhow::bail!("Empty search query");
+    }
+    Ok(terms.join(" "))
+}
+
+/// Split on whitespace outside double quotes, keeping the quotes
+fn tokenize(query: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        anyhow::bail!("Unterminated quote in search query");
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+fn normalize_term(token: &str) -> Result<String> {
+    // Leading '-' negates a term in KQL
+    let (negation, term) = match token.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", token),
+    };
+
+    let name_end = term
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(term.len());
+    let (name, rest) = term.split_at(name_end);
+    let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+        return Ok(token.to_string());
+    };
+    let value = &rest[op.len()..];
+    // Not a property: nothing before the operator, or a URL
+    if name.is_empty() || value.starts_with("//") {
+        return Ok(token.to_string());
+    }
+
+    let property = match name.to_lowercase().as_str() {
+        "hasattachment" => "hasattachments".to_string(),
+        other => other.to_string(),
+    };
+    // Ordinary text that happens to contain a colon ("Re:", ticket:123);
+    // quoted so KQL searches for it instead of rejecting the property
+    if !PROPERTIES.contains(&property.as_str()) {
+        return Ok(format!("{}\"{}\"", negation, term.replace('"', "")));
+    }
+    if value.is_empty() {
+        anyhow::bail!("Missing value for '{}'", name);
+    }
+
+    let value = match property.as_str() {
+        "hasattachments" => match value.to_lowercase().as_str() {
+            "yes" | "true" => "true".to_string(),
+            "no" | "false" => "false".to_string(),
+            _ => anyhow::bail!("hasattachments takes yes or no, not '{}'", value),
+        },
+        "received" | "sent" => normalize_date(value)?,
+        _ => value.to_string(),
+    };
+    Ok(format!("{}{}{}{}", negation, property, op, value))
+}
+
+fn normalize_date(value: &str) -> Result<String> {
+    let unquoted = value.trim_matches('"');
+    // Dates and KQL keywords such as received:today pass through
+    if unquoted.len() == 10 && chrono::NaiveDate::parse_from_str(unquoted, "%Y-%m-%d").is_ok() {
+        return Ok(unquoted.to_string());
+    }
+    match dates::parse_past(unquoted) {
+        Ok(dt) => Ok(dt
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d")
+            .to_string()),
+        Err(_) => Ok(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(
+                "from:alice subject:\"monthly invoice\" hasattachment:yes received>=2024-01-01"
+            )
+            .unwrap(),
+            "from:alice subject:\"monthly invoice\" hasattachments:true received>=2024-01-01"
+        );
+        assert_eq!(normalize("budget  report").unwrap(), "budget report");
+        assert_eq!(normalize("-From:bob").unwrap(), "-from:bob");
+        assert_eq!(
+            normalize("https://example.com").unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(normalize("Re: budget").unwrap(), "\"Re:\" budget");
+        assert_eq!(normalize("-ticket:123").unwrap(), "-\"ticket:123\"");
+    }
+
+    #[test]
+    fn test_normalize_errors() {
+        assert!(normalize("subject:").is_err());
+        assert!(normalize("subject:\"unterminated").is_err());
+        assert!(normalize("hasattachments:maybe").is_err());
+        assert!(normalize("   ").is_err());
+    }
+}
@@ -1,3 +1,33 @@
+//! Microsoft Graph mail client behind the `outlook` command-line tool.
+//!
+//! - [`auth`] signs in (browser, device code, client credentials, managed
+//!   identity or the Windows broker) and refreshes the cached tokens.
+//! - [`config`] loads and saves settings and tokens from the config directory.
+//! - [`api`] is the Graph client: folders, messages, categories, rules,
+//!   subscriptions and `$batch` bulk actions, with retries and throttling.
+//!
+//! ```no_run
+//! use futures::TryStreamExt;
+//! use outlook_graph::{api, config};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! // Tokens saved by `outlook login`
+//! let tokens = config::load_tokens()?;
+//! let client = api::Client::new(&tokens.access_token, api::RetryPolicy::default());
+//! let mut unread = std::pin::pin!(client.messages_stream(Some("inbox"), Some("isRead eq false")));
+//! while let Some(msg) = unread.try_next().await? {
+//!     println!("{}", msg.subject.unwrap_or_default());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Settings that apply to every request (`--mailbox`, proxy, retry policy,
+//! immutable IDs) are process-wide and set with the `api::set_*` functions.
+
 pub mod api;
 pub mod auth;
 pub mod config;
+pub mod secure;
+pub mod status;
+pub mod telemetry;
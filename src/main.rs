@@ -1,9 +1,35 @@
-mod api;
-mod auth;
-mod config;
+mod color;
+mod completions;
+mod daemon;
+mod dates;
+mod docs;
+mod filters;
+mod hooks;
+mod ids;
+mod kql;
+mod pager;
+mod pick;
+mod stats;
+mod table;
+mod template;
+mod tui;
+mod undo;
+mod unsubscribe;
+mod update;
+mod webhook;
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use outlook_graph::{api, auth, config, secure, status, telemetry};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::builder::PossibleValuesParser;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::ArgValueCandidates;
+use status::{info, note};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(name = "outlook")]
@@ -13,100 +39,1047 @@ struct Cli {
     #[arg(long, global = true)]
     json: bool,
 
+    /// Output format for listings [default: text, or output in config]
+    #[arg(long, global = true, value_enum)]
+    output: Option<Output>,
+
+    /// Use a shared or delegated mailbox instead of your own
+    #[arg(long, global = true)]
+    mailbox: Option<String>,
+
+    /// Account profile to use instead of the default (see `outlook account`)
+    #[arg(long, global = true, value_parser = config::parse_account_name)]
+    account: Option<String>,
+
+    /// Color output: auto (terminal and no NO_COLOR), always or never
+    /// [default: auto, or "color" in config]
+    #[arg(long, global = true, value_enum)]
+    color: Option<ColorChoice>,
+
+    /// Show dates relative to now ("2h ago", "yesterday")
+    #[arg(long, global = true)]
+    relative: bool,
+
+    /// Only print results and errors, not confirmations or progress
+    #[arg(long, global = true)]
+    quiet: bool,
+
+    /// Print the changes a command would make without making them
+    #[arg(long, global = true, visible_alias = "preview")]
+    dry_run: bool,
+
+    /// Don't ask before deleting or clearing many messages; needed to do so
+    /// when there's no terminal to ask on
+    #[arg(short = 'y', long, global = true)]
+    yes: bool,
+
+    /// Log each Graph request to stderr: method, URL, status, request-id and
+    /// timing. Repeat (-vv) to include headers and bodies; tokens are redacted
+    #[arg(short = 'v', long = "debug", global = true, action = clap::ArgAction::Count)]
+    debug: u8,
+
+    /// Don't page `list` and `read` output
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// Maximum number of requests in flight during bulk operations
+    #[arg(long, global = true, default_value_t = api::DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+
+    /// Seconds before a request gives up [default: 30, or "timeout" in config]
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Retries for failed or throttled requests [default: 3, or "max_retries" in config]
+    #[arg(long, global = true, value_name = "N")]
+    max_retries: Option<u32>,
+
+    /// Milliseconds before the first retry, doubled each time
+    /// [default: 1000, or "initial_backoff" in config]
+    #[arg(long, global = true, value_name = "MS")]
+    initial_backoff: Option<u64>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum DocsFormat {
+    /// roff man pages (outlook.1, outlook-list.1, ...)
+    Man,
+    /// Markdown pages (outlook.md, outlook-list.md, ...)
+    Markdown,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    /// One `id | from | subject` line per item
+    Text,
+    /// Aligned columns sized to the terminal
+    Table,
+    /// Comma-separated values with a header row
+    Csv,
+    /// Tab-separated values with a header row
+    Tsv,
+    /// Same as --json
+    Json,
+    /// One JSON object per line, printed as each page arrives
+    Ndjson,
+}
+
+impl Output {
+    fn separator(self) -> Option<char> {
+        match self {
+            Output::Csv => Some(','),
+            Output::Tsv => Some('\t'),
+            _ => None,
+        }
+    }
+}
+
+// Messages `list` shows without -n or page_size in config
+const DEFAULT_LIST_SIZE: u32 = 100;
+
+#[derive(Args)]
+struct ListArgs {
+    /// Maximum number of messages to show [default: 100, or page_size in config]
+    #[arg(short = 'n', long)]
+    max: Option<u32>,
+    /// Search query: free text or KQL (from:alice subject:"invoice" received>=7d)
+    #[arg(short, long, value_parser = kql::normalize)]
+    query: Option<String>,
+    /// Folder to filter by (inbox, sent, drafts, archive, trash, spam)
+    /// [default: inbox, or folder in config]
+    #[arg(short, long, add = ArgValueCandidates::new(completions::well_known_folders))]
+    label: Option<String>,
+    /// Show only unread messages
+    #[arg(short, long)]
+    unread: bool,
+    /// Show only the Focused inbox tab
+    #[arg(long, conflicts_with_all = ["other", "query"])]
+    focused: bool,
+    /// Show only the Other inbox tab
+    #[arg(long, conflicts_with = "query")]
+    other: bool,
+    /// Group messages into one line per conversation
+    #[arg(long, conflicts_with = "format")]
+    threads: bool,
+    /// Show only messages with attachments
+    #[arg(long)]
+    has_attachments: bool,
+    /// Extra OData $filter expression, combined with the other filters
+    #[arg(long, conflicts_with = "query")]
+    filter: Option<String>,
+    /// Only messages with this category
+    #[arg(long, add = ArgValueCandidates::new(completions::categories))]
+    category: Option<String>,
+    /// Only messages from this address or domain
+    #[arg(long)]
+    from: Option<String>,
+    /// Only messages received on or after this time ("7d", "last monday", "2024-06-01")
+    #[arg(long, value_parser = dates::parse_past)]
+    since: Option<DateTime<Utc>>,
+    /// Only messages received before this time
+    #[arg(long, value_parser = dates::parse_past)]
+    before: Option<DateTime<Utc>>,
+    /// Print each message with a template, e.g. "{date:%Y-%m-%d} {from:<30} {subject}"
+    #[arg(long, value_parser = parse_message_format)]
+    format: Option<template::Template>,
+    /// Only fetch and print these fields, e.g. "id,subject,webLink,size"
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = PossibleValuesParser::new(MESSAGE_FIELDS.iter().copied()),
+        conflicts_with_all = ["format", "threads"]
+    )]
+    fields: Vec<String>,
+    /// Sort order (size is sorted locally within the fetched messages)
+    #[arg(long, value_enum)]
+    sort: Option<SortKey>,
+    /// Sort ascending (default for from, subject)
+    #[arg(long, conflicts_with = "desc")]
+    asc: bool,
+    /// Sort descending (default for date, size)
+    #[arg(long)]
+    desc: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SortKey {
+    Date,
+    From,
+    Subject,
+    Size,
+}
+
+impl SortKey {
+    fn property(self) -> Option<&'static str> {
+        match self {
+            SortKey::Date => Some("receivedDateTime"),
+            SortKey::From => Some("from/emailAddress/name"),
+            SortKey::Subject => Some("subject"),
+            SortKey::Size => None,
+        }
+    }
+}
+
+impl ListArgs {
+    fn max(&self) -> u32 {
+        self.max.unwrap_or(DEFAULT_LIST_SIZE)
+    }
+
+    fn descending(&self) -> bool {
+        match (self.asc, self.desc) {
+            (true, _) => false,
+            (_, true) => true,
+            _ => matches!(self.sort, Some(SortKey::Date | SortKey::Size)),
+        }
+    }
+
+    /// Server-side $orderby for the chosen sort, if Graph supports it
+    fn order_by(&self) -> Option<String> {
+        let property = self.sort?.property()?;
+        let direction = if self.descending() { "desc" } else { "asc" };
+        Some(format!("{} {}", property, direction))
+    }
+
+    /// Whether a message falls within --since/--before
+    fn in_range(&self, msg: &api::Message) -> bool {
+        let received = msg
+            .received_date_time
+            .as_deref()
+            .and_then(dates::parse_graph_time);
+        match received {
+            Some(at) => {
+                self.since.is_none_or(|since| at >= since)
+                    && self.before.is_none_or(|before| at < before)
+            }
+            None => self.since.is_none() && self.before.is_none(),
+        }
+    }
+
+    /// $search can't be combined with $filter, so search results are
+    /// checked against the filtering flags locally
+    fn matches_search_result(&self, msg: &api::Message) -> bool {
+        self.in_range(msg)
+            && self.from.as_deref().is_none_or(|from| msg.is_from(from))
+            && (!self.has_attachments || msg.has_attachments == Some(true))
+            && self.category.as_deref().is_none_or(|wanted| {
+                msg.categories
+                    .iter()
+                    .flatten()
+                    .any(|c| c.eq_ignore_ascii_case(wanted))
+            })
+    }
+
+    /// The fields --fields or --format print
+    fn shown_fields(&self) -> Vec<&str> {
+        match &self.format {
+            Some(template) => template.fields().collect(),
+            None => self.fields.iter().map(String::as_str).collect(),
+        }
+    }
+
+    /// $select for --fields or --format, plus the properties local
+    /// filtering and sorting read
+    fn select(&self) -> Option<String> {
+        let fields = self.shown_fields();
+        if fields.is_empty() {
+            return None;
+        }
+        let mut properties = vec!["id"];
+        properties.extend(fields.into_iter().filter_map(field_property));
+        if self.query.is_some() {
+            properties.extend(["receivedDateTime", "from", "hasAttachments", "categories"]);
+        }
+        if let Some(property) = self.sort.and_then(SortKey::property) {
+            properties.extend(property.split('/').next());
+        }
+        properties.sort_unstable();
+        properties.dedup();
+        Some(properties.join(","))
+    }
+
+    fn include_size(&self) -> bool {
+        self.sort == Some(SortKey::Size) || self.shown_fields().contains(&"size")
+    }
+
+    /// Sort to apply after fetching: $search results can't be ordered
+    /// server-side, and neither can size
+    fn local_sort(&self) -> Option<SortKey> {
+        self.sort
+            .filter(|key| self.query.is_some() || key.property().is_none())
+    }
+
+    /// OData $filter combining every filtering flag
+    fn filter(&self) -> Option<String> {
+        let mut clauses = Vec::new();
+        if let Some(since) = self.since {
+            clauses.push(format!(
+                "receivedDateTime ge {}",
+                dates::odata_timestamp(since)
+            ));
+        }
+        if let Some(before) = self.before {
+            clauses.push(format!(
+                "receivedDateTime lt {}",
+                dates::odata_timestamp(before)
+            ));
+        }
+        if let Some(from) = &self.from {
+            clauses.push(sender_clause(from));
+        }
+        if self.unread {
+            clauses.push("isRead eq false".to_string());
+        }
+        if self.has_attachments {
+            clauses.push("hasAttachments eq true".to_string());
+        }
+        if let Some(category) = &self.category {
+            clauses.push(category_clause(category));
+        }
+        if self.focused {
+            clauses.push("inferenceClassification eq 'focused'".to_string());
+        }
+        if self.other {
+            clauses.push("inferenceClassification eq 'other'".to_string());
+        }
+        if let Some(raw) = &self.filter {
+            clauses.push(format!("({})", raw));
+        }
+        // Graph rejects a $filter that doesn't start with the $orderby
+        // property (InefficientFilter), so lead with an always-true clause
+        let anchor = match self.sort {
+            Some(SortKey::Date) if self.since.is_none() => {
+                Some("receivedDateTime ge 1900-01-01T00:00:00Z")
+            }
+            Some(SortKey::From) if !clauses.is_empty() => Some("from/emailAddress/name ne null"),
+            Some(SortKey::Subject) if !clauses.is_empty() => Some("subject ne null"),
+            _ => None,
+        };
+        if let Some(anchor) = anchor {
+            clauses.insert(0, anchor.to_string());
+        }
+        (!clauses.is_empty()).then(|| clauses.join(" and "))
+    }
+}
+
+/// OData clause matching an exact sender address, or any address at a domain
+fn sender_clause(sender: &str) -> String {
+    let sender = sender.trim().to_lowercase().replace('\'', "''");
+    match sender.strip_prefix('@') {
+        Some(domain) => format!("endswith(from/emailAddress/address, '@{}')", domain),
+        None if sender.contains('@') => format!("from/emailAddress/address eq '{}'", sender),
+        None => format!("endswith(from/emailAddress/address, '@{}')", sender),
+    }
+}
+
+/// OData filter for messages tagged with a category
+fn category_clause(category: &str) -> String {
+    format!("categories/any(c:c eq '{}')", category.replace('\'', "''"))
+}
+
+/// Sort fetched messages locally, for searches and properties Graph can't order by
+fn sort_messages(messages: &mut [api::Message], key: SortKey, descending: bool) {
+    match key {
+        SortKey::Date => messages.sort_by(|a, b| a.received_date_time.cmp(&b.received_date_time)),
+        SortKey::From => messages.sort_by_key(|m| m.get_from().unwrap_or_default().to_lowercase()),
+        SortKey::Subject => {
+            messages.sort_by_key(|m| m.subject.clone().unwrap_or_default().to_lowercase())
+        }
+        SortKey::Size => messages.sort_by_key(|m| m.get_size().unwrap_or(0)),
+    }
+    if descending {
+        messages.reverse();
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Classification {
+    Focused,
+    Other,
+}
+
+impl Classification {
+    fn as_str(self) -> &'static str {
+        match self {
+            Classification::Focused => "focused",
+            Classification::Other => "other",
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RsvpResponse {
+    Accept,
+    Tentative,
+    Decline,
+}
+
+impl RsvpResponse {
+    /// The Graph event action for this response
+    fn action(self) -> &'static str {
+        match self {
+            RsvpResponse::Accept => "accept",
+            RsvpResponse::Tentative => "tentativelyAccept",
+            RsvpResponse::Decline => "decline",
+        }
+    }
+}
+
+/// Select messages by search query and/or age instead of by ID
+#[derive(Args)]
+struct QueryArgs {
+    /// Act on every message matching this search query (free text or KQL)
+    #[arg(long, value_parser = kql::normalize)]
+    query: Option<String>,
+    /// Only messages received more than this long ago (e.g. 12h, 30d, 2w)
+    #[arg(long)]
+    older_than: Option<String>,
+}
+
+impl QueryArgs {
+    fn is_set(&self) -> bool {
+        self.query.is_some() || self.older_than.is_some()
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SweepTarget {
+    Archive,
+    Trash,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportKind {
+    Junk,
+    Phishing,
+    #[value(name = "notjunk")]
+    NotJunk,
+}
+
+#[derive(Subcommand)]
+enum StatsAction {
+    /// Bytes per folder and the largest messages, for quota cleanup
+    Size {
+        /// Only this folder (default: the whole mailbox)
+        #[arg(long, add = ArgValueCandidates::new(completions::folders))]
+        folder: Option<String>,
+        /// How many of the largest messages to list
+        #[arg(short = 'n', long, default_value = "10")]
+        top: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print one setting
+    Get { key: String },
+    /// Change a setting (checked before it is saved)
+    Set { key: String, value: String },
+    /// Go back to a setting's default
+    Unset { key: String },
+    /// Every setting with its value
+    List,
+}
+
+#[derive(Subcommand)]
+enum AccountAction {
+    /// Create an account profile and log in to it
+    Add {
+        /// Profile name, e.g. "work"
+        #[arg(value_parser = config::parse_account_name)]
+        name: String,
+        /// Use device code flow
+        #[arg(long, short)]
+        device: bool,
+        /// Tenant ID or domain to sign in to
+        #[arg(long, value_parser = auth::parse_tenant)]
+        tenant: Option<String>,
+        /// Make this the default account
+        #[arg(long)]
+        default: bool,
+    },
+    /// List account profiles (* marks the default)
+    List,
+    /// Make an account the default for commands without `--account`
+    Switch {
+        /// Profile name; "none" goes back to the top-level login
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContactsAction {
+    /// Addresses from From/To/Cc on every message, most frequent first
+    Extract {
+        /// How far back to look (e.g. 1y, 90d, 2024-01-01)
+        #[arg(long, default_value = "1y")]
+        since: String,
+        /// Only the first N addresses
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Save a message's sender as a contact
+    Add {
+        /// Message whose From name and address to save
+        #[arg(long)]
+        from_message: String,
+        /// Also add the sender to the safe-senders list
+        #[arg(long)]
+        trust: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SafelistAction {
+    /// Trust a sender address or domain (also unblocks it)
+    Add {
+        /// Sender address or domain
+        address: String,
+    },
+    /// Stop trusting a sender
+    Remove {
+        /// Sender address or domain
+        address: String,
+    },
+    /// Show trusted senders
+    Show,
+    /// Move Junk messages from trusted senders back to the inbox
+    Rescue,
+}
+
+#[derive(Subcommand)]
+enum LabelsAction {
+    /// List categories (the default)
+    List,
+    /// Create a category
+    Create {
+        /// Category name
+        name: String,
+        /// Color: a name such as "teal" or "darkblue", preset0-preset24, or none
+        #[arg(long, value_parser = color::parse_preset)]
+        color: Option<String>,
+    },
+    /// Rename a category, re-tagging every message that carries it
+    Rename {
+        /// Current name
+        #[arg(add = ArgValueCandidates::new(completions::categories))]
+        old: String,
+        /// New name
+        new: String,
+    },
+    /// Count the messages carrying each category, per folder
+    Stats,
+    /// Delete a category
+    Delete {
+        /// Category name
+        #[arg(add = ArgValueCandidates::new(completions::categories))]
+        name: String,
+        /// Also remove it from every message that carries it
+        #[arg(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum RulesAction {
+    /// List inbox rules
+    List,
+    /// Create an inbox rule
+    Create {
+        /// Rule name
+        name: String,
+        /// Match messages from this address (repeatable)
+        #[arg(long)]
+        from: Vec<String>,
+        /// Match messages whose subject contains this text (repeatable)
+        #[arg(long)]
+        subject_contains: Vec<String>,
+        /// Move matching messages to this folder
+        #[arg(long, add = ArgValueCandidates::new(completions::folders))]
+        move_to: Option<String>,
+        /// Add this category to matching messages (repeatable)
+        #[arg(long, add = ArgValueCandidates::new(completions::categories))]
+        label: Vec<String>,
+        /// Mark matching messages as read
+        #[arg(long)]
+        mark_read: bool,
+        /// Don't run later rules on matching messages
+        #[arg(long)]
+        stop: bool,
+    },
+    /// Delete an inbox rule
+    Delete {
+        /// Rule ID or name
+        rule: String,
+    },
+    /// Enable an inbox rule
+    Enable {
+        /// Rule ID or name
+        rule: String,
+    },
+    /// Disable an inbox rule
+    Disable {
+        /// Rule ID or name
+        rule: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FilterAction {
+    /// Apply filters to messages received since the last run
+    Run {
+        /// Folder to filter
+        #[arg(long, default_value = "inbox", add = ArgValueCandidates::new(completions::well_known_folders))]
+        folder: String,
+        /// Look back this far instead of to the last run (e.g. 7d)
+        #[arg(long)]
+        since: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnoozeAction {
+    /// Move messages whose snooze has expired back to the inbox (for cron)
+    Wake,
+    /// List snoozed messages
+    List,
+}
+
 #[derive(Subcommand)]
 enum Commands {
-    /// Set custom OAuth client ID (optional - has built-in default)
+    /// Show and change settings (client_id, folder, page_size, output, ...)
     Config {
-        /// Client ID (Application ID from Azure)
-        client_id: String,
+        #[command(subcommand)]
+        action: ConfigAction,
     },
     /// Authenticate with Microsoft (opens browser)
     Login {
         /// Use device code flow (for first-party app IDs that don't allow localhost redirect)
         #[arg(long, short)]
         device: bool,
+        /// On Windows, sign in through the Web Account Manager broker with
+        /// the Windows work or school account, for conditional access that
+        /// requires a compliant device. Needs your own app registration.
+        #[arg(long, conflicts_with_all = ["device", "client_cert", "managed_identity"])]
+        broker: bool,
+        /// Tenant ID or domain to sign in to, for tenants that block the
+        /// common endpoint (saved for later sessions)
+        #[arg(long, value_parser = auth::parse_tenant)]
+        tenant: Option<String>,
+        /// Space-separated permissions to request instead of the full set,
+        /// e.g. "Mail.Read" (saved; commands needing more are refused).
+        /// "default" goes back to the full set.
+        #[arg(long)]
+        scopes: Option<String>,
+        /// PKCS#12 (.pfx) certificate that authenticates your own app
+        /// registration as a confidential client (saved; "none" stops using it)
+        #[arg(long, value_name = "PFX")]
+        client_cert: Option<PathBuf>,
+        /// On Azure, get app-only tokens from the VM or container's managed
+        /// identity instead of signing in; give a client ID for a
+        /// user-assigned identity. Needs --mailbox or "mailbox" in config.
+        #[arg(
+            long,
+            value_name = "CLIENT_ID",
+            num_args = 0..=1,
+            default_missing_value = auth::SYSTEM_IDENTITY,
+            conflicts_with = "client_cert"
+        )]
+        managed_identity: Option<String>,
+    },
+    /// Sign out: delete saved tokens and cached message references
+    Logout {
+        /// Also revoke every session of this account with Microsoft, on all
+        /// devices (asks for the User.RevokeSessions.All permission first)
+        #[arg(long)]
+        revoke: bool,
+        /// Also end the browser's Microsoft sign-in session
+        #[arg(long)]
+        browser: bool,
+    },
+    /// Show the signed-in account, tenant, granted permissions and token expiry
+    Whoami,
+    /// Manage account profiles, each with its own login and settings
+    Account {
+        #[command(subcommand)]
+        action: AccountAction,
     },
     /// List categories (like Gmail labels)
-    Labels,
-    /// Sync categories: create master categories for any used on messages
+    Labels {
+        #[command(subcommand)]
+        action: Option<LabelsAction>,
+    },
+    /// List mail folders with message counts
+    Folders,
+    /// Sync categories: create master categories for any used on messages in any folder
     SyncLabels,
     /// List messages
-    List {
-        /// Maximum number of messages to show
-        #[arg(short = 'n', long, default_value = "100")]
-        max: u32,
-        /// Search query
-        #[arg(short, long)]
+    List(ListArgs),
+    /// Fuzzy-find a recent message and print its ID, e.g. `outlook read $(outlook pick)`
+    Pick {
+        /// Search query to pick from (free text or KQL) instead of a folder
+        #[arg(short, long, value_parser = kql::normalize)]
         query: Option<String>,
-        /// Folder to filter by (inbox, sent, drafts, archive, trash, spam)
-        #[arg(short, long, default_value = "inbox")]
-        label: String,
-        /// Show only unread messages
+        /// Folder to pick from [default: inbox]
+        #[arg(
+            short,
+            long,
+            conflicts_with = "query",
+            add = ArgValueCandidates::new(completions::well_known_folders)
+        )]
+        label: Option<String>,
+        /// How many recent messages to offer
+        #[arg(short = 'n', long, default_value = "200")]
+        max: u32,
+        /// Allow marking several messages with Tab; prints one ID per line
         #[arg(short, long)]
-        unread: bool,
+        multi: bool,
     },
     /// Read a specific message
     Read {
         /// Message ID
         id: String,
+        /// Mark the message as read (default from `read_marks_read` in config.json)
+        #[arg(long, conflicts_with = "no_mark_read")]
+        mark_read: bool,
+        /// Leave the message unread even if config.json says otherwise
+        #[arg(long)]
+        no_mark_read: bool,
+        /// Print the HTML body as-is instead of rendering it as text
+        #[arg(long)]
+        html: bool,
+        /// How to render HTML bodies
+        #[arg(long, value_enum, default_value = "text", conflicts_with = "html")]
+        render: Render,
+        /// Have Graph convert the body to plain text instead of rendering
+        /// HTML here (default from `text_body` in config)
+        #[arg(long, conflicts_with_all = ["html", "render", "browser", "raw"])]
+        text_body: bool,
+        /// Open the HTML body, with inline images, in the default browser
+        #[arg(long, conflicts_with = "html")]
+        browser: bool,
+        /// Print the complete MIME source
+        #[arg(long, conflicts_with_all = ["html", "browser"])]
+        raw: bool,
+        /// Print the message with a template, e.g. "{from}: {subject}\n\n{body}"
+        #[arg(long, value_parser = parse_message_format, conflicts_with_all = ["browser", "raw"])]
+        format: Option<template::Template>,
+        /// Only fetch and print these fields, e.g. "subject,webLink,size"
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_parser = PossibleValuesParser::new(MESSAGE_FIELDS.iter().copied()),
+            conflicts_with_all = ["format", "browser", "raw", "html"]
+        )]
+        fields: Vec<String>,
     },
-    /// Archive a message (move to Archive folder)
-    Archive {
+    /// Print a message's internet headers
+    Headers {
+        /// Message ID
+        id: String,
+        /// Only this header (case-insensitive)
+        name: Option<String>,
+    },
+    /// Move a message between the Focused and Other inbox tabs
+    Classify {
         /// Message ID
         id: String,
+        /// Target tab
+        #[arg(value_enum)]
+        tab: Classification,
+        /// Always classify future mail from this sender the same way
+        #[arg(long)]
+        always: bool,
     },
-    /// Mark a message as spam (move to Junk)
+    /// Archive messages (move to Archive folder)
+    Archive {
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(
+            required_unless_present_any = ["query", "older_than"],
+            conflicts_with_all = ["query", "older_than"]
+        )]
+        ids: Vec<String>,
+        #[command(flatten)]
+        select: QueryArgs,
+    },
+    /// List or download the attachments of messages
+    Attachments {
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(
+            required_unless_present_any = ["query", "older_than"],
+            conflicts_with_all = ["query", "older_than"]
+        )]
+        ids: Vec<String>,
+        #[command(flatten)]
+        select: QueryArgs,
+        /// Download into this directory, under <sender>/<date>/
+        #[arg(long)]
+        save: Option<PathBuf>,
+        /// Only attachments with this extension (pdf) or content type (image/png, image/)
+        #[arg(long = "type")]
+        kind: Option<String>,
+    },
+    /// Mark messages as spam (move to Junk)
     Spam {
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Report a message to Microsoft as junk, phishing or not junk, then move it
+    Report {
         /// Message ID
         id: String,
+        /// What the message is
+        #[arg(value_enum)]
+        kind: ReportKind,
+        /// Only report; leave the message where it is
+        #[arg(long)]
+        no_move: bool,
     },
     /// Remove from spam and move to inbox
     Unspam {
-        /// Message ID
-        id: String,
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(required = true)]
+        ids: Vec<String>,
     },
-    /// Add a category to a message
+    /// Add a category to messages
     Label {
-        /// Message ID
-        id: String,
-        /// Category to add
-        label: String,
+        /// Message IDs ("-" reads IDs from stdin), then the category to add.
+        /// With --query or --older-than, just the category.
+        #[arg(required = true, value_name = "ID... CATEGORY")]
+        args: Vec<String>,
+        #[command(flatten)]
+        select: QueryArgs,
     },
-    /// Remove a category from a message
+    /// Remove a category from messages
     Unlabel {
-        /// Message ID
-        id: String,
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(required = true)]
+        ids: Vec<String>,
         /// Category to remove
         label: String,
     },
-    /// Clear all categories from a message
+    /// Clear all categories from messages
     ClearLabels {
-        /// Message ID (or "all" to clear from all inbox messages)
-        id: String,
+        /// Message IDs ("-" reads IDs from stdin, "all" clears every message in --folders)
+        #[arg(required = true)]
+        ids: Vec<String>,
+        /// With "all": comma-separated folders, or "all" for the whole mailbox
+        #[arg(long, default_value = "inbox")]
+        folders: String,
     },
-    /// Mark a message as read
+    /// Mark messages as read
     MarkRead {
-        /// Message ID
-        id: String,
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(required = true)]
+        ids: Vec<String>,
     },
-    /// Mark a message as unread
+    /// Mark messages as unread
     MarkUnread {
-        /// Message ID
-        id: String,
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(required = true)]
+        ids: Vec<String>,
     },
-    /// Move a message to trash (Deleted Items)
+    /// Reverse the last moves, category or read state changes made to messages
+    Undo {
+        /// How many of the most recent actions to undo
+        #[arg(default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        count: u16,
+        /// Show what can be undone, most recent first
+        #[arg(long, conflicts_with = "count")]
+        list: bool,
+    },
+    /// Move messages to trash (Deleted Items)
     Delete {
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(
+            required_unless_present_any = ["query", "older_than"],
+            conflicts_with_all = ["query", "older_than"]
+        )]
+        ids: Vec<String>,
+        #[command(flatten)]
+        select: QueryArgs,
+        /// Folder to search with --query (default: inbox)
+        #[arg(long, add = ArgValueCandidates::new(completions::well_known_folders))]
+        folder: Option<String>,
+    },
+    /// Print the links in a message body
+    Links {
         /// Message ID
         id: String,
+        /// Only links to this domain (or its subdomains)
+        #[arg(long)]
+        domain: Option<String>,
+    },
+    /// Convert saved message IDs to immutable IDs (or back)
+    TranslateId {
+        /// Message IDs ("-" reads IDs from stdin)
+        #[arg(required = true)]
+        ids: Vec<String>,
+        /// Convert immutable IDs back to regular ones
+        #[arg(long)]
+        reverse: bool,
     },
     /// Unsubscribe from a mailing list (opens unsubscribe link)
     Unsubscribe {
         /// Message ID
+        #[arg(required_unless_present = "from", conflicts_with = "from")]
+        id: Option<String>,
+        /// Use the most recent inbox message from this address or domain
+        #[arg(long)]
+        from: Option<String>,
+        /// With --from, also archive the sender's existing inbox mail
+        #[arg(long, requires = "from")]
+        archive: bool,
+        /// When only a mailto: link is offered, send the unsubscribe email
+        /// through Graph instead of opening a mail client
+        #[arg(long)]
+        send: bool,
+        /// Send the unsubscribe email from this alias or mailbox (needs
+        /// SendAs or SendOnBehalf rights)
+        #[arg(long, requires = "send")]
+        send_as: Option<String>,
+    },
+    /// Remove every inbox message from a sender or domain
+    Sweep {
+        /// Sender address (alice@example.com) or domain (example.com)
+        sender: String,
+        /// Where to move the messages
+        #[arg(long, value_enum, default_value = "trash")]
+        to: SweepTarget,
+    },
+    /// Block a sender so their future mail goes straight to Junk
+    Block {
+        /// Message ID, sender address or domain
+        target: String,
+    },
+    /// Manage senders whose mail should never stay in Junk
+    Safelist {
+        #[command(subcommand)]
+        action: SafelistAction,
+    },
+    /// Manage server-side inbox rules
+    Rules {
+        #[command(subcommand)]
+        action: RulesAction,
+    },
+    /// Snooze a message until later, or wake messages that are due
+    #[command(args_conflicts_with_subcommands = true)]
+    Snooze {
+        #[command(subcommand)]
+        action: Option<SnoozeAction>,
+        /// Message ID
+        id: Option<String>,
+        /// When to bring it back ("tomorrow 9am", "monday", "3h", "2024-06-01 8am")
+        #[arg(long)]
+        until: Option<String>,
+    },
+    /// Top senders, mailing lists and daily volume, to decide what to unsubscribe from
+    Stats {
+        #[command(subcommand)]
+        action: Option<StatsAction>,
+        /// How far back to look (e.g. 90d, 2024-01-01)
+        #[arg(long, default_value = "90d")]
+        since: String,
+        /// Folder to analyze
+        #[arg(long, default_value = "inbox", add = ArgValueCandidates::new(completions::folders))]
+        folder: String,
+        /// How many senders and lists to show
+        #[arg(short = 'n', long, default_value = "10")]
+        top: usize,
+    },
+    /// Answer a meeting invitation
+    Rsvp {
+        /// Invitation message ID
         id: String,
+        #[arg(value_enum)]
+        response: RsvpResponse,
+        /// Message to include with the response
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Work with the people you correspond with
+    Contacts {
+        #[command(subcommand)]
+        action: ContactsAction,
+    },
+    /// Clear old mail out of folders such as Junk and Deleted Items
+    Expire {
+        /// Folder to clean (repeatable; pairs with --older-than in order).
+        /// Without it, the [expire] table in filters.toml is used.
+        #[arg(long, requires = "older_than", add = ArgValueCandidates::new(completions::folders))]
+        folder: Vec<String>,
+        /// Age past which mail in the matching --folder expires (e.g. 14d)
+        #[arg(long, requires = "folder")]
+        older_than: Vec<String>,
+    },
+    /// Run filters, the safelist rescue and scheduled cleanups in one pass (for cron)
+    Triage,
+    /// Run local filters from filters.toml
+    Filter {
+        #[command(subcommand)]
+        action: FilterAction,
+    },
+    /// Subscribe to push notifications for new inbox messages
+    Subscribe {
+        /// Public HTTPS URL that forwards to `outlook serve`
+        url: String,
+        /// Subscription lifetime in minutes (Graph allows at most 10070)
+        #[arg(long, default_value = "10070")]
+        minutes: i64,
+    },
+    /// Listen for change notifications and keep subscriptions renewed
+    Serve {
+        /// Local port to listen on
+        #[arg(short, long, default_value = "8080")]
+        port: u16,
+    },
+    /// Browse and triage mail in a full-screen terminal interface
+    Tui,
+    /// Print a shell completion script, e.g. `source <(outlook completions bash)`
+    Completions {
+        /// Shell to complete for
+        #[arg(
+            required_unless_present = "refresh",
+            value_parser = PossibleValuesParser::new(completions::shells())
+        )]
+        shell: Option<String>,
+        /// Refetch the folder and category names offered while completing
+        #[arg(long, hide = true)]
+        refresh: bool,
+    },
+    /// Download and install the latest release over this binary
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+    /// Generate documentation for every command from its --help, for packaging
+    Docs {
+        #[arg(value_enum)]
+        format: DocsFormat,
+        /// Write one file per command into this directory, instead of printing
+        /// the top-level man page or every markdown page to stdout
+        #[arg(short, long)]
+        out_dir: Option<PathBuf>,
+    },
+    /// Answer JSON-RPC on a unix socket, keeping tokens and folder counts fresh
+    Daemon {
+        /// Socket to listen on (default: daemon.sock in the config directory)
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Seconds between refreshes of the token and cached folder counts
+        #[arg(long, default_value = "60", value_parser = clap::value_parser!(u64).range(1..))]
+        refresh: u64,
     },
 }
 
@@ -123,343 +1096,4930 @@ fn normalize_folder(folder: &str) -> String {
     }
 }
 
-async fn get_client() -> Result<api::Client> {
-    let cfg = config::load_config()?;
-    let client_id = cfg.client_id();
+// Exit codes scripts can branch on (listed in the README)
+const EXIT_FAILURE: u8 = 1;
+const EXIT_NOT_LOGGED_IN: u8 = 2;
+const EXIT_NOT_FOUND: u8 = 3;
+const EXIT_THROTTLED: u8 = 4;
+const EXIT_CIRCUIT_OPEN: u8 = 5;
 
-    let tokens = match config::load_tokens() {
-        Ok(t) => t,
-        Err(_) => anyhow::bail!("Not logged in. Run 'outlook login' first"),
-    };
+#[derive(Debug)]
+struct NotLoggedIn;
 
-    let client = api::Client::new(&tokens.access_token);
+impl std::fmt::Display for NotLoggedIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not logged in. Run 'outlook login' first")
+    }
+}
 
-    match client.list_folders().await {
-        Ok(_) => Ok(client),
-        Err(_) => {
-            let new_tokens = auth::refresh_token(client_id, &tokens.refresh_token).await?;
-            Ok(api::Client::new(&new_tokens.access_token))
-        }
+impl std::error::Error for NotLoggedIn {}
+
+fn exit_code(err: &anyhow::Error) -> u8 {
+    if err.downcast_ref::<NotLoggedIn>().is_some() || err.chain().any(|e| e.is::<NotLoggedIn>()) {
+        return EXIT_NOT_LOGGED_IN;
+    }
+    if err.chain().any(|e| e.is::<ids::UnknownShortId>()) {
+        return EXIT_NOT_FOUND;
+    }
+    if err.chain().any(|e| e.is::<api::Throttled>()) {
+        return EXIT_CIRCUIT_OPEN;
+    }
+    let graph = err
+        .chain()
+        .find_map(|e| e.downcast_ref::<api::GraphError>());
+    match graph.map(|e| e.status) {
+        Some(401) => EXIT_NOT_LOGGED_IN,
+        Some(404) => EXIT_NOT_FOUND,
+        Some(429) => EXIT_THROTTLED,
+        _ => EXIT_FAILURE,
     }
 }
 
-fn save_config(client_id: String) -> Result<()> {
-    let cfg = config::Config {
-        client_id: Some(client_id),
-    };
-    config::save_config(&cfg)?;
-    println!("Custom client ID saved to {:?}", config::config_dir());
-    Ok(())
+/// `{"error": {...}}` for `--json` output, so wrappers can parse failures
+fn error_json(err: &anyhow::Error) -> serde_json::Value {
+    let graph = err
+        .chain()
+        .find_map(|e| e.downcast_ref::<api::GraphError>());
+    serde_json::json!({
+        "error": {
+            "message": format!("{:#}", err),
+            "exitCode": exit_code(err),
+            "status": graph.map(|e| e.status),
+            "code": graph.and_then(|e| e.code.as_deref()),
+            "requestId": graph.and_then(|e| e.request_id.as_deref()),
+            "retryAfter": err
+                .chain()
+                .find_map(|e| e.downcast_ref::<api::Throttled>())
+                .map(|e| e.retry_after.as_secs_f64().ceil() as u64),
+        }
+    })
 }
 
-async fn login(device: bool) -> Result<()> {
+// Refresh access tokens this close to expiry
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 300;
+
+async fn get_client() -> Result<api::Client> {
     let cfg = config::load_config()?;
     let client_id = cfg.client_id();
 
-    let _ = std::fs::remove_file(config::tokens_path());
+    // Only a missing token file means signing in; a locked or unreadable
+    // store says what's wrong with it
+    let tokens = config::load_tokens().map_err(|e| {
+        if config::tokens_missing(&e) {
+            NotLoggedIn.into()
+        } else {
+            e
+        }
+    })?;
 
-    if device {
-        auth::login_device_code(client_id).await?;
+    // Refresh ahead of expiry rather than failing the first request
+    let tokens = if tokens.expires_within(TOKEN_REFRESH_MARGIN_SECS) {
+        refresh_tokens(client_id, cfg.tenant(), &tokens.access_token).await?
     } else {
-        auth::login(client_id).await?;
-    }
-    println!("Login successful! Tokens saved.");
-    Ok(())
+        tokens
+    };
+
+    let client_id = client_id.to_string();
+    let tenant = cfg.tenant().to_string();
+    let refresh: api::TokenRefresh = Arc::new(move || {
+        let client_id = client_id.clone();
+        let tenant = tenant.clone();
+        Box::pin(async move {
+            let stale = config::load_tokens()?.access_token;
+            Ok(refresh_tokens(&client_id, &tenant, &stale)
+                .await?
+                .access_token)
+        })
+    });
+    Ok(api::Client::new(&tokens.access_token, api::retry_policy()).with_token_refresh(refresh))
 }
 
-async fn list_labels(json: bool) -> Result<()> {
-    let client = get_client().await?;
-    let categories = client.list_categories().await?;
+/// Refresh under the token lock, so concurrent runs don't each redeem the
+/// same refresh token and save one the server has already rotated away
+async fn refresh_tokens(client_id: &str, tenant: &str, stale: &str) -> Result<config::Tokens> {
+    let _lock = config::lock_tokens()?;
+    // Another process may have refreshed while we waited for the lock
+    let tokens = config::load_tokens()?;
+    if tokens.access_token != stale && !tokens.expires_within(TOKEN_REFRESH_MARGIN_SECS) {
+        return Ok(tokens);
+    }
+    let cfg = config::load_config()?;
+    if let Some(identity) = &cfg.managed_identity {
+        return auth::managed_identity_token(identity).await;
+    }
+    let refreshed = match &cfg.broker_account {
+        Some(account) => auth::broker_token(client_id, tenant, account, &login_scopes(&cfg)).await,
+        None => auth::refresh_token(client_id, tenant, &tokens.refresh_token, &[]).await,
+    };
+    refreshed.map_err(|err| {
+        if auth::refresh_rejected(&err) {
+            err.context(NotLoggedIn)
+        } else {
+            err
+        }
+    })
+}
 
-    if let Some(cats) = categories.value {
-        if json {
-            println!("{}", serde_json::to_string(&cats)?);
+/// Permissions `login` asks for with this config
+fn login_scopes(cfg: &config::Config) -> Vec<&str> {
+    let mut scopes: Vec<&str> = match &cfg.scopes {
+        Some(custom) => custom.iter().map(String::as_str).collect(),
+        None if cfg.read_only() => auth::READ_ONLY_SCOPES.to_vec(),
+        None => auth::SCOPES.to_vec(),
+    };
+    if api::mailbox().is_some() && cfg.scopes.is_none() {
+        if cfg.read_only() {
+            scopes.push("Mail.Read.Shared");
         } else {
-            println!("Categories:");
-            for cat in cats {
-                let color = cat.color.as_deref().unwrap_or("none");
-                println!("  {} (color: {})", cat.display_name, color);
-            }
+            scopes.extend(auth::SHARED_SCOPES);
         }
-    } else if json {
-        println!("[]");
-    } else {
-        println!("No categories found.");
     }
-    Ok(())
+    scopes
 }
 
-async fn sync_labels() -> Result<()> {
-    let client = get_client().await?;
-
-    let master = client.list_categories().await?;
-    let master_names: std::collections::HashSet<String> = master
-        .value
-        .unwrap_or_default()
+/// Get consent for the INCREMENTAL_SCOPES a command needs: from a token
+/// refresh if it was already granted, otherwise by signing in again with them
+async fn ensure_consent(command: &Commands, cfg: &config::Config) -> Result<()> {
+    let needed: Vec<&str> = required_scopes(command, false)
         .into_iter()
-        .map(|c| c.display_name.to_lowercase())
+        .filter(|scope| auth::INCREMENTAL_SCOPES.contains(scope))
         .collect();
-
-    let messages = client.list_messages("inbox", None, 200).await?;
-    let mut found: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-    if let Some(msgs) = messages.value {
-        for msg in msgs {
-            if let Some(cats) = msg.categories {
-                for cat in cats {
-                    if !master_names.contains(&cat.to_lowercase()) {
-                        found.insert(cat);
-                    }
-                }
+    // App-only tokens carry whatever application permissions an admin granted
+    if needed.is_empty() || cfg.managed_identity.is_some() {
+        return Ok(());
+    }
+    let Ok(tokens) = config::load_tokens() else {
+        return Ok(());
+    };
+    // None for personal accounts, whose tokens can't be read
+    let has_needed = |token: &str| {
+        auth::decode_claims(token).map(|claims| {
+            needed
+                .iter()
+                .all(|scope| claims.scopes.iter().any(|g| auth::grants(g, scope)))
+        })
+    };
+    if has_needed(&tokens.access_token) == Some(true) {
+        return Ok(());
+    }
+
+    let mut scopes = login_scopes(cfg);
+    scopes.extend(&needed);
+    let client_id = cfg.client_id();
+    {
+        let _lock = config::lock_tokens()?;
+        let refreshed = match &cfg.broker_account {
+            Some(account) => auth::broker_token(client_id, cfg.tenant(), account, &scopes).await,
+            None => {
+                auth::refresh_token(client_id, cfg.tenant(), &tokens.refresh_token, &scopes).await
             }
+        };
+        if refreshed.is_ok_and(|tokens| has_needed(&tokens.access_token) != Some(false)) {
+            return Ok(());
         }
     }
+    note!(
+        "This command needs {}, which you haven't granted yet; signing in again to ask for it",
+        needed.join(", ")
+    );
+    let context = || format!("Failed to get consent for {}", needed.join(", "));
+    if cfg.broker_account.is_none() {
+        auth::login(client_id, cfg.tenant(), &scopes)
+            .await
+            .with_context(context)?;
+        return Ok(());
+    }
+    let account = auth::broker_login(client_id, cfg.tenant(), &scopes)
+        .await
+        .with_context(context)?;
+    // The broker may have been given another account than before
+    let mut saved = config::load_saved_config()?;
+    saved.broker_account = Some(account);
+    config::save_config(&saved)
+}
 
-    if found.is_empty() {
-        println!("All categories are already in master list.");
-    } else {
-        for cat in &found {
-            client.create_category(cat, None).await?;
-            println!("Created category: {}", cat);
+/// Permissions a command needs, for checking against `login --scopes`
+fn required_scopes(command: &Commands, read_marks_read: bool) -> Vec<&'static str> {
+    const READ: &str = "Mail.Read";
+    const WRITE: &str = "Mail.ReadWrite";
+    const SETTINGS_READ: &str = "MailboxSettings.Read";
+    const SETTINGS: &str = "MailboxSettings.ReadWrite";
+    match command {
+        Commands::Config { .. } | Commands::Login { .. } => vec![],
+        Commands::Docs { .. } | Commands::SelfUpdate { .. } => vec![],
+        Commands::Whoami | Commands::Account { .. } => vec![],
+        Commands::Logout { revoke, .. } => {
+            if *revoke {
+                vec!["User.RevokeSessions.All"]
+            } else {
+                vec![]
+            }
+        }
+        Commands::Folders
+        | Commands::List(_)
+        | Commands::Pick { .. }
+        | Commands::Headers { .. }
+        | Commands::Links { .. }
+        | Commands::TranslateId { .. }
+        | Commands::Stats { .. }
+        | Commands::Attachments { .. }
+        | Commands::Subscribe { .. }
+        | Commands::Serve { .. }
+        | Commands::Daemon { .. } => vec![READ],
+        Commands::Tui => vec![WRITE, SETTINGS, "Mail.Send"],
+        Commands::Completions { refresh, .. } => {
+            if *refresh {
+                vec![READ, SETTINGS_READ]
+            } else {
+                vec![]
+            }
+        }
+        Commands::Read {
+            mark_read,
+            no_mark_read,
+            ..
+        } => {
+            if *mark_read || (read_marks_read && !no_mark_read) {
+                vec![WRITE]
+            } else {
+                vec![READ]
+            }
         }
-        println!("Synced {} categories.", found.len());
+        Commands::Labels { action } => match action {
+            None | Some(LabelsAction::List) => vec![SETTINGS_READ],
+            Some(LabelsAction::Stats) => vec![READ, SETTINGS_READ],
+            Some(_) => vec![WRITE, SETTINGS],
+        },
+        Commands::SyncLabels => vec![READ, SETTINGS],
+        Commands::Label { .. } => vec![WRITE, SETTINGS],
+        Commands::Classify { always, .. } => {
+            if *always {
+                vec![WRITE, SETTINGS]
+            } else {
+                vec![WRITE]
+            }
+        }
+        Commands::Report { .. } => vec![WRITE, "ThreatAssessment.ReadWrite.All"],
+        Commands::Archive { .. }
+        | Commands::Spam { .. }
+        | Commands::Unspam { .. }
+        | Commands::Unlabel { .. }
+        | Commands::ClearLabels { .. }
+        | Commands::MarkRead { .. }
+        | Commands::MarkUnread { .. }
+        | Commands::Delete { .. }
+        | Commands::Sweep { .. }
+        | Commands::Expire { .. }
+        | Commands::Triage
+        | Commands::Filter { .. } => vec![WRITE],
+        // Listing only reads the local snooze file
+        Commands::Snooze {
+            action: Some(SnoozeAction::List),
+            ..
+        } => vec![],
+        Commands::Snooze { .. } => vec![WRITE],
+        Commands::Undo { list, .. } => {
+            if *list {
+                vec![]
+            } else {
+                vec![WRITE]
+            }
+        }
+        Commands::Unsubscribe { send, .. } => {
+            // The one-click POST changes things on the sender's side, so it
+            // counts as a write (and is refused in read-only mode)
+            let mut scopes = vec![WRITE];
+            if *send {
+                scopes.push("Mail.Send");
+            }
+            scopes
+        }
+        Commands::Rules {
+            action: RulesAction::List,
+        } => vec![SETTINGS_READ],
+        Commands::Block { .. } | Commands::Rules { .. } => vec![WRITE, SETTINGS],
+        Commands::Safelist { action } => match action {
+            SafelistAction::Add { .. } => vec![SETTINGS],
+            SafelistAction::Rescue => vec![WRITE],
+            SafelistAction::Remove { .. } | SafelistAction::Show => vec![],
+        },
+        Commands::Rsvp { .. } => vec![READ, "Calendars.ReadWrite"],
+        Commands::Contacts { action } => match action {
+            ContactsAction::Extract { .. } => vec![READ],
+            ContactsAction::Add { .. } => vec![READ, "Contacts.ReadWrite"],
+        },
+    }
+}
+
+/// Refuse commands that need permissions missing from `login --scopes`.
+/// INCREMENTAL_SCOPES are left to `ensure_consent`.
+fn check_scopes(command: &Commands, granted: &[String], read_marks_read: bool) -> Result<()> {
+    // Other people's mailboxes need the .Shared form of the mail permissions
+    let shared = api::mailbox().is_some();
+    let missing: Vec<String> = required_scopes(command, read_marks_read)
+        .into_iter()
+        .filter(|scope| !auth::INCREMENTAL_SCOPES.contains(scope))
+        .map(|scope| {
+            if shared && scope.starts_with("Mail.") {
+                format!("{}.Shared", scope)
+            } else {
+                scope.to_string()
+            }
+        })
+        .filter(|scope| !granted.iter().any(|g| auth::grants(g, scope)))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "This command needs {}, but login only granted: {}. Log in again with `outlook login --scopes` including it.",
+            missing.join(", "),
+            granted.join(" ")
+        );
     }
     Ok(())
 }
 
-async fn list_messages(
-    max: u32,
-    query: Option<String>,
-    label: String,
-    unread: bool,
-    json: bool,
-) -> Result<()> {
-    let client = get_client().await?;
-    let folder = normalize_folder(&label);
+/// In read-only mode, refuse commands that need more than read permissions
+fn check_read_only(command: &Commands) -> Result<()> {
+    let writes = required_scopes(command, false)
+        .iter()
+        .any(|scope| !scope.ends_with(".Read"));
+    if writes {
+        anyhow::bail!(
+            "Read-only mode is on (the \"read_only\" setting), and this command changes the mailbox"
+        );
+    }
+    Ok(())
+}
 
-    let filter = if unread {
-        Some("isRead eq false")
+async fn whoami(output: Output) -> Result<()> {
+    let client = get_client().await?;
+    // Read after get_client, which may have refreshed the token
+    let claims = auth::decode_claims(&config::load_tokens()?.access_token);
+    let claims = claims.as_ref();
+    let app = claims.and_then(|c| c.app.as_deref());
+    // App-only tokens have no /me, only the mailbox they were pointed at
+    let user = if app.is_some() {
+        if api::mailbox().is_none() {
+            anyhow::bail!(
+                "An app-only token has no user of its own; pass --mailbox or set the \"mailbox\" setting"
+            );
+        }
+        client.me().await?
     } else {
-        None
+        client.signed_in_user().await?
     };
 
-    let list = if let Some(q) = &query {
-        client.search_messages(q, max).await?
-    } else {
-        client.list_messages(&folder, filter, max).await?
-    };
+    if matches!(output, Output::Json | Output::Ndjson) {
+        let json = serde_json::json!({
+            "displayName": user.display_name,
+            "userPrincipalName": user.user_principal_name,
+            "mail": user.mail,
+            "mailbox": api::mailbox(),
+            "app": app,
+            "tenant": claims.and_then(|c| c.tenant.as_deref()),
+            "scopes": claims.map(|c| &c.scopes),
+            "expires": claims.and_then(|c| c.expires).map(|e| e.to_rfc3339()),
+        });
+        println!("{}", serde_json::to_string(&json)?);
+        return Ok(());
+    }
 
-    if let Some(messages) = list.value {
-        if json {
-            let items: Vec<_> = messages
-                .iter()
-                .map(|msg| {
-                    serde_json::json!({
-                        "id": msg.id,
-                        "from": msg.get_from(),
-                        "subject": msg.subject,
-                        "date": msg.received_date_time,
-                        "snippet": msg.body_preview,
-                        "isRead": msg.is_read,
-                        "categories": msg.categories,
-                    })
-                })
-                .collect();
-            println!("{}", serde_json::to_string(&items)?);
-        } else {
-            for msg in messages {
-                let from = msg.get_from().unwrap_or_else(|| "Unknown".to_string());
-                let subject = msg.subject.as_deref().unwrap_or("(no subject)");
-                println!("{} | {} | {}", msg.id, from, subject);
+    let account = match &user.display_name {
+        Some(name) => format!("{} <{}>", name, user.user_principal_name),
+        None => user.user_principal_name.clone(),
+    };
+    match app {
+        Some(app) => {
+            println!("Signed in as app {}", app);
+            println!("Mailbox: {}", account);
+        }
+        None => {
+            println!("Signed in as {}", account);
+            if let Some(mailbox) = api::mailbox() {
+                println!("Mailbox: {}", mailbox);
             }
         }
-    } else if !json {
-        println!("No messages found.");
-    } else {
-        println!("[]");
+    }
+    match claims {
+        Some(claims) => {
+            if let Some(tenant) = &claims.tenant {
+                println!("Tenant:  {}", tenant);
+            }
+            println!("Scopes:  {}", claims.scopes.join(" "));
+            if let Some(expires) = claims.expires {
+                println!(
+                    "Expires: {} ({})",
+                    expires
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M"),
+                    dates::relative(expires)
+                );
+            }
+        }
+        None => println!("Token details are not readable for personal Microsoft accounts"),
     }
     Ok(())
 }
 
-async fn read_message(id: String, json: bool) -> Result<()> {
-    let client = get_client().await?;
-    let msg = client.get_message(&id).await?;
+async fn logout(revoke: bool, browser: bool) -> Result<()> {
+    if revoke {
+        let client = get_client().await?;
+        client
+            .revoke_sign_in_sessions()
+            .await
+            .context("Failed to revoke sessions (local tokens were kept)")?;
+        info!("Revoked all sign-in sessions for this account");
+    }
 
-    if json {
-        println!(
-            "{}",
-            serde_json::to_string(&serde_json::json!({
-                "id": msg.id,
-                "from": msg.get_from(),
-                "to": msg.get_to(),
-                "subject": msg.subject,
-                "date": msg.received_date_time,
-                "body": msg.get_body_text(),
-                "snippet": msg.body_preview,
-                "isRead": msg.is_read,
-                "categories": msg.categories,
-            }))?
-        );
+    if status::dry_run() {
+        println!("Would delete the saved tokens and cached message references");
+        if browser {
+            println!(
+                "Would open {}",
+                auth::oauth_url(config::load_config()?.tenant(), "logout")
+            );
+        }
+        return Ok(());
+    }
+    let had_tokens = config::remove_tokens()?;
+    for cache in [ids::SHORT_IDS_FILE, LAST_LIST_FILE] {
+        config::remove_state(cache)?;
+    }
+    if had_tokens {
+        info!("Logged out. Tokens and cached message references deleted.");
     } else {
-        println!(
-            "From: {}",
-            msg.get_from().unwrap_or_else(|| "Unknown".to_string())
-        );
-        println!(
-            "To: {}",
-            msg.get_to().unwrap_or_else(|| "Unknown".to_string())
-        );
-        println!(
-            "Subject: {}",
-            msg.subject.as_deref().unwrap_or("(no subject)")
-        );
-        println!(
-            "Date: {}",
-            msg.received_date_time.as_deref().unwrap_or("Unknown")
-        );
-        println!("---");
+        info!("Not logged in. Cached message references deleted.");
+    }
 
-        if let Some(body) = msg.get_body_text() {
-            println!("{}", body);
-        } else if let Some(preview) = &msg.body_preview {
-            println!("{}", preview);
-        }
+    if browser {
+        let url = auth::oauth_url(config::load_config()?.tenant(), "logout");
+        info!("Opening {}", url);
+        open::that(&url)?;
     }
     Ok(())
 }
 
-async fn archive_message(id: String) -> Result<()> {
-    let client = get_client().await?;
-    client.archive(&id).await?;
-    println!("Archived {}", id);
+async fn manage_accounts(action: AccountAction, output: Output) -> Result<()> {
+    match action {
+        AccountAction::Add {
+            name,
+            device,
+            tenant,
+            default,
+        } => {
+            config::create_account(&name)?;
+            config::set_account(Some(name.clone()));
+            login(device, false, tenant, None, None, None).await?;
+            if default {
+                config::set_default_account(Some(name.clone()))?;
+                info!("Account '{}' added and set as default", name);
+            } else {
+                info!(
+                    "Account '{}' added. Use it with `outlook --account {} ...`",
+                    name, name
+                );
+            }
+        }
+        AccountAction::List => {
+            let default = config::default_account()?;
+            let accounts = config::list_accounts()?;
+            if matches!(output, Output::Json | Output::Ndjson) {
+                let json: Vec<_> = accounts
+                    .iter()
+                    .map(|name| {
+                        serde_json::json!({
+                            "name": name,
+                            "default": default.as_deref() == Some(name.as_str()),
+                            "loggedIn": config::account_logged_in(name),
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string(&json)?);
+            } else if accounts.is_empty() {
+                info!("No account profiles. Add one with `outlook account add <name>`.");
+            } else {
+                for name in &accounts {
+                    let marker = if default.as_deref() == Some(name.as_str()) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    let status = if config::account_logged_in(name) {
+                        ""
+                    } else {
+                        " (not logged in)"
+                    };
+                    println!("{} {}{}", marker, name, status);
+                }
+            }
+        }
+        AccountAction::Switch { name } if status::dry_run() => {
+            if name != "none" && !config::account_exists(&config::parse_account_name(&name)?) {
+                anyhow::bail!("No account named '{}'", name);
+            }
+            println!("Would make '{}' the default account", name);
+        }
+        AccountAction::Switch { name } => {
+            if name == "none" {
+                config::set_default_account(None)?;
+                info!("No default account; commands use the top-level login");
+            } else {
+                let name = config::parse_account_name(&name)?;
+                if !config::account_exists(&name) {
+                    anyhow::bail!(
+                        "No account named '{}'. Add it with `outlook account add {}`.",
+                        name,
+                        name
+                    );
+                }
+                config::set_default_account(Some(name.clone()))?;
+                info!("Default account is now '{}'", name);
+            }
+        }
+    }
     Ok(())
 }
 
-async fn spam_message(id: String) -> Result<()> {
-    let client = get_client().await?;
-    let msg = client.get_message(&id).await?;
-    if let Some(url) = msg.get_unsubscribe_url() {
-        if url.starts_with("http") {
-            let _ = open::that(&url);
+fn manage_config(action: ConfigAction, output: Output) -> Result<()> {
+    // Reads show environment overrides; writes leave them out of the file
+    let mut cfg = match action {
+        ConfigAction::Set { .. } | ConfigAction::Unset { .. } => config::load_saved_config()?,
+        ConfigAction::Get { .. } | ConfigAction::List => config::load_config()?,
+    };
+    // The default account lives in the top-level config, whichever account is active
+    let is_account = |key: &str| key.eq_ignore_ascii_case("account");
+    match action {
+        ConfigAction::Get { key } => {
+            let value = if is_account(&key) {
+                config::default_account()?
+            } else {
+                cfg.get(&key)?
+            };
+            match value {
+                Some(value) => println!("{}", value),
+                None => anyhow::bail!("'{}' is not set", key),
+            }
+        }
+        ConfigAction::Set { key, value } => {
+            cfg.set(&key, &value)?;
+            if status::dry_run() {
+                println!("Would set {} = {}", key.to_lowercase(), value.trim());
+                return Ok(());
+            }
+            if is_account(&key) {
+                config::set_default_account(cfg.default_account)?;
+            } else {
+                config::save_config(&cfg)?;
+            }
+            info!("{} = {}", key.to_lowercase(), value.trim());
+        }
+        ConfigAction::Unset { key } => {
+            cfg.unset(&key)?;
+            if status::dry_run() {
+                println!("Would unset {}", key.to_lowercase());
+                return Ok(());
+            }
+            if is_account(&key) {
+                config::set_default_account(None)?;
+            } else {
+                config::save_config(&cfg)?;
+            }
+            info!("{} unset", key.to_lowercase());
+        }
+        ConfigAction::List => {
+            let default_account = config::default_account()?;
+            let mut values = Vec::new();
+            for (key, description) in config::KEYS {
+                let value = if is_account(key) {
+                    default_account.clone()
+                } else {
+                    cfg.get(key)?
+                };
+                values.push((*key, value, *description));
+            }
+            if matches!(output, Output::Json | Output::Ndjson) {
+                let json: serde_json::Map<String, serde_json::Value> = values
+                    .into_iter()
+                    .filter_map(|(key, value, _)| Some((key.to_string(), value?.into())))
+                    .collect();
+                println!("{}", serde_json::to_string(&json)?);
+            } else {
+                let rows: Vec<Vec<String>> = values
+                    .into_iter()
+                    .map(|(key, value, description)| {
+                        vec![
+                            key.to_string(),
+                            value.unwrap_or_else(|| "-".to_string()),
+                            description.to_string(),
+                        ]
+                    })
+                    .collect();
+                print!(
+                    "{}",
+                    table::render(
+                        &["KEY", "VALUE", "DESCRIPTION"],
+                        &rows,
+                        &[1, 2],
+                        table::terminal_width()
+                    )
+                );
+            }
         }
     }
-    client.mark_spam(&id).await?;
-    println!("Marked as spam {}", id);
     Ok(())
 }
 
-async fn unspam_message(id: String) -> Result<()> {
-    let client = get_client().await?;
-    client.unspam(&id).await?;
-    println!("Moved to inbox {}", id);
+async fn login(
+    device: bool,
+    broker: bool,
+    tenant: Option<String>,
+    scopes: Option<String>,
+    client_cert: Option<PathBuf>,
+    managed_identity: Option<String>,
+) -> Result<()> {
+    let mut cfg = config::load_saved_config()?;
+    if let Some(identity) = managed_identity {
+        // App-only tokens have no /me; Graph needs a mailbox to act on
+        if api::mailbox().is_none() {
+            anyhow::bail!(
+                "A managed identity has no mailbox of its own; pass --mailbox or set the \"mailbox\" setting"
+            );
+        }
+        let _ = config::remove_tokens();
+        auth::managed_identity_token(&identity).await?;
+        cfg.mailbox = api::mailbox().map(String::from);
+        cfg.managed_identity = Some(identity);
+        config::save_config(&cfg)?;
+        info!("Managed identity token saved; it is renewed from Azure as needed.");
+        return Ok(());
+    }
+    cfg.managed_identity = None;
+    // Saved again below once the broker has signed in
+    cfg.broker_account = None;
+    if broker {
+        if !cfg!(windows) {
+            anyhow::bail!("--broker only works on Windows");
+        }
+        if cfg.client_id.is_none() {
+            anyhow::bail!(
+                "--broker needs your own app registration with the redirect URI {}; set its ID with `outlook config set client_id <id>` first",
+                auth::broker_redirect_uri("<id>")
+            );
+        }
+    }
+    if client_cert.as_deref() == Some(std::path::Path::new("none")) {
+        cfg.client_cert = None;
+    } else if let Some(path) = client_cert {
+        if cfg.client_id.is_none() {
+            anyhow::bail!(
+                "--client-cert needs your own app registration; set its ID with `outlook config set client_id <id>` first"
+            );
+        }
+        // Fail now rather than after the browser round-trip
+        auth::ClientCert::load(&path)?;
+        cfg.client_cert = Some(std::path::absolute(&path)?);
+    }
+    if scopes.as_deref() == Some("default") {
+        cfg.scopes = None;
+    } else if let Some(scopes) = scopes {
+        let mut scopes: Vec<String> = scopes.split_whitespace().map(String::from).collect();
+        // Without a refresh token every session would need a new login
+        if !scopes
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case("offline_access"))
+        {
+            scopes.push("offline_access".to_string());
+        }
+        cfg.scopes = Some(scopes);
+    }
+    config::save_config(&cfg)?;
+    // Sign in with what's in effect, including OUTLOOK_CLI_* overrides
+    if tenant.is_some() {
+        cfg.tenant = tenant.clone();
+    }
+    cfg.apply_env(|name| std::env::var(name).ok())?;
+    let client_id = cfg.client_id();
+
+    let _ = config::remove_tokens();
+
+    let scopes = login_scopes(&cfg);
+    let mut broker_account = None;
+    if broker {
+        broker_account = Some(auth::broker_login(client_id, cfg.tenant(), &scopes).await?);
+    } else if device {
+        auth::login_device_code(client_id, cfg.tenant(), &scopes).await?;
+    } else {
+        auth::login(client_id, cfg.tenant(), &scopes).await?;
+    }
+    // Only remember a tenant once signing in to it has worked
+    if tenant.is_some() || broker_account.is_some() {
+        let mut saved = config::load_saved_config()?;
+        if tenant.is_some() {
+            saved.tenant = tenant;
+        }
+        saved.broker_account = broker_account;
+        config::save_config(&saved)?;
+    }
+    info!("Login successful! Tokens saved.");
     Ok(())
 }
 
-async fn add_label(id: String, label: String) -> Result<()> {
-    let client = get_client().await?;
-    client.ensure_category(&label).await?;
-    client.add_category(&id, &label).await?;
-    println!("Added category {} to {}", label, id);
-    Ok(())
+async fn labels(
+    action: Option<LabelsAction>,
+    output: Output,
+    yes: bool,
+    concurrency: usize,
+) -> Result<()> {
+    match action.unwrap_or(LabelsAction::List) {
+        LabelsAction::List => list_labels(output).await,
+        LabelsAction::Create { name, color } => {
+            let client = get_client().await?;
+            let created = client.create_category(&name, color.as_deref()).await?;
+            if output == Output::Json {
+                println!("{}", serde_json::to_string(&created)?);
+            } else {
+                info!(
+                    "Created category {} ({})",
+                    created.display_name,
+                    created.color.as_deref().unwrap_or("none")
+                );
+            }
+            Ok(())
+        }
+        LabelsAction::Rename { old, new } => rename_label(&old, &new, concurrency).await,
+        LabelsAction::Delete { name, purge } => delete_label(&name, purge, yes, concurrency).await,
+        LabelsAction::Stats => label_stats(output, concurrency).await,
+    }
 }
 
-async fn remove_label(id: String, label: String) -> Result<()> {
-    let client = get_client().await?;
-    client.remove_category(&id, &label).await?;
-    println!("Removed category {} from {}", label, id);
-    Ok(())
+#[derive(serde::Serialize)]
+struct LabelStats {
+    name: String,
+    color: Option<String>,
+    total: usize,
+    /// Largest first
+    folders: Vec<FolderCount>,
 }
 
-async fn clear_labels(id: String) -> Result<()> {
-    let client = get_client().await?;
-    if id == "all" {
-        let messages = client.list_messages("inbox", None, 200).await?;
-        let mut count = 0;
-        if let Some(msgs) = messages.value {
-            for msg in msgs {
-                if msg
-                    .categories
-                    .as_ref()
-                    .map(|c| !c.is_empty())
-                    .unwrap_or(false)
-                {
-                    client.update_categories(&msg.id, &[]).await?;
-                    println!(
-                        "Cleared categories from: {}",
-                        msg.subject.as_deref().unwrap_or("(no subject)")
-                    );
-                    count += 1;
+#[derive(Clone, serde::Serialize)]
+struct FolderCount {
+    folder: String,
+    messages: usize,
+}
+
+async fn label_stats(output: Output, concurrency: usize) -> Result<()> {
+    use futures::stream::{self, StreamExt};
+
+    let client = bulk_client(concurrency).await?;
+    let categories = client.list_categories().await?.value.unwrap_or_default();
+    let folder_names: std::collections::HashMap<String, String> = client
+        .list_folders()
+        .await?
+        .value
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| (f.id, f.display_name))
+        .collect();
+
+    let counts: Vec<Result<std::collections::HashMap<String, usize>>> = stream::iter(&categories)
+        .map(|c| {
+            let filter = category_clause(&c.display_name);
+            let client = &client;
+            async move { client.count_by_folder(&filter).await }
+        })
+        .buffered(client.concurrency())
+        .collect()
+        .await;
+
+    let mut stats = Vec::new();
+    for (category, counts) in categories.into_iter().zip(counts) {
+        let mut folders: Vec<FolderCount> = counts?
+            .into_iter()
+            .map(|(id, messages)| FolderCount {
+                folder: folder_names.get(&id).cloned().unwrap_or(id),
+                messages,
+            })
+            .collect();
+        folders.sort_by(|a, b| {
+            b.messages
+                .cmp(&a.messages)
+                .then_with(|| a.folder.cmp(&b.folder))
+        });
+        stats.push(LabelStats {
+            name: category.display_name,
+            color: category.color,
+            total: folders.iter().map(|f| f.messages).sum(),
+            folders,
+        });
+    }
+    print_label_stats(&stats, output)
+}
+
+fn print_label_stats(stats: &[LabelStats], output: Output) -> Result<()> {
+    // One row per category and folder; unused categories get a single empty row
+    let rows: Vec<Vec<String>> = stats
+        .iter()
+        .flat_map(|s| {
+            let folders = if s.folders.is_empty() {
+                vec![FolderCount {
+                    folder: String::new(),
+                    messages: 0,
+                }]
+            } else {
+                s.folders.clone()
+            };
+            folders
+                .into_iter()
+                .map(|f| vec![s.name.clone(), f.folder, f.messages.to_string()])
+        })
+        .collect();
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(stats)?),
+        Output::Ndjson => print_ndjson(stats)?,
+        Output::Csv | Output::Tsv => print!(
+            "{}",
+            table::delimited(
+                &["category", "folder", "messages"],
+                &rows,
+                output.separator().unwrap_or(',')
+            )
+        ),
+        Output::Table => print!(
+            "{}",
+            table::render(
+                &["CATEGORY", "FOLDER", "MESSAGES"],
+                &rows,
+                &[0, 1],
+                table::terminal_width()
+            )
+        ),
+        Output::Text if stats.is_empty() => info!("No categories found."),
+        Output::Text => {
+            for s in stats {
+                let folders: Vec<String> = s
+                    .folders
+                    .iter()
+                    .map(|f| format!("{} {}", f.folder, f.messages))
+                    .collect();
+                if folders.is_empty() {
+                    println!("{}: unused", s.name);
+                } else {
+                    println!("{}: {} ({})", s.name, s.total, folders.join(", "));
                 }
             }
         }
-        println!("Cleared categories from {} messages.", count);
+    }
+    Ok(())
+}
+
+async fn delete_label(name: &str, purge: bool, yes: bool, concurrency: usize) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let categories = client.list_categories().await?.value.unwrap_or_default();
+    let category = categories
+        .iter()
+        .find(|c| c.display_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow::anyhow!("No category named '{}'", name))?;
+
+    let mut stripped = 0;
+    if purge {
+        let messages = client
+            .filter_all_messages(&category_clause(&category.display_name))
+            .await?;
+        let action = format!("Remove {} from", category.display_name);
+        if !confirm(&action, messages.len(), yes)? {
+            return Ok(());
+        }
+        let before: Vec<_> = messages.iter().map(|m| Some(undo::Before::of(m))).collect();
+        let (ids, current) = category_snapshots(messages);
+        let results = client
+            .write_categories(current, |categories| {
+                categories.retain(|c| !c.eq_ignore_ascii_case(name))
+            })
+            .await?;
+        let action = format!("remove {}", category.display_name);
+        undo::record_categories(&action, &ids, &before, &results);
+        stripped = results.iter().filter(|r| r.is_ok()).count();
+        report_bulk(&ids, results, "Removed from")?;
+    }
+
+    if let Some(id) = &category.id {
+        client.delete_category(id).await?;
+    }
+    if purge {
+        info!(
+            "Deleted category {} and removed it from {} message(s)",
+            category.display_name, stripped
+        );
     } else {
-        client.update_categories(&id, &[]).await?;
-        println!("Cleared all categories from {}", id);
+        info!("Deleted category {}", category.display_name);
     }
     Ok(())
 }
 
-async fn mark_read(id: String) -> Result<()> {
-    let client = get_client().await?;
-    client.mark_read(&id).await?;
-    println!("Marked as read: {}", id);
+/// Graph can't rename a master category, so create the new one with the
+/// old color, move every message over, and only then delete the old one
+async fn rename_label(old: &str, new: &str, concurrency: usize) -> Result<()> {
+    if old.eq_ignore_ascii_case(new) {
+        anyhow::bail!(
+            "Category names are case-insensitive; '{}' and '{}' are the same",
+            old,
+            new
+        );
+    }
+    let client = bulk_client(concurrency).await?;
+    let categories = client.list_categories().await?.value.unwrap_or_default();
+    let find = |name: &str| {
+        categories
+            .iter()
+            .find(|c| c.display_name.eq_ignore_ascii_case(name))
+    };
+    let source = find(old).ok_or_else(|| anyhow::anyhow!("No category named '{}'", old))?;
+    if find(new).is_none() {
+        client.create_category(new, source.color.as_deref()).await?;
+    }
+
+    let messages = client
+        .filter_all_messages(&category_clause(&source.display_name))
+        .await?;
+    let (ids, current) = category_snapshots(messages);
+    let results = client
+        .write_categories(current, |categories| {
+            *categories = rename_category(std::mem::take(categories), old, new)
+        })
+        .await?;
+    // Keep the old category around if any message still carries it
+    report_bulk(&ids, results, "Relabeled")?;
+
+    if let Some(id) = &source.id {
+        client.delete_category(id).await?;
+    }
+    info!(
+        "Renamed {} to {} on {} message(s)",
+        source.display_name,
+        new,
+        ids.len()
+    );
     Ok(())
 }
 
-async fn mark_unread(id: String) -> Result<()> {
+/// Swap `old` for `new` in a message's categories, without duplicates
+fn rename_category(categories: Vec<String>, old: &str, new: &str) -> Vec<String> {
+    let mut renamed: Vec<String> = Vec::new();
+    for category in categories {
+        let category = if category.eq_ignore_ascii_case(old) {
+            new.to_string()
+        } else {
+            category
+        };
+        if !renamed.iter().any(|c| c.eq_ignore_ascii_case(&category)) {
+            renamed.push(category);
+        }
+    }
+    renamed
+}
+
+async fn list_labels(output: Output) -> Result<()> {
     let client = get_client().await?;
-    client.mark_unread(&id).await?;
-    println!("Marked as unread: {}", id);
+    let categories = client.list_categories().await?.value.unwrap_or_default();
+    completions::remember_categories(&categories);
+
+    let rows: Vec<Vec<String>> = categories
+        .iter()
+        .map(|c| vec![c.display_name.clone(), c.color.clone().unwrap_or_default()])
+        .collect();
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(&categories)?),
+        Output::Ndjson => print_ndjson(&categories)?,
+        Output::Csv | Output::Tsv => print!(
+            "{}",
+            table::delimited(&["name", "color"], &rows, output.separator().unwrap_or(','))
+        ),
+        Output::Table => print!(
+            "{}",
+            table::render(&["NAME", "COLOR"], &rows, &[0], table::terminal_width())
+        ),
+        Output::Text if categories.is_empty() => info!("No categories found."),
+        Output::Text => {
+            println!("Categories:");
+            for cat in &categories {
+                let color = match cat.color.as_deref() {
+                    Some(preset) => match color::preset_name(preset) {
+                        Some(name) => format!("{}, {}", name, preset),
+                        None => preset.to_string(),
+                    },
+                    None => "none".to_string(),
+                };
+                println!("  {} (color: {})", cat.display_name, color);
+            }
+        }
+    }
     Ok(())
 }
 
-async fn delete_message(id: String) -> Result<()> {
+/// One JSON object per line
+fn print_ndjson<T: serde::Serialize>(items: &[T]) -> Result<()> {
+    for item in items {
+        println!("{}", serde_json::to_string(item)?);
+    }
+    Ok(())
+}
+
+async fn list_folders(output: Output) -> Result<()> {
     let client = get_client().await?;
-    client.trash(&id).await?;
-    println!("Moved to trash {}", id);
+    let folders = client.list_folders().await?.value.unwrap_or_default();
+    completions::remember_folders(&folders);
+
+    let rows: Vec<Vec<String>> = folders
+        .iter()
+        .map(|f| {
+            vec![
+                f.display_name.clone(),
+                f.total_item_count.unwrap_or_default().to_string(),
+                f.unread_item_count.unwrap_or_default().to_string(),
+                f.id.clone(),
+            ]
+        })
+        .collect();
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(&folders)?),
+        Output::Ndjson => print_ndjson(&folders)?,
+        Output::Csv | Output::Tsv => print!(
+            "{}",
+            table::delimited(
+                &["name", "total", "unread", "id"],
+                &rows,
+                output.separator().unwrap_or(',')
+            )
+        ),
+        Output::Table => print!(
+            "{}",
+            table::render(
+                &["NAME", "TOTAL", "UNREAD", "ID"],
+                &rows,
+                &[0, 3],
+                table::terminal_width()
+            )
+        ),
+        Output::Text => {
+            for f in &folders {
+                println!(
+                    "{} ({} messages, {} unread)",
+                    f.display_name,
+                    f.total_item_count.unwrap_or_default(),
+                    f.unread_item_count.unwrap_or_default()
+                );
+            }
+        }
+    }
     Ok(())
 }
 
-async fn unsubscribe(id: String) -> Result<()> {
+async fn sync_labels() -> Result<()> {
     let client = get_client().await?;
-    let msg = client.get_message(&id).await?;
-    if let Some(url) = msg.get_unsubscribe_url() {
-        println!("Opening unsubscribe link: {}", url);
-        open::that(&url)?;
+
+    let master = client.list_categories().await?;
+    let master_names: std::collections::HashSet<String> = master
+        .value
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| c.display_name.to_lowercase())
+        .collect();
+
+    // Every folder, every page: categories only show up on the mail using them
+    let found: Vec<String> = client
+        .used_categories()
+        .await?
+        .into_iter()
+        .filter(|cat| !master_names.contains(&cat.to_lowercase()))
+        .collect();
+
+    if found.is_empty() {
+        info!("All categories are already in master list.");
     } else {
-        anyhow::bail!("No unsubscribe link found in message headers");
+        let results = client.create_categories(&found).await?;
+        let mut created = 0;
+        for (cat, result) in found.iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    info!("Created category: {}", cat);
+                    created += 1;
+                }
+                Err(e) => eprintln!("Failed to create category {}: {}", cat, e),
+            }
+        }
+        info!("Synced {} categories.", created);
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn list_messages(args: ListArgs, output: Output) -> Result<()> {
+    let client = get_client().await?;
+    if output == Output::Ndjson {
+        return stream_messages(&client, &args).await;
+    }
 
-    match cli.command {
-        Commands::Config { client_id } => save_config(client_id)?,
-        Commands::Login { device } => login(device).await?,
-        Commands::Labels => list_labels(cli.json).await?,
-        Commands::SyncLabels => sync_labels().await?,
-        Commands::List {
-            max,
-            query,
-            label,
-            unread,
-        } => list_messages(max, query, label, unread, cli.json).await?,
-        Commands::Read { id } => read_message(id, cli.json).await?,
-        Commands::Archive { id } => archive_message(id).await?,
-        Commands::Spam { id } => spam_message(id).await?,
-        Commands::Unspam { id } => unspam_message(id).await?,
-        Commands::Label { id, label } => add_label(id, label).await?,
-        Commands::Unlabel { id, label } => remove_label(id, label).await?,
-        Commands::ClearLabels { id } => clear_labels(id).await?,
-        Commands::MarkRead { id } => mark_read(id).await?,
-        Commands::MarkUnread { id } => mark_unread(id).await?,
-        Commands::Delete { id } => delete_message(id).await?,
-        Commands::Unsubscribe { id } => unsubscribe(id).await?,
+    let list = fetch_listing(&client, &args, args.max()).await?;
+    let mut messages = list.value.unwrap_or_default();
+    if args.query.is_some() {
+        messages.retain(|m| args.matches_search_result(m));
+    }
+    if let Some(key) = args.local_sort() {
+        sort_messages(&mut messages, key, args.descending());
     }
+    let _pager = pager::start();
+    if let Some(template) = &args.format {
+        let mut short_ids = ids::ShortIds::load()?;
+        for (i, msg) in messages.iter().enumerate() {
+            let short_id = short_ids.shorten(&msg.id);
+            println!("{}", render_message(template, msg, &short_id, Some(i + 1)));
+        }
+        short_ids.save()?;
+        return save_listing(messages.into_iter().map(|m| m.id).collect());
+    }
+    if !args.fields.is_empty() {
+        save_listing(messages.iter().map(|m| m.id.clone()).collect())?;
+        return print_fields(&messages, &args.fields, output, true);
+    }
+    if args.threads {
+        let threads = group_threads(&messages);
+        save_listing(threads.iter().map(|t| t.latest.id.clone()).collect())?;
+        return print_threads(&threads, output);
+    }
+    save_listing(messages.iter().map(|m| m.id.clone()).collect())?;
+    let palette = load_palette(&client, &messages).await;
+    print_messages(&messages, output, true, &palette)
+}
 
-    Ok(())
+/// Offer recent messages in a fuzzy finder and print the picked IDs
+async fn pick_messages(
+    query: Option<String>,
+    label: Option<String>,
+    max: u32,
+    multi: bool,
+) -> Result<()> {
+    if !std::io::stderr().is_terminal() {
+        anyhow::bail!("outlook pick needs a terminal on stderr to draw on");
+    }
+    let client = get_client().await?;
+    let options = api::ListOptions::default();
+    let list = match &query {
+        Some(q) => client.search_messages(q, &options, max).await?,
+        None => {
+            let folder = normalize_folder(label.as_deref().unwrap_or("inbox"));
+            client.list_messages_with(&folder, &options, max).await?
+        }
+    };
+    let messages = list.value.unwrap_or_default();
+    if messages.is_empty() {
+        anyhow::bail!("No messages to pick from");
+    }
+    let lines: Vec<String> = messages
+        .iter()
+        .map(|msg| {
+            let date = msg
+                .received_date_time
+                .as_deref()
+                .map(dates::display_graph)
+                .unwrap_or_default();
+            format!(
+                "{}  {}  {}",
+                date,
+                msg.get_from().unwrap_or_default(),
+                msg.subject.as_deref().unwrap_or("(no subject)")
+            )
+        })
+        .collect();
+
+    let picked = pick::pick(&lines, multi)?;
+    if picked.is_empty() {
+        anyhow::bail!("Nothing picked");
+    }
+    for i in picked {
+        println!("{}", messages[i].id);
+    }
+    Ok(())
+}
+
+/// Category colors for colored output; empty when color is off or no
+/// message has categories, to save the request
+async fn load_palette(client: &api::Client, messages: &[api::Message]) -> color::Palette {
+    let categorized = messages
+        .iter()
+        .any(|m| m.categories.as_ref().is_some_and(|c| !c.is_empty()));
+    if !color::enabled() || !categorized {
+        return color::Palette::new();
+    }
+    match client.list_categories().await {
+        Ok(list) => list
+            .value
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|c| Some((c.display_name.to_lowercase(), c.color?)))
+            .collect(),
+        Err(_) => color::Palette::new(),
+    }
+}
+
+/// First page of a listing: a search when --query is given, otherwise a
+/// filtered and ordered folder listing
+async fn fetch_listing(
+    client: &api::Client,
+    args: &ListArgs,
+    top: u32,
+) -> Result<api::MessageList> {
+    let select = args.select();
+    if let Some(q) = &args.query {
+        let options = api::ListOptions {
+            select: select.as_deref(),
+            include_size: args.include_size(),
+            ..Default::default()
+        };
+        return client.search_messages(q, &options, top).await;
+    }
+    let folder = normalize_folder(args.label.as_deref().unwrap_or("inbox"));
+    let filter = args.filter();
+    let order_by = args.order_by();
+    let options = api::ListOptions {
+        filter: filter.as_deref(),
+        order_by: order_by.as_deref(),
+        select: select.as_deref(),
+        include_size: args.include_size(),
+    };
+    client.list_messages_with(&folder, &options, top).await
+}
+
+/// Print one JSON object per line as pages arrive, up to --max messages
+async fn stream_messages(client: &api::Client, args: &ListArgs) -> Result<()> {
+    if args.threads || args.local_sort().is_some() {
+        anyhow::bail!(
+            "--output ndjson can't be combined with --threads or a locally sorted --sort"
+        );
+    }
+    let max = args.max() as usize;
+    let mut short_ids = ids::ShortIds::load()?;
+    let mut listed = Vec::new();
+    let mut page = fetch_listing(client, args, args.max().min(api::PAGE_SIZE)).await?;
+
+    loop {
+        for msg in page.value.take().unwrap_or_default() {
+            if listed.len() >= max {
+                break;
+            }
+            if args.query.is_some() && !args.matches_search_result(&msg) {
+                continue;
+            }
+            let json = if args.fields.is_empty() {
+                message_json(&msg, &mut short_ids)
+            } else {
+                let short_id = short_ids.shorten(&msg.id);
+                let index = listed.len() + 1;
+                fields_json(&msg, &args.fields, &short_id, Some(index))
+            };
+            println!("{}", json);
+            listed.push(msg.id);
+        }
+        std::io::Write::flush(&mut std::io::stdout())?;
+        match page.next_link.take() {
+            Some(link) if listed.len() < max => page = client.next_page(&link).await?,
+            _ => break,
+        }
+    }
+
+    save_listing(listed)?;
+    short_ids.save()
+}
+
+// Messages sharing a conversationId, summarized by their newest message
+struct Thread<'a> {
+    latest: &'a api::Message,
+    count: usize,
+    unread: usize,
+}
+
+/// Group messages by conversation, keeping the order in which each conversation
+/// first appears (newest first for date-sorted listings)
+fn group_threads(messages: &[api::Message]) -> Vec<Thread<'_>> {
+    let mut threads: Vec<Thread> = Vec::new();
+    let mut index: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+
+    for msg in messages {
+        let key = msg.conversation_id.as_deref().unwrap_or(&msg.id);
+        let unread = usize::from(msg.is_read == Some(false));
+        match index.get(key) {
+            Some(&i) => {
+                let thread = &mut threads[i];
+                thread.count += 1;
+                thread.unread += unread;
+                if msg.received_date_time > thread.latest.received_date_time {
+                    thread.latest = msg;
+                }
+            }
+            None => {
+                index.insert(key, threads.len());
+                threads.push(Thread {
+                    latest: msg,
+                    count: 1,
+                    unread,
+                });
+            }
+        }
+    }
+    threads
+}
+
+fn print_threads(threads: &[Thread], output: Output) -> Result<()> {
+    let mut short_ids = ids::ShortIds::load()?;
+    if output == Output::Json {
+        let items: Vec<_> = threads
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "conversationId": t.latest.conversation_id,
+                    "latestId": t.latest.id,
+                    "shortId": short_ids.shorten(&t.latest.id),
+                    "from": t.latest.get_from(),
+                    "subject": t.latest.subject,
+                    "date": t.latest.received_date_time,
+                    "messages": t.count,
+                    "unread": t.unread,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&items)?);
+    } else if let Some(sep) = output.separator() {
+        let rows: Vec<Vec<String>> = threads
+            .iter()
+            .map(|t| {
+                vec![
+                    t.latest.conversation_id.clone().unwrap_or_default(),
+                    t.latest.id.clone(),
+                    short_ids.shorten(&t.latest.id),
+                    t.latest.get_from().unwrap_or_default(),
+                    t.latest.subject.clone().unwrap_or_default(),
+                    t.latest.received_date_time.clone().unwrap_or_default(),
+                    t.count.to_string(),
+                    t.unread.to_string(),
+                ]
+            })
+            .collect();
+        let headers = [
+            "conversationId",
+            "latestId",
+            "shortId",
+            "from",
+            "subject",
+            "date",
+            "messages",
+            "unread",
+        ];
+        print!("{}", table::delimited(&headers, &rows, sep));
+    } else if threads.is_empty() {
+        info!("No messages found.");
+    } else if output == Output::Table {
+        let rows: Vec<Vec<String>> = threads
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                vec![
+                    format!("@{}", i + 1),
+                    short_ids.shorten(&t.latest.id),
+                    t.count.to_string(),
+                    short_date(t.latest.received_date_time.as_deref()),
+                    t.latest.get_from().unwrap_or_default(),
+                    t.latest.subject.clone().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        print!(
+            "{}",
+            table::render(
+                &["#", "ID", "MSGS", "DATE", "FROM", "SUBJECT"],
+                &rows,
+                &[4, 5],
+                table::terminal_width()
+            )
+        );
+    } else {
+        for (i, t) in threads.iter().enumerate() {
+            println!(
+                "@{:<3} {} | {} msg{} | {} | {}",
+                i + 1,
+                short_ids.shorten(&t.latest.id),
+                t.count,
+                if t.count == 1 { "" } else { "s" },
+                t.latest
+                    .received_date_time
+                    .as_deref()
+                    .map_or_else(|| "Unknown".to_string(), dates::display_graph),
+                t.latest.subject.as_deref().unwrap_or("(no subject)")
+            );
+        }
+    }
+    short_ids.save()
+}
+
+fn message_json(msg: &api::Message, short_ids: &mut ids::ShortIds) -> serde_json::Value {
+    serde_json::json!({
+        "id": msg.id,
+        "shortId": short_ids.shorten(&msg.id),
+        "from": msg.get_from(),
+        "subject": msg.subject,
+        "date": msg.received_date_time,
+        "snippet": msg.body_preview,
+        "isRead": msg.is_read,
+        "categories": msg.categories,
+        "hasAttachments": msg.has_attachments,
+    })
+}
+
+// Fields available to --format, named like the --json keys
+const MESSAGE_FIELDS: &[&str] = &[
+    "index",
+    "id",
+    "shortId",
+    "from",
+    "to",
+    "subject",
+    "date",
+    "snippet",
+    "body",
+    "isRead",
+    "categories",
+    "hasAttachments",
+    "webLink",
+    "size",
+];
+
+/// The Graph property behind a message field; `None` for fields that are
+/// always present (id) or computed (index, shortId, size)
+fn field_property(field: &str) -> Option<&'static str> {
+    match field {
+        "from" => Some("from"),
+        "to" => Some("toRecipients"),
+        "subject" => Some("subject"),
+        "date" => Some("receivedDateTime"),
+        "snippet" => Some("bodyPreview"),
+        "body" => Some("body"),
+        "isRead" => Some("isRead"),
+        "categories" => Some("categories"),
+        "hasAttachments" => Some("hasAttachments"),
+        "webLink" => Some("webLink"),
+        _ => None,
+    }
+}
+
+/// One message field as JSON, named like the --json keys
+fn message_field(
+    msg: &api::Message,
+    name: &str,
+    short_id: &str,
+    index: Option<usize>,
+) -> serde_json::Value {
+    use serde_json::json;
+
+    match name {
+        "index" => json!(index.map(|i| format!("@{}", i))),
+        "id" => json!(msg.id),
+        "shortId" => json!(short_id),
+        "from" => json!(msg.get_from()),
+        "to" => json!(msg.get_to()),
+        "subject" => json!(msg.subject),
+        "date" => json!(msg.received_date_time),
+        "snippet" => json!(msg.body_preview),
+        "body" => json!(msg.get_body_plain(wrap_width())),
+        "isRead" => json!(msg.is_read),
+        "categories" => json!(msg.categories),
+        "hasAttachments" => json!(msg.has_attachments),
+        "webLink" => json!(msg.web_link),
+        "size" => json!(msg.get_size()),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn fields_json(
+    msg: &api::Message,
+    fields: &[String],
+    short_id: &str,
+    index: Option<usize>,
+) -> serde_json::Value {
+    let object: serde_json::Map<String, serde_json::Value> = fields
+        .iter()
+        .map(|f| (f.clone(), message_field(msg, f, short_id, index)))
+        .collect();
+    serde_json::Value::Object(object)
+}
+
+/// A field as a single text cell; dates are shown like the other listings
+fn field_text(name: &str, value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) if name == "date" => dates::display_graph(s),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(|item| field_text(name, item))
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    }
+}
+
+/// Print only the chosen fields, in the chosen order, for every output format
+fn print_fields(
+    messages: &[api::Message],
+    fields: &[String],
+    output: Output,
+    numbered: bool,
+) -> Result<()> {
+    let mut short_ids = ids::ShortIds::load()?;
+    let objects: Vec<serde_json::Value> = messages
+        .iter()
+        .enumerate()
+        .map(|(i, msg)| {
+            let short_id = short_ids.shorten(&msg.id);
+            fields_json(msg, fields, &short_id, numbered.then_some(i + 1))
+        })
+        .collect();
+    short_ids.save()?;
+
+    let rows: Vec<Vec<String>> = objects
+        .iter()
+        .map(|obj| fields.iter().map(|f| field_text(f, &obj[f])).collect())
+        .collect();
+    let headers: Vec<&str> = fields.iter().map(String::as_str).collect();
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(&objects)?),
+        Output::Ndjson => print_ndjson(&objects)?,
+        Output::Csv | Output::Tsv => {
+            let sep = output.separator().unwrap_or(',');
+            print!("{}", table::delimited(&headers, &rows, sep));
+        }
+        Output::Table => {
+            let upper: Vec<String> = headers.iter().map(|h| h.to_uppercase()).collect();
+            let upper: Vec<&str> = upper.iter().map(String::as_str).collect();
+            let shrinkable: Vec<usize> = (0..fields.len()).collect();
+            print!(
+                "{}",
+                table::render(&upper, &rows, &shrinkable, table::terminal_width())
+            );
+        }
+        Output::Text => {
+            for row in &rows {
+                println!("{}", row.join(" | "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_message_format(input: &str) -> Result<template::Template> {
+    template::parse(input)?.check(MESSAGE_FIELDS)
+}
+
+fn render_message(
+    template: &template::Template,
+    msg: &api::Message,
+    short_id: &str,
+    index: Option<usize>,
+) -> String {
+    use template::Value;
+
+    template.render(|name| {
+        if name == "date" {
+            return msg
+                .received_date_time
+                .as_deref()
+                .and_then(dates::parse_graph_time)
+                .map(Value::Date);
+        }
+        match message_field(msg, name, short_id, index) {
+            serde_json::Value::Null => None,
+            value => Some(Value::Text(field_text(name, &value))),
+        }
+    })
+}
+
+/// "2024-05-15T10:00:00Z" -> "2024-05-15 12:00" in local time, or "2h ago"
+fn short_date(value: Option<&str>) -> String {
+    value.map(dates::display_graph).unwrap_or_default()
+}
+
+/// Print messages under their short IDs; `numbered` prefixes the `@N`
+/// positions saved by `list`
+fn print_messages(
+    messages: &[api::Message],
+    output: Output,
+    numbered: bool,
+    palette: &color::Palette,
+) -> Result<()> {
+    let mut short_ids = ids::ShortIds::load()?;
+    if matches!(output, Output::Json | Output::Ndjson) {
+        let items: Vec<_> = messages
+            .iter()
+            .map(|msg| message_json(msg, &mut short_ids))
+            .collect();
+        if output == Output::Json {
+            println!("{}", serde_json::to_string(&items)?);
+        } else {
+            print_ndjson(&items)?;
+        }
+    } else if let Some(sep) = output.separator() {
+        let rows: Vec<Vec<String>> = messages
+            .iter()
+            .map(|msg| {
+                vec![
+                    msg.id.clone(),
+                    short_ids.shorten(&msg.id),
+                    msg.received_date_time.clone().unwrap_or_default(),
+                    msg.get_from().unwrap_or_default(),
+                    msg.subject.clone().unwrap_or_default(),
+                    msg.is_read.unwrap_or_default().to_string(),
+                    msg.has_attachments.unwrap_or_default().to_string(),
+                    msg.categories.as_deref().unwrap_or_default().join(";"),
+                ]
+            })
+            .collect();
+        let headers = [
+            "id",
+            "shortId",
+            "date",
+            "from",
+            "subject",
+            "isRead",
+            "hasAttachments",
+            "categories",
+        ];
+        print!("{}", table::delimited(&headers, &rows, sep));
+    } else if messages.is_empty() {
+        info!("No messages found.");
+    } else if output == Output::Table {
+        let rows: Vec<Vec<String>> = messages
+            .iter()
+            .enumerate()
+            .map(|(i, msg)| {
+                let mut row = vec![
+                    short_ids.shorten(&msg.id),
+                    short_date(msg.received_date_time.as_deref()),
+                    if msg.has_attachments == Some(true) {
+                        "@"
+                    } else {
+                        ""
+                    }
+                    .to_string(),
+                    msg.get_from().unwrap_or_default(),
+                    msg.subject.clone().unwrap_or_default(),
+                ];
+                if numbered {
+                    row.insert(0, format!("@{}", i + 1));
+                }
+                row
+            })
+            .collect();
+        let mut headers = vec!["ID", "DATE", "A", "FROM", "SUBJECT"];
+        let mut shrinkable = vec![3, 4];
+        if numbered {
+            headers.insert(0, "#");
+            shrinkable = vec![4, 5];
+        }
+        let rendered = table::render(&headers, &rows, &shrinkable, table::terminal_width());
+        for (i, line) in rendered.lines().enumerate() {
+            // Line 0 is the header row
+            match i.checked_sub(1).map(|row| &messages[row]) {
+                Some(msg) if msg.is_read == Some(false) => println!("{}", color::bold(line)),
+                _ => println!("{}", line),
+            }
+        }
+    } else {
+        for (i, msg) in messages.iter().enumerate() {
+            if numbered {
+                print!("@{:<3} ", i + 1);
+            }
+            let from = msg.get_from().unwrap_or_else(|| "Unknown".to_string());
+            let subject = msg.subject.as_deref().unwrap_or("(no subject)");
+            let subject = if msg.is_read == Some(false) {
+                color::bold(subject)
+            } else {
+                subject.to_string()
+            };
+            let attachment = if msg.has_attachments == Some(true) {
+                "@"
+            } else {
+                " "
+            };
+            let categories: Vec<String> = msg
+                .categories
+                .iter()
+                .flatten()
+                .map(|c| color::category(c, palette))
+                .collect();
+            println!(
+                "{} | {} | {} | {}{}",
+                short_ids.shorten(&msg.id),
+                attachment,
+                color::dim(&from),
+                subject,
+                if categories.is_empty() {
+                    String::new()
+                } else {
+                    format!(" {}", categories.join(" "))
+                }
+            );
+        }
+    }
+    short_ids.save()
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Render {
+    /// Plain text with numbered link footnotes
+    Text,
+    /// Markdown, styled with ANSI escapes when printing to a terminal
+    Markdown,
+}
+
+/// Render HTML with bold/italic/underlined links as ANSI escapes
+fn render_terminal(html: &str, width: usize) -> String {
+    use html2text::render::RichAnnotation;
+
+    let style = |annotations: &[RichAnnotation], text: &str| {
+        let codes: Vec<&str> = annotations
+            .iter()
+            .filter_map(|a| match a {
+                RichAnnotation::Strong => Some("1"),
+                RichAnnotation::Emphasis => Some("3"),
+                RichAnnotation::Link(_) => Some("4;34"),
+                RichAnnotation::Strikeout => Some("9"),
+                RichAnnotation::Code | RichAnnotation::Preformat(_) => Some("2"),
+                _ => None,
+            })
+            .collect();
+        if codes.is_empty() {
+            text.to_string()
+        } else {
+            format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text)
+        }
+    };
+    html2text::config::rich()
+        .coloured(html.as_bytes(), width, style)
+        .unwrap_or_else(|_| html.to_string())
+}
+
+// Column to wrap rendered message bodies at
+const MAX_WRAP_WIDTH: usize = 100;
+
+fn wrap_width() -> usize {
+    table::terminal_width().clamp(20, MAX_WRAP_WIDTH)
+}
+
+async fn headers(id: String, name: Option<String>, json: bool) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let msg = client.get_message(&id).await?;
+
+    if let Some(name) = name {
+        let value = msg
+            .get_header(&name)
+            .ok_or_else(|| anyhow::anyhow!("No {} header", name))?;
+        if json {
+            println!("{}", serde_json::json!({ "name": name, "value": value }));
+        } else {
+            println!("{}: {}", name, value);
+        }
+        return Ok(());
+    }
+
+    let headers = msg.internet_message_headers.unwrap_or_default();
+    if json {
+        let items: Vec<_> = headers
+            .iter()
+            .map(|h| serde_json::json!({ "name": h.name, "value": h.value }))
+            .collect();
+        println!("{}", serde_json::to_string(&items)?);
+    } else {
+        for h in &headers {
+            println!("{}: {}", h.name, h.value);
+        }
+    }
+    Ok(())
+}
+
+async fn translate_ids(ids: Vec<String>, reverse: bool, json: bool) -> Result<()> {
+    let ids = resolve_targets(ids)?;
+    let client = get_client().await?;
+    let translated = client.translate_ids(&ids, !reverse).await?;
+    if json {
+        println!("{}", serde_json::to_string(&translated)?);
+        return Ok(());
+    }
+    let mut failed = 0;
+    for item in &translated {
+        match &item.target_id {
+            Some(target) => println!("{}\t{}", item.source_id, target),
+            None => {
+                eprintln!("Could not translate {}", item.source_id);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} IDs could not be translated",
+            failed,
+            translated.len()
+        );
+    }
+    Ok(())
+}
+
+async fn links(id: String, domain: Option<String>, json: bool) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let msg = client.get_message(&id).await?;
+
+    let links: Vec<String> = msg
+        .get_links()
+        .into_iter()
+        .filter(|link| domain.as_deref().is_none_or(|d| link_in_domain(link, d)))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string(&links)?);
+    } else {
+        for link in &links {
+            println!("{}", link);
+        }
+    }
+    Ok(())
+}
+
+fn link_in_domain(link: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    url::Url::parse(link)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_lowercase))
+        .is_some_and(|host| host == domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Print a message's MIME source exactly as Graph returns it
+async fn read_raw(id: String, mark_read: bool) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let mime = client.get_mime(&id).await?;
+    if mark_read {
+        client.mark_read(&id, None).await?;
+    }
+    let _pager = pager::start();
+    std::io::Write::write_all(&mut std::io::stdout(), &mime)?;
+    Ok(())
+}
+
+/// Write the HTML body to a temp file, inlining cid: images, and open it
+async fn open_in_browser(id: String, mark_read: bool) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let msg = client.get_message(&id).await?;
+    let body = msg.get_body_text().unwrap_or_default();
+    let html = if msg.is_html() {
+        let attachments = client.list_attachments(&id).await?;
+        inline_cid_images(&body, &attachments)
+    } else {
+        format!("<pre>{}</pre>", escape_html(&body))
+    };
+
+    // Mail content: keep the temp file private to the user. It is left in
+    // place, as the browser may not have read it by the time we exit.
+    let path = secure::temp_file("outlook-", ".html", html.as_bytes())?.keep()?;
+    info!("Opening {}", path.display());
+    open::that(&path)?;
+
+    if mark_read && msg.is_read != Some(true) {
+        client.mark_read(&id, msg.etag.as_deref()).await?;
+    }
+    Ok(())
+}
+
+/// Replace `cid:` references with data: URLs built from inline attachments
+fn inline_cid_images(html: &str, attachments: &[api::Attachment]) -> String {
+    let mut html = html.to_string();
+    for attachment in attachments {
+        let (Some(cid), Some(bytes)) = (&attachment.content_id, &attachment.content_bytes) else {
+            continue;
+        };
+        let cid = cid.trim_start_matches('<').trim_end_matches('>');
+        let content_type = attachment
+            .content_type
+            .as_deref()
+            .unwrap_or("application/octet-stream");
+        html = html.replace(
+            &format!("cid:{}", cid),
+            &format!("data:{};base64,{}", content_type, bytes),
+        );
+    }
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// `read --fields`: fetch only the chosen properties and print them as
+/// `name: value` lines, or one JSON object
+async fn read_fields(id: String, mark_read: bool, fields: &[String], output: Output) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let mut properties = vec!["id", "isRead"];
+    properties.extend(fields.iter().filter_map(|f| field_property(f)));
+    properties.sort_unstable();
+    properties.dedup();
+    let include_size = fields.iter().any(|f| f == "size");
+    let msg = client
+        .get_message_select(&id, &properties.join(","), include_size)
+        .await?;
+    if mark_read && msg.is_read != Some(true) {
+        client.mark_read(&id, msg.etag.as_deref()).await?;
+    }
+
+    let mut short_ids = ids::ShortIds::load()?;
+    let short_id = short_ids.shorten(&msg.id);
+    short_ids.save()?;
+    let object = fields_json(&msg, fields, &short_id, None);
+    let _pager = pager::start();
+    if matches!(output, Output::Json | Output::Ndjson) {
+        println!("{}", object);
+    } else {
+        for field in fields {
+            println!("{}: {}", field, field_text(field, &object[field]));
+        }
+    }
+    Ok(())
+}
+
+/// Render a message body; `None` prints the raw content
+async fn read_message(
+    id: String,
+    mark_read: bool,
+    render: Option<Render>,
+    text_body: bool,
+    format: Option<template::Template>,
+    json: bool,
+) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let msg = if text_body {
+        client.get_message_text(&id).await?
+    } else {
+        client.get_message(&id).await?
+    };
+    if mark_read && msg.is_read != Some(true) {
+        client.mark_read(&id, msg.etag.as_deref()).await?;
+    }
+
+    let _pager = pager::start();
+    if let Some(template) = format {
+        let short_id = ids::ShortIds::load()?.shorten(&msg.id);
+        println!("{}", render_message(&template, &msg, &short_id, None));
+    } else if json {
+        println!(
+            "{}",
+            serde_json::to_string(&serde_json::json!({
+                "id": msg.id,
+                "from": msg.get_from(),
+                "to": msg.get_to(),
+                "subject": msg.subject,
+                "date": msg.received_date_time,
+                "body": msg.get_body_text(),
+                "text": msg.get_body_plain(wrap_width()),
+                "snippet": msg.body_preview,
+                "isRead": msg.is_read,
+                "categories": msg.categories,
+            }))?
+        );
+    } else {
+        println!(
+            "From: {}",
+            color::dim(&msg.get_from().unwrap_or_else(|| "Unknown".to_string()))
+        );
+        println!(
+            "To: {}",
+            msg.get_to().unwrap_or_else(|| "Unknown".to_string())
+        );
+        println!(
+            "Subject: {}",
+            color::bold(msg.subject.as_deref().unwrap_or("(no subject)"))
+        );
+        println!(
+            "Date: {}",
+            msg.received_date_time
+                .as_deref()
+                .map_or_else(|| "Unknown".to_string(), dates::display_graph)
+        );
+        println!("---");
+
+        let body = match render {
+            None => msg.get_body_text(),
+            // Markdown is styled for a terminal, and left as Markdown
+            // (headings, lists, quotes, reference links) for pipes
+            Some(Render::Markdown) if std::io::stdout().is_terminal() && msg.is_html() => msg
+                .get_body_text()
+                .map(|html| render_terminal(&html, wrap_width())),
+            Some(_) => msg.get_body_plain(wrap_width()),
+        };
+        if let Some(body) = body {
+            println!("{}", body);
+        } else if let Some(preview) = &msg.body_preview {
+            println!("{}", preview);
+        }
+    }
+    Ok(())
+}
+
+const LAST_LIST_FILE: &str = "last_list.json";
+
+/// Remember the IDs shown by `list` so later commands can refer to `@N`
+fn save_listing(ids: Vec<String>) -> Result<()> {
+    config::save_state(LAST_LIST_FILE, &ids)
+}
+
+/// `@3`, `@1-5` or `@last` (as opposed to an `@domain` sender)
+fn is_ref(arg: &str) -> bool {
+    arg.strip_prefix('@').is_some_and(|spec| {
+        spec == "last" || (!spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit() || c == '-'))
+    })
+}
+
+/// Expand a position reference against the IDs of the last listing
+fn expand_ref(reference: &str, listing: &[String]) -> Result<Vec<String>> {
+    let spec = reference.trim_start_matches('@');
+    let position = |s: &str| -> Result<usize> {
+        let n = if s == "last" {
+            listing.len()
+        } else {
+            s.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid message reference '{}'", reference))?
+        };
+        if n == 0 || n > listing.len() {
+            anyhow::bail!(
+                "{} is out of range (the last listing had {} messages)",
+                reference,
+                listing.len()
+            );
+        }
+        Ok(n)
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (position(start)?, position(end)?),
+        None => (position(spec)?, position(spec)?),
+    };
+    if start > end {
+        anyhow::bail!("Invalid range '{}'", reference);
+    }
+    Ok(listing[start - 1..end].to_vec())
+}
+
+/// Resolve a single message argument that may be an `@N` reference
+fn resolve_id(id: String) -> Result<String> {
+    if !is_ref(&id) {
+        return ids::ShortIds::load()?.resolve(&id);
+    }
+    let listing: Vec<String> = config::load_state(LAST_LIST_FILE)?;
+    let mut ids = expand_ref(&id, &listing)?;
+    if ids.len() != 1 {
+        anyhow::bail!("{} refers to {} messages; expected one", id, ids.len());
+    }
+    Ok(ids.remove(0))
+}
+
+/// Expand message ID arguments; `-` reads IDs from stdin, either one per
+/// line/word or as the JSON emitted by `outlook list --json`
+fn resolve_targets(ids: Vec<String>) -> Result<Vec<String>> {
+    let mut targets = Vec::new();
+    let mut listing: Option<Vec<String>> = None;
+    let short_ids = ids::ShortIds::load()?;
+    for id in ids {
+        if id == "-" {
+            let mut input = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)?;
+            for id in parse_target_input(&input) {
+                targets.push(short_ids.resolve(&id)?);
+            }
+        } else if is_ref(&id) {
+            if listing.is_none() {
+                listing = Some(config::load_state(LAST_LIST_FILE)?);
+            }
+            targets.extend(expand_ref(&id, listing.as_deref().unwrap_or_default())?);
+        } else {
+            targets.push(short_ids.resolve(&id)?);
+        }
+    }
+    if targets.is_empty() {
+        anyhow::bail!("No message IDs given");
+    }
+    Ok(targets)
+}
+
+fn parse_target_input(input: &str) -> Vec<String> {
+    fn collect_ids(value: &serde_json::Value, out: &mut Vec<String>) {
+        match value {
+            serde_json::Value::String(id) => out.push(id.clone()),
+            serde_json::Value::Array(items) => items.iter().for_each(|v| collect_ids(v, out)),
+            serde_json::Value::Object(obj) => {
+                if let Some(serde_json::Value::String(id)) = obj.get("id") {
+                    out.push(id.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut ids = Vec::new();
+    for value in serde_json::Deserializer::from_str(input).into_iter::<serde_json::Value>() {
+        match value {
+            Ok(value) => collect_ids(&value, &mut ids),
+            Err(_) => return input.split_whitespace().map(str::to_string).collect(),
+        }
+    }
+    ids
+}
+
+/// Ask before a destructive operation on several messages, as in "Delete 42
+/// messages? [y/N]". `--yes` answers for the user, and is required when
+/// there's no terminal to ask on. False if the user said no.
+fn confirm(action: &str, count: usize, yes: bool) -> Result<bool> {
+    // A dry run changes nothing, so there's nothing to confirm
+    if count <= 1 || yes || status::dry_run() {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        anyhow::bail!(
+            "Not confirmed: {} {} messages. Pass --yes to go ahead.",
+            action,
+            count
+        );
+    }
+    eprint!("{} {} messages? [y/N] ", action, count);
+    std::io::Write::flush(&mut std::io::stderr())?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let confirmed = is_yes(&answer);
+    if !confirmed {
+        note!("Cancelled");
+    }
+    Ok(confirmed)
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Print one line per target plus a summary, and fail if any of them failed
+fn report_bulk(ids: &[String], results: Vec<Result<()>>, done: &str) -> Result<()> {
+    let mut failed = 0;
+    for (id, result) in ids.iter().zip(results) {
+        match result {
+            Ok(()) => info!("{} {}", done, id),
+            Err(e) => {
+                eprintln!("Failed on {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+    if ids.len() > 1 {
+        note!("{} succeeded, {} failed", ids.len() - failed, failed);
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} messages failed", failed, ids.len());
+    }
+    Ok(())
+}
+
+async fn bulk_client(concurrency: usize) -> Result<api::Client> {
+    Ok(get_client().await?.with_concurrency(concurrency))
+}
+
+async fn move_messages(
+    ids: Vec<String>,
+    folder: &str,
+    done: &str,
+    concurrency: usize,
+) -> Result<()> {
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    let results = move_and_hook(&client, &ids, folder).await?;
+    report_bulk(&ids, results, done)
+}
+
+/// Move messages via $batch, journaling where they came from for `undo`, and
+/// run the post_archive hook on each one moved to the Archive folder
+async fn move_and_hook(
+    client: &api::Client,
+    ids: &[String],
+    destination: &str,
+) -> Result<Vec<Result<()>>> {
+    let before = undo::snapshot(client, ids).await;
+    let moved = client.move_messages_returning(ids, destination).await?;
+    undo::record_moves(destination, &before, &moved);
+    if destination != "archive" || hooks::command(hooks::Hook::PostArchive).is_none() {
+        return Ok(moved.into_iter().map(|r| r.map(|_| ())).collect());
+    }
+    let mut short_ids = ids::ShortIds::load()?;
+    let archived: Vec<serde_json::Value> = moved
+        .iter()
+        .filter_map(|r| r.as_ref().ok())
+        .map(|msg| message_json(msg, &mut short_ids))
+        .collect();
+    short_ids.save()?;
+    hooks::run_each(hooks::Hook::PostArchive, &archived).await;
+    Ok(moved.into_iter().map(|r| r.map(|_| ())).collect())
+}
+
+/// Find every message matching a query and/or age, following all result pages.
+/// Searches cover all folders when `folder` is None; age-only selection uses the inbox.
+async fn find_matching(
+    client: &api::Client,
+    folder: Option<&str>,
+    select: &QueryArgs,
+) -> Result<Vec<api::Message>> {
+    let cutoff = select
+        .older_than
+        .as_deref()
+        .map(dates::cutoff)
+        .transpose()?;
+
+    let messages = match &select.query {
+        Some(q) => client.search_all_messages(folder, q).await?,
+        None => {
+            let filter =
+                cutoff.map(|c| format!("receivedDateTime lt {}", dates::odata_timestamp(c)));
+            client
+                .list_all_messages(folder.unwrap_or("inbox"), filter.as_deref())
+                .await?
+        }
+    };
+
+    // $search can't be combined with a $filter, so apply the age limit locally
+    Ok(match cutoff {
+        Some(cutoff) => messages
+            .into_iter()
+            .filter(|msg| {
+                msg.received_date_time
+                    .as_deref()
+                    .and_then(dates::parse_graph_time)
+                    .is_some_and(|received| received < cutoff)
+            })
+            .collect(),
+        None => messages,
+    })
+}
+
+/// Move every message matching `select` out of `folder`, or list them when previewing
+async fn move_matching(
+    select: &QueryArgs,
+    folder: Option<&str>,
+    destination: &str,
+    done: &str,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let messages = find_matching(&client, folder, select).await?;
+    move_found(
+        &client,
+        messages,
+        status::dry_run(),
+        destination,
+        done,
+        json,
+    )
+    .await
+}
+
+/// Move already-selected messages, or just list them when previewing
+async fn move_found(
+    client: &api::Client,
+    messages: Vec<api::Message>,
+    preview: bool,
+    destination: &str,
+    done: &str,
+    json: bool,
+) -> Result<()> {
+    if preview {
+        let output = if json { Output::Json } else { Output::Text };
+        print_messages(&messages, output, false, &color::Palette::new())?;
+        if !json {
+            note!("{} messages match.", messages.len());
+        }
+        return Ok(());
+    }
+    if messages.is_empty() {
+        info!("No matching messages.");
+        return Ok(());
+    }
+
+    let ids: Vec<String> = messages.into_iter().map(|msg| msg.id).collect();
+    let results = move_and_hook(client, &ids, destination).await?;
+    let succeeded = results.iter().filter(|r| r.is_ok()).count();
+    let outcome = report_bulk(&ids, results, done);
+    info!("{} {} of {} matching messages.", done, succeeded, ids.len());
+    outcome
+}
+
+async fn archive_message(
+    ids: Vec<String>,
+    select: QueryArgs,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    if select.is_set() {
+        return move_matching(
+            &select,
+            Some("inbox"),
+            "archive",
+            "Archived",
+            json,
+            concurrency,
+        )
+        .await;
+    }
+    move_messages(ids, "archive", "Archived", concurrency).await
+}
+
+/// Whether an attachment matches `--type`: a file extension, a full content
+/// type, or a content type prefix ending in "/"
+fn attachment_matches(attachment: &api::Attachment, kind: &str) -> bool {
+    let kind = kind.trim_start_matches('.').to_lowercase();
+    if kind.contains('/') {
+        let content_type = attachment
+            .content_type
+            .as_deref()
+            .unwrap_or("")
+            .to_lowercase();
+        return if kind.ends_with('/') {
+            content_type.starts_with(&kind)
+        } else {
+            content_type == kind
+        };
+    }
+    attachment
+        .name
+        .as_deref()
+        .and_then(|name| name.rsplit_once('.'))
+        .is_some_and(|(_, ext)| ext.eq_ignore_ascii_case(&kind))
+}
+
+/// A file or directory name that can't escape its parent directory
+fn safe_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+    if cleaned.is_empty() {
+        "attachment".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// `dir/name`, or `dir/name (2).ext` and so on if that file already exists
+fn unused_path(dir: &std::path::Path, name: &str) -> std::path::PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    (2..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|p| !p.exists())
+        .expect("some numbered name is free")
+}
+
+async fn attachments(
+    ids: Vec<String>,
+    select: QueryArgs,
+    save: Option<PathBuf>,
+    kind: Option<String>,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let messages = if select.is_set() {
+        find_matching(&client, None, &select)
+            .await?
+            .into_iter()
+            .filter(|msg| msg.has_attachments == Some(true))
+            .collect()
+    } else {
+        let mut messages = Vec::new();
+        for id in resolve_targets(ids)? {
+            messages.push(client.get_message(&id).await?);
+        }
+        messages
+    };
+
+    let mut short_ids = ids::ShortIds::load()?;
+    let mut report = Vec::new();
+    for msg in &messages {
+        let files = client.list_attachment_info(&msg.id).await?;
+        let files = files.iter().filter(|a| {
+            a.is_inline != Some(true) && kind.as_deref().is_none_or(|k| attachment_matches(a, k))
+        });
+        for attachment in files {
+            let name = safe_file_name(attachment.name.as_deref().unwrap_or(""));
+            let short_id = short_ids.shorten(&msg.id);
+            // Attached messages and cloud links have no file to save
+            if save.is_some() && !attachment.is_file() {
+                note!(
+                    "Skipped {} in {}: {} attachments can't be saved",
+                    name,
+                    short_id,
+                    attachment.kind()
+                );
+                report.push(serde_json::json!({
+                    "messageId": msg.id,
+                    "shortId": short_id,
+                    "name": name,
+                    "contentType": attachment.content_type,
+                    "bytes": attachment.size,
+                    "path": null,
+                    "skipped": attachment.kind(),
+                }));
+                continue;
+            }
+            let path = match &save {
+                Some(dir) if !status::dry_run() => {
+                    let sender = safe_file_name(msg.get_from_address().unwrap_or("unknown"));
+                    let date = msg
+                        .received_date_time
+                        .as_deref()
+                        .and_then(dates::parse_graph_time)
+                        .map(|dt| dt.with_timezone(&chrono::Local).date_naive().to_string())
+                        .unwrap_or_else(|| "undated".to_string());
+                    let dir = dir.join(sender).join(date);
+                    std::fs::create_dir_all(&dir)
+                        .with_context(|| format!("Failed to create {}", dir.display()))?;
+                    let path = unused_path(&dir, &name);
+                    let content = client
+                        .get_attachment_content(&msg.id, &attachment.id)
+                        .await?;
+                    std::fs::write(&path, content)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    Some(path)
+                }
+                _ => None,
+            };
+
+            if !json {
+                match &path {
+                    Some(path) => println!("Saved {}", path.display()),
+                    None => println!(
+                        "{}  {}  {}  {}",
+                        short_id,
+                        name,
+                        stats::human_size(attachment.size.unwrap_or(0)),
+                        attachment.content_type.as_deref().unwrap_or("")
+                    ),
+                }
+            }
+            report.push(serde_json::json!({
+                "messageId": msg.id,
+                "shortId": short_id,
+                "name": name,
+                "contentType": attachment.content_type,
+                "bytes": attachment.size,
+                "path": path,
+            }));
+        }
+    }
+    short_ids.save()?;
+
+    if json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else if report.is_empty() {
+        info!("No attachments found.");
+    }
+    Ok(())
+}
+
+async fn classify(id: String, tab: Classification, always: bool) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    client.set_classification(&id, tab.as_str()).await?;
+    info!("Moved {} to {}", id, tab.as_str());
+
+    if always {
+        let msg = client.get_message(&id).await?;
+        let sender = msg
+            .from
+            .ok_or_else(|| anyhow::anyhow!("Message has no sender"))?
+            .email_address;
+        let address = sender.address.clone().unwrap_or_default();
+        client
+            .create_classification_override(&sender, tab.as_str())
+            .await?;
+        info!("Mail from {} will go to {}", address, tab.as_str());
+    }
+    Ok(())
+}
+
+async fn report(id: String, kind: ReportKind, no_move: bool) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let me = client.me().await?;
+    let (category, block, folder, label) = match kind {
+        ReportKind::Junk => ("spam", true, "junkemail", "junk"),
+        ReportKind::Phishing => ("phishing", true, "junkemail", "phishing"),
+        ReportKind::NotJunk => ("spam", false, "inbox", "not junk"),
+    };
+    client
+        .report_message(&id, me.address(), category, block)
+        .await
+        .map_err(|e| {
+            let forbidden = e
+                .chain()
+                .filter_map(|c| c.downcast_ref::<api::GraphError>())
+                .any(|g| g.status == 403);
+            if forbidden {
+                e.context("Reporting needs the ThreatAssessment.ReadWrite.All permission")
+            } else {
+                e
+            }
+        })?;
+    info!("Reported as {}", label);
+
+    if !no_move {
+        client.move_message(&id, folder).await?;
+        info!("Moved to {}", if block { "Junk" } else { "Inbox" });
+    }
+    Ok(())
+}
+
+async fn spam_message(ids: Vec<String>, concurrency: usize) -> Result<()> {
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    // Only open unsubscribe pages for a single message; bulk runs would spawn a tab per message
+    if let [id] = ids.as_slice() {
+        let msg = client.get_message(id).await?;
+        if let Some(url) = msg.get_unsubscribe_url()
+            && url.starts_with("http")
+        {
+            let _ = open::that(&url);
+        }
+    }
+    let results = move_and_hook(&client, &ids, "junkemail").await?;
+    report_bulk(&ids, results, "Marked as spam")
+}
+
+async fn unspam_message(ids: Vec<String>, concurrency: usize) -> Result<()> {
+    move_messages(ids, "inbox", "Moved to inbox", concurrency).await
+}
+
+/// `label a b Work` -> (["a", "b"], "Work")
+fn split_label_args(mut args: Vec<String>) -> Result<(Vec<String>, String)> {
+    let label = args
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("Missing category"))?;
+    Ok((args, label))
+}
+
+async fn add_label(
+    ids: Vec<String>,
+    label: String,
+    select: QueryArgs,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    if select.is_set() {
+        return label_matching(&select, &label, json, concurrency).await;
+    }
+    if ids.is_empty() {
+        anyhow::bail!("Give message IDs before the category, or select messages with --query");
+    }
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    client.ensure_category(&label).await?;
+    let results = edit_labels(&client, &ids, &format!("label {}", label), |categories| {
+        if !categories.iter().any(|c| c.eq_ignore_ascii_case(&label)) {
+            categories.push(label.clone());
+        }
+    })
+    .await?;
+    report_bulk(&ids, results, &format!("Added category {} to", label))
+}
+
+async fn remove_label(ids: Vec<String>, label: String, concurrency: usize) -> Result<()> {
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    let results = edit_labels(&client, &ids, &format!("unlabel {}", label), |categories| {
+        categories.retain(|c| !c.eq_ignore_ascii_case(&label))
+    })
+    .await?;
+    report_bulk(&ids, results, &format!("Removed category {} from", label))
+}
+
+/// `edit_categories`, journaled for `undo` as `action`
+async fn edit_labels(
+    client: &api::Client,
+    ids: &[String],
+    action: &str,
+    edit: impl Fn(&mut Vec<String>),
+) -> Result<Vec<Result<()>>> {
+    let before = undo::snapshot(client, ids).await;
+    let results = client.edit_categories(ids, edit).await?;
+    undo::record_categories(action, ids, &before, &results);
+    Ok(results)
+}
+
+/// Add a category to every message matching `select`, or list them when previewing.
+/// Search results already carry their categories, so only the writes are batched.
+async fn label_matching(
+    select: &QueryArgs,
+    label: &str,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let messages = find_matching(&client, None, select).await?;
+    if status::dry_run() {
+        let output = if json { Output::Json } else { Output::Text };
+        print_messages(&messages, output, false, &color::Palette::new())?;
+        if !json {
+            note!("{} messages match.", messages.len());
+        }
+        return Ok(());
+    }
+
+    let matched = messages.len();
+    let has_label =
+        |categories: &[String]| categories.iter().any(|c| c.eq_ignore_ascii_case(label));
+    let unlabeled: Vec<api::Message> = messages
+        .into_iter()
+        .filter(|msg| !has_label(msg.categories.as_deref().unwrap_or_default()))
+        .collect();
+    if unlabeled.is_empty() {
+        info!("No matching messages need the label.");
+        return Ok(());
+    }
+
+    client.ensure_category(label).await?;
+    let before: Vec<_> = unlabeled
+        .iter()
+        .map(|m| Some(undo::Before::of(m)))
+        .collect();
+    let (ids, current) = category_snapshots(unlabeled);
+    let results = client
+        .write_categories(current, |categories| {
+            if !has_label(categories) {
+                categories.push(label.to_string());
+            }
+        })
+        .await?;
+    undo::record_categories(&format!("label {}", label), &ids, &before, &results);
+    let succeeded = results.iter().filter(|r| r.is_ok()).count();
+    let outcome = report_bulk(&ids, results, &format!("Added category {} to", label));
+    info!(
+        "Labeled {} of {} matching messages ({} already had it).",
+        succeeded,
+        ids.len(),
+        matched - ids.len()
+    );
+    outcome
+}
+
+/// IDs of listed messages, and the categories and ETags they were listed with
+fn category_snapshots(
+    messages: Vec<api::Message>,
+) -> (Vec<String>, Vec<(String, api::CategorySnapshot)>) {
+    let current: Vec<(String, api::CategorySnapshot)> = messages
+        .into_iter()
+        .map(|msg| {
+            let snapshot = api::CategorySnapshot {
+                categories: msg.categories.unwrap_or_default(),
+                etag: msg.etag,
+            };
+            (msg.id, snapshot)
+        })
+        .collect();
+    (current.iter().map(|(id, _)| id.clone()).collect(), current)
+}
+
+// Messages per progress line when clearing a whole mailbox
+const CLEAR_CHUNK: usize = 200;
+
+async fn clear_labels(
+    ids: Vec<String>,
+    folders: &str,
+    yes: bool,
+    concurrency: usize,
+) -> Result<()> {
+    if ids.len() == 1 && ids[0] == "all" {
+        return clear_all_labels(folders, yes, concurrency).await;
+    }
+
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    let before = undo::snapshot(&client, &ids).await;
+    let results = client.update_categories_many(&ids, &[]).await?;
+    undo::record_categories("clear-labels", &ids, &before, &results);
+    report_bulk(&ids, results, "Cleared all categories from")
+}
+
+/// `clear-labels all`: clear every categorized message in `folders`, once
+/// confirmed (a dry run lists them instead)
+async fn clear_all_labels(folders: &str, yes: bool, concurrency: usize) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let labeled = if folders.eq_ignore_ascii_case("all") {
+        client.list_categorized(None).await?
+    } else {
+        let mut labeled = Vec::new();
+        for folder in folders.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            labeled.extend(
+                client
+                    .list_categorized(Some(&normalize_folder(folder)))
+                    .await?,
+            );
+        }
+        labeled
+    };
+
+    if labeled.is_empty() {
+        info!("No messages have categories.");
+        return Ok(());
+    }
+    if status::dry_run() {
+        for msg in &labeled {
+            println!(
+                "{} [{}]",
+                msg.subject.as_deref().unwrap_or("(no subject)"),
+                msg.categories.as_deref().unwrap_or_default().join(", ")
+            );
+        }
+        note!("{} messages have categories.", labeled.len());
+        return Ok(());
+    }
+    if !confirm("Clear categories from", labeled.len(), yes)? {
+        return Ok(());
+    }
+
+    let mut cleared = Vec::new();
+    let mut before = Vec::new();
+    let mut failed = 0;
+    for chunk in labeled.chunks(CLEAR_CHUNK) {
+        let ids: Vec<String> = chunk.iter().map(|msg| msg.id.clone()).collect();
+        for (msg, result) in chunk
+            .iter()
+            .zip(client.update_categories_many(&ids, &[]).await?)
+        {
+            match result {
+                Ok(()) => {
+                    cleared.push(msg.id.clone());
+                    before.push(Some(undo::Before::of(msg)));
+                }
+                Err(e) => {
+                    let subject = msg.subject.as_deref().unwrap_or("(no subject)");
+                    eprintln!("Failed to clear categories from {}: {}", subject, e);
+                    failed += 1;
+                }
+            }
+        }
+        note!("{}/{} messages done", cleared.len() + failed, labeled.len());
+    }
+    let succeeded: Vec<Result<()>> = cleared.iter().map(|_| Ok(())).collect();
+    undo::record_categories("clear-labels", &cleared, &before, &succeeded);
+    info!("Cleared categories from {} messages.", cleared.len());
+    if failed > 0 {
+        anyhow::bail!("{} of {} messages failed", failed, labeled.len());
+    }
+    Ok(())
+}
+
+async fn mark_read(ids: Vec<String>, concurrency: usize) -> Result<()> {
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    let before = undo::snapshot(&client, &ids).await;
+    let results = client.set_read_many(&ids, true).await?;
+    undo::record_read("mark-read", &ids, &before, &results);
+    report_bulk(&ids, results, "Marked as read:")
+}
+
+async fn mark_unread(ids: Vec<String>, concurrency: usize) -> Result<()> {
+    let ids = resolve_targets(ids)?;
+    let client = bulk_client(concurrency).await?;
+    let before = undo::snapshot(&client, &ids).await;
+    let results = client.set_read_many(&ids, false).await?;
+    undo::record_read("mark-unread", &ids, &before, &results);
+    report_bulk(&ids, results, "Marked as unread:")
+}
+
+async fn sweep(
+    sender: String,
+    to: SweepTarget,
+    preview: bool,
+    yes: bool,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let messages = messages_from(&client, &sender).await?;
+
+    let (destination, done) = match to {
+        SweepTarget::Archive => ("archive", "Archived"),
+        SweepTarget::Trash => ("deleteditems", "Moved to trash"),
+    };
+    if matches!(to, SweepTarget::Trash) && !confirm("Delete", messages.len(), yes)? {
+        return Ok(());
+    }
+    move_found(&client, messages, preview, destination, done, json).await
+}
+
+/// Every inbox message from a sender address or domain
+async fn messages_from(client: &api::Client, sender: &str) -> Result<Vec<api::Message>> {
+    // Exact addresses can be filtered server-side; domains go through KQL search
+    let messages = if sender.contains('@') {
+        let filter = format!(
+            "from/emailAddress/address eq '{}'",
+            sender.replace('\'', "''")
+        );
+        client.list_all_messages("inbox", Some(&filter)).await?
+    } else {
+        client
+            .search_all_messages(Some("inbox"), &format!("from:{}", sender))
+            .await?
+    };
+    Ok(messages
+        .into_iter()
+        .filter(|msg| msg.is_from(sender))
+        .collect())
+}
+
+async fn block(target: String) -> Result<()> {
+    let target = resolve_id(target)?;
+    let client = get_client().await?;
+
+    // Message IDs never contain '@' or '.', addresses and domains always do
+    let (sender, message_id) = if target.contains('@') || target.contains('.') {
+        (target.to_lowercase(), None)
+    } else {
+        let msg = client.get_message(&target).await?;
+        let sender = msg
+            .get_from_address()
+            .ok_or_else(|| anyhow::anyhow!("Message has no sender address"))?
+            .to_lowercase();
+        (sender, Some(target))
+    };
+
+    if client.block_sender(&sender).await? {
+        info!("Blocked {}", sender);
+    } else {
+        info!("{} is already blocked", sender);
+    }
+
+    if let Some(id) = message_id {
+        client.mark_spam(&id).await?;
+        info!("Marked as spam {}", id);
+    }
+    Ok(())
+}
+
+// Graph has no safe-senders API, so the list is kept locally and applied by
+// `safelist rescue`, `triage` and `serve`
+const SAFELIST_FILE: &str = "safelist.json";
+// How often `serve` moves trusted mail out of Junk
+const RESCUE_INTERVAL_SECS: u64 = 300;
+
+/// Junk messages from any of the trusted senders
+async fn trusted_junk(client: &api::Client, senders: &[String]) -> Result<Vec<api::Message>> {
+    let mut messages = client.list_all_messages("junkemail", None).await?;
+    messages.retain(|msg| senders.iter().any(|s| msg.is_from(s)));
+    Ok(messages)
+}
+
+/// Move mail from trusted senders out of Junk, returning how many messages
+/// were (or, previewing, would be) moved
+async fn rescue_trusted(client: &api::Client, preview: bool) -> Result<usize> {
+    let senders: Vec<String> = config::load_state(SAFELIST_FILE)?;
+    if senders.is_empty() {
+        return Ok(0);
+    }
+    let ids: Vec<String> = trusted_junk(client, &senders)
+        .await?
+        .into_iter()
+        .map(|msg| msg.id)
+        .collect();
+    if preview || ids.is_empty() {
+        return Ok(ids.len());
+    }
+    let results = client.move_messages(&ids, "inbox").await?;
+    Ok(results.iter().filter(|r| r.is_ok()).count())
+}
+
+async fn safelist(
+    action: SafelistAction,
+    dry_run: bool,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    let mut senders: Vec<String> = config::load_state(SAFELIST_FILE)?;
+
+    match action {
+        SafelistAction::Add { address } => {
+            let address = address.to_lowercase();
+            if senders.contains(&address) {
+                info!("{} is already trusted", address);
+            } else if dry_run {
+                println!("Would trust {}", address);
+            } else {
+                senders.push(address.clone());
+                config::save_state(SAFELIST_FILE, &senders)?;
+                info!("Trusted {}", address);
+            }
+            let client = get_client().await?;
+            if client.unblock_sender(&address).await? {
+                info!("Unblocked {}", address);
+            }
+        }
+        SafelistAction::Remove { address } => {
+            let address = address.to_lowercase();
+            let before = senders.len();
+            senders.retain(|s| *s != address);
+            if senders.len() == before {
+                anyhow::bail!("{} is not in the safe-senders list", address);
+            }
+            if dry_run {
+                println!("Would remove {}", address);
+                return Ok(());
+            }
+            config::save_state(SAFELIST_FILE, &senders)?;
+            info!("Removed {}", address);
+        }
+        SafelistAction::Show => {
+            if json {
+                println!("{}", serde_json::to_string(&senders)?);
+            } else if senders.is_empty() {
+                info!("No trusted senders.");
+            } else {
+                for sender in &senders {
+                    println!("{}", sender);
+                }
+            }
+        }
+        SafelistAction::Rescue => {
+            let client = bulk_client(concurrency).await?;
+            let messages = trusted_junk(&client, &senders).await?;
+            move_found(&client, messages, dry_run, "inbox", "Moved to inbox", json).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a well-known folder name, folder ID or display name to a folder ID
+async fn resolve_folder_id(client: &api::Client, name: &str) -> Result<String> {
+    if let Ok(folder) = client.get_folder(&normalize_folder(name)).await {
+        return Ok(folder.id);
+    }
+    client
+        .list_folders()
+        .await?
+        .value
+        .unwrap_or_default()
+        .into_iter()
+        .find(|f| f.display_name.eq_ignore_ascii_case(name))
+        .map(|f| f.id)
+        .ok_or_else(|| anyhow::anyhow!("Folder not found: {}", name))
+}
+
+async fn find_rule(client: &api::Client, rule: &str) -> Result<api::MessageRule> {
+    client
+        .list_rules()
+        .await?
+        .value
+        .unwrap_or_default()
+        .into_iter()
+        .find(|r| r.id.as_deref() == Some(rule) || r.display_name.eq_ignore_ascii_case(rule))
+        .ok_or_else(|| anyhow::anyhow!("Rule not found: {}", rule))
+}
+
+fn describe_rule(rule: &api::MessageRule) -> String {
+    let mut conditions = Vec::new();
+    if let Some(c) = &rule.conditions {
+        for addr in c.from_addresses.iter().flatten() {
+            if let Some(a) = &addr.email_address.address {
+                conditions.push(format!("from {}", a));
+            }
+        }
+        for sender in c.sender_contains.iter().flatten() {
+            conditions.push(format!("sender contains \"{}\"", sender));
+        }
+        for subject in c.subject_contains.iter().flatten() {
+            conditions.push(format!("subject contains \"{}\"", subject));
+        }
+    }
+
+    let mut actions = Vec::new();
+    if let Some(a) = &rule.actions {
+        if a.move_to_folder.is_some() {
+            actions.push("move".to_string());
+        }
+        for cat in a.assign_categories.iter().flatten() {
+            actions.push(format!("label {}", cat));
+        }
+        if a.mark_as_read == Some(true) {
+            actions.push("mark read".to_string());
+        }
+        if a.delete == Some(true) {
+            actions.push("delete".to_string());
+        }
+        if a.stop_processing_rules == Some(true) {
+            actions.push("stop".to_string());
+        }
+    }
+
+    format!("{} -> {}", conditions.join(" and "), actions.join(", "))
+}
+
+async fn set_rule_enabled(client: &api::Client, rule: &str, enable: bool) -> Result<()> {
+    let rule = find_rule(client, rule).await?;
+    client
+        .update_rule(
+            rule.id.as_deref().unwrap_or(""),
+            &serde_json::json!({ "isEnabled": enable }),
+        )
+        .await?;
+    info!(
+        "{} rule {}",
+        if enable { "Enabled" } else { "Disabled" },
+        rule.display_name
+    );
+    Ok(())
+}
+
+async fn rules(action: RulesAction, json: bool) -> Result<()> {
+    let client = get_client().await?;
+
+    match action {
+        RulesAction::List => {
+            let rules = client.list_rules().await?.value.unwrap_or_default();
+            if json {
+                println!("{}", serde_json::to_string(&rules)?);
+            } else if rules.is_empty() {
+                info!("No rules found.");
+            } else {
+                for rule in &rules {
+                    let state = if rule.is_enabled == Some(false) {
+                        "disabled"
+                    } else {
+                        "enabled"
+                    };
+                    println!(
+                        "{} | {} | {} | {}",
+                        rule.id.as_deref().unwrap_or(""),
+                        state,
+                        rule.display_name,
+                        describe_rule(rule)
+                    );
+                }
+            }
+        }
+        RulesAction::Create {
+            name,
+            from,
+            subject_contains,
+            move_to,
+            label,
+            mark_read,
+            stop,
+        } => {
+            if from.is_empty() && subject_contains.is_empty() {
+                anyhow::bail!("A rule needs at least one condition (--from or --subject-contains)");
+            }
+            if move_to.is_none() && label.is_empty() && !mark_read {
+                anyhow::bail!(
+                    "A rule needs at least one action (--move-to, --label or --mark-read)"
+                );
+            }
+
+            let move_to_folder = match &move_to {
+                Some(folder) => Some(resolve_folder_id(&client, folder).await?),
+                None => None,
+            };
+            for cat in &label {
+                client.ensure_category(cat).await?;
+            }
+
+            let rule = api::MessageRule {
+                display_name: name,
+                sequence: Some(1),
+                is_enabled: Some(true),
+                conditions: Some(api::RuleConditions {
+                    from_addresses: (!from.is_empty()).then(|| {
+                        from.iter()
+                            .map(|addr| api::Recipient {
+                                email_address: api::EmailAddress {
+                                    name: None,
+                                    address: Some(addr.clone()),
+                                },
+                            })
+                            .collect()
+                    }),
+                    subject_contains: (!subject_contains.is_empty()).then_some(subject_contains),
+                    ..Default::default()
+                }),
+                actions: Some(api::RuleActions {
+                    move_to_folder,
+                    assign_categories: (!label.is_empty()).then_some(label),
+                    mark_as_read: mark_read.then_some(true),
+                    stop_processing_rules: stop.then_some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let created = client.create_rule(&rule).await?;
+            if json {
+                println!("{}", serde_json::to_string(&created)?);
+            } else {
+                info!(
+                    "Created rule {} ({})",
+                    created.display_name,
+                    created.id.as_deref().unwrap_or("")
+                );
+            }
+        }
+        RulesAction::Delete { rule } => {
+            let rule = find_rule(&client, &rule).await?;
+            client.delete_rule(rule.id.as_deref().unwrap_or("")).await?;
+            info!("Deleted rule {}", rule.display_name);
+        }
+        RulesAction::Enable { rule } => set_rule_enabled(&client, &rule, true).await?,
+        RulesAction::Disable { rule } => set_rule_enabled(&client, &rule, false).await?,
+    }
+    Ok(())
+}
+
+/// Apply filters.toml to messages received since the last run (or `since`)
+async fn run_filters(
+    client: &api::Client,
+    folder: &str,
+    since: Option<&str>,
+    preview: bool,
+    verbose: bool,
+) -> Result<filters::RunSummary> {
+    let compiled = filters::load()?.filters;
+    if compiled.is_empty() {
+        anyhow::bail!(
+            "No filters: add [[filter]] tables to {}",
+            filters::filters_path().display()
+        );
+    }
+    let mut state = filters::load_state()?;
+    let started = dates::odata_timestamp(chrono::Utc::now());
+
+    let since = match (since, &state.last_run) {
+        (Some(age), _) => dates::odata_timestamp(dates::cutoff(age)?),
+        (None, Some(last_run)) => last_run.clone(),
+        (None, None) => dates::odata_timestamp(dates::cutoff("1d")?),
+    };
+    let messages = client
+        .list_all_with_headers(
+            &normalize_folder(folder),
+            Some(&format!("receivedDateTime ge {}", since)),
+        )
+        .await?;
+
+    let mut summary = filters::RunSummary {
+        scanned: messages.len(),
+        ..Default::default()
+    };
+    let mut updates = Vec::new();
+    let mut moves: std::collections::BTreeMap<String, Vec<String>> = Default::default();
+    let mut labels = std::collections::BTreeSet::new();
+
+    for msg in &messages {
+        let actions = filters::evaluate(&compiled, msg);
+        if actions.matched.is_empty() {
+            continue;
+        }
+        summary.matched += 1;
+        if verbose {
+            println!(
+                "{}: {} -> {}",
+                actions.matched.join(", "),
+                msg.subject.as_deref().unwrap_or("(no subject)"),
+                actions.describe()
+            );
+        }
+
+        let mut body = serde_json::Map::new();
+        if !actions.labels.is_empty() {
+            let mut categories = msg.categories.clone().unwrap_or_default();
+            for label in &actions.labels {
+                if !categories.iter().any(|c| c.eq_ignore_ascii_case(label)) {
+                    categories.push(label.clone());
+                }
+                labels.insert(label.clone());
+            }
+            body.insert("categories".to_string(), serde_json::json!(categories));
+        }
+        if actions.mark_read && msg.is_read != Some(true) {
+            body.insert("isRead".to_string(), serde_json::json!(true));
+        }
+        if !body.is_empty() {
+            updates.push((msg.id.clone(), serde_json::Value::Object(body)));
+        }
+        if let Some(dest) = actions.destination {
+            moves.entry(dest).or_default().push(msg.id.clone());
+        }
+    }
+
+    if preview {
+        return Ok(summary);
+    }
+
+    for label in &labels {
+        client.ensure_category(label).await?;
+    }
+    // The next run starts at the oldest message that failed, so it's retried
+    let mut retry_from: Option<chrono::DateTime<chrono::Utc>> = None;
+    let mut failed = |id: &str| {
+        let received = messages
+            .iter()
+            .find(|m| m.id == id)
+            .and_then(|m| m.received_date_time.as_deref())
+            .and_then(dates::parse_graph_time);
+        if let Some(received) = received {
+            retry_from = Some(retry_from.map_or(received, |r| r.min(received)));
+        }
+    };
+    // Update before moving: a move gives the message a new ID
+    let results = client.update_messages(&updates).await?;
+    for ((id, _), result) in updates.iter().zip(results) {
+        match result {
+            Ok(()) => summary.updated += 1,
+            Err(e) => {
+                eprintln!("Filter update failed: {}", e);
+                summary.failed += 1;
+                failed(id);
+            }
+        }
+    }
+    for (dest, ids) in &moves {
+        let folder_id = resolve_folder_id(client, dest).await?;
+        let results = client.move_messages(ids, &folder_id).await?;
+        for (id, result) in ids.iter().zip(results) {
+            match result {
+                Ok(()) => summary.moved += 1,
+                Err(e) => {
+                    eprintln!("Filter move failed: {}", e);
+                    summary.failed += 1;
+                    failed(id);
+                }
+            }
+        }
+    }
+
+    state.last_run = Some(retry_from.map_or(started, dates::odata_timestamp));
+    filters::save_state(&state)?;
+    Ok(summary)
+}
+
+const SNOOZE_FILE: &str = "snoozed.json";
+const SNOOZE_FOLDER: &str = "Snoozed";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SnoozedMessage {
+    // ID in the Snoozed folder (moving a message changes its ID)
+    id: String,
+    subject: Option<String>,
+    wake_at: String,
+}
+
+async fn snooze_folder_id(client: &api::Client) -> Result<String> {
+    let existing = client
+        .list_folders()
+        .await?
+        .value
+        .unwrap_or_default()
+        .into_iter()
+        .find(|f| f.display_name.eq_ignore_ascii_case(SNOOZE_FOLDER));
+    match existing {
+        Some(folder) => Ok(folder.id),
+        None => Ok(client.create_folder(SNOOZE_FOLDER).await?.id),
+    }
+}
+
+async fn snooze(
+    action: Option<SnoozeAction>,
+    id: Option<String>,
+    until: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let mut snoozed: Vec<SnoozedMessage> = config::load_state(SNOOZE_FILE)?;
+
+    match action {
+        Some(SnoozeAction::List) => {
+            if json {
+                println!("{}", serde_json::to_string(&snoozed)?);
+            } else if snoozed.is_empty() {
+                info!("No snoozed messages.");
+            } else {
+                for entry in &snoozed {
+                    println!(
+                        "{} | {} | {}",
+                        entry.id,
+                        dates::display_graph(&entry.wake_at),
+                        entry.subject.as_deref().unwrap_or("(no subject)")
+                    );
+                }
+            }
+        }
+        Some(SnoozeAction::Wake) => {
+            let now = chrono::Utc::now();
+            let (due, waiting): (Vec<_>, Vec<_>) = snoozed.into_iter().partition(|entry| {
+                dates::parse_graph_time(&entry.wake_at).is_none_or(|wake| wake <= now)
+            });
+            if due.is_empty() {
+                return Ok(());
+            }
+
+            let client = get_client().await?;
+            let mut remaining = waiting;
+            for entry in due {
+                let woke = match client.move_message(&entry.id, "inbox").await {
+                    Ok(moved) => client.mark_unread(&moved.id, moved.etag.as_deref()).await,
+                    Err(e) => Err(e),
+                };
+                match woke {
+                    Ok(()) => info!(
+                        "Woke {}",
+                        entry.subject.as_deref().unwrap_or("(no subject)")
+                    ),
+                    Err(e) => {
+                        eprintln!("Failed to wake {}: {}", entry.id, e);
+                        remaining.push(entry);
+                    }
+                }
+            }
+            if !status::dry_run() {
+                config::save_state(SNOOZE_FILE, &remaining)?;
+            }
+        }
+        None => {
+            let id = resolve_id(id.ok_or_else(|| anyhow::anyhow!("Message ID required"))?)?;
+            let until = until.ok_or_else(|| anyhow::anyhow!("--until is required"))?;
+            let wake_at = dates::parse_when(&until)?;
+            if wake_at <= chrono::Utc::now() {
+                anyhow::bail!("Snooze time {} is in the past", wake_at);
+            }
+
+            let client = get_client().await?;
+            let msg = client.get_message(&id).await?;
+            let folder_id = snooze_folder_id(&client).await?;
+            let moved = client.move_message(&id, &folder_id).await?;
+
+            snoozed.push(SnoozedMessage {
+                id: moved.id,
+                subject: msg.subject,
+                wake_at: dates::odata_timestamp(wake_at),
+            });
+            if !status::dry_run() {
+                config::save_state(SNOOZE_FILE, &snoozed)?;
+            }
+            info!(
+                "Snoozed {} until {}",
+                id,
+                wake_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+            );
+        }
+    }
+    Ok(())
+}
+
+// Outcome of one `outlook triage` pass
+#[derive(Default, serde::Serialize)]
+struct TriageSummary {
+    filters: Option<filters::RunSummary>,
+    rescued: usize,
+    junk_expired: usize,
+    archived: usize,
+    expired: usize,
+    errors: Vec<String>,
+}
+
+/// IDs of every message in `folder` matching an OData filter, streamed so only
+/// the IDs of a large folder are kept. Collected before acting on them, since
+/// moving messages while paging would shift the later pages.
+async fn matching_ids(client: &api::Client, folder: &str, filter: &str) -> Result<Vec<String>> {
+    use futures::TryStreamExt;
+    client
+        .messages_stream(Some(folder), Some(filter))
+        .map_ok(|msg| msg.id)
+        .try_collect()
+        .await
+}
+
+/// Move messages in `folder` matching an OData filter, returning how many moved
+async fn cleanup(
+    client: &api::Client,
+    folder: &str,
+    filter: &str,
+    destination: &str,
+    preview: bool,
+) -> Result<usize> {
+    let ids = matching_ids(client, folder, filter).await?;
+    if preview {
+        return Ok(ids.len());
+    }
+    let results = client.move_messages(&ids, destination).await?;
+    Ok(results.iter().filter(|r| r.is_ok()).count())
+}
+
+async fn mail_stats(since: &str, folder: &str, top: usize, output: Output) -> Result<()> {
+    let client = get_client().await?;
+    let since = dates::parse_past(since)?;
+    let folder_id = resolve_folder_id(&client, folder).await?;
+    let filter = format!("receivedDateTime ge {}", dates::odata_timestamp(since));
+    let messages = client
+        .list_all_with_headers(&folder_id, Some(&filter))
+        .await?;
+    let stats = stats::summarize(&messages, top);
+
+    if matches!(output, Output::Json | Output::Ndjson) {
+        println!("{}", serde_json::to_string(&stats)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} messages since {} ({} unread, {})",
+        stats.messages,
+        since.with_timezone(&chrono::Local).date_naive(),
+        stats.unread,
+        percent(stats.unread, stats.messages)
+    );
+    let width = table::terminal_width();
+    for (title, counts) in [("SENDER", &stats.top_senders), ("LIST", &stats.top_lists)] {
+        if counts.is_empty() {
+            continue;
+        }
+        let rows: Vec<Vec<String>> = counts
+            .iter()
+            .map(|c| {
+                vec![
+                    c.name.clone(),
+                    c.messages.to_string(),
+                    percent(c.unread, c.messages),
+                ]
+            })
+            .collect();
+        println!();
+        print!(
+            "{}",
+            table::render(&[title, "MESSAGES", "UNREAD"], &rows, &[0], width)
+        );
+    }
+    if !stats.per_day.is_empty() {
+        let rows: Vec<Vec<String>> = stats
+            .per_day
+            .iter()
+            .map(|d| vec![d.date.clone(), d.messages.to_string()])
+            .collect();
+        println!();
+        print!("{}", table::render(&["DAY", "MESSAGES"], &rows, &[], width));
+    }
+    Ok(())
+}
+
+async fn rsvp(id: String, response: RsvpResponse, comment: Option<String>) -> Result<()> {
+    let id = resolve_id(id)?;
+    let client = get_client().await?;
+    let event_id = client.get_invite_event_id(&id).await?;
+    client
+        .respond_to_event(&event_id, response.action(), comment.as_deref())
+        .await?;
+    let done = match response {
+        RsvpResponse::Accept => "Accepted",
+        RsvpResponse::Tentative => "Tentatively accepted",
+        RsvpResponse::Decline => "Declined",
+    };
+    info!("{} the invitation", done);
+    Ok(())
+}
+
+async fn contacts(action: ContactsAction, output: Output, concurrency: usize) -> Result<()> {
+    match action {
+        ContactsAction::Extract { since, limit } => {
+            let client = get_client().await?;
+            let since = dates::parse_past(&since)?;
+            let me = client.me().await?;
+            let mut tally = stats::CorrespondentTally::new(me.address());
+            let filter = format!("receivedDateTime ge {}", dates::odata_timestamp(since));
+            client
+                .for_each_addressed(&filter, |msg| tally.add(&msg))
+                .await?;
+            let mut correspondents = tally.finish();
+            if let Some(limit) = limit {
+                correspondents.truncate(limit);
+            }
+            print_correspondents(&correspondents, output)
+        }
+        ContactsAction::Add {
+            from_message,
+            trust,
+        } => {
+            let id = resolve_id(from_message)?;
+            let client = get_client().await?;
+            let msg = client.get_message(&id).await?;
+            let sender = msg
+                .from
+                .as_ref()
+                .map(|r| &r.email_address)
+                .ok_or_else(|| anyhow::anyhow!("Message has no sender"))?;
+            let address = sender
+                .address
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("Message has no sender address"))?;
+            let name = sender
+                .name
+                .as_deref()
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .unwrap_or(&address)
+                .to_string();
+
+            match client.find_contact(&address).await? {
+                Some(contact) => info!(
+                    "{} is already a contact ({})",
+                    address,
+                    contact.display_name.as_deref().unwrap_or("no name")
+                ),
+                None => {
+                    client.create_contact(&name, &address).await?;
+                    info!("Added contact {} <{}>", name, address);
+                }
+            }
+            if trust {
+                let json = matches!(output, Output::Json | Output::Ndjson);
+                safelist(
+                    SafelistAction::Add { address },
+                    status::dry_run(),
+                    json,
+                    concurrency,
+                )
+                .await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_correspondents(correspondents: &[stats::Correspondent], output: Output) -> Result<()> {
+    let rows: Vec<Vec<String>> = correspondents
+        .iter()
+        .map(|c| {
+            vec![
+                c.address.clone(),
+                c.name.clone(),
+                c.messages.to_string(),
+                c.from.to_string(),
+                c.to.to_string(),
+                c.cc.to_string(),
+            ]
+        })
+        .collect();
+    match output {
+        Output::Json => println!("{}", serde_json::to_string(correspondents)?),
+        Output::Ndjson => print_ndjson(correspondents)?,
+        Output::Csv | Output::Tsv => print!(
+            "{}",
+            table::delimited(
+                &["address", "name", "messages", "from", "to", "cc"],
+                &rows,
+                output.separator().unwrap_or(',')
+            )
+        ),
+        Output::Table => print!(
+            "{}",
+            table::render(
+                &["ADDRESS", "NAME", "MESSAGES", "FROM", "TO", "CC"],
+                &rows,
+                &[0, 1],
+                table::terminal_width()
+            )
+        ),
+        Output::Text if correspondents.is_empty() => info!("No correspondents found."),
+        Output::Text => {
+            for c in correspondents {
+                let who = if c.name.is_empty() {
+                    c.address.clone()
+                } else {
+                    format!("{} <{}>", c.name, c.address)
+                };
+                println!(
+                    "{:>5}  {} (from {}, to {}, cc {})",
+                    c.messages, who, c.from, c.to, c.cc
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn size_report(folder: Option<&str>, top: usize, output: Output) -> Result<()> {
+    let client = get_client().await?;
+    let folder_id = match folder {
+        Some(name) => Some(resolve_folder_id(&client, name).await?),
+        None => None,
+    };
+    let mut tally = stats::SizeTally::new(top);
+    client
+        .for_each_sized(folder_id.as_deref(), |msg| tally.add(msg))
+        .await?;
+    let (mut folders, largest) = tally.finish();
+
+    let folder_names: std::collections::HashMap<String, String> = client
+        .list_folders()
+        .await?
+        .value
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| (f.id, f.display_name))
+        .collect();
+    for f in &mut folders {
+        if let Some(name) = folder_names.get(&f.folder) {
+            f.folder = name.clone();
+        }
+    }
+
+    let mut short_ids = ids::ShortIds::load()?;
+    let mut messages = Vec::new();
+    for (bytes, msg) in &largest {
+        let attachments = if msg.has_attachments == Some(true) {
+            client.list_attachment_info(&msg.id).await?
+        } else {
+            Vec::new()
+        };
+        messages.push(serde_json::json!({
+            "id": msg.id,
+            "shortId": short_ids.shorten(&msg.id),
+            "from": msg.get_from(),
+            "subject": msg.subject,
+            "date": msg.received_date_time,
+            "bytes": bytes,
+            "attachments": attachments
+                .iter()
+                .map(|a| serde_json::json!({ "name": a.name, "bytes": a.size }))
+                .collect::<Vec<_>>(),
+        }));
+    }
+    short_ids.save()?;
+
+    let total: u64 = folders.iter().map(|f| f.bytes).sum();
+    if matches!(output, Output::Json | Output::Ndjson) {
+        let report = serde_json::json!({
+            "totalBytes": total,
+            "folders": folders,
+            "largest": messages,
+        });
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(());
+    }
+
+    let count: usize = folders.iter().map(|f| f.messages).sum();
+    println!("{} in {} messages", stats::human_size(total), count);
+    let width = table::terminal_width();
+    let rows: Vec<Vec<String>> = folders
+        .iter()
+        .map(|f| {
+            vec![
+                f.folder.clone(),
+                f.messages.to_string(),
+                stats::human_size(f.bytes),
+            ]
+        })
+        .collect();
+    println!();
+    print!(
+        "{}",
+        table::render(&["FOLDER", "MESSAGES", "SIZE"], &rows, &[0], width)
+    );
+
+    let rows: Vec<Vec<String>> = messages
+        .iter()
+        .map(|m| {
+            let attachments: Vec<String> = m["attachments"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|a| {
+                    format!(
+                        "{} ({})",
+                        a["name"].as_str().unwrap_or("?"),
+                        stats::human_size(a["bytes"].as_u64().unwrap_or(0))
+                    )
+                })
+                .collect();
+            vec![
+                m["shortId"].as_str().unwrap_or_default().to_string(),
+                stats::human_size(m["bytes"].as_u64().unwrap_or(0)),
+                short_date(m["date"].as_str()),
+                m["from"].as_str().unwrap_or_default().to_string(),
+                m["subject"].as_str().unwrap_or_default().to_string(),
+                attachments.join(", "),
+            ]
+        })
+        .collect();
+    if !rows.is_empty() {
+        println!();
+        print!(
+            "{}",
+            table::render(
+                &["ID", "SIZE", "DATE", "FROM", "SUBJECT", "ATTACHMENTS"],
+                &rows,
+                &[3, 4, 5],
+                width
+            )
+        );
+    }
+    Ok(())
+}
+
+fn percent(part: usize, total: usize) -> String {
+    if total == 0 {
+        return "0%".to_string();
+    }
+    format!("{}%", part * 100 / total)
+}
+
+/// Clear mail older than `age` out of a folder. Deleted Items is emptied;
+/// other folders are moved there. Returns how many messages expired.
+async fn expire_folder(
+    client: &api::Client,
+    folder: &str,
+    age: &str,
+    preview: bool,
+    yes: bool,
+) -> Result<usize> {
+    let cutoff = dates::cutoff(age)?;
+    let filter = format!("receivedDateTime lt {}", dates::odata_timestamp(cutoff));
+    let folder_id = resolve_folder_id(client, folder).await?;
+    if normalize_folder(folder) != "deleteditems" {
+        return cleanup(client, &folder_id, &filter, "deleteditems", preview).await;
+    }
+
+    let ids = matching_ids(client, &folder_id, &filter).await?;
+    if preview {
+        return Ok(ids.len());
+    }
+    // Deleting from Deleted Items can't be undone
+    if !confirm("Permanently delete", ids.len(), yes)? {
+        return Ok(0);
+    }
+    let results = client.delete_messages(&ids).await?;
+    Ok(results.iter().filter(|r| r.is_ok()).count())
+}
+
+async fn expire(
+    folders: Vec<String>,
+    ages: Vec<String>,
+    preview: bool,
+    yes: bool,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    if folders.len() != ages.len() {
+        anyhow::bail!("Give one --older-than for each --folder");
+    }
+    let rules: Vec<(String, String)> = if folders.is_empty() {
+        filters::load()?.expire.into_iter().collect()
+    } else {
+        folders.into_iter().zip(ages).collect()
+    };
+    if rules.is_empty() {
+        anyhow::bail!(
+            "Nothing to expire: pass --folder and --older-than, or add an [expire] table to filters.toml"
+        );
+    }
+
+    let client = bulk_client(concurrency).await?;
+    let mut report = Vec::new();
+    for (folder, age) in &rules {
+        let count = expire_folder(&client, folder, age, preview, yes)
+            .await
+            .with_context(|| format!("Failed to expire {}", folder))?;
+        if !json {
+            let verb = if preview { "would expire" } else { "expired" };
+            println!("{}: {} {} messages older than {}", folder, verb, count, age);
+        }
+        report.push(serde_json::json!({ "folder": folder, "olderThan": age, "expired": count }));
+    }
+    if json {
+        println!("{}", serde_json::to_string(&report)?);
+    }
+    Ok(())
+}
+
+async fn triage(preview: bool, yes: bool, json: bool, concurrency: usize) -> Result<()> {
+    let client = bulk_client(concurrency).await?;
+    let config = filters::load()?;
+    let mut summary = TriageSummary::default();
+
+    if !config.filters.is_empty() {
+        match run_filters(&client, "inbox", None, preview, false).await {
+            Ok(result) => summary.filters = Some(result),
+            Err(e) => summary.errors.push(format!("filters: {:#}", e)),
+        }
+    }
+
+    // Before expire_junk, which would otherwise delete trusted mail
+    match rescue_trusted(&client, preview).await {
+        Ok(n) => summary.rescued = n,
+        Err(e) => summary.errors.push(format!("safelist: {:#}", e)),
+    }
+
+    if let Some(age) = &config.triage.expire_junk {
+        let result = match dates::cutoff(age) {
+            Ok(cutoff) => {
+                let filter = format!("receivedDateTime lt {}", dates::odata_timestamp(cutoff));
+                cleanup(&client, "junkemail", &filter, "deleteditems", preview).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(n) => summary.junk_expired = n,
+            Err(e) => summary.errors.push(format!("expire_junk: {:#}", e)),
+        }
+    }
+
+    for (folder, age) in &config.expire {
+        match expire_folder(&client, folder, age, preview, yes).await {
+            Ok(n) => summary.expired += n,
+            Err(e) => summary.errors.push(format!("expire {}: {:#}", folder, e)),
+        }
+    }
+
+    if let Some(age) = &config.triage.archive_read {
+        let result = match dates::cutoff(age) {
+            Ok(cutoff) => {
+                let filter = format!(
+                    "isRead eq true and receivedDateTime lt {}",
+                    dates::odata_timestamp(cutoff)
+                );
+                cleanup(&client, "inbox", &filter, "archive", preview).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(n) => summary.archived = n,
+            Err(e) => summary.errors.push(format!("archive_read: {:#}", e)),
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&summary)?);
+    } else {
+        let f = summary.filters.as_ref();
+        println!(
+            "scanned={} matched={} updated={} moved={} rescued={} junk_expired={} expired={} archived={} errors={}",
+            f.map_or(0, |f| f.scanned),
+            f.map_or(0, |f| f.matched),
+            f.map_or(0, |f| f.updated),
+            f.map_or(0, |f| f.moved),
+            summary.rescued,
+            summary.junk_expired,
+            summary.expired,
+            summary.archived,
+            summary.errors.len()
+        );
+        for error in &summary.errors {
+            eprintln!("{}", error);
+        }
+    }
+
+    if !summary.errors.is_empty() {
+        anyhow::bail!("Triage finished with {} error(s)", summary.errors.len());
+    }
+    Ok(())
+}
+
+async fn filter(action: FilterAction, preview: bool, json: bool, concurrency: usize) -> Result<()> {
+    match action {
+        FilterAction::Run { folder, since } => {
+            let client = bulk_client(concurrency).await?;
+            let summary = run_filters(&client, &folder, since.as_deref(), preview, !json).await?;
+            if json {
+                println!("{}", serde_json::to_string(&summary)?);
+            } else {
+                println!(
+                    "Scanned {}, matched {}, updated {}, moved {}, failed {}.",
+                    summary.scanned,
+                    summary.matched,
+                    summary.updated,
+                    summary.moved,
+                    summary.failed
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn delete_message(
+    ids: Vec<String>,
+    select: QueryArgs,
+    folder: Option<String>,
+    yes: bool,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    if select.is_set() {
+        // Like `archive --query`, never reach into Deleted Items or Junk unasked
+        let folder = normalize_folder(folder.as_deref().unwrap_or("inbox"));
+        let client = bulk_client(concurrency).await?;
+        let messages = find_matching(&client, Some(&folder), &select).await?;
+        if !confirm("Delete", messages.len(), yes)? {
+            return Ok(());
+        }
+        let dry_run = status::dry_run();
+        return move_found(
+            &client,
+            messages,
+            dry_run,
+            "deleteditems",
+            "Moved to trash",
+            json,
+        )
+        .await;
+    }
+    let ids = resolve_targets(ids)?;
+    if !confirm("Delete", ids.len(), yes)? {
+        return Ok(());
+    }
+    move_messages(ids, "deleteditems", "Moved to trash", concurrency).await
+}
+
+async fn unsubscribe(
+    id: Option<String>,
+    from: Option<String>,
+    archive: bool,
+    send: bool,
+    send_as: Option<&str>,
+    json: bool,
+    concurrency: usize,
+) -> Result<()> {
+    let client = get_client().await?;
+    let id = match (id, &from) {
+        (Some(id), _) => resolve_id(id)?,
+        (None, Some(sender)) => messages_from(&client, sender)
+            .await?
+            .into_iter()
+            .max_by(|a, b| a.received_date_time.cmp(&b.received_date_time))
+            .map(|msg| msg.id)
+            .ok_or_else(|| anyhow::anyhow!("No inbox messages from {}", sender))?,
+        (None, None) => anyhow::bail!("Give a message ID or --from"),
+    };
+    let msg = client.get_message(&id).await?;
+    unsubscribe_message(&client, &msg, send, send_as).await?;
+
+    if archive && let Some(sender) = from {
+        sweep(
+            sender,
+            SweepTarget::Archive,
+            status::dry_run(),
+            false,
+            json,
+            concurrency,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn unsubscribe_message(
+    client: &api::Client,
+    msg: &api::Message,
+    send: bool,
+    send_as: Option<&str>,
+) -> Result<()> {
+    if status::dry_run() {
+        let how = match msg.get_one_click_url() {
+            Some(url) => format!("via one-click link: {}", url),
+            None => msg
+                .get_unsubscribe_url()
+                .map(|url| format!("with {}", url))
+                .ok_or_else(|| anyhow::anyhow!("No unsubscribe link found in message headers"))?,
+        };
+        println!("Would unsubscribe {}", how);
+        return Ok(());
+    }
+    if let Some(url) = msg.get_one_click_url() {
+        match unsubscribe::one_click(&url).await {
+            Ok(()) => {
+                info!("Unsubscribed via one-click link: {}", url);
+                return Ok(());
+            }
+            Err(e) => eprintln!(
+                "One-click unsubscribe failed ({}), opening the link instead",
+                e
+            ),
+        }
+    }
+    let url = msg
+        .get_unsubscribe_url()
+        .ok_or_else(|| anyhow::anyhow!("No unsubscribe link found in message headers"))?;
+    if send && let Some(mailto) = unsubscribe::parse_mailto(&url) {
+        let message = api::mail_message(&mailto.to, &mailto.subject, &mailto.body, send_as);
+        hooks::run(hooks::Hook::PreSend, &message)
+            .await
+            .context("Not sending the unsubscribe email")?;
+        client
+            .send_mail(&mailto.to, &mailto.subject, &mailto.body, send_as)
+            .await?;
+        info!("Sent unsubscribe email to {}", mailto.to);
+    } else {
+        info!("Opening unsubscribe link: {}", url);
+        open::that(&url)?;
+    }
+    Ok(())
+}
+
+async fn subscribe(url: String, minutes: i64, json: bool) -> Result<()> {
+    let client = get_client().await?;
+    let sub = webhook::subscribe(&client, &url, minutes).await?;
+    if json {
+        println!("{}", serde_json::to_string(&sub)?);
+    } else {
+        println!(
+            "Created subscription {} (expires {})",
+            sub.id, sub.expiration_date_time
+        );
+    }
+    Ok(())
+}
+
+async fn serve(port: u16) -> Result<()> {
+    tokio::spawn(async {
+        loop {
+            match get_client().await {
+                Ok(client) => match webhook::renew_due(&client).await {
+                    Ok(0) => {}
+                    Ok(n) => eprintln!("Renewed {} subscription(s)", n),
+                    Err(e) => eprintln!("Subscription renewal failed: {}", e),
+                },
+                Err(e) => eprintln!("Subscription renewal failed: {}", e),
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+        }
+    });
+
+    // New mail in Junk raises no inbox notification, so trusted senders'
+    // mail is looked for on a timer instead, unless moving it isn't allowed
+    let config = config::load_config()?;
+    let can_move = config.scopes.as_ref().is_none_or(|granted| {
+        granted
+            .iter()
+            .any(|scope| scope.to_lowercase().starts_with("mail.readwrite"))
+    });
+    if can_move && !config.read_only() {
+        tokio::spawn(async {
+            loop {
+                let rescued = match get_client().await {
+                    Ok(client) => rescue_trusted(&client, false).await,
+                    Err(e) => Err(e),
+                };
+                match rescued {
+                    Ok(0) => {}
+                    Ok(n) => eprintln!("Moved {} message(s) from trusted senders out of Junk", n),
+                    Err(e) => eprintln!("Safelist rescue failed: {:#}", e),
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(RESCUE_INTERVAL_SECS)).await;
+            }
+        });
+    }
+
+    // Hooked messages are fetched with a long-lived client, which refreshes
+    // its token when Graph rejects it. One at a time, so the hooks don't
+    // race each other saving short IDs.
+    let on_created: Option<webhook::OnCreated> = match hooks::command(hooks::Hook::OnNewMessage) {
+        Some(_) => {
+            let client = get_client().await?;
+            let inbox = client.get_folder("inbox").await?.id;
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+            tokio::spawn(async move {
+                while let Some(id) = rx.recv().await {
+                    if let Err(e) = new_message_hook(&client, &inbox, &id).await {
+                        eprintln!("{:#}", e);
+                    }
+                }
+            });
+            Some(Arc::new(move |id: String| {
+                let _ = tx.send(id);
+            }))
+        }
+        None => None,
+    };
+    webhook::serve(port, on_created).await
+}
+
+// Messages a daemon list or search returns without "max"
+const DAEMON_LIST_SIZE: u32 = 25;
+
+/// Folder counts the daemon refreshes in the background, so status bars
+/// polling for unread mail don't each cost a Graph request
+type MailboxSnapshot = Arc<tokio::sync::RwLock<Option<serde_json::Value>>>;
+
+async fn run_daemon(socket: Option<PathBuf>, refresh: u64) -> Result<()> {
+    let path = match socket {
+        Some(path) => path,
+        None => daemon::socket_path()?,
+    };
+    let client = Arc::new(get_client().await?);
+    let snapshot = MailboxSnapshot::default();
+
+    let (background, cached) = (client.clone(), snapshot.clone());
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = keep_token_fresh(&background).await {
+                eprintln!("Token refresh failed: {:#}", e);
+            }
+            match take_snapshot(&background).await {
+                Ok(taken) => *cached.write().await = Some(taken),
+                Err(e) => eprintln!("Folder refresh failed: {:#}", e),
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(refresh)).await;
+        }
+    });
+
+    let handler: daemon::Handler = Arc::new(move |request| {
+        let (client, snapshot) = (client.clone(), snapshot.clone());
+        Box::pin(async move { daemon_call(&client, &snapshot, request).await })
+    });
+    daemon::serve(&path, handler).await
+}
+
+/// Swap in saved tokens when they're close to expiry (refreshing them) or
+/// when another run already refreshed them
+async fn keep_token_fresh(client: &api::Client) -> Result<()> {
+    let cfg = config::load_config()?;
+    let mut tokens = config::load_tokens()?;
+    if tokens.expires_within(TOKEN_REFRESH_MARGIN_SECS) {
+        tokens = refresh_tokens(cfg.client_id(), cfg.tenant(), &tokens.access_token).await?;
+    }
+    client.set_access_token(&tokens.access_token);
+    Ok(())
+}
+
+async fn take_snapshot(client: &api::Client) -> Result<serde_json::Value> {
+    let folders = client.list_folders().await?.value.unwrap_or_default();
+    let inbox = client.get_folder("inbox").await?;
+    Ok(serde_json::json!({
+        "folders": folders,
+        "unread": inbox.unread_item_count.unwrap_or_default(),
+        "updated": Utc::now().to_rfc3339(),
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct DaemonListParams {
+    folder: Option<String>,
+    filter: Option<String>,
+    query: Option<String>,
+    max: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+struct DaemonReadParams {
+    id: String,
+}
+
+/// Answer one daemon request. Methods only read the mailbox.
+async fn daemon_call(
+    client: &api::Client,
+    snapshot: &MailboxSnapshot,
+    request: daemon::Request,
+) -> Result<serde_json::Value, daemon::RpcError> {
+    let cached = || async {
+        if let Some(taken) = snapshot.read().await.clone() {
+            return Ok::<_, anyhow::Error>(taken);
+        }
+        let taken = take_snapshot(client).await?;
+        *snapshot.write().await = Some(taken.clone());
+        Ok(taken)
+    };
+    match request.method.as_str() {
+        "ping" => Ok("pong".into()),
+        "unread" => {
+            let taken = cached().await?;
+            Ok(serde_json::json!({ "unread": taken["unread"], "updated": taken["updated"] }))
+        }
+        "folders" => Ok(cached().await?["folders"].clone()),
+        "refresh" => {
+            let taken = take_snapshot(client).await?;
+            *snapshot.write().await = Some(taken.clone());
+            Ok(taken)
+        }
+        "list" => {
+            let params: DaemonListParams = request.params()?;
+            let max = params.max.unwrap_or(DAEMON_LIST_SIZE);
+            let list = match &params.query {
+                Some(q) => {
+                    client
+                        .search_messages(q, &api::ListOptions::default(), max)
+                        .await?
+                }
+                None => {
+                    let folder = normalize_folder(params.folder.as_deref().unwrap_or("inbox"));
+                    client
+                        .list_messages(&folder, params.filter.as_deref(), max)
+                        .await?
+                }
+            };
+            let mut short_ids = ids::ShortIds::load()?;
+            let messages: Vec<serde_json::Value> = list
+                .value
+                .unwrap_or_default()
+                .iter()
+                .map(|msg| message_json(msg, &mut short_ids))
+                .collect();
+            short_ids.save()?;
+            Ok(messages.into())
+        }
+        "read" => {
+            let params: DaemonReadParams = request.params()?;
+            let mut short_ids = ids::ShortIds::load()?;
+            let msg = client
+                .get_message_text(&short_ids.resolve(&params.id)?)
+                .await?;
+            let mut json = message_json(&msg, &mut short_ids);
+            short_ids.save()?;
+            json["body"] = msg.get_body_text().into();
+            Ok(json)
+        }
+        method => Err(daemon::RpcError::new(
+            daemon::METHOD_NOT_FOUND,
+            format!("Unknown method '{}'", method),
+        )),
+    }
+}
+
+/// Run the on_new_message hook with a message a notification announced
+/// Run on_new_message for a message a notification announced, if it is
+/// (still) in the inbox
+async fn new_message_hook(client: &api::Client, inbox: &str, id: &str) -> Result<()> {
+    let msg = client.get_message(id).await?;
+    if msg.parent_folder_id.as_deref() != Some(inbox) {
+        return Ok(());
+    }
+    let mut short_ids = ids::ShortIds::load()?;
+    let json = message_json(&msg, &mut short_ids);
+    short_ids.save()?;
+    hooks::run(hooks::Hook::OnNewMessage, &json).await
+}
+
+/// `outlook docs`: generated from the same clap definitions as --help
+fn write_docs(format: DocsFormat, out_dir: Option<PathBuf>) -> Result<()> {
+    let cmd = Cli::command();
+    match (format, out_dir) {
+        (DocsFormat::Man, Some(dir)) => {
+            let count = docs::write_man_pages(cmd, &dir)?;
+            info!("Wrote {} man pages to {}", count, dir.display());
+        }
+        (DocsFormat::Markdown, Some(dir)) => {
+            let count = docs::write_markdown_pages(cmd, &dir)?;
+            info!("Wrote {} markdown pages to {}", count, dir.display());
+        }
+        (DocsFormat::Man, None) => docs::render_man_page(cmd, &mut std::io::stdout())?,
+        (DocsFormat::Markdown, None) => print!("{}", docs::render_markdown(cmd)),
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Answers (and exits) when a completion script is asking for candidates
+    clap_complete::CompleteEnv::with_factory(Cli::command)
+        .var(completions::COMPLETE_VAR)
+        .complete();
+    let cli = Cli::parse();
+    status::set_quiet(cli.quiet);
+    status::set_verbosity(cli.debug);
+    status::set_dry_run(cli.dry_run);
+    // Held until exit so buffered spans and metrics are flushed
+    let _telemetry = match telemetry::init() {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            note!("Telemetry disabled: {:#}", e);
+            None
+        }
+    };
+    let json = cli.json || matches!(cli.output, Some(Output::Json | Output::Ndjson));
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if json {
+                println!("{}", error_json(&e));
+            } else {
+                eprintln!("Error: {:?}", e);
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    let account = match cli.account.clone() {
+        Some(name) => Some(name),
+        None => config::default_account()?,
+    };
+    if let Some(name) = &account
+        && !config::account_exists(name)
+        && !matches!(
+            cli.command,
+            Commands::Login { .. } | Commands::Account { .. }
+        )
+    {
+        anyhow::bail!(
+            "No account named '{}'. Add it with `outlook account add {}`.",
+            name,
+            name
+        );
+    }
+    config::set_account(account);
+    let config = config::load_config()?;
+
+    // Flags win over config, which wins over the built-in defaults
+    let color_choice = cli
+        .color
+        .or_else(|| ColorChoice::from_str(config.color.as_deref()?, true).ok())
+        .unwrap_or(ColorChoice::Auto);
+    color::set_enabled(match color_choice {
+        ColorChoice::Auto => color::auto(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    });
+    let output = if cli.json {
+        Output::Json
+    } else {
+        cli.output
+            .or_else(|| Output::from_str(config.output.as_deref()?, true).ok())
+            .unwrap_or(Output::Text)
+    };
+    if let Commands::List(args) = &mut cli.command {
+        args.max
+            .get_or_insert(config.page_size.unwrap_or(DEFAULT_LIST_SIZE));
+        if args.label.is_none() {
+            args.label = config.folder.clone();
+        }
+    }
+    api::set_mailbox(cli.mailbox.clone().or(config.mailbox.clone()));
+    api::set_proxy(config.proxy.as_deref())?;
+    api::set_immutable_ids(config.immutable_ids.unwrap_or(false));
+    hooks::set_commands(&config);
+    let defaults = api::RetryPolicy::default();
+    api::set_retry_policy(api::RetryPolicy {
+        timeout: cli
+            .timeout
+            .or(config.timeout)
+            .map_or(defaults.timeout, std::time::Duration::from_secs),
+        max_retries: cli
+            .max_retries
+            .or(config.max_retries)
+            .unwrap_or(defaults.max_retries),
+        initial_backoff: cli
+            .initial_backoff
+            .or(config.initial_backoff)
+            .map_or(defaults.initial_backoff, std::time::Duration::from_millis),
+    });
+    dates::set_display(dates::Display {
+        relative: cli.relative,
+        format: config
+            .date_format
+            .clone()
+            .unwrap_or_else(|| dates::DEFAULT_FORMAT.to_string()),
+    });
+
+    // Read-only mode and dry runs never mark messages read as a side effect
+    let read_marks_read =
+        config.read_marks_read.unwrap_or(false) && !config.read_only() && !cli.dry_run;
+    if config.read_only() {
+        check_read_only(&cli.command)?;
+    }
+    if let Some(granted) = &config.scopes {
+        check_scopes(&cli.command, granted, read_marks_read)?;
+    }
+    if !cli.dry_run {
+        ensure_consent(&cli.command, &config).await?;
+    }
+
+    let pageable = matches!(
+        cli.command,
+        Commands::List(_) | Commands::Read { browser: false, .. }
+    );
+    if pageable
+        && !cli.no_pager
+        && !matches!(output, Output::Json | Output::Ndjson)
+        && config.pager.unwrap_or(true)
+    {
+        pager::enable();
+    }
+
+    match cli.command {
+        Commands::Login { .. }
+        | Commands::Account {
+            action: AccountAction::Add { .. },
+        } if cli.dry_run => anyhow::bail!("Signing in can't be a dry run"),
+        Commands::Config { action } => manage_config(action, output)?,
+        Commands::Login {
+            device,
+            broker,
+            tenant,
+            scopes,
+            client_cert,
+            managed_identity,
+        } => {
+            login(
+                device,
+                broker,
+                tenant,
+                scopes,
+                client_cert,
+                managed_identity,
+            )
+            .await?
+        }
+        Commands::Logout { revoke, browser } => logout(revoke, browser).await?,
+        Commands::Whoami => whoami(output).await?,
+        Commands::Account { action } => manage_accounts(action, output).await?,
+        Commands::Labels { action } => labels(action, output, cli.yes, cli.concurrency).await?,
+        Commands::Folders => list_folders(output).await?,
+        Commands::SyncLabels => sync_labels().await?,
+        Commands::List(args) => list_messages(args, output).await?,
+        Commands::Pick {
+            query,
+            label,
+            max,
+            multi,
+        } => pick_messages(query, label, max, multi).await?,
+        Commands::Read {
+            id,
+            mark_read,
+            no_mark_read,
+            html,
+            render,
+            browser,
+            raw,
+            format,
+            fields,
+            text_body,
+        } => {
+            let mark_read = match (mark_read, no_mark_read) {
+                (true, _) => true,
+                (_, true) => false,
+                _ => read_marks_read,
+            };
+            if raw {
+                read_raw(id, mark_read).await?
+            } else if browser {
+                open_in_browser(id, mark_read).await?
+            } else if !fields.is_empty() {
+                read_fields(id, mark_read, &fields, output).await?
+            } else {
+                let render = if html { None } else { Some(render) };
+                // Graph's conversion replaces local rendering, so only for plain text
+                let text_body = matches!(render, Some(Render::Text))
+                    && (text_body || config.text_body.unwrap_or(false));
+                read_message(id, mark_read, render, text_body, format, cli.json).await?
+            }
+        }
+        Commands::Classify { id, tab, always } => classify(id, tab, always).await?,
+        Commands::Archive { ids, select } => {
+            archive_message(ids, select, cli.json, cli.concurrency).await?
+        }
+        Commands::Attachments {
+            ids,
+            select,
+            save,
+            kind,
+        } => attachments(ids, select, save, kind, cli.json, cli.concurrency).await?,
+        Commands::Spam { ids } => spam_message(ids, cli.concurrency).await?,
+        Commands::Report { id, kind, no_move } => report(id, kind, no_move).await?,
+        Commands::Unspam { ids } => unspam_message(ids, cli.concurrency).await?,
+        Commands::Label { args, select } => {
+            let (ids, label) = split_label_args(args)?;
+            add_label(ids, label, select, cli.json, cli.concurrency).await?
+        }
+        Commands::Unlabel { ids, label } => remove_label(ids, label, cli.concurrency).await?,
+        Commands::ClearLabels { ids, folders } => {
+            clear_labels(ids, &folders, cli.yes, cli.concurrency).await?
+        }
+        Commands::MarkRead { ids } => mark_read(ids, cli.concurrency).await?,
+        Commands::MarkUnread { ids } => mark_unread(ids, cli.concurrency).await?,
+        Commands::Undo { list: true, .. } => undo::list(cli.json)?,
+        Commands::Undo { count, .. } => {
+            undo::undo(&bulk_client(cli.concurrency).await?, count.into()).await?
+        }
+        Commands::Delete {
+            ids,
+            select,
+            folder,
+        } => delete_message(ids, select, folder, cli.yes, cli.json, cli.concurrency).await?,
+        Commands::Headers { id, name } => headers(id, name, cli.json).await?,
+        Commands::Links { id, domain } => links(id, domain, cli.json).await?,
+        Commands::TranslateId { ids, reverse } => translate_ids(ids, reverse, cli.json).await?,
+        Commands::Unsubscribe {
+            id,
+            from,
+            archive,
+            send,
+            send_as,
+        } => {
+            let send_as = send_as.as_deref();
+            unsubscribe(id, from, archive, send, send_as, cli.json, cli.concurrency).await?
+        }
+        Commands::Sweep { sender, to } => {
+            sweep(sender, to, cli.dry_run, cli.yes, cli.json, cli.concurrency).await?
+        }
+        Commands::Block { target } => block(target).await?,
+        Commands::Safelist { action } => {
+            safelist(action, cli.dry_run, cli.json, cli.concurrency).await?
+        }
+        Commands::Rules { action } => rules(action, cli.json).await?,
+        Commands::Snooze { action, id, until } => snooze(action, id, until, cli.json).await?,
+        Commands::Stats {
+            action: Some(StatsAction::Size { folder, top }),
+            ..
+        } => size_report(folder.as_deref(), top, output).await?,
+        Commands::Stats {
+            action: None,
+            since,
+            folder,
+            top,
+        } => mail_stats(&since, &folder, top, output).await?,
+        Commands::Rsvp {
+            id,
+            response,
+            comment,
+        } => rsvp(id, response, comment).await?,
+        Commands::Contacts { action } => contacts(action, output, cli.concurrency).await?,
+        Commands::Expire { folder, older_than } => {
+            expire(
+                folder,
+                older_than,
+                cli.dry_run,
+                cli.yes,
+                cli.json,
+                cli.concurrency,
+            )
+            .await?
+        }
+        Commands::Triage => triage(cli.dry_run, cli.yes, cli.json, cli.concurrency).await?,
+        Commands::Filter { action } => {
+            filter(action, cli.dry_run, cli.json, cli.concurrency).await?
+        }
+        Commands::Subscribe { url, minutes } => subscribe(url, minutes, cli.json).await?,
+        Commands::Serve { port } => serve(port).await?,
+        Commands::Daemon { socket, refresh } => run_daemon(socket, refresh).await?,
+        Commands::Tui if cli.dry_run => anyhow::bail!("The TUI can't do a dry run"),
+        Commands::Tui => tui::run(get_client().await?, read_marks_read).await?,
+        Commands::Docs { format, out_dir } => write_docs(format, out_dir)?,
+        Commands::SelfUpdate { check } => update::self_update(check).await?,
+        Commands::Completions { shell, refresh } => {
+            if refresh {
+                completions::refresh(&get_client().await?).await?;
+            }
+            if let Some(shell) = shell {
+                completions::write_script(&shell, &mut std::io::stdout())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_confirm() {
+        // One message, or --yes, needs no question
+        assert!(confirm("Delete", 1, false).unwrap());
+        assert!(confirm("Delete", 500, true).unwrap());
+        assert!(is_yes("y\n"));
+        assert!(is_yes(" YES "));
+        assert!(!is_yes("\n"));
+        assert!(!is_yes("yep"));
+
+        let cli = Cli::try_parse_from(["outlook", "clear-labels", "all", "-y"]).unwrap();
+        assert!(cli.yes);
+    }
+
+    #[test]
+    fn test_dry_run_flag() {
+        let parse = |args: &[&str]| Cli::try_parse_from(args).unwrap().dry_run;
+        assert!(parse(&["outlook", "--dry-run", "archive", "abc"]));
+        assert!(parse(&["outlook", "sweep", "foo.com", "--preview"]));
+        assert!(parse(&["outlook", "filter", "run", "--dry-run"]));
+        assert!(!parse(&["outlook", "delete", "abc"]));
+    }
+
+    #[test]
+    fn test_check_scopes() {
+        let granted = vec!["Mail.Read".to_string(), "offline_access".to_string()];
+        let check = |args: &[&str], read_marks_read| {
+            let cli = Cli::try_parse_from(args).unwrap();
+            check_scopes(&cli.command, &granted, read_marks_read)
+        };
+        assert!(check(&["outlook", "list"], false).is_ok());
+        assert!(check(&["outlook", "read", "abc"], false).is_ok());
+        assert!(check(&["outlook", "read", "abc"], true).is_err());
+        let err = check(&["outlook", "archive", "abc"], false).unwrap_err();
+        assert!(err.to_string().contains("Mail.ReadWrite"));
+        assert!(check(&["outlook", "unsubscribe", "abc", "--send"], false).is_err());
+        let err = check(&["outlook", "rules", "list"], false).unwrap_err();
+        assert!(err.to_string().contains("MailboxSettings.Read"));
+        assert!(!err.to_string().contains("ReadWrite"));
+        // Asked for when needed rather than refused
+        let err = check(&["outlook", "report", "abc", "phishing"], false).unwrap_err();
+        assert!(!err.to_string().contains("ThreatAssessment"));
+
+        let read_only =
+            |args: &[&str]| check_read_only(&Cli::try_parse_from(args).unwrap().command);
+        assert!(read_only(&["outlook", "list", "--unread"]).is_ok());
+        assert!(read_only(&["outlook", "labels"]).is_ok());
+        assert!(read_only(&["outlook", "read", "abc", "--mark-read"]).is_err());
+        assert!(read_only(&["outlook", "delete", "abc"]).is_err());
+        assert!(read_only(&["outlook", "unsubscribe", "abc"]).is_err());
+        assert!(read_only(&["outlook", "snooze", "list"]).is_ok());
+        assert!(read_only(&["outlook", "rules", "list"]).is_ok());
+        assert!(read_only(&["outlook", "rules", "disable", "Jira"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_target_input_lines() {
+        assert_eq!(parse_target_input("abc\ndef\n"), vec!["abc", "def"]);
+    }
+
+    #[test]
+    fn test_parse_target_input_list_json() {
+        let input = r#"[{"id":"abc","subject":"hi"},{"id":"def"}]"#;
+        assert_eq!(parse_target_input(input), vec!["abc", "def"]);
+    }
+
+    #[test]
+    fn test_attachment_filters() {
+        let attachment: api::Attachment = serde_json::from_value(serde_json::json!({
+            "id": "a1", "name": "Invoice.PDF", "contentType": "application/pdf"
+        }))
+        .unwrap();
+        assert!(attachment_matches(&attachment, "pdf"));
+        assert!(attachment_matches(&attachment, ".pdf"));
+        assert!(attachment_matches(&attachment, "application/pdf"));
+        assert!(attachment_matches(&attachment, "application/"));
+        assert!(!attachment_matches(&attachment, "image/"));
+        assert!(!attachment_matches(&attachment, "png"));
+
+        assert_eq!(safe_file_name("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(safe_file_name("a:b\\c.txt"), "a_b_c.txt");
+        assert_eq!(safe_file_name(" .. "), "attachment");
+    }
+
+    #[test]
+    fn test_inline_cid_images() {
+        let attachments: Vec<api::Attachment> = serde_json::from_value(serde_json::json!([
+            { "id": "a1", "contentId": "<logo@x>", "contentType": "image/png", "contentBytes": "iVBORw0=" },
+            { "id": "a2", "name": "report.pdf" }
+        ]))
+        .unwrap();
+        assert_eq!(
+            inline_cid_images(r#"<img src="cid:logo@x">"#, &attachments),
+            r#"<img src="data:image/png;base64,iVBORw0=">"#
+        );
+    }
+
+    #[test]
+    fn test_render_terminal() {
+        let out = render_terminal("<h2>News</h2><p><b>Big</b> day</p>", 80);
+        assert!(out.contains("## News"));
+        assert!(out.contains("\x1b[1mBig\x1b[0m day"));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let graph = |status| {
+            anyhow::Error::new(api::GraphError {
+                status,
+                code: None,
+                message: String::new(),
+                request_id: None,
+            })
+        };
+        assert_eq!(
+            exit_code(&anyhow::Error::new(NotLoggedIn)),
+            EXIT_NOT_LOGGED_IN
+        );
+        assert_eq!(
+            exit_code(&graph(404).context("Failed to fetch")),
+            EXIT_NOT_FOUND
+        );
+        assert_eq!(exit_code(&graph(429)), EXIT_THROTTLED);
+        let throttled = api::Throttled {
+            retry_after: std::time::Duration::from_secs(30),
+        };
+        assert_eq!(
+            exit_code(&anyhow::Error::new(throttled).context("Failed to move")),
+            EXIT_CIRCUIT_OPEN
+        );
+        assert_eq!(exit_code(&graph(400)), EXIT_FAILURE);
+        let rejected = anyhow::anyhow!("invalid_grant").context(NotLoggedIn);
+        assert_eq!(
+            exit_code(&rejected.context("Failed to list")),
+            EXIT_NOT_LOGGED_IN
+        );
+        let unknown = anyhow::Error::new(ids::UnknownShortId("0123abcd".to_string()));
+        assert_eq!(exit_code(&unknown), EXIT_NOT_FOUND);
+        assert_eq!(exit_code(&anyhow::anyhow!("boom")), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_error_json() {
+        let err = anyhow::Error::new(api::GraphError {
+            status: 404,
+            code: Some("ErrorItemNotFound".to_string()),
+            message: "Not found".to_string(),
+            request_id: Some("abc-123".to_string()),
+        })
+        .context("Failed to fetch message");
+        let json = error_json(&err);
+        assert_eq!(json["error"]["code"], "ErrorItemNotFound");
+        assert_eq!(json["error"]["requestId"], "abc-123");
+        assert_eq!(json["error"]["exitCode"], EXIT_NOT_FOUND);
+        assert_eq!(
+            json["error"]["message"],
+            "Failed to fetch message: HTTP 404 - Not found"
+        );
+    }
+
+    #[test]
+    fn test_ids_conflict_with_query() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["outlook"], args].concat());
+        assert!(parse(&["archive", "abc"]).is_ok());
+        assert!(parse(&["archive", "--query", "from:bob"]).is_ok());
+        assert!(parse(&["archive", "abc", "--query", "from:bob"]).is_err());
+        assert!(parse(&["delete", "abc", "--older-than", "30d"]).is_err());
+        assert!(parse(&["list", "--focused", "--query", "budget"]).is_err());
+        assert!(parse(&["list", "--other", "--query", "budget"]).is_err());
+    }
+
+    #[test]
+    fn test_login_credentials_conflict() {
+        let login = |args: &[&str]| Cli::try_parse_from([&["outlook", "login"], args].concat());
+        assert!(login(&["--managed-identity"]).is_ok());
+        assert!(login(&["--client-cert", "app.pfx"]).is_ok());
+        assert!(login(&["--managed-identity", "--client-cert", "app.pfx"]).is_err());
+        assert!(login(&["--broker", "--device"]).is_err());
+        assert!(login(&["--broker", "--managed-identity"]).is_err());
+    }
+
+    #[test]
+    fn test_list_select() {
+        let args = |argv: &[&str]| match Cli::parse_from(argv).command {
+            Commands::List(args) => args,
+            _ => unreachable!(),
+        };
+        assert_eq!(args(&["outlook", "list"]).select(), None);
+        let list = args(&["outlook", "list", "--fields", "subject,webLink,size"]);
+        assert_eq!(list.select().as_deref(), Some("id,subject,webLink"));
+        assert!(list.include_size());
+        let sorted = args(&["outlook", "list", "--fields", "id", "--sort", "from"]);
+        assert_eq!(sorted.select().as_deref(), Some("from,id"));
+        assert!(Cli::try_parse_from(["outlook", "list", "--fields", "nope"]).is_err());
+        let formatted = args(&["outlook", "list", "--format", "{date} {to} {body:.40}"]);
+        assert_eq!(
+            formatted.select().as_deref(),
+            Some("body,id,receivedDateTime,toRecipients")
+        );
+    }
+
+    #[test]
+    fn test_list_filter_leads_with_sort() {
+        let filter = |argv: &[&str]| match Cli::parse_from(argv).command {
+            Commands::List(args) => args.filter(),
+            _ => unreachable!(),
+        };
+        assert_eq!(filter(&["outlook", "list", "--sort", "from"]), None);
+        assert_eq!(
+            filter(&["outlook", "list", "--sort", "from", "--unread"]).as_deref(),
+            Some("from/emailAddress/name ne null and isRead eq false")
+        );
+        assert_eq!(
+            filter(&["outlook", "list", "--sort", "subject", "--unread"]).as_deref(),
+            Some("subject ne null and isRead eq false")
+        );
+        assert_eq!(
+            filter(&["outlook", "list", "--sort", "date", "--unread"]).as_deref(),
+            Some("receivedDateTime ge 1900-01-01T00:00:00Z and isRead eq false")
+        );
+    }
+
+    #[test]
+    fn test_rename_category() {
+        let tags = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            rename_category(tags(&["Work", "receipts"]), "Receipts", "Bills"),
+            tags(&["Work", "Bills"])
+        );
+        assert_eq!(
+            rename_category(tags(&["Receipts", "Bills"]), "Receipts", "Bills"),
+            tags(&["Bills"])
+        );
+    }
+
+    #[test]
+    fn test_link_in_domain() {
+        assert!(link_in_domain("https://track.ups.com/x", "ups.com"));
+        assert!(link_in_domain("https://UPS.com/", "ups.com"));
+        assert!(!link_in_domain("https://notups.com/", "ups.com"));
+    }
+
+    #[test]
+    fn test_expand_ref() {
+        let listing: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(expand_ref("@2", &listing).unwrap(), vec!["b"]);
+        assert_eq!(expand_ref("@2-4", &listing).unwrap(), vec!["b", "c", "d"]);
+        assert_eq!(expand_ref("@last", &listing).unwrap(), vec!["d"]);
+        assert!(expand_ref("@0", &listing).is_err());
+        assert!(expand_ref("@5", &listing).is_err());
+        assert!(expand_ref("@3-1", &listing).is_err());
+        assert!(is_ref("@1-5"));
+        assert!(!is_ref("@example.com"));
+    }
+
+    #[test]
+    fn test_sender_clause() {
+        assert_eq!(
+            sender_clause("Alice@Example.com"),
+            "from/emailAddress/address eq 'alice@example.com'"
+        );
+        assert_eq!(
+            sender_clause("@example.com"),
+            "endswith(from/emailAddress/address, '@example.com')"
+        );
+        assert_eq!(
+            sender_clause("o'brien@example.com"),
+            "from/emailAddress/address eq 'o''brien@example.com'"
+        );
+    }
+
+    #[test]
+    fn test_group_threads() {
+        let messages: Vec<api::Message> = serde_json::from_value(serde_json::json!([
+            { "id": "a2", "conversationId": "a", "receivedDateTime": "2024-01-03T00:00:00Z", "isRead": false },
+            { "id": "b1", "conversationId": "b", "receivedDateTime": "2024-01-02T00:00:00Z" },
+            { "id": "a1", "conversationId": "a", "receivedDateTime": "2024-01-01T00:00:00Z", "isRead": false },
+        ]))
+        .unwrap();
+        let threads = group_threads(&messages);
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].latest.id, "a2");
+        assert_eq!(threads[0].count, 2);
+        assert_eq!(threads[0].unread, 2);
+        assert_eq!(threads[1].latest.id, "b1");
+    }
+
+    #[test]
+    fn test_label_accepts_multiple_ids() {
+        let cli = Cli::try_parse_from(["outlook", "label", "a", "b", "Work"]).unwrap();
+        match cli.command {
+            Commands::Label { args, .. } => {
+                let (ids, label) = split_label_args(args).unwrap();
+                assert_eq!(ids, vec!["a", "b"]);
+                assert_eq!(label, "Work");
+            }
+            _ => panic!("expected label command"),
+        }
+        let cli =
+            Cli::try_parse_from(["outlook", "label", "--query", "from:jira@", "Jira"]).unwrap();
+        match cli.command {
+            Commands::Label { args, select } => {
+                let (ids, label) = split_label_args(args).unwrap();
+                assert!(ids.is_empty());
+                assert_eq!(label, "Jira");
+                assert!(select.is_set());
+            }
+            _ => panic!("expected label command"),
+        }
+    }
 }
@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::{
+    io::IsTerminal,
+    os::fd::AsRawFd,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const DEFAULT_PAGER: &str = "less";
+// Quit if the output fits on one screen, keep colors, don't clear the screen
+const DEFAULT_LESS: &str = "FRX";
+
+/// Redirects stdout into a pager until dropped
+pub struct Pager {
+    child: Child,
+}
+
+/// Let `start` page this command's output. Commands call `start` once their
+/// data is fetched, so sign-in prompts never share the terminal with a pager.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Start `$PAGER` (default `less`) and point stdout at it. Does nothing unless
+/// enabled, when stdout isn't a terminal, when the pager is disabled with
+/// `PAGER=` or `cat`, or when its program isn't installed.
+#[cfg(unix)]
+pub fn start() -> Option<Pager> {
+    if !ENABLED.load(Ordering::Relaxed) || !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_string());
+    let program = pager.split_whitespace().next()?;
+    if program == "cat" || !installed(program) {
+        return None;
+    }
+
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&pager).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", DEFAULT_LESS);
+    }
+    let child = command.spawn().ok()?;
+
+    let stdin = child.stdin.as_ref()?;
+    // SAFETY: both descriptors are open; dup2 atomically replaces fd 1
+    if unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) } < 0 {
+        return None;
+    }
+    // Quitting the pager early should end the program quietly rather than
+    // make the next println! panic on a broken pipe
+    // SAFETY: restoring the default disposition of a signal
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    Some(Pager { child })
+}
+
+// `sh` exits 127 for a missing program, leaving stdout a pipe nobody reads
+#[cfg(unix)]
+fn installed(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+// Redirecting stdout relies on dup2; Windows output is never paged
+#[cfg(not(unix))]
+pub fn start() -> Option<Pager> {
+    None
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+        // The pager only sees EOF once every handle to its stdin is closed
+        drop(self.child.stdin.take());
+        // SAFETY: fd 1 is ours; nothing writes to stdout after this point
+        #[cfg(unix)]
+        unsafe {
+            libc::close(libc::STDOUT_FILENO);
+        }
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,299 @@
+// `outlook pick`: an fzf-style fuzzy finder over message lines. The finder
+// draws on stderr so stdout carries only the picked IDs, as in
+// `outlook read $(outlook pick)`.
+
+use anyhow::Result;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::collections::BTreeSet;
+use std::io::Stderr;
+
+/// Where a pattern matched a candidate
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub score: i64,
+    /// Matched character indices, in order
+    pub positions: Vec<usize>,
+}
+
+/// Match `pattern` against `candidate` as in fzf: every whitespace-separated
+/// term must appear as a subsequence. Case is ignored unless the pattern has
+/// an uppercase letter. Consecutive characters and word starts score higher.
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<Match> {
+    let case_sensitive = pattern.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let chars: Vec<char> = candidate.chars().map(fold).collect();
+    let mut result = Match {
+        score: 0,
+        positions: Vec::new(),
+    };
+    for term in pattern.split_whitespace() {
+        let term = term.chars().map(fold);
+        let (score, positions) = match_term(term, &chars)?;
+        result.score += score;
+        result.positions.extend(positions);
+    }
+    result.positions.sort_unstable();
+    result.positions.dedup();
+    Some(result)
+}
+
+/// Score one term against the (case-folded) candidate, trying every start
+/// and keeping the best-scoring greedy match from it
+fn match_term(term: impl Iterator<Item = char>, chars: &[char]) -> Option<(i64, Vec<usize>)> {
+    let term: Vec<char> = term.collect();
+    let first = *term.first()?;
+    (0..chars.len())
+        .filter(|&start| chars[start] == first)
+        .filter_map(|start| {
+            let mut positions = vec![start];
+            let mut next = start + 1;
+            for &c in &term[1..] {
+                let found = chars[next..].iter().position(|&x| x == c)?;
+                positions.push(next + found);
+                next += found + 1;
+            }
+            Some((score_positions(&positions, chars), positions))
+        })
+        .max_by_key(|(score, positions)| (*score, std::cmp::Reverse(positions[0])))
+}
+
+fn score_positions(positions: &[usize], chars: &[char]) -> i64 {
+    let mut score = 0;
+    for (i, &at) in positions.iter().enumerate() {
+        score += 16;
+        if at == 0 || !chars[at - 1].is_alphanumeric() {
+            score += 8;
+        }
+        match i.checked_sub(1).map(|prev| positions[prev]) {
+            Some(prev) if prev + 1 == at => score += 12,
+            Some(prev) => score -= (at - prev - 1).min(8) as i64,
+            None => {}
+        }
+    }
+    score
+}
+
+/// Candidate indices matching `pattern`, best first; ties keep their order
+fn filter(pattern: &str, lines: &[String]) -> Vec<(usize, Vec<usize>)> {
+    let mut matched: Vec<(usize, Match)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| Some((i, fuzzy_match(pattern, line)?)))
+        .collect();
+    matched.sort_by_key(|(i, m)| (std::cmp::Reverse(m.score), *i));
+    matched.into_iter().map(|(i, m)| (i, m.positions)).collect()
+}
+
+struct Picker<'a> {
+    lines: &'a [String],
+    multi: bool,
+    query: String,
+    matches: Vec<(usize, Vec<usize>)>,
+    cursor: ListState,
+    marked: BTreeSet<usize>,
+}
+
+impl<'a> Picker<'a> {
+    fn new(lines: &'a [String], multi: bool) -> Self {
+        let mut picker = Self {
+            lines,
+            multi,
+            query: String::new(),
+            matches: Vec::new(),
+            cursor: ListState::default(),
+            marked: BTreeSet::new(),
+        };
+        picker.refilter();
+        picker
+    }
+
+    fn refilter(&mut self) {
+        self.matches = filter(&self.query, self.lines);
+        self.cursor.select((!self.matches.is_empty()).then_some(0));
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.cursor
+            .selected()
+            .and_then(|i| self.matches.get(i))
+            .map(|(line, _)| *line)
+    }
+
+    fn step(&mut self, delta: isize) {
+        if let Some(at) = self.cursor.selected() {
+            let next = at.saturating_add_signed(delta);
+            self.cursor
+                .select(Some(next.min(self.matches.len().saturating_sub(1))));
+        }
+    }
+
+    /// Handle a key; Some(picked) once the user accepts, Some([]) if they quit
+    fn key(&mut self, key: KeyEvent) -> Option<Vec<usize>> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(Vec::new()),
+            KeyCode::Char('c' | 'g') if ctrl => return Some(Vec::new()),
+            KeyCode::Enter => {
+                if self.marked.is_empty() {
+                    return Some(self.current().into_iter().collect());
+                }
+                return Some(self.marked.iter().copied().collect());
+            }
+            KeyCode::Up => self.step(-1),
+            KeyCode::Char('p' | 'k') if ctrl => self.step(-1),
+            KeyCode::Down => self.step(1),
+            KeyCode::Char('n' | 'j') if ctrl => self.step(1),
+            KeyCode::Tab if self.multi => {
+                if let Some(line) = self.current() {
+                    if !self.marked.remove(&line) {
+                        self.marked.insert(line);
+                    }
+                    self.step(1);
+                }
+            }
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.refilter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.refilter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.refilter();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list, count, prompt] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let items: Vec<ListItem> = self
+            .matches
+            .iter()
+            .map(|(line, positions)| {
+                let mark = if self.marked.contains(line) { ">" } else { " " };
+                let mut spans = vec![Span::raw(format!("{} ", mark)).bold()];
+                spans.extend(self.lines[*line].chars().enumerate().map(|(i, c)| {
+                    if positions.binary_search(&i).is_ok() {
+                        Span::raw(c.to_string()).bold().underlined()
+                    } else {
+                        Span::raw(c.to_string())
+                    }
+                }));
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let list_widget = List::new(items).highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list_widget, list, &mut self.cursor);
+
+        let mut counter = format!("  {}/{}", self.matches.len(), self.lines.len());
+        if !self.marked.is_empty() {
+            counter.push_str(&format!(" ({} marked)", self.marked.len()));
+        }
+        frame.render_widget(Paragraph::new(counter).dim(), count);
+        frame.render_widget(Paragraph::new(format!("> {}_", self.query)), prompt);
+    }
+}
+
+/// Let the user pick among `lines` (one, or several with `multi`) and return
+/// the picked indices in the original order; empty if they cancelled.
+/// Draws on stderr, which should be a terminal.
+pub fn pick(lines: &[String], multi: bool) -> Result<Vec<usize>> {
+    enable_raw_mode()?;
+    execute!(std::io::stderr(), EnterAlternateScreen)?;
+    let result = Terminal::new(CrosstermBackend::new(std::io::stderr()))
+        .map_err(anyhow::Error::from)
+        .and_then(|mut terminal| event_loop(&mut terminal, Picker::new(lines, multi)));
+    let _ = execute!(std::io::stderr(), LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+    result
+}
+
+fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stderr>>,
+    mut picker: Picker,
+) -> Result<Vec<usize>> {
+    loop {
+        terminal.draw(|frame| picker.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(mut picked) = picker.key(key) {
+                picked.sort_unstable();
+                return Ok(picked);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        let m = fuzzy_match("inv", "Jan 2  Ann  Invoice 42").unwrap();
+        assert_eq!(m.positions, [12, 13, 14]);
+        assert!(fuzzy_match("xyz", "Invoice").is_none());
+        // Every term has to match, in any order
+        assert!(fuzzy_match("42 ann", "Jan 2  Ann  Invoice 42").is_some());
+        assert!(fuzzy_match("42 bob", "Jan 2  Ann  Invoice 42").is_none());
+        // Uppercase makes the match case-sensitive
+        assert!(fuzzy_match("Ann", "ann").is_none());
+        assert!(fuzzy_match("", "anything").is_some());
+
+        // Runs and word starts beat scattered letters
+        let run = fuzzy_match("lunch", "Lunch on Friday").unwrap();
+        let scattered = fuzzy_match("lunch", "Last update: no change").unwrap();
+        assert!(run.score > scattered.score);
+    }
+
+    #[test]
+    fn test_picker_keys() {
+        let lines: Vec<String> = ["Invoice March", "Lunch", "Invoice April"]
+            .map(String::from)
+            .into();
+        let press = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut picker = Picker::new(&lines, true);
+        for c in "inv".chars() {
+            assert_eq!(picker.key(press(KeyCode::Char(c))), None);
+        }
+        assert_eq!(picker.matches.len(), 2);
+        picker.key(press(KeyCode::Tab));
+        picker.key(press(KeyCode::Tab));
+        assert_eq!(picker.key(press(KeyCode::Enter)), Some(vec![0, 2]));
+
+        let mut single = Picker::new(&lines, false);
+        single.key(press(KeyCode::Down));
+        single.key(press(KeyCode::Tab));
+        assert!(single.marked.is_empty());
+        assert_eq!(single.key(press(KeyCode::Enter)), Some(vec![1]));
+        assert_eq!(single.key(press(KeyCode::Esc)), Some(vec![]));
+    }
+}
@@ -0,0 +1,140 @@
+// Files only the current user can read: tokens, config and cached mail.
+// Unix uses mode 0600/0700; Windows replaces the inherited ACL with one
+// that grants access to the file's owner alone.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Create a directory (and its parents) readable only by the current user
+pub fn create_dir(dir: &Path) -> io::Result<()> {
+    if !dir.exists() {
+        fs::create_dir_all(dir)?;
+        restrict(dir)?;
+    }
+    Ok(())
+}
+
+/// Write a file readable only by the current user, replacing any contents
+pub fn write(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = open(path, true)?;
+    file.write_all(content)
+}
+
+/// Create a private file under a fresh random name in the temp directory,
+/// so an existing file or symlink there is never reused. Deleted when the
+/// returned path is dropped, unless it is kept.
+pub fn temp_file(prefix: &str, suffix: &str, content: &[u8]) -> io::Result<tempfile::TempPath> {
+    let mut file = tempfile::Builder::new()
+        .prefix(prefix)
+        .suffix(suffix)
+        .tempfile()?;
+    #[cfg(windows)]
+    restrict(file.path())?;
+    file.write_all(content)?;
+    Ok(file.into_temp_path())
+}
+
+/// Open a private file for writing, creating it if needed. A symlink at
+/// `path` is refused rather than followed.
+pub fn open(path: &Path, truncate: bool) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(truncate);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+        let file = options.open(path)?;
+        // The mode only applies to new files, so tighten an existing one too
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(windows)]
+    {
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(io::Error::other(format!("{} is a symlink", path.display())));
+        }
+        let file = options.open(path)?;
+        restrict(path)?;
+        Ok(file)
+    }
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if path.is_dir() { 0o700 } else { 0o600 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(windows)]
+fn restrict(path: &Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    };
+    use windows_sys::Win32::Security::{
+        DACL_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+        SetFileSecurityW,
+    };
+
+    // Protected (not inherited) DACL: full control for the owner, inherited
+    // by anything created inside a directory
+    let sddl: Vec<u16> = "D:P(A;OICI;FA;;;OW)".encode_utf16().chain([0]).collect();
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut descriptor: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+    // SAFETY: both strings are NUL-terminated; the descriptor is allocated by
+    // the first call and freed with LocalFree once applied
+    unsafe {
+        if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            std::ptr::null_mut(),
+        ) == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let applied = SetFileSecurityW(
+            wide_path.as_ptr(),
+            DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+            descriptor,
+        );
+        LocalFree(descriptor);
+        if applied == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("outlook-secure-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        create_dir(&dir.join("nested")).unwrap();
+        write(&dir.join("nested/tokens.json"), b"{}").unwrap();
+
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir.join("nested")), 0o700);
+        assert_eq!(mode(&dir.join("nested/tokens.json")), 0o600);
+
+        // Existing files are tightened, and symlinks aren't followed
+        let loose = dir.join("loose.json");
+        fs::write(&loose, b"{}").unwrap();
+        fs::set_permissions(&loose, fs::Permissions::from_mode(0o644)).unwrap();
+        write(&loose, b"[]").unwrap();
+        assert_eq!(mode(&loose), 0o600);
+        std::os::unix::fs::symlink(&loose, dir.join("link.json")).unwrap();
+        assert!(write(&dir.join("link.json"), b"x").is_err());
+        assert_eq!(fs::read(&loose).unwrap(), b"[]");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
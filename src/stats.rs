@@ -0,0 +1,370 @@
+use chrono::Local;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::api::Message;
+use crate::dates;
+
+// Aggregate counts over a set of messages, for `outlook stats`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MailStats {
+    pub messages: usize,
+    pub unread: usize,
+    pub top_senders: Vec<Count>,
+    pub top_lists: Vec<Count>,
+    /// Messages per local day, oldest first
+    pub per_day: Vec<DayCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Count {
+    pub name: String,
+    pub messages: usize,
+    pub unread: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DayCount {
+    pub date: String,
+    pub messages: usize,
+}
+
+/// The list identifier from a List-Id header: the `<...>` part when present
+/// ("Dev list <dev.lists.example.com>" -> "dev.lists.example.com")
+pub fn list_id(header: &str) -> String {
+    let id = match (header.rfind('<'), header.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &header[start + 1..end],
+        _ => header,
+    };
+    id.trim().to_lowercase()
+}
+
+/// Summarize messages, keeping the `top` busiest senders and lists.
+/// List-Ids are only counted for messages fetched with their headers.
+pub fn summarize(messages: &[Message], top: usize) -> MailStats {
+    let mut senders: HashMap<String, Count> = HashMap::new();
+    let mut lists: HashMap<String, Count> = HashMap::new();
+    let mut per_day: BTreeMap<String, usize> = BTreeMap::new();
+    let mut unread = 0;
+
+    for msg in messages {
+        let is_unread = !msg.is_read.unwrap_or(true);
+        unread += usize::from(is_unread);
+
+        let sender = msg.get_from_address().unwrap_or("(unknown)").to_lowercase();
+        tally(&mut senders, sender, is_unread);
+        if let Some(header) = msg.get_header("List-Id") {
+            tally(&mut lists, list_id(header), is_unread);
+        }
+        if let Some(received) = msg
+            .received_date_time
+            .as_deref()
+            .and_then(dates::parse_graph_time)
+        {
+            let day = received.with_timezone(&Local).date_naive().to_string();
+            *per_day.entry(day).or_insert(0) += 1;
+        }
+    }
+
+    MailStats {
+        messages: messages.len(),
+        unread,
+        top_senders: busiest(senders, top),
+        top_lists: busiest(lists, top),
+        per_day: per_day
+            .into_iter()
+            .map(|(date, messages)| DayCount { date, messages })
+            .collect(),
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FolderSize {
+    /// Folder ID until the caller swaps in display names
+    pub folder: String,
+    pub messages: usize,
+    pub bytes: u64,
+}
+
+/// Running per-folder byte totals plus the `top` largest messages seen
+pub struct SizeTally {
+    top: usize,
+    folders: HashMap<String, FolderSize>,
+    largest: Vec<(u64, Message)>,
+}
+
+impl SizeTally {
+    pub fn new(top: usize) -> Self {
+        SizeTally {
+            top,
+            folders: HashMap::new(),
+            largest: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, msg: Message) {
+        let bytes = msg.get_size().unwrap_or(0);
+        let folder = msg.parent_folder_id.clone().unwrap_or_default();
+        let entry = self.folders.entry(folder.clone()).or_insert(FolderSize {
+            folder,
+            messages: 0,
+            bytes: 0,
+        });
+        entry.messages += 1;
+        entry.bytes += bytes;
+
+        // Trim in batches rather than on every message
+        self.largest.push((bytes, msg));
+        if self.largest.len() >= self.top.max(1) * 4 {
+            self.trim();
+        }
+    }
+
+    fn trim(&mut self) {
+        self.largest
+            .sort_by_key(|(bytes, _)| std::cmp::Reverse(*bytes));
+        self.largest.truncate(self.top);
+    }
+
+    /// Folders by total size and the largest messages, biggest first
+    pub fn finish(mut self) -> (Vec<FolderSize>, Vec<(u64, Message)>) {
+        self.trim();
+        let mut folders: Vec<FolderSize> = self.folders.into_values().collect();
+        folders.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.folder.cmp(&b.folder)));
+        (folders, self.largest)
+    }
+}
+
+// How often one address appears on messages, by role
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Correspondent {
+    pub address: String,
+    pub name: String,
+    pub messages: usize,
+    pub from: usize,
+    pub to: usize,
+    pub cc: usize,
+}
+
+/// Running From/To/Cc counts per address, skipping the mailbox owner
+pub struct CorrespondentTally {
+    own_address: String,
+    seen: HashMap<String, Correspondent>,
+}
+
+impl CorrespondentTally {
+    pub fn new(own_address: &str) -> Self {
+        CorrespondentTally {
+            own_address: own_address.to_lowercase(),
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, msg: &Message) {
+        // Count each address once per message, under its first role
+        let mut counted = std::collections::HashSet::new();
+        let roles = [
+            (0, msg.from.as_slice()),
+            (1, msg.to_recipients.as_deref().unwrap_or_default()),
+            (2, msg.cc_recipients.as_deref().unwrap_or_default()),
+        ];
+        for (role, recipients) in roles {
+            for recipient in recipients {
+                let Some(address) = recipient.email_address.address.as_deref() else {
+                    continue;
+                };
+                let address = address.trim().to_lowercase();
+                if address.is_empty()
+                    || address == self.own_address
+                    || !counted.insert(address.clone())
+                {
+                    continue;
+                }
+                let entry = self.seen.entry(address.clone()).or_insert(Correspondent {
+                    address,
+                    name: String::new(),
+                    messages: 0,
+                    from: 0,
+                    to: 0,
+                    cc: 0,
+                });
+                if entry.name.is_empty()
+                    && let Some(name) = &recipient.email_address.name
+                {
+                    entry.name = name.trim().to_string();
+                }
+                entry.messages += 1;
+                match role {
+                    0 => entry.from += 1,
+                    1 => entry.to += 1,
+                    _ => entry.cc += 1,
+                }
+            }
+        }
+    }
+
+    /// Most frequent first; ties by address
+    pub fn finish(self) -> Vec<Correspondent> {
+        let mut all: Vec<Correspondent> = self.seen.into_values().collect();
+        all.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.address.cmp(&b.address)));
+        all
+    }
+}
+
+/// Bytes as "512 B", "1.5 KB", "23.4 MB" (powers of 1024)
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn tally(counts: &mut HashMap<String, Count>, name: String, unread: bool) {
+    let count = counts.entry(name.clone()).or_insert(Count {
+        name,
+        messages: 0,
+        unread: 0,
+    });
+    count.messages += 1;
+    count.unread += usize::from(unread);
+}
+
+// Highest message counts first; ties by name so output is stable
+fn busiest(counts: HashMap<String, Count>, top: usize) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts.into_values().collect();
+    counts.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.name.cmp(&b.name)));
+    counts.truncate(top);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, is_read: bool, list_id: Option<&str>) -> Message {
+        let headers: Vec<serde_json::Value> = list_id
+            .map(|id| vec![serde_json::json!({ "name": "List-Id", "value": id })])
+            .unwrap_or_default();
+        serde_json::from_value(serde_json::json!({
+            "id": "m1",
+            "from": { "emailAddress": { "address": from } },
+            "isRead": is_read,
+            "internetMessageHeaders": headers,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_summarize() {
+        let messages = vec![
+            message("news@foo.com", false, Some("Foo news <news.foo.com>")),
+            message("News@foo.com", false, Some("<news.foo.com>")),
+            message("alice@example.com", true, None),
+            message("bob@example.com", true, None),
+        ];
+        let stats = summarize(&messages, 2);
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.unread, 2);
+        assert_eq!(
+            stats.top_senders,
+            vec![
+                Count {
+                    name: "news@foo.com".to_string(),
+                    messages: 2,
+                    unread: 2
+                },
+                Count {
+                    name: "alice@example.com".to_string(),
+                    messages: 1,
+                    unread: 0
+                },
+            ]
+        );
+        assert_eq!(stats.top_lists.len(), 1);
+        assert_eq!(stats.top_lists[0].name, "news.foo.com");
+        assert!(stats.per_day.is_empty());
+    }
+
+    #[test]
+    fn test_correspondents() {
+        let msg = |value: serde_json::Value| -> Message { serde_json::from_value(value).unwrap() };
+        let address = |a: &str| serde_json::json!({ "emailAddress": { "address": a, "name": "" } });
+        let mut tally = CorrespondentTally::new("Me@example.com");
+        tally.add(&msg(serde_json::json!({
+            "id": "m1",
+            "from": { "emailAddress": { "address": "Alice@example.com", "name": "Alice" } },
+            "toRecipients": [address("me@example.com"), address("bob@example.com")],
+            "ccRecipients": [address("alice@example.com")],
+        })));
+        tally.add(&msg(serde_json::json!({
+            "id": "m2",
+            "from": address("me@example.com"),
+            "toRecipients": [address("alice@example.com")],
+        })));
+        let all = tally.finish();
+        assert_eq!(all.len(), 2);
+        assert_eq!(
+            all[0],
+            Correspondent {
+                address: "alice@example.com".to_string(),
+                name: "Alice".to_string(),
+                messages: 2,
+                from: 1,
+                to: 1,
+                cc: 0
+            }
+        );
+        assert_eq!(all[1].address, "bob@example.com");
+        assert_eq!(all[1].to, 1);
+    }
+
+    fn sized(id: &str, folder: &str, bytes: u64) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "parentFolderId": folder,
+            "singleValueExtendedProperties": [
+                { "id": "Integer 0x0E08", "value": bytes.to_string() }
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_size_tally() {
+        let mut tally = SizeTally::new(2);
+        for (i, bytes) in [10, 500, 30, 400, 20].into_iter().enumerate() {
+            let folder = if i % 2 == 0 { "inbox" } else { "archive" };
+            tally.add(sized(&format!("m{}", i), folder, bytes));
+        }
+        let (folders, largest) = tally.finish();
+        assert_eq!(
+            folders,
+            vec![
+                FolderSize {
+                    folder: "archive".to_string(),
+                    messages: 2,
+                    bytes: 900
+                },
+                FolderSize {
+                    folder: "inbox".to_string(),
+                    messages: 3,
+                    bytes: 60
+                },
+            ]
+        );
+        let largest: Vec<u64> = largest.iter().map(|(bytes, _)| *bytes).collect();
+        assert_eq!(largest, vec![500, 400]);
+
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// 1 logs every Graph request (`--debug`/`-v`), 2 adds headers and bodies (`-vv`)
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// `--dry-run`: print writes instead of sending them
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// `println!` for confirmations and other chatter that `--quiet` hides.
+/// `--dry-run` hides it too, as nothing it would confirm happened.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! outlook_info {
+    ($($arg:tt)*) => {
+        if !$crate::status::quiet() && !$crate::status::dry_run() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `eprintln!` for progress notes (retries, partial counts) that `--quiet` hides
+#[doc(hidden)]
+#[macro_export]
+macro_rules! outlook_note {
+    ($($arg:tt)*) => {
+        if !$crate::status::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// `eprintln!` for request logging at `level` or above; shown even with `--quiet`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! outlook_debug {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::status::verbosity() >= $level {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// #[macro_export] puts macros at the crate root, so they get prefixed names
+// there and are used as `status::info!` and so on
+pub use crate::{outlook_debug as debug, outlook_info as info, outlook_note as note};
@@ -0,0 +1,152 @@
+#[cfg(unix)]
+use std::io::IsTerminal;
+
+const DEFAULT_WIDTH: usize = 100;
+// Columns are never shrunk below this many characters
+const MIN_COLUMN: usize = 8;
+const SEPARATOR: &str = "  ";
+
+/// Width of the terminal on stdout, falling back to $COLUMNS and then 100
+pub fn terminal_width() -> usize {
+    #[cfg(unix)]
+    if std::io::stdout().is_terminal() {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: TIOCGWINSZ only writes into the winsize we pass
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return size.ws_col as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Render rows as aligned columns under a header row, truncating the
+/// `shrinkable` columns (widest first) until the table fits in `width`
+pub fn render(
+    headers: &[&str],
+    rows: &[Vec<String>],
+    shrinkable: &[usize],
+    width: usize,
+) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let separators = SEPARATOR.len() * headers.len().saturating_sub(1);
+    while widths.iter().sum::<usize>() + separators > width {
+        let widest = shrinkable
+            .iter()
+            .copied()
+            .filter(|&i| widths[i] > MIN_COLUMN)
+            .max_by_key(|&i| widths[i]);
+        match widest {
+            Some(i) => widths[i] -= 1,
+            None => break,
+        }
+    }
+
+    let mut out = String::new();
+    let header: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &w)| format!("{:<w$}", truncate(cell, w)))
+            .collect();
+        out.push_str(cells.join(SEPARATOR).trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Render rows as CSV (`sep` = ',') or TSV (`sep` = '\t') with a header row.
+/// CSV fields are quoted as in RFC 4180; TSV escapes tabs, newlines and
+/// backslashes as `\t`, `\n` and `\\`.
+pub fn delimited(headers: &[&str], rows: &[Vec<String>], sep: char) -> String {
+    let mut out = String::new();
+    let header: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .map(|cell| {
+                if sep == '\t' {
+                    escape_tsv(cell)
+                } else {
+                    quote_csv(cell, sep)
+                }
+            })
+            .collect();
+        out.push_str(&cells.join(&sep.to_string()));
+        out.push('\n');
+    }
+    out
+}
+
+fn quote_csv(cell: &str, sep: char) -> String {
+    if cell.contains([sep, '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+fn escape_tsv(cell: &str) -> String {
+    cell.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_fits_width() {
+        let rows = vec![vec![
+            "1".to_string(),
+            "A very long subject line that will not fit".to_string(),
+        ]];
+        let out = render(&["#", "SUBJECT"], &rows, &[1], 20);
+        assert_eq!(out, "#  SUBJECT\n1  A very long subj…\n");
+    }
+
+    #[test]
+    fn test_delimited() {
+        let rows = vec![vec!["Smith, Jo".to_string(), "Say \"hi\"\tnow".to_string()]];
+        assert_eq!(
+            delimited(&["from", "subject"], &rows, ','),
+            "from,subject\n\"Smith, Jo\",\"Say \"\"hi\"\"\tnow\"\n"
+        );
+        assert_eq!(
+            delimited(&["from", "subject"], &rows, '\t'),
+            "from\tsubject\nSmith, Jo\tSay \"hi\"\\tnow\n"
+        );
+    }
+
+    #[test]
+    fn test_render_natural_width() {
+        let rows = vec![
+            vec!["a".to_string(), "xyz".to_string()],
+            vec!["bbb".to_string(), "z".to_string()],
+        ];
+        let out = render(&["K", "V"], &rows, &[1], 80);
+        assert_eq!(out, "K    V\na    xyz\nbbb  z\n");
+    }
+}
@@ -0,0 +1,147 @@
+// Optional OpenTelemetry export. Graph calls and sign-in flows always emit
+// `tracing` spans; with the `otel` feature and OTEL_EXPORTER_OTLP_ENDPOINT
+// set, spans and request metrics are sent to an OTLP/HTTP collector.
+// Without either they cost next to nothing.
+
+use std::time::Duration;
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use anyhow::{Context, Result};
+    use opentelemetry::metrics::{Counter, Histogram};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::sync::OnceLock;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    const SERVICE_NAME: &str = "outlook-cli";
+
+    /// Flushes pending spans and metrics when dropped at exit
+    pub struct Telemetry {
+        tracer: SdkTracerProvider,
+        meter: SdkMeterProvider,
+    }
+
+    impl Drop for Telemetry {
+        fn drop(&mut self) {
+            let _ = self.tracer.shutdown();
+            let _ = self.meter.shutdown();
+        }
+    }
+
+    pub fn init() -> Result<Option<Telemetry>> {
+        let configured = [
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+            "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT",
+        ]
+        .iter()
+        .any(|name| std::env::var_os(name).is_some_and(|v| !v.is_empty()));
+        if !configured {
+            return Ok(None);
+        }
+
+        let mut resource = Resource::builder();
+        if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+            resource = resource.with_service_name(SERVICE_NAME);
+        }
+        let resource = resource.build();
+
+        let spans = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .context("Failed to set up the OTLP span exporter")?;
+        let tracer = SdkTracerProvider::builder()
+            .with_resource(resource.clone())
+            .with_batch_exporter(spans)
+            .build();
+        let metrics = opentelemetry_otlp::MetricExporter::builder()
+            .with_http()
+            .build()
+            .context("Failed to set up the OTLP metric exporter")?;
+        let meter = SdkMeterProvider::builder()
+            .with_resource(resource)
+            .with_periodic_exporter(metrics)
+            .build();
+        opentelemetry::global::set_meter_provider(meter.clone());
+
+        let layer = tracing_opentelemetry::layer().with_tracer(tracer.tracer(SERVICE_NAME));
+        tracing_subscriber::registry()
+            .with(layer)
+            .try_init()
+            .context("Failed to install the tracing subscriber")?;
+        Ok(Some(Telemetry { tracer, meter }))
+    }
+
+    pub struct Instruments {
+        pub duration: Histogram<f64>,
+        pub retries: Counter<u64>,
+        pub throttled: Counter<u64>,
+    }
+
+    pub fn instruments() -> &'static Instruments {
+        static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+        INSTRUMENTS.get_or_init(|| {
+            let meter = opentelemetry::global::meter(SERVICE_NAME);
+            Instruments {
+                duration: meter
+                    .f64_histogram("graph.request.duration")
+                    .with_unit("s")
+                    .with_description("Time for each Graph request, including failed attempts")
+                    .build(),
+                retries: meter
+                    .u64_counter("graph.request.retries")
+                    .with_description("Graph requests retried after an error or throttling")
+                    .build(),
+                throttled: meter
+                    .u64_counter("graph.request.throttled")
+                    .with_description("Graph responses with 429 or 503")
+                    .build(),
+            }
+        })
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use otlp::init;
+
+/// Stand-in when built without the `otel` feature
+#[cfg(not(feature = "otel"))]
+pub struct Telemetry;
+
+#[cfg(not(feature = "otel"))]
+pub fn init() -> anyhow::Result<Option<Telemetry>> {
+    Ok(None)
+}
+
+/// One Graph request attempt; `status` is None when it never got a response
+pub fn record_request(method: &str, status: Option<u16>, elapsed: Duration) {
+    #[cfg(feature = "otel")]
+    otlp::instruments().duration.record(
+        elapsed.as_secs_f64(),
+        &[
+            opentelemetry::KeyValue::new("http.request.method", method.to_string()),
+            opentelemetry::KeyValue::new("http.response.status_code", status.map_or(0, i64::from)),
+        ],
+    );
+    #[cfg(not(feature = "otel"))]
+    let _ = (method, status, elapsed);
+}
+
+/// A retry; `reason` is "throttled", "server_error" or "network"
+pub fn record_retry(reason: &'static str) {
+    #[cfg(feature = "otel")]
+    otlp::instruments()
+        .retries
+        .add(1, &[opentelemetry::KeyValue::new("reason", reason)]);
+    #[cfg(not(feature = "otel"))]
+    let _ = reason;
+}
+
+pub fn record_throttled() {
+    #[cfg(feature = "otel")]
+    otlp::instruments().throttled.add(1, &[]);
+}
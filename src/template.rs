@@ -0,0 +1,208 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Write;
+
+/// A `--format` string such as `"{date:%Y-%m-%d} {from:<30} {subject:.60}"`.
+///
+/// `{name}` inserts a field. After a colon, date fields take a strftime
+/// pattern; other fields take an alignment and width (`<30`, `>8`, `^20`)
+/// and/or a maximum length (`.60`). `{{`, `}}`, `\t` and `\n` are literals.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field { name: String, spec: Option<String> },
+}
+
+pub enum Value {
+    Text(String),
+    Date(DateTime<Utc>),
+}
+
+pub fn parse(input: &str) -> Result<Template> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '\\' if matches!(chars.peek(), Some('t' | 'n')) => {
+                literal.push(if chars.next() == Some('t') {
+                    '\t'
+                } else {
+                    '\n'
+                });
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => anyhow::bail!("Unclosed '{{' in format"),
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                let (name, spec) = match field.split_once(':') {
+                    Some((name, spec)) => (name, Some(spec.to_string())),
+                    None => (field.as_str(), None),
+                };
+                if name.is_empty() {
+                    anyhow::bail!("Empty field name in format");
+                }
+                parts.push(Part::Field {
+                    name: name.to_string(),
+                    spec,
+                });
+            }
+            '}' => anyhow::bail!("Unmatched '}}' in format (use '}}}}' for a literal brace)"),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(Template { parts })
+}
+
+impl Template {
+    /// Fail on fields outside `known`, so typos show up before any request
+    pub fn check(self, known: &[&str]) -> Result<Self> {
+        for part in &self.parts {
+            if let Part::Field { name, .. } = part
+                && !known.contains(&name.as_str())
+            {
+                anyhow::bail!(
+                    "Unknown format field '{}' (available: {})",
+                    name,
+                    known.join(", ")
+                );
+            }
+        }
+        Ok(self)
+    }
+
+    /// The field names the template inserts, in order
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Field { name, .. } => Some(name.as_str()),
+            Part::Literal(_) => None,
+        })
+    }
+
+    pub fn render(&self, lookup: impl Fn(&str) -> Option<Value>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Field { name, spec } => {
+                    let value = lookup(name);
+                    out.push_str(&format_value(value, spec.as_deref()));
+                }
+            }
+        }
+        out
+    }
+}
+
+fn format_value(value: Option<Value>, spec: Option<&str>) -> String {
+    let text = match value {
+        Some(Value::Date(dt)) => {
+            let local = dt.with_timezone(&Local);
+            return match spec {
+                Some(pattern) if pattern.contains('%') => {
+                    // An invalid pattern makes Display fail; show it as-is
+                    let mut out = String::new();
+                    match write!(out, "{}", local.format(pattern)) {
+                        Ok(()) => out,
+                        Err(_) => pattern.to_string(),
+                    }
+                }
+                _ => pad(&crate::dates::display(dt), spec),
+            };
+        }
+        Some(Value::Text(text)) => text,
+        None => String::new(),
+    };
+    pad(&text, spec)
+}
+
+// Apply an alignment/width/precision spec like "<30", ">8", "^20.20" or ".60"
+fn pad(text: &str, spec: Option<&str>) -> String {
+    let Some(spec) = spec else {
+        return text.to_string();
+    };
+    let (align, rest) = match spec.chars().next() {
+        Some(c @ ('<' | '>' | '^')) => (c, &spec[1..]),
+        _ => ('<', spec),
+    };
+    let (width, precision) = match rest.split_once('.') {
+        Some((w, p)) => (w.parse().unwrap_or(0), p.parse().ok()),
+        None => (rest.parse().unwrap_or(0), None),
+    };
+
+    let mut text: String = match precision {
+        Some(max) if text.chars().count() > max => {
+            let mut cut: String = text.chars().take(max.saturating_sub(1)).collect();
+            cut.push('…');
+            cut
+        }
+        _ => text.to_string(),
+    };
+    let len = text.chars().count();
+    if len < width {
+        let fill = width - len;
+        text = match align {
+            '>' => format!("{}{}", " ".repeat(fill), text),
+            '^' => format!(
+                "{}{}{}",
+                " ".repeat(fill / 2),
+                text,
+                " ".repeat(fill - fill / 2)
+            ),
+            _ => format!("{}{}", text, " ".repeat(fill)),
+        };
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<Value> {
+        match name {
+            "from" => Some(Value::Text("alice".to_string())),
+            "subject" => Some(Value::Text("Quarterly report".to_string())),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let t = parse("{from:<8}|{subject:.9}|{from:>7}|{{x}}\\t{missing}").unwrap();
+        assert_eq!(t.render(lookup), "alice   |Quarterl…|  alice|{x}\t");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("{from").is_err());
+        assert!(parse("from}").is_err());
+        assert!(parse("{}").is_err());
+        assert!(parse("{form}").unwrap().check(&["from"]).is_err());
+    }
+}
@@ -0,0 +1,554 @@
+// `outlook tui`: folders on the left, conversations above a preview on the
+// right. Actions go through the same api::Client (and hooks) as the
+// commands, one conversation at a time.
+
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+
+use crate::{api, dates, group_threads, hooks, secure};
+
+// Messages loaded per folder; enough for a few screens of conversations
+const FOLDER_PAGE: u32 = 100;
+const HELP: &str = "tab pane  j/k move  a archive  d delete  l label  r reply  g refresh  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pane {
+    Folders,
+    Messages,
+    Preview,
+}
+
+/// A line of text being typed, e.g. a category name
+struct Prompt {
+    label: &'static str,
+    text: String,
+}
+
+struct App {
+    client: api::Client,
+    mark_read: bool,
+    folders: Vec<api::Folder>,
+    folder: ListState,
+    messages: Vec<api::Message>,
+    thread: ListState,
+    focus: Pane,
+    // Rendered previews by message ID
+    previews: HashMap<String, Text<'static>>,
+    scroll: u16,
+    prompt: Option<Prompt>,
+    status: String,
+    width: u16,
+}
+
+/// Run the TUI until the user quits; `mark_read` marks previewed messages read
+pub async fn run(client: api::Client, mark_read: bool) -> Result<()> {
+    let mut app = App::new(client, mark_read);
+    app.load_folders().await?;
+
+    let mut terminal = ratatui::init();
+    let result = app.event_loop(&mut terminal).await;
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new(client: api::Client, mark_read: bool) -> Self {
+        Self {
+            client,
+            mark_read,
+            folders: Vec::new(),
+            folder: ListState::default(),
+            messages: Vec::new(),
+            thread: ListState::default(),
+            focus: Pane::Messages,
+            previews: HashMap::new(),
+            scroll: 0,
+            prompt: None,
+            status: HELP.to_string(),
+            width: 80,
+        }
+    }
+
+    async fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            self.load_preview().await;
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // Reading the terminal blocks; give this thread up while it does
+            let event = tokio::task::block_in_place(event::read)?;
+            let Event::Key(key) = event else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if self.prompt.is_some() {
+                self.prompt_key(key).await;
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
+                KeyCode::Tab => self.focus = next_pane(self.focus),
+                KeyCode::BackTab => self.focus = next_pane(next_pane(self.focus)),
+                KeyCode::Down | KeyCode::Char('j') => self.step(1).await,
+                KeyCode::Up | KeyCode::Char('k') => self.step(-1).await,
+                KeyCode::Enter if self.focus == Pane::Folders => self.focus = Pane::Messages,
+                KeyCode::Enter => self.focus = Pane::Preview,
+                KeyCode::Char('g') => {
+                    let result = self.reload().await;
+                    self.report(result, "Refreshed");
+                }
+                KeyCode::Char('a') => self.move_thread("archive", "Archived").await,
+                KeyCode::Char('d') => self.move_thread("deleteditems", "Deleted").await,
+                KeyCode::Char('l') if self.selected().is_some() => {
+                    self.prompt = Some(Prompt {
+                        label: "Label",
+                        text: String::new(),
+                    });
+                }
+                KeyCode::Char('r') => {
+                    let result = self.reply(terminal).await;
+                    self.report(result, "Reply sent");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [folders, right] =
+            Layout::horizontal([Constraint::Percentage(20), Constraint::Percentage(80)])
+                .areas(main);
+        let [list, preview] =
+            Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(right);
+        self.width = preview.width.saturating_sub(2);
+
+        self.draw_folders(frame, folders);
+        self.draw_threads(frame, list);
+        self.draw_preview(frame, preview);
+        let line = match &self.prompt {
+            Some(prompt) => format!("{}: {}_", prompt.label, prompt.text),
+            None => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(line).reversed(), status);
+    }
+
+    fn draw_folders(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .folders
+            .iter()
+            .map(|f| match f.unread_item_count.unwrap_or_default() {
+                0 => ListItem::new(f.display_name.clone()),
+                n => ListItem::new(format!("{} ({})", f.display_name, n)).bold(),
+            })
+            .collect();
+        let list = List::new(items)
+            .block(pane_block("Folders", self.focus == Pane::Folders))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, area, &mut self.folder);
+    }
+
+    fn draw_threads(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = group_threads(&self.messages)
+            .iter()
+            .map(|t| {
+                let msg = t.latest;
+                let date = msg
+                    .received_date_time
+                    .as_deref()
+                    .map(dates::display_graph)
+                    .unwrap_or_default();
+                let count = if t.count > 1 {
+                    format!(" ({})", t.count)
+                } else {
+                    String::new()
+                };
+                let line = Line::from(vec![
+                    Span::raw(format!("{}  ", date)).dim(),
+                    Span::raw(format!("{:<24.24}  ", msg.get_from().unwrap_or_default())),
+                    Span::raw(msg.subject.clone().unwrap_or_default()),
+                    Span::raw(count).dim(),
+                ]);
+                if t.unread > 0 {
+                    ListItem::new(line).bold()
+                } else {
+                    ListItem::new(line)
+                }
+            })
+            .collect();
+        let title = self
+            .folder
+            .selected()
+            .and_then(|i| self.folders.get(i))
+            .map_or("Messages", |f| f.display_name.as_str());
+        let list = List::new(items)
+            .block(pane_block(title, self.focus == Pane::Messages))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, area, &mut self.thread);
+    }
+
+    fn draw_preview(&self, frame: &mut Frame, area: Rect) {
+        let text = self
+            .selected()
+            .and_then(|msg| self.previews.get(&msg.id).cloned())
+            .unwrap_or_default();
+        let preview = Paragraph::new(text)
+            .block(pane_block("Preview", self.focus == Pane::Preview))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(preview, area);
+    }
+
+    /// Latest message of the selected conversation
+    fn selected(&self) -> Option<&api::Message> {
+        let threads = group_threads(&self.messages);
+        self.thread
+            .selected()
+            .and_then(|i| threads.get(i).map(|t| t.latest))
+    }
+
+    /// IDs of every loaded message in the selected conversation
+    fn selected_ids(&self) -> Vec<String> {
+        let Some(latest) = self.selected() else {
+            return Vec::new();
+        };
+        match &latest.conversation_id {
+            Some(conversation) => self
+                .messages
+                .iter()
+                .filter(|m| m.conversation_id.as_ref() == Some(conversation))
+                .map(|m| m.id.clone())
+                .collect(),
+            None => vec![latest.id.clone()],
+        }
+    }
+
+    async fn step(&mut self, delta: i32) {
+        match self.focus {
+            Pane::Folders => {
+                let len = self.folders.len();
+                if move_selection(&mut self.folder, len, delta) {
+                    let result = self.load_messages().await;
+                    self.report(result, HELP);
+                }
+            }
+            Pane::Messages => {
+                let len = group_threads(&self.messages).len();
+                if move_selection(&mut self.thread, len, delta) {
+                    self.scroll = 0;
+                }
+            }
+            Pane::Preview => {
+                self.scroll = self.scroll.saturating_add_signed(delta as i16);
+            }
+        }
+    }
+
+    async fn load_folders(&mut self) -> Result<()> {
+        self.folders = self.client.list_folders().await?.value.unwrap_or_default();
+        let inbox = self.client.get_folder("inbox").await?;
+        let index = self.folders.iter().position(|f| f.id == inbox.id);
+        self.folder
+            .select(index.or(Some(0)).filter(|_| !self.folders.is_empty()));
+        self.load_messages().await
+    }
+
+    async fn load_messages(&mut self) -> Result<()> {
+        let Some(folder) = self.folder.selected().and_then(|i| self.folders.get(i)) else {
+            return Ok(());
+        };
+        self.messages = self
+            .client
+            .list_messages(&folder.id, None, FOLDER_PAGE)
+            .await?
+            .value
+            .unwrap_or_default();
+        self.thread.select((!self.messages.is_empty()).then_some(0));
+        self.scroll = 0;
+        Ok(())
+    }
+
+    async fn reload(&mut self) -> Result<()> {
+        let selected = self.folder.selected();
+        self.folders = self.client.list_folders().await?.value.unwrap_or_default();
+        self.folder
+            .select(selected.filter(|&i| i < self.folders.len()));
+        self.load_messages().await
+    }
+
+    /// Fetch and render the selected message unless it's already cached
+    async fn load_preview(&mut self) {
+        let Some(msg) = self.selected() else {
+            return;
+        };
+        if self.previews.contains_key(&msg.id) {
+            return;
+        }
+        let id = msg.id.clone();
+        let text = match self.client.get_message(&id).await {
+            Ok(full) => {
+                if self.mark_read && full.is_read == Some(false) {
+                    let _ = self.client.mark_read(&id, full.etag.as_deref()).await;
+                }
+                render_preview(&full, self.width as usize)
+            }
+            Err(e) => Text::raw(format!("{:#}", e)),
+        };
+        self.previews.insert(id, text);
+    }
+
+    async fn move_thread(&mut self, destination: &str, done: &str) {
+        let ids = self.selected_ids();
+        if ids.is_empty() {
+            return;
+        }
+        let result = async {
+            let failed = crate::move_and_hook(&self.client, &ids, destination)
+                .await?
+                .into_iter()
+                .find_map(Result::err);
+            match failed {
+                Some(e) => Err(e),
+                None => self.load_messages().await,
+            }
+        }
+        .await;
+        self.report(result, done);
+    }
+
+    async fn prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Backspace => {
+                prompt.text.pop();
+            }
+            KeyCode::Char(c) => prompt.text.push(c),
+            KeyCode::Enter => {
+                let label = prompt.text.trim().to_string();
+                self.prompt = None;
+                if !label.is_empty() {
+                    let result = self.label(&label).await;
+                    self.report(result, &format!("Labeled {}", label));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn label(&mut self, label: &str) -> Result<()> {
+        self.client.ensure_category(label).await?;
+        let ids = self.selected_ids();
+        let action = format!("label {}", label);
+        let results = crate::edit_labels(&self.client, &ids, &action, |categories| {
+            if !categories.iter().any(|c| c.eq_ignore_ascii_case(label)) {
+                categories.push(label.to_string());
+            }
+        })
+        .await?;
+        if let Some(e) = results.into_iter().find_map(Result::err) {
+            return Err(e);
+        }
+        self.load_messages().await
+    }
+
+    /// Write a reply in $EDITOR and send it; an empty reply is dropped
+    async fn reply(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let Some(msg) = self.selected() else {
+            return Ok(());
+        };
+        let Some(to) = msg.get_from_address().map(String::from) else {
+            anyhow::bail!("This message has no sender to reply to");
+        };
+        let (id, subject) = (msg.id.clone(), msg.subject.clone().unwrap_or_default());
+
+        ratatui::restore();
+        let comment = edit_text(&format!("\n\n# Reply to {} about \"{}\"\n", to, subject));
+        *terminal = ratatui::init();
+        let comment = comment?;
+        if comment.is_empty() {
+            anyhow::bail!("Empty reply, not sent");
+        }
+
+        let subject = format!("Re: {}", subject.trim_start_matches("Re: "));
+        let message = api::mail_message(&to, &subject, &comment, None);
+        hooks::run(hooks::Hook::PreSend, &message)
+            .await
+            .context("Not sending the reply")?;
+        self.client.reply(&id, &comment).await
+    }
+
+    fn report(&mut self, result: Result<()>, done: &str) {
+        self.status = match result {
+            Ok(()) => done.to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+    }
+}
+
+fn next_pane(pane: Pane) -> Pane {
+    match pane {
+        Pane::Folders => Pane::Messages,
+        Pane::Messages => Pane::Preview,
+        Pane::Preview => Pane::Folders,
+    }
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(format!(" {} ", title));
+    if focused {
+        block.border_style(Style::new().add_modifier(Modifier::BOLD))
+    } else {
+        block.border_style(Style::new().dim())
+    }
+}
+
+/// Move a list selection by `delta` within `len` items; true if it changed
+fn move_selection(state: &mut ListState, len: usize, delta: i32) -> bool {
+    if len == 0 {
+        return false;
+    }
+    let current = state.selected().unwrap_or(0);
+    let next = current.saturating_add_signed(delta as isize).min(len - 1);
+    state.select(Some(next));
+    next != current
+}
+
+fn render_preview(msg: &api::Message, width: usize) -> Text<'static> {
+    let header = |name: &str, value: String| {
+        Line::from(vec![
+            Span::raw(format!("{}: ", name)).bold(),
+            Span::raw(value),
+        ])
+    };
+    let mut lines = vec![
+        header("From", msg.get_from().unwrap_or_default()),
+        header("To", msg.get_to().unwrap_or_default()),
+        header(
+            "Date",
+            msg.received_date_time
+                .as_deref()
+                .map(dates::display_graph)
+                .unwrap_or_default(),
+        ),
+        header("Subject", msg.subject.clone().unwrap_or_default()),
+        Line::default(),
+    ];
+    let body = msg.get_body_plain(width.max(20)).unwrap_or_default();
+    lines.extend(body.lines().map(|line| Line::raw(line.to_string())));
+    Text::from(lines)
+}
+
+/// Let the user write text in $VISUAL/$EDITOR (default vi). Lines starting
+/// with '#' are dropped, as in a commit message.
+fn edit_text(template: &str) -> Result<String> {
+    // Deleted when dropped, after the editor has been read back
+    let path = secure::temp_file("outlook-reply-", ".txt", template.as_bytes())?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let status = editor_command(&editor, &path)
+        .status()
+        .with_context(|| format!("Failed to run {}", editor));
+    let text = std::fs::read_to_string(&path);
+    if !status?.success() {
+        anyhow::bail!("{} exited unsuccessfully, reply not sent", editor);
+    }
+    let text = text?;
+    let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+    Ok(lines.join("\n").trim().to_string())
+}
+
+// The editor setting may carry arguments ("code --wait"), so go through the shell
+#[cfg(unix)]
+fn editor_command(editor: &str, path: &std::path::Path) -> std::process::Command {
+    let mut command = std::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path);
+    command
+}
+
+#[cfg(windows)]
+fn editor_command(editor: &str, path: &std::path::Path) -> std::process::Command {
+    let mut command = std::process::Command::new("cmd");
+    command
+        .arg("/C")
+        .arg(format!("{} \"{}\"", editor, path.display()));
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_selection() {
+        let mut state = ListState::default();
+        assert!(!move_selection(&mut state, 0, 1));
+        assert!(move_selection(&mut state, 3, 1));
+        assert_eq!(state.selected(), Some(1));
+        assert!(move_selection(&mut state, 3, 5));
+        assert_eq!(state.selected(), Some(2));
+        assert!(!move_selection(&mut state, 3, 1));
+        assert!(move_selection(&mut state, 3, -9));
+        assert_eq!(state.selected(), Some(0));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut app = App::new(
+            api::Client::new("token", api::RetryPolicy::default()),
+            false,
+        );
+        app.folders = serde_json::from_value(serde_json::json!([
+            { "id": "f1", "displayName": "Inbox", "unreadItemCount": 2 },
+            { "id": "f2", "displayName": "Archive" },
+        ]))
+        .unwrap();
+        app.folder.select(Some(0));
+        app.messages = serde_json::from_value(serde_json::json!([
+            { "id": "m2", "conversationId": "c1", "subject": "Re: Lunch", "isRead": false,
+              "receivedDateTime": "2026-01-02T10:00:00Z",
+              "from": { "emailAddress": { "name": "Ann", "address": "ann@example.com" } } },
+            { "id": "m1", "conversationId": "c1", "subject": "Lunch", "isRead": true,
+              "receivedDateTime": "2026-01-01T10:00:00Z" },
+            { "id": "m3", "conversationId": "c2", "subject": "Invoice", "isRead": true,
+              "receivedDateTime": "2026-01-01T09:00:00Z" },
+        ]))
+        .unwrap();
+        app.thread.select(Some(0));
+        assert_eq!(app.selected_ids(), ["m2", "m1"]);
+
+        let mut terminal =
+            ratatui::Terminal::new(ratatui::backend::TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Inbox (2)"));
+        assert!(screen.contains("Re: Lunch (2)"));
+        assert!(screen.contains("Invoice"));
+        assert!(!screen.contains("Lunch (1)"));
+    }
+}
@@ -0,0 +1,350 @@
+// `outlook undo`: a local journal of recent changes to messages. Commands
+// that move messages or change their categories or read state append an
+// entry recording how the messages were before, and `undo` puts them back.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::status::{self, info, note};
+use crate::{api, config, dates};
+
+const JOURNAL_FILE: &str = "undo.json";
+// Older entries are dropped once the journal is this long
+const MAX_ENTRIES: usize = 50;
+
+/// A message as it was before a change
+#[derive(Debug, Clone, PartialEq)]
+pub struct Before {
+    folder: Option<String>,
+    is_read: Option<bool>,
+    categories: Vec<String>,
+}
+
+impl Before {
+    pub fn of(msg: &api::Message) -> Self {
+        Self {
+            folder: msg.parent_folder_id.clone(),
+            is_read: msg.is_read,
+            categories: msg.categories.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Read how `ids` are now, ahead of changing them. Messages that can't be
+/// read are changed all the same; they just can't be undone.
+pub async fn snapshot(client: &api::Client, ids: &[String]) -> Vec<Option<Before>> {
+    if status::dry_run() {
+        return vec![None; ids.len()];
+    }
+    match client.get_message_states(ids).await {
+        Ok(states) => states
+            .into_iter()
+            .map(|state| state.ok().as_ref().map(Before::of))
+            .collect(),
+        Err(e) => {
+            note!("Can't record this for undo: {:#}", e);
+            vec![None; ids.len()]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "messages", rename_all = "camelCase")]
+enum Change {
+    /// Each moved message by its new ID, with the folder it came from
+    Moved(Vec<(String, String)>),
+    /// Each message with the categories it had
+    Categories(Vec<(String, Vec<String>)>),
+    /// Each message with whether it was read
+    Read(Vec<(String, bool)>),
+}
+
+impl Change {
+    fn len(&self) -> usize {
+        match self {
+            Change::Moved(messages) => messages.len(),
+            Change::Categories(messages) => messages.len(),
+            Change::Read(messages) => messages.len(),
+        }
+    }
+
+    /// Follow messages whose IDs changed when an undo moved them back
+    fn rename(&mut self, renamed: &HashMap<String, String>) {
+        let ids: Vec<&mut String> = match self {
+            Change::Moved(messages) => messages.iter_mut().map(|(id, _)| id).collect(),
+            Change::Categories(messages) => messages.iter_mut().map(|(id, _)| id).collect(),
+            Change::Read(messages) => messages.iter_mut().map(|(id, _)| id).collect(),
+        };
+        for id in ids {
+            if let Some(new) = renamed.get(id.as_str()) {
+                *id = new.clone();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    at: DateTime<Utc>,
+    /// What was done, e.g. "archive" or "label Jira"
+    action: String,
+    change: Change,
+}
+
+/// Journal messages moved to `destination`, by their IDs after the move
+pub fn record_moves(destination: &str, before: &[Option<Before>], moved: &[Result<api::Message>]) {
+    let messages = before
+        .iter()
+        .zip(moved)
+        .filter_map(|(before, moved)| {
+            let from = before.as_ref()?.folder.clone()?;
+            Some((moved.as_ref().ok()?.id.clone(), from))
+        })
+        .collect();
+    let action = match destination {
+        "archive" => "archive".to_string(),
+        "deleteditems" => "delete".to_string(),
+        "junkemail" => "spam".to_string(),
+        "inbox" => "move to inbox".to_string(),
+        _ => "move".to_string(),
+    };
+    record(&action, Change::Moved(messages));
+}
+
+/// Journal a category change to `ids`
+pub fn record_categories(
+    action: &str,
+    ids: &[String],
+    before: &[Option<Before>],
+    results: &[Result<()>],
+) {
+    let messages = changed(ids, before, results)
+        .map(|(id, before)| (id.clone(), before.categories.clone()))
+        .collect();
+    record(action, Change::Categories(messages));
+}
+
+/// Journal a read state change to `ids`
+pub fn record_read(
+    action: &str,
+    ids: &[String],
+    before: &[Option<Before>],
+    results: &[Result<()>],
+) {
+    let messages = changed(ids, before, results)
+        .filter_map(|(id, before)| Some((id.clone(), before.is_read?)))
+        .collect();
+    record(action, Change::Read(messages));
+}
+
+/// The messages a change succeeded on, with how they were before
+fn changed<'a>(
+    ids: &'a [String],
+    before: &'a [Option<Before>],
+    results: &'a [Result<()>],
+) -> impl Iterator<Item = (&'a String, &'a Before)> {
+    ids.iter()
+        .zip(before)
+        .zip(results)
+        .filter_map(|((id, before), result)| {
+            Some((id, before.as_ref().filter(|_| result.is_ok())?))
+        })
+}
+
+// Best effort: the change itself has already been made
+fn record(action: &str, change: Change) {
+    if status::dry_run() || change.len() == 0 {
+        return;
+    }
+    let result = config::load_state(JOURNAL_FILE).and_then(|mut journal: Vec<Entry>| {
+        journal.push(Entry {
+            at: Utc::now(),
+            action: action.to_string(),
+            change,
+        });
+        let excess = journal.len().saturating_sub(MAX_ENTRIES);
+        journal.drain(..excess);
+        config::save_state(JOURNAL_FILE, &journal)
+    });
+    if let Err(e) = result {
+        note!("Can't record this for undo: {:#}", e);
+    }
+}
+
+/// Print the journal, most recent first
+pub fn list(json: bool) -> Result<()> {
+    let journal: Vec<Entry> = config::load_state(JOURNAL_FILE)?;
+    if json {
+        let newest_first: Vec<&Entry> = journal.iter().rev().collect();
+        println!("{}", serde_json::to_string(&newest_first)?);
+    } else if journal.is_empty() {
+        info!("Nothing to undo.");
+    } else {
+        for (n, entry) in journal.iter().rev().enumerate() {
+            println!(
+                "{:>3}  {}  {} ({} messages)",
+                n + 1,
+                dates::display(entry.at),
+                entry.action,
+                entry.change.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reverse the `count` most recent entries, newest first
+pub async fn undo(client: &api::Client, count: usize) -> Result<()> {
+    let mut journal: Vec<Entry> = config::load_state(JOURNAL_FILE)?;
+    if journal.is_empty() {
+        anyhow::bail!("Nothing to undo");
+    }
+    let mut failed = 0;
+    for _ in 0..count {
+        let Some(entry) = journal.pop() else {
+            break;
+        };
+        let (renamed, errors) = revert(client, &entry.change).await?;
+        for (id, e) in &errors {
+            eprintln!("Failed to undo {} on {}: {}", entry.action, id, e);
+        }
+        failed += errors.len();
+        info!(
+            "Undid {} ({} messages)",
+            entry.action,
+            entry.change.len() - errors.len()
+        );
+        for older in &mut journal {
+            older.change.rename(&renamed);
+        }
+        if !status::dry_run() {
+            config::save_state(JOURNAL_FILE, &journal)?;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} messages couldn't be restored", failed);
+    }
+    Ok(())
+}
+
+type Failures = Vec<(String, anyhow::Error)>;
+
+/// Put messages back as `change` found them. Returns the new IDs of messages
+/// moved back, and the messages that couldn't be restored.
+async fn revert(
+    client: &api::Client,
+    change: &Change,
+) -> Result<(HashMap<String, String>, Failures)> {
+    let mut renamed = HashMap::new();
+    let mut failed = Vec::new();
+    match change {
+        Change::Moved(messages) => {
+            let mut by_folder: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+            for (id, from) in messages {
+                by_folder.entry(from).or_default().push(id.clone());
+            }
+            for (folder, ids) in by_folder {
+                let moved = client.move_messages_returning(&ids, folder).await?;
+                for (id, result) in ids.into_iter().zip(moved) {
+                    match result {
+                        Ok(msg) => {
+                            renamed.insert(id, msg.id);
+                        }
+                        Err(e) => failed.push((id, e)),
+                    }
+                }
+            }
+        }
+        Change::Categories(messages) => {
+            let updates: Vec<api::CategoryUpdate> = messages
+                .iter()
+                .map(|(id, categories)| api::CategoryUpdate {
+                    id: id.clone(),
+                    categories: categories.clone(),
+                    etag: None,
+                })
+                .collect();
+            let results = client.update_categories_each(&updates).await?;
+            for (update, result) in updates.into_iter().zip(results) {
+                if let Err(e) = result {
+                    failed.push((update.id, e));
+                }
+            }
+        }
+        Change::Read(messages) => {
+            for is_read in [true, false] {
+                let ids: Vec<String> = messages
+                    .iter()
+                    .filter(|(_, read)| *read == is_read)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                if ids.is_empty() {
+                    continue;
+                }
+                let results = client.set_read_many(&ids, is_read).await?;
+                for (id, result) in ids.into_iter().zip(results) {
+                    if let Err(e) = result {
+                        failed.push((id, e));
+                    }
+                }
+            }
+        }
+    }
+    Ok((renamed, failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_changes() {
+        let ids: Vec<String> = ["a", "b", "c"].map(String::from).into();
+        let before = vec![
+            Some(Before {
+                folder: Some("inbox-id".to_string()),
+                is_read: Some(false),
+                categories: vec!["Jira".to_string()],
+            }),
+            None,
+            Some(Before {
+                folder: Some("inbox-id".to_string()),
+                is_read: Some(true),
+                categories: Vec::new(),
+            }),
+        ];
+        // Only messages read beforehand and changed successfully are kept
+        let results = vec![Ok(()), Ok(()), Err(anyhow::anyhow!("failed"))];
+        let kept: Vec<&String> = changed(&ids, &before, &results).map(|(id, _)| id).collect();
+        assert_eq!(kept, ["a"]);
+
+        let mut change = Change::Moved(vec![
+            ("a".to_string(), "inbox-id".to_string()),
+            ("c".to_string(), "inbox-id".to_string()),
+        ]);
+        change.rename(&HashMap::from([("c".to_string(), "c2".to_string())]));
+        assert_eq!(
+            change,
+            Change::Moved(vec![
+                ("a".to_string(), "inbox-id".to_string()),
+                ("c2".to_string(), "inbox-id".to_string()),
+            ])
+        );
+
+        let entry = Entry {
+            at: Utc::now(),
+            action: "mark-read".to_string(),
+            change: Change::Read(vec![("a".to_string(), false)]),
+        };
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["change"]["kind"], "read");
+        assert_eq!(
+            json["change"]["messages"][0],
+            serde_json::json!(["a", false])
+        );
+        let back: Entry = serde_json::from_value(json).unwrap();
+        assert_eq!(back.change, entry.change);
+    }
+}
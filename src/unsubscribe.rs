@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+const ONE_CLICK_BODY: &str = "List-Unsubscribe=One-Click";
+const DEFAULT_SUBJECT: &str = "unsubscribe";
+
+/// The email a `mailto:` unsubscribe link asks for
+#[derive(Debug, PartialEq)]
+pub struct Mailto {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Parse `mailto:list@example.com?subject=unsubscribe&body=...` (RFC 6068)
+pub fn parse_mailto(url: &str) -> Option<Mailto> {
+    let rest = url
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+        .map(|_| &url[7..])?;
+    let (to, query) = rest.split_once('?').unwrap_or((rest, ""));
+    // Percent-encoding only: unlike form data, `+` is a literal plus
+    let decode = |s: &str| {
+        urlencoding::decode(s)
+            .map(|d| d.into_owned())
+            .unwrap_or_else(|_| s.to_string())
+    };
+    let to = decode(to);
+    if to.is_empty() {
+        return None;
+    }
+
+    let mut mailto = Mailto {
+        to,
+        subject: DEFAULT_SUBJECT.to_string(),
+        body: String::new(),
+    };
+    for pair in query.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match key.to_lowercase().as_str() {
+            "subject" if !value.is_empty() => mailto.subject = decode(value),
+            "body" => mailto.body = decode(value),
+            _ => {}
+        }
+    }
+    Some(mailto)
+}
+
+/// Unsubscribe with an RFC 8058 one-click POST. The request carries no
+/// cookies or credentials, as the RFC requires.
+pub async fn one_click(url: &str) -> Result<()> {
+    let response = crate::api::http_client()
+        .timeout(Duration::from_secs(30))
+        .build()?
+        .post(url)
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        )
+        .body(ONE_CLICK_BODY)
+        .send()
+        .await
+        .context("Failed to send unsubscribe request")?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Unsubscribe request failed with HTTP {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mailto() {
+        assert_eq!(
+            parse_mailto("mailto:list@example.com?subject=Remove%20me&body=please"),
+            Some(Mailto {
+                to: "list@example.com".to_string(),
+                subject: "Remove me".to_string(),
+                body: "please".to_string(),
+            })
+        );
+        let bare = parse_mailto("MAILTO:leave-123@lists.example.com").unwrap();
+        assert_eq!(bare.to, "leave-123@lists.example.com");
+        assert_eq!(bare.subject, "unsubscribe");
+        let verp = parse_mailto("mailto:unsub+abc123@list.example.com?subject=a+b").unwrap();
+        assert_eq!(verp.to, "unsub+abc123@list.example.com");
+        assert_eq!(verp.subject, "a+b");
+        assert_eq!(parse_mailto("https://example.com/unsub"), None);
+        assert_eq!(parse_mailto("mailto:"), None);
+    }
+}
@@ -0,0 +1,243 @@
+// `outlook self-update`: replace the running binary with the latest GitHub
+// release. SHA256SUMS must carry a valid minisign signature from the project
+// key, and the download must match its line in that file.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::api;
+use crate::status::{self, info};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/Osso/outlook-cli/releases/latest";
+const CHECKSUMS: &str = "SHA256SUMS";
+const SIGNATURE: &str = "SHA256SUMS.minisig";
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+// Public half of the key release.yml signs SHA256SUMS with (MINISIGN_SECRET_KEY)
+const PUBLIC_KEY: &str = "RWS8+kMtoKzJ6KPUlc1lj3mMRhkdo46ufG1ijeo7LHpFJ1ekPA5RqTZm";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .with_context(|| format!("Release {} has no {}", self.tag_name, name))
+    }
+}
+
+/// The release asset built for this platform, as named by release.yml
+fn asset_name() -> Option<String> {
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        _ => return None,
+    };
+    match std::env::consts::OS {
+        "linux" => Some(format!("outlook-linux-{}", arch)),
+        _ => None,
+    }
+}
+
+/// "v1.2.3" or "1.2.3" as numbers; pre-release and build suffixes are ignored
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim_start_matches('v');
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(version)
+}
+
+/// The SHA-256 listed for `name` in a `sha256sum` output
+fn expected_checksum<'a>(sums: &'a str, name: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (hash, file) = line.split_once(char::is_whitespace)?;
+        // sha256sum marks binary-mode entries with '*'
+        let file = file.trim_start().trim_start_matches('*');
+        (file == name).then_some(hash)
+    })
+}
+
+fn verify_signature(data: &[u8], signature: &str, public_key: &str) -> Result<()> {
+    let key = minisign_verify::PublicKey::from_base64(public_key)
+        .context("Invalid release public key")?;
+    let signature =
+        minisign_verify::Signature::decode(signature).context("Invalid release signature")?;
+    key.verify(data, &signature, false)
+        .context("Release signature doesn't match")
+}
+
+fn verify_checksum(binary: &[u8], sums: &str, name: &str) -> Result<()> {
+    let expected = expected_checksum(sums, name)
+        .with_context(|| format!("{} doesn't list {}", CHECKSUMS, name))?;
+    let actual: String = Sha256::digest(binary)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            name,
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+async fn download(http: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let response = http
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download {}", url))?
+        .error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Install the latest release over the running binary, or with `check_only`
+/// just report whether there is one
+pub async fn self_update(check_only: bool) -> Result<()> {
+    let http = api::http_client()
+        .user_agent(concat!("outlook-cli/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let release: Release = http
+        .get(LATEST_RELEASE_URL)
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .context("Failed to check for releases")?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let latest = parse_version(&release.tag_name)
+        .with_context(|| format!("Unrecognized release tag: {}", release.tag_name))?;
+    let current = parse_version(CURRENT_VERSION).context("Unrecognized version")?;
+    if latest <= current {
+        info!("outlook {} is the latest version", CURRENT_VERSION);
+        return Ok(());
+    }
+    if check_only {
+        println!(
+            "outlook {} is available (you have {}); run `outlook self-update` to install it",
+            release.tag_name.trim_start_matches('v'),
+            CURRENT_VERSION
+        );
+        return Ok(());
+    }
+
+    let name = asset_name().with_context(|| {
+        format!(
+            "No release builds for {}-{}; update with cargo instead",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        )
+    })?;
+    if status::dry_run() {
+        println!(
+            "Would install outlook {} over {}",
+            release.tag_name.trim_start_matches('v'),
+            std::env::current_exe()?.display()
+        );
+        return Ok(());
+    }
+    let sums = download(&http, &release.asset(CHECKSUMS)?.browser_download_url).await?;
+    let signature = download(&http, &release.asset(SIGNATURE)?.browser_download_url).await?;
+    verify_signature(&sums, &String::from_utf8_lossy(&signature), PUBLIC_KEY)
+        .context("Refusing to install an update without a valid signature")?;
+    let binary = download(&http, &release.asset(&name)?.browser_download_url).await?;
+    verify_checksum(&binary, &String::from_utf8_lossy(&sums), &name)?;
+
+    install(&binary)?;
+    info!(
+        "Updated outlook {} -> {}",
+        CURRENT_VERSION,
+        release.tag_name.trim_start_matches('v')
+    );
+    Ok(())
+}
+
+/// Swap the running executable for `binary`
+fn install(binary: &[u8]) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let dir = exe
+        .parent()
+        .context("Can't find the directory outlook is installed in")?;
+    // Stage next to the binary so the final rename stays on one filesystem
+    let staged = dir.join(format!(".outlook-update-{}", std::process::id()));
+    let result = std::fs::write(&staged, binary)
+        .and_then(|()| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+            }
+            self_replace::self_replace(&staged)
+        })
+        .with_context(|| format!("Failed to replace {}", exe.display()));
+    let _ = std::fs::remove_file(&staged);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version("0.10.0-rc.1"), Some((0, 10, 0)));
+        assert_eq!(parse_version("1.2"), None);
+        assert_eq!(parse_version("1.2.3.4"), None);
+        assert!(parse_version("v0.10.0") > parse_version("0.9.9"));
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let sums = "\
+9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  outlook-linux-amd64
+0000000000000000000000000000000000000000000000000000000000000000 *outlook-linux-arm64
+";
+        assert_eq!(
+            expected_checksum(sums, "outlook-linux-arm64"),
+            Some("0000000000000000000000000000000000000000000000000000000000000000")
+        );
+        verify_checksum(b"test", sums, "outlook-linux-amd64").unwrap();
+        assert!(verify_checksum(b"tampered", sums, "outlook-linux-amd64").is_err());
+        assert!(verify_checksum(b"test", sums, "outlook-macos-arm64").is_err());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        // Test vector from minisign-verify
+        let key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+        let signature = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1633700835\tfile:test\tprehashed
+wLMDjy9FLAuxZ3q4NlEvkgtyhrr0gtTu6KC4KBJdITbbOeAi1zBIYo0v4iTgt8jJpIidRJnp94ABQkJAgAooBQ==";
+        verify_signature(b"test", signature, key).unwrap();
+        assert!(verify_signature(b"tampered", signature, key).is_err());
+        assert!(verify_signature(b"test", signature, PUBLIC_KEY).is_err());
+    }
+
+    #[test]
+    fn test_release_key() {
+        let signature = "untrusted comment: signature from minisign secret key
+RUS8+kMtoKzJ6MOP+whUVQJoftHeVpXV8aeGr3QB8bdeaMRZ81zNFqAWmA6O8uTTvkD9BWisrj+0kGXBpaDlKhxFRPeUvsMw1Qo=
+trusted comment: timestamp:1700000000\tfile:test\tprehashed
+oS+0C7rvxr+ap6l5/VXsLXqJC3CfYOBlwn+bA2Pgae5tZQv8WosB/gRH5jDvexV5TEUjGrZ4jiQjSNUxzGGuAQ==";
+        verify_signature(b"test", signature, PUBLIC_KEY).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use url::Url;
+
+use crate::api::{Client, Subscription};
+use crate::config;
+
+const SUBSCRIPTIONS_FILE: &str = "subscriptions.json";
+// Graph caps mail subscriptions at 10080 minutes (7 days); stay just under it
+pub const MAX_LIFETIME_MINUTES: i64 = 10070;
+// Renew anything that expires within this window
+const RENEW_WINDOW_HOURS: i64 = 24;
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct NotificationBatch {
+    value: Vec<Notification>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Notification {
+    #[serde(rename = "subscriptionId")]
+    pub subscription_id: String,
+    #[serde(rename = "clientState")]
+    pub client_state: Option<String>,
+    #[serde(rename = "changeType")]
+    pub change_type: String,
+    pub resource: String,
+    #[serde(rename = "resourceData")]
+    pub resource_data: Option<ResourceData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResourceData {
+    pub id: Option<String>,
+}
+
+/// Called with the ID of each message a "created" notification announces
+pub type OnCreated = Arc<dyn Fn(String) + Send + Sync>;
+
+struct HttpRequest {
+    method: String,
+    target: String,
+    body: Vec<u8>,
+}
+
+pub fn expiration_from_now(minutes: i64) -> String {
+    (Utc::now() + chrono::Duration::minutes(minutes)).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn load_subscriptions() -> Result<Vec<Subscription>> {
+    config::load_state(SUBSCRIPTIONS_FILE)
+}
+
+fn save_subscriptions(subscriptions: &[Subscription]) -> Result<()> {
+    config::save_state(SUBSCRIPTIONS_FILE, &subscriptions)
+}
+
+/// Create an inbox subscription and remember it for renewal and clientState checks
+pub async fn subscribe(
+    client: &Client,
+    notification_url: &str,
+    minutes: i64,
+) -> Result<Subscription> {
+    let client_state = oauth2::CsrfToken::new_random().secret().to_string();
+    let subscription = client
+        .create_subscription(
+            notification_url,
+            &expiration_from_now(minutes.min(MAX_LIFETIME_MINUTES)),
+            &client_state,
+        )
+        .await?;
+
+    let mut subscriptions = load_subscriptions()?;
+    subscriptions.retain(|s| s.id != subscription.id);
+    subscriptions.push(Subscription {
+        client_state: Some(client_state),
+        ..subscription.clone()
+    });
+    save_subscriptions(&subscriptions)?;
+    Ok(subscription)
+}
+
+/// Renew stored subscriptions that expire soon, dropping ones Graph no longer knows about
+pub async fn renew_due(client: &Client) -> Result<usize> {
+    let mut subscriptions = load_subscriptions()?;
+    let threshold = Utc::now() + chrono::Duration::hours(RENEW_WINDOW_HOURS);
+    let mut renewed = 0;
+    let mut kept = Vec::new();
+
+    for mut sub in subscriptions.drain(..) {
+        let expires = DateTime::parse_from_rfc3339(&sub.expiration_date_time)
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now());
+        if expires > threshold {
+            kept.push(sub);
+            continue;
+        }
+
+        match client
+            .renew_subscription(&sub.id, &expiration_from_now(MAX_LIFETIME_MINUTES))
+            .await
+        {
+            Ok(updated) => {
+                sub.expiration_date_time = updated.expiration_date_time;
+                renewed += 1;
+                kept.push(sub);
+            }
+            Err(e) if expires < Utc::now() => {
+                eprintln!("Dropping expired subscription {}: {}", sub.id, e);
+            }
+            Err(e) => {
+                eprintln!("Failed to renew subscription {}: {}", sub.id, e);
+                kept.push(sub);
+            }
+        }
+    }
+
+    save_subscriptions(&kept)?;
+    Ok(renewed)
+}
+
+/// Accept Graph notifications on a local port, printing each one as a JSON line
+pub async fn serve(port: u16, on_created: Option<OnCreated>) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("Failed to bind to port {}", port))?;
+    eprintln!("Listening for notifications on port {}...", port);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let on_created = on_created.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, on_created).await {
+                eprintln!("Notification handling failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, on_created: Option<OnCreated>) -> Result<()> {
+    let request = read_request(&mut stream).await?;
+
+    // Graph validates a new endpoint by POSTing ?validationToken=... and
+    // expects the decoded token echoed back as text/plain within 10 seconds
+    if let Some(token) = validation_token(&request.target) {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            token.len(),
+            token
+        );
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+
+    if request.method != "POST" {
+        stream
+            .write_all(b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n")
+            .await?;
+        return Ok(());
+    }
+
+    let batch: NotificationBatch = match serde_json::from_slice(&request.body) {
+        Ok(batch) => batch,
+        Err(_) => {
+            stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Acknowledge quickly; Graph retries deliveries that take too long
+    stream
+        .write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n")
+        .await?;
+
+    let subscriptions = load_subscriptions()?;
+    for notification in batch.value {
+        let known = subscriptions.iter().any(|s| {
+            s.id == notification.subscription_id && s.client_state == notification.client_state
+        });
+        if !known {
+            eprintln!(
+                "Ignoring notification with unknown subscription or clientState: {}",
+                notification.subscription_id
+            );
+            continue;
+        }
+        println!("{}", serde_json::to_string(&notification)?);
+        if let Some(on_created) = &on_created
+            && notification.change_type == "created"
+            && let Some(id) = notification.resource_data.and_then(|data| data.id)
+        {
+            on_created(id);
+        }
+    }
+    Ok(())
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<HttpRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("Connection closed before request headers");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = find_header_end(&buf) {
+            break pos;
+        }
+        if buf.len() > MAX_REQUEST_BYTES {
+            anyhow::bail!("Request headers too large");
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let (method, target, content_length) =
+        parse_head(&head).ok_or_else(|| anyhow::anyhow!("Invalid request"))?;
+    if content_length > MAX_REQUEST_BYTES {
+        anyhow::bail!("Request body too large");
+    }
+
+    let mut body = buf[header_end + 4..].to_vec();
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+
+    Ok(HttpRequest {
+        method,
+        target,
+        body,
+    })
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+fn parse_head(head: &str) -> Option<(String, String, usize)> {
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+
+    Some((method, target, content_length))
+}
+
+fn validation_token(target: &str) -> Option<String> {
+    let url = Url::parse(&format!("http://localhost{}", target)).ok()?;
+    url.query_pairs()
+        .find(|(key, _)| key == "validationToken")
+        .map(|(_, value)| value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_head() {
+        let head = "POST /notify HTTP/1.1\r\nHost: example.com\r\nContent-Length: 42";
+        assert_eq!(
+            parse_head(head),
+            Some(("POST".to_string(), "/notify".to_string(), 42))
+        );
+    }
+
+    #[test]
+    fn test_validation_token() {
+        assert_eq!(
+            validation_token("/notify?validationToken=Validation%3A%20Testing"),
+            Some("Validation: Testing".to_string())
+        );
+        assert_eq!(validation_token("/notify"), None);
+    }
+}
@@ -0,0 +1,231 @@
+// The Graph client against a local mock server: paging, retries and errors
+
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use outlook_graph::api::{Client, GraphError, RetryPolicy};
+use serde_json::json;
+use wiremock::matchers::{
+    body_partial_json, header, method, path, query_param, query_param_is_missing,
+};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn client(server: &MockServer) -> Client {
+    let retry = RetryPolicy {
+        initial_backoff: Duration::ZERO,
+        ..RetryPolicy::default()
+    };
+    Client::new("test-token", retry).with_base_url(&server.uri())
+}
+
+fn message(id: &str) -> serde_json::Value {
+    json!({ "id": id, "subject": format!("Subject {}", id) })
+}
+
+#[tokio::test]
+async fn test_messages_stream_follows_next_link() {
+    let server = MockServer::start().await;
+    let next = format!("{}/me/mailFolders/inbox/messages?$skip=2", server.uri());
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders/inbox/messages"))
+        .and(query_param_is_missing("$skip"))
+        .and(query_param("$filter", "isRead eq false"))
+        .and(header("Authorization", "Bearer test-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "value": [message("1"), message("2")],
+            "@odata.nextLink": next,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders/inbox/messages"))
+        .and(query_param("$skip", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "value": [message("3")],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = client(&server);
+    let ids: Vec<String> = client
+        .messages_stream(Some("inbox"), Some("isRead eq false"))
+        .map_ok(|msg| msg.id)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(ids, ["1", "2", "3"]);
+}
+
+#[tokio::test]
+async fn test_messages_stream_is_lazy() {
+    let server = MockServer::start().await;
+    let next = format!("{}/me/messages?$skip=1", server.uri());
+    Mock::given(method("GET"))
+        .and(path("/me/messages"))
+        .and(query_param_is_missing("$skip"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "value": [message("1")],
+            "@odata.nextLink": next,
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/me/messages"))
+        .and(query_param("$skip", "1"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let client = client(&server);
+    let mut messages = std::pin::pin!(client.messages_stream(None, None));
+    let first = messages.try_next().await.unwrap().unwrap();
+    assert_eq!(first.id, "1");
+}
+
+#[tokio::test]
+async fn test_retries_after_429() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "value": [{ "id": "AAMk", "displayName": "Inbox" }],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let folders = client(&server).list_folders().await.unwrap();
+    let names: Vec<String> = folders
+        .value
+        .unwrap()
+        .into_iter()
+        .map(|f| f.display_name)
+        .collect();
+    assert_eq!(names, ["Inbox"]);
+}
+
+#[tokio::test]
+async fn test_gives_up_after_max_retries() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders"))
+        .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "0"))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let retry = RetryPolicy {
+        max_retries: 1,
+        initial_backoff: Duration::ZERO,
+        ..RetryPolicy::default()
+    };
+    let client = Client::new("test-token", retry).with_base_url(&server.uri());
+    let err = client.list_folders().await.unwrap_err();
+    let graph = err.downcast_ref::<GraphError>().unwrap();
+    assert_eq!(graph.status, 503);
+}
+
+#[tokio::test]
+async fn test_maps_graph_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/me/messages/missing"))
+        .respond_with(
+            ResponseTemplate::new(404)
+                .insert_header("request-id", "abc-123")
+                .set_body_json(json!({
+                    "error": {
+                        "code": "ErrorItemNotFound",
+                        "message": "The specified object was not found in the store.",
+                    }
+                })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let err = client(&server).get_message("missing").await.unwrap_err();
+    let graph = err.downcast_ref::<GraphError>().unwrap();
+    assert_eq!(graph.status, 404);
+    assert_eq!(graph.code.as_deref(), Some("ErrorItemNotFound"));
+    assert_eq!(graph.request_id.as_deref(), Some("abc-123"));
+    assert!(graph.message.contains("not found"));
+}
+
+#[tokio::test]
+async fn test_batch_retries_throttled_items() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/$batch"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "responses": [
+                { "id": "0", "status": 201 },
+                { "id": "1", "status": 429, "headers": { "Retry-After": "0" } },
+                { "id": "2", "status": 404, "body": { "error": { "code": "ErrorItemNotFound", "message": "Not found" } } },
+            ]
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    // Only the throttled item is sent again
+    Mock::given(method("POST"))
+        .and(path("/$batch"))
+        .and(body_partial_json(json!({ "requests": [{ "id": "1" }] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "responses": [{ "id": "1", "status": 201 }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let ids = ["a", "b", "c"].map(String::from);
+    let results = client(&server)
+        .move_messages(&ids, "archive")
+        .await
+        .unwrap();
+    assert!(results[0].is_ok());
+    assert!(results[1].is_ok());
+    let err = results[2].as_ref().unwrap_err();
+    assert_eq!(err.downcast_ref::<GraphError>().unwrap().status, 404);
+}
+
+#[tokio::test]
+async fn test_refreshes_token_without_retry_budget() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders"))
+        .and(header("Authorization", "Bearer test-token"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/me/mailFolders"))
+        .and(header("Authorization", "Bearer fresh-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "value": [] })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let retry = RetryPolicy {
+        max_retries: 0,
+        ..RetryPolicy::default()
+    };
+    let refresh: outlook_graph::api::TokenRefresh =
+        std::sync::Arc::new(|| Box::pin(async { Ok("fresh-token".to_string()) }));
+    let client = Client::new("test-token", retry)
+        .with_base_url(&server.uri())
+        .with_token_refresh(refresh);
+    client.list_folders().await.unwrap();
+}